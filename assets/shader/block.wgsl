@@ -14,13 +14,33 @@
 
 @group(#{MATERIAL_BIND_GROUP}) @binding(0) var my_array_texture: texture_2d_array<f32>;
 @group(#{MATERIAL_BIND_GROUP}) @binding(1) var my_array_texture_sampler: sampler;
+// same as BlockMaterial::alpha_cutoff. 0 for anything that isn't cutout, so nothing gets discarded
+@group(#{MATERIAL_BIND_GROUP}) @binding(2) var<uniform> alpha_cutoff: f32;
+
+// overlay id of faces without an overlay, same as BlockMaterial::NO_OVERLAY
+const NO_OVERLAY: u32 = 0xFFFFFFFFu;
+// same as POSITION_STEPS in render/block.rs
+const POSITION_STEPS: f32 = 16.0;
+// same as UV_STEPS in render/block.rs
+const UV_STEPS: f32 = 512.0;
 
 struct Vertex {
     @builtin(instance_index) instance_index: u32,
+#ifdef PACKED_POSITION
+    // 10 bits per axis, see pack_position
+    @location(0) position: u32,
+#else
     @location(0) position: vec3<f32>,
-    @location(1) uv: vec2<f32>,
+#endif
+    // in steps of a texture, see pack_uv. Past 1 the texture repeats
+    @location(1) uv: vec2<u32>,
     @location(2) texture_id: u32,
-    @location(3) normal: vec3<f32>
+    // w is unused
+    @location(3) normal: vec4<f32>,
+    @location(4) overlay_id: u32,
+    @location(5) tint: u32,
+    // baked light, alpha is 1 for emissive faces
+    @location(6) color: vec4<f32>,
 };
 
 struct VertexOutput {
@@ -29,10 +49,20 @@ struct VertexOutput {
     @location(1) world_normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
     @location(3) @interpolate(flat) texture_id: u32,
+    @location(4) @interpolate(flat) overlay_id: u32,
+    @location(5) @interpolate(flat) tint: u32,
+    @location(6) color: vec4<f32>,
 }
 
 // I really don't know how WGSL works so we winging this shit
 
+#ifdef PACKED_POSITION
+fn unpack_position(packed: u32) -> vec3<f32> {
+    let steps = vec3<u32>(packed & 0x3FFu, (packed >> 10u) & 0x3FFu, (packed >> 20u) & 0x3FFu);
+    return vec3<f32>(steps) / POSITION_STEPS;
+}
+#endif
+
 @vertex
 fn vertex(
     vertex: Vertex
@@ -41,12 +71,20 @@ fn vertex(
     let world_from_local = get_world_from_local(vertex.instance_index);
 
     var out: VertexOutput;
-    out.world_normal = mesh_normal_local_to_world(vertex.normal, vertex.instance_index);
-
-    out.world_position = mesh_position_local_to_world(world_from_local, vec4<f32>(vertex.position, 1.0));
+    out.world_normal = mesh_normal_local_to_world(vertex.normal.xyz, vertex.instance_index);
+
+#ifdef PACKED_POSITION
+    let position = unpack_position(vertex.position);
+#else
+    let position = vertex.position;
+#endif
+    out.world_position = mesh_position_local_to_world(world_from_local, vec4<f32>(position, 1.0));
     out.position = position_world_to_clip(out.world_position.xyz);
-    out.uv = vertex.uv;
+    out.uv = vec2<f32>(vertex.uv) / UV_STEPS;
     out.texture_id = vertex.texture_id;
+    out.overlay_id = vertex.overlay_id;
+    out.tint = vertex.tint;
+    out.color = vertex.color;
 
     return out;
 }
@@ -56,10 +94,26 @@ fn vertex(
 fn fragment(
     mesh: VertexOutput,
 ) -> @location(0) vec4<f32> {
-    var index = mesh.texture_id;
-
-    // sample a 2d array texture
-    return textureSample(my_array_texture, my_array_texture_sampler, mesh.uv, index);
+    let tint = unpack4x8unorm(mesh.tint);
+    let has_overlay = mesh.overlay_id != NO_OVERLAY;
+
+    // sample a 2d array texture.
+    // textureSample can't be in a branch, so the overlay is always sampled and thrown away if there isn't one.
+    let base = textureSample(my_array_texture, my_array_texture_sampler, mesh.uv, mesh.texture_id);
+    let overlay = textureSample(my_array_texture, my_array_texture_sampler, mesh.uv, select(mesh.texture_id, mesh.overlay_id, has_overlay));
+
+    // alpha test for cutout blocks. Has to come after all the textureSamples, since discard makes control flow non-uniform.
+    if base.a < alpha_cutoff {
+        discard;
+    }
+
+    // the tint goes on the overlay if there is one, otherwise on the whole face
+    let base_color = select(base.rgb * tint.rgb, base.rgb, has_overlay);
+    let overlay_alpha = select(0.0, overlay.a, has_overlay);
+    let color = mix(base_color, overlay.rgb * tint.rgb, overlay_alpha);
+    // light is baked into the vertex color when meshing. Emissive faces have alpha set, and skip it
+    let light = select(mesh.color.rgb, vec3(1.0), mesh.color.a > 0.5);
+    return vec4(color * light, base.a);
 
 //    return vec4(mesh.uv, 0.0, 1.0);
 
@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, HashMap as StdHashMap};
+use std::hint::black_box;
+use std::sync::Arc;
+use bevy::math::{ivec3, vec2, vec3, IVec3, Vec2, Vec3};
+use bevy::platform::collections::HashMap as FastHashMap;
+use criterion::{criterion_group, criterion_main, Criterion};
+use gtclone::asset::block::{BlockAsset, BlockModelFace, CullMode, FaceType, ModelVertex, RenderLayer};
+use gtclone::core::profiling::PipelineProfiler;
+use gtclone::registry::block::Block;
+use gtclone::registry::Registry;
+use gtclone::render::block::{BlockModelMinimal, FaceMinimal, MeshDataCache};
+use gtclone::render::chunk::create_chunk_mesh;
+use gtclone::world::block::{BlockState, BlockStateTable, Direction};
+use gtclone::world::chunk::{ChunkData, PackedChunkData, PaletteEntry};
+use gtclone::world::generation::{GenerationConfig, HeightMapProvider};
+use gtclone::world::create_noise_height_map;
+use gtclone::world::pipeline::GenerationPipeline;
+
+// the blocks noise terrain needs, plus air which the registry always has
+const BLOCKS: [&str; 4] = ["stone", "dirt", "grass_block", "oak_planks"];
+
+fn block_registry() -> Registry<Block> {
+    let mut block_reg = Registry::<Block>::new("block");
+    for id in BLOCKS {
+        block_reg.register(Block::from_asset(&BlockAsset {
+            id: String::from(id),
+            hardness: 1,
+            states: vec![],
+            default_state: BTreeMap::new(),
+            models: vec![],
+            sound_group: None,
+            tool: None,
+            harvest_level: 0,
+            render_layer: RenderLayer::Opaque,
+            emissive: false,
+            collision_shape: None,
+            tags: vec![],
+        })).unwrap();
+    }
+    block_reg.freeze();
+    block_reg
+}
+
+fn quad(corners: [Vec3; 4], normal: Vec3, cull_mode: Direction) -> BlockModelFace {
+    let uvs = [vec2(0.0, 1.0), vec2(1.0, 1.0), vec2(1.0, 0.0), vec2(0.0, 0.0)];
+    BlockModelFace {
+        face_type: FaceType::Quad,
+        vertices: corners.into_iter().zip(uvs).map(|(pos, uv): (Vec3, Vec2)| ModelVertex { pos, uv }).collect(),
+        normal,
+        texture: String::from("all"),
+        cull_mode: CullMode::Face(cull_mode),
+        overlay: None,
+        tint: None,
+        uv_rotation: 0,
+        uv_flip: false,
+        emissive: false,
+    }
+}
+
+// same faces as model/block/base/full.model.ron
+fn cube_model(texture_id: u32) -> BlockModelMinimal {
+    let faces = [
+        quad([vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 1.0), vec3(1.0, 1.0, 1.0), vec3(0.0, 1.0, 1.0)], Vec3::Z, Direction::North),
+        quad([vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(1.0, 1.0, 0.0)], Vec3::NEG_Z, Direction::South),
+        quad([vec3(1.0, 0.0, 1.0), vec3(1.0, 0.0, 0.0), vec3(1.0, 1.0, 0.0), vec3(1.0, 1.0, 1.0)], Vec3::X, Direction::East),
+        quad([vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0), vec3(0.0, 1.0, 1.0), vec3(0.0, 1.0, 0.0)], Vec3::NEG_X, Direction::West),
+        quad([vec3(0.0, 1.0, 1.0), vec3(1.0, 1.0, 1.0), vec3(1.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)], Vec3::Y, Direction::Up),
+        quad([vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(1.0, 0.0, 1.0), vec3(0.0, 0.0, 1.0)], Vec3::NEG_Y, Direction::Down),
+    ];
+    let faces = faces.iter()
+        .map(|face| FaceMinimal::from_asset(face, texture_id, None).unwrap())
+        .collect();
+    BlockModelMinimal::new(faces, &Direction::ALL)
+}
+
+// every block but air gets a full cube, with its own texture layer
+fn mesh_cache(block_reg: &Registry<Block>) -> MeshDataCache {
+    let table = BlockStateTable::from_registry(block_reg).unwrap();
+    let mut models = vec![None; table.len()];
+    for (i, id) in BLOCKS.iter().enumerate() {
+        let state = BlockState::new(id, block_reg).unwrap();
+        let state_id = table.get_id(&state).unwrap();
+        models[state_id.get_index()] = Some(cube_model(i as u32));
+    }
+    MeshDataCache::new(table, models)
+}
+
+// fills a chunk in the order ChunkData stores blocks. `f` returns a palette id: 0 is air, 1 is stone, 2 is dirt.
+fn build_chunk(block_reg: &Registry<Block>, f: impl Fn(IVec3) -> usize) -> ChunkData {
+    let mut palette = vec![
+        PaletteEntry::new(BlockState::new("air", block_reg).unwrap()),
+        PaletteEntry::new(BlockState::new("stone", block_reg).unwrap()),
+        PaletteEntry::new(BlockState::new("dirt", block_reg).unwrap()),
+    ];
+    let mut vec = Vec::with_capacity(ChunkData::BLOCKS_PER_CHUNK);
+    for y in 0..ChunkData::CHUNK_SIZE {
+        for x in 0..ChunkData::CHUNK_SIZE {
+            for z in 0..ChunkData::CHUNK_SIZE {
+                let id = f(ivec3(x as i32, y as i32, z as i32));
+                palette[id].increment_ref_count();
+                vec.push(id as u8);
+            }
+        }
+    }
+    ChunkData::with_data(vec, palette)
+}
+
+fn test_chunks(block_reg: &Registry<Block>) -> Vec<(&'static str, ChunkData)> {
+    let half = ChunkData::CHUNK_SIZE as i32 / 2;
+    vec![
+        // lower half stone, upper half air
+        ("flat", build_chunk(block_reg, |pos| if pos.y < half { 1 } else { 0 })),
+        // worst case, every face of every block is visible
+        ("checkerboard", build_chunk(block_reg, |pos| if (pos.x + pos.y + pos.z) % 2 == 0 { 1 } else { 0 })),
+        // stone ball with a dirt shell
+        ("sphere", build_chunk(block_reg, |pos| {
+            let dist = (pos - IVec3::splat(half)).as_vec3().length();
+            if dist < 12.0 { 1 } else if dist < 14.0 { 2 } else { 0 }
+        })),
+    ]
+}
+
+fn bench_meshing(c: &mut Criterion) {
+    let block_reg = block_registry();
+    let cache = mesh_cache(&block_reg);
+    let profiler = PipelineProfiler::default();
+    let air = ChunkData::single(BlockState::new("air", &block_reg).unwrap());
+
+    let mut group = c.benchmark_group("create_chunk_mesh");
+    for (name, chunk) in test_chunks(&block_reg) {
+        group.bench_function(name, |b| {
+            b.iter(|| create_chunk_mesh(black_box(&chunk), &cache, [&air; 6], &profiler))
+        });
+    }
+    group.finish();
+}
+
+fn bench_generation(c: &mut Criterion) {
+    let block_reg = block_registry();
+    let height_map: Arc<dyn HeightMapProvider> = Arc::new(create_noise_height_map(0, &GenerationConfig::default()));
+    let pipeline = GenerationPipeline::noise(height_map);
+
+    // a chunk at the surface, so every stage places something
+    c.bench_function("generate_noise_chunk", |b| {
+        b.iter(|| pipeline.generate(black_box(IVec3::ZERO), 0, &block_reg))
+    });
+}
+
+fn bench_packing(c: &mut Criterion) {
+    let block_reg = block_registry();
+
+    let mut group = c.benchmark_group("packed_chunk_data");
+    for (name, chunk) in test_chunks(&block_reg) {
+        group.bench_function(format!("pack/{name}"), |b| {
+            b.iter(|| PackedChunkData::from(black_box(&chunk)))
+        });
+        let packed = PackedChunkData::from(&chunk);
+        group.bench_function(format!("unpack/{name}"), |b| {
+            b.iter(|| ChunkData::try_from(black_box(packed.clone())).unwrap())
+        });
+    }
+    group.finish();
+}
+
+// the chunk map's hasher against std's SipHash, looking up every chunk in a cube around the player like meshing and neighbor checks do
+fn bench_chunk_lookup(c: &mut Criterion) {
+    let positions = (-12..=12)
+        .flat_map(|x| (-12..=12).flat_map(move |y| (-12..=12).map(move |z| ivec3(x, y, z))))
+        .collect::<Vec<_>>();
+    let std_map = positions.iter().enumerate().map(|(i, pos)| (*pos, i)).collect::<StdHashMap<_, _>>();
+    let fast_map = positions.iter().enumerate().map(|(i, pos)| (*pos, i)).collect::<FastHashMap<_, _>>();
+
+    let mut group = c.benchmark_group("chunk_lookup");
+    group.bench_function("sip_hash", |b| {
+        b.iter(|| positions.iter().filter_map(|pos| std_map.get(black_box(pos))).sum::<usize>())
+    });
+    group.bench_function("fixed_hash", |b| {
+        b.iter(|| positions.iter().filter_map(|pos| fast_map.get(black_box(pos))).sum::<usize>())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_meshing, bench_generation, bench_packing, bench_chunk_lookup);
+criterion_main!(benches);
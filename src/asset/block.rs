@@ -1,27 +1,82 @@
 use crate::asset::AssetLoaderError;
-use crate::asset::AssetLoaderError::{InvalidRon, Io};
-use crate::core::errors::RegistryError;
-use crate::core::state::LoadingState;
+use crate::asset::AssetLoaderError::InvalidRon;
 use crate::world::block::Direction;
 use bevy::asset::io::Reader;
-use bevy::asset::{ron, AssetLoader, AssetPath, LoadContext, RenderAssetUsages};
+use bevy::asset::{ron, AssetLoader, AssetPath, LoadContext};
 use bevy::prelude::*;
-use bevy::render::render_resource::{Extent3d, TextureDimension};
 use bevy::tasks::ConditionalSendFuture;
-use bimap::hash::Iter;
-use bimap::BiMap;
-use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
-use std::iter::Map;
+use crate::math::block::BlockPos;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Hash, Clone, PartialEq, Eq, Asset, TypePath, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Asset, TypePath, Serialize, Deserialize)]
 #[serde(rename="Block")]
 pub struct BlockAsset {
     pub id: String,
     pub hardness: u32,
     pub states: Vec<BlockStateAsset>,
     pub default_state: BTreeMap<String, String>,
-    pub models: Vec<BlockStateModelDef>
+    /// Left empty for blocks that exist but draw nothing, like barriers. They still collide unless `collision_shape` says otherwise.
+    #[serde(default)]
+    pub models: Vec<BlockStateModelDef>,
+    /// The group of sounds this block plays when placed or broken, e.g. stone, wood, grass.
+    #[serde(default)]
+    pub sound_group: Option<String>,
+    /// The tool needed to get this block back when breaking it. None means anything works, including bare hands.
+    #[serde(default)]
+    pub tool: Option<ToolType>,
+    /// The minimum level of `tool` needed. 0 is wood, 1 is stone, 2 is iron.
+    #[serde(default)]
+    pub harvest_level: u32,
+    /// How this block's faces get drawn. Opaque unless the texture has see-through parts.
+    #[serde(default)]
+    pub render_layer: RenderLayer,
+    /// Draws every face of this block at full brightness, like glowstone or lava. Doesn't light up anything around it.
+    #[serde(default)]
+    pub emissive: bool,
+    /// Boxes the player collides with, which can differ from the model. None means the box around the model of each state,
+    /// or a full cube for blocks without a model.
+    #[serde(default)]
+    pub collision_shape: Option<Vec<CollisionBox>>,
+    /// Categories this block belongs to, e.g. "wooden" or "replaceable". See [`crate::registry::tag::BlockTags`].
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// A box a block collides with, from `min` to `max` in block local coordinates.
+/// A full block is (0, 0, 0) to (1, 1, 1), but boxes can stick out of the block, e.g. a fence is 1.5 tall.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CollisionBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl CollisionBox {
+    pub const FULL: CollisionBox = CollisionBox { min: Vec3::ZERO, max: Vec3::ONE };
+}
+
+/// Kinds of tools, for blocks that need one to be harvested.
+#[derive(Debug, Hash, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToolType {
+    Pickaxe,
+    Axe,
+    Shovel,
+}
+
+/// Which chunk mesh a block's faces go in. Each layer is drawn with its own material.
+#[derive(Debug, Default, Hash, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RenderLayer {
+    /// Fully solid, e.g. stone.
+    #[default]
+    Opaque,
+    /// Pixels are either fully solid or fully see-through, e.g. leaves and flowers.
+    Cutout,
+    /// Partially see-through, e.g. glass and water. Alpha blended.
+    Translucent,
+}
+
+impl RenderLayer {
+    pub const ALL: [RenderLayer; 3] = [RenderLayer::Opaque, RenderLayer::Cutout, RenderLayer::Translucent];
 }
 
 #[derive(Debug, Hash, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -36,18 +91,17 @@ pub struct BlockStateAsset {
 pub struct BlockStateModelDef {
     pub state: BTreeMap<String, String>,
     model: String,
+    /// Property the model is rotated by, e.g. `facing`. The model is authored facing north,
+    /// and the east, south and west versions are generated at load by rotating it around Y.
+    #[serde(default)]
+    pub rotate: Option<String>,
     #[serde(skip)]
     pub model_handle: Handle<BlockModelAsset>
 }
 
 
-fn get_state<'a>(key: &str, states: &'a Vec<BlockStateAsset>) -> Option<&'a BlockStateAsset> {
-    for state in states.iter() {
-        if state.name == key {
-            return Some(state);
-        }
-    }
-    None
+fn get_state<'a>(key: &str, states: &'a [BlockStateAsset]) -> Option<&'a BlockStateAsset> {
+    states.iter().find(|state| state.name == key)
 }
 
 
@@ -75,6 +129,9 @@ impl AssetLoader for BlockLoader {
                 let active_state = &model_def.state;
                 // validate the active state
                 validate_state(block.id.as_str(), active_state, &block.states)?;
+                if let Some(property) = &model_def.rotate {
+                    validate_rotation(block.id.as_str(), property, &block.states)?;
+                }
 
                 // TODO: exhaustiveness checking
 
@@ -93,7 +150,7 @@ impl AssetLoader for BlockLoader {
     }
 }
 
-fn validate_state(id: &str, state: &BTreeMap<String, String>, state_def: &Vec<BlockStateAsset>) -> Result<(), AssetLoaderError> {
+fn validate_state(id: &str, state: &BTreeMap<String, String>, state_def: &[BlockStateAsset]) -> Result<(), AssetLoaderError> {
     
     for (k, v) in state.iter() {
         match get_state(k, state_def) {
@@ -112,6 +169,21 @@ fn validate_state(id: &str, state: &BTreeMap<String, String>, state_def: &Vec<Bl
     Ok(())
 }
 
+// rotated models need every horizontal direction as a value of the property
+fn validate_rotation(id: &str, property: &str, state_def: &[BlockStateAsset]) -> Result<(), AssetLoaderError> {
+    let Some(def) = get_state(property, state_def) else {
+        let str = format!("Cannot rotate by state {}, as it does not exist for block {}", property, id);
+        return Err(InvalidRon(str));
+    };
+    for dir in Direction::HORIZONTAL.iter() {
+        if !def.values.iter().any(|v| v == dir.get_name()) {
+            let str = format!("Cannot rotate by state {} in block {}, as it is missing value {}", property, id, dir.get_name());
+            return Err(InvalidRon(str));
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone, PartialEq, Asset, TypePath, Deserialize)]
 #[serde(rename="BlockModel")]
 pub struct BlockModelAsset {
@@ -126,6 +198,10 @@ pub struct BlockModelAsset {
     pub textures: BTreeMap<String, String>,
     #[serde(skip)]
     pub texture_handles: BTreeMap<String, Handle<Image>>,
+    /// Shade the model smoothly, by giving each vertex the average normal of every face touching it instead of the face's own.
+    /// Meant for rounded models, cubes look wrong with it. Left out, it's taken from the parent, or off if no parent sets it.
+    #[serde(default)]
+    pub smooth_normals: Option<bool>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -136,7 +212,77 @@ pub struct BlockModelFace {
     pub vertices: Vec<ModelVertex>,
     pub normal: Vec3,
     pub texture: String,
-    pub cull_mode: Option<Direction>,
+    /// The side of the block that culls this face when it's covered, e.g. `Some(Up)`. Left out, it's worked out from the face,
+    /// see [`BlockModelFace::infer_cull_mode`]. Set to `None` for a face that's never culled.
+    #[serde(default, deserialize_with = "deserialize_cull_mode")]
+    pub cull_mode: CullMode,
+    /// Texture key of a grayscale overlay drawn over `texture`, like the grass on the side of a grass block.
+    #[serde(default)]
+    pub overlay: Option<String>,
+    /// Colors the overlay if there is one, otherwise the whole face.
+    #[serde(default)]
+    pub tint: Option<Tint>,
+    /// Rotates the uvs clockwise around the middle of the texture, in degrees. Must be 0, 90, 180 or 270.
+    #[serde(default)]
+    pub uv_rotation: u32,
+    /// Mirrors the uvs horizontally. Done before `uv_rotation`.
+    #[serde(default)]
+    pub uv_flip: bool,
+    /// Draws this face at full brightness, ignoring lighting.
+    #[serde(default)]
+    pub emissive: bool,
+}
+
+impl BlockModelFace {
+    /// The side that culls this face, from `cull_mode` if it was set, otherwise from [`BlockModelFace::infer_cull_mode`].
+    pub fn get_cull_mode(&self) -> Option<Direction> {
+        match self.cull_mode {
+            CullMode::Inferred => self.infer_cull_mode(),
+            CullMode::Never => None,
+            CullMode::Face(dir) => Some(dir),
+        }
+    }
+
+    /// Works out the side that culls this face: a face pointing straight out of a side, with every vertex on that side of the block.
+    /// Faces inside the block, or at an angle, are never culled.
+    pub fn infer_cull_mode(&self) -> Option<Direction> {
+        let normal = self.normal.normalize_or_zero();
+        let dir = Direction::ALL.into_iter()
+            .find(|d| normal.abs_diff_eq(IVec3::ZERO.offset(*d).as_vec3(), 1e-4))?;
+        let axis = IVec3::ZERO.offset(dir).as_vec3();
+        // the side is at 1 along positive axes, and 0 along negative ones
+        let side = axis.max_element();
+        let on_side = !self.vertices.is_empty() && self.vertices.iter()
+            .all(|v| (v.pos.dot(axis.abs()) - side).abs() < 1e-4);
+        on_side.then_some(dir)
+    }
+}
+
+/// Which side of the block culls a face, see [`BlockModelFace::cull_mode`].
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum CullMode {
+    /// Worked out from the face, see [`BlockModelFace::infer_cull_mode`].
+    #[default]
+    Inferred,
+    /// Never culled, even on a side of the block.
+    Never,
+    /// Culled when this side of the block is covered.
+    Face(Direction),
+}
+
+// only called when the field is there, a field that was left out gets the default instead
+fn deserialize_cull_mode<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CullMode, D::Error> {
+    Ok(match Option::<Direction>::deserialize(deserializer)? {
+        Some(dir) => CullMode::Face(dir),
+        None => CullMode::Never,
+    })
+}
+
+/// Where a face's tint color comes from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub enum Tint {
+    Grass,
+    Foliage,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize)]
@@ -165,7 +311,7 @@ impl AssetLoader for BlockModelLoader {
     type Settings = ();
     type Error = AssetLoaderError;
 
-    fn load(&self, reader: &mut dyn Reader, settings: &Self::Settings, load_context: &mut LoadContext) -> impl ConditionalSendFuture<Output=std::result::Result<Self::Asset, Self::Error>> {
+    fn load(&self, reader: &mut dyn Reader, _settings: &Self::Settings, load_context: &mut LoadContext) -> impl ConditionalSendFuture<Output=std::result::Result<Self::Asset, Self::Error>> {
         Box::pin(async move {
             let mut bytes = Vec::new();
             reader.read_to_end(&mut bytes).await?;
@@ -184,6 +330,10 @@ impl AssetLoader for BlockModelLoader {
             
             // setup texture map
             for (k, v) in model.textures.iter() {
+                // `#key` points at another key instead of a texture, and is resolved when building the model
+                if v.starts_with('#') {
+                    continue;
+                }
                 let texture_str = format!("texture/{}.ktx2", v.clone());
                 model.texture_handles.insert(k.clone(), load_context.load(AssetPath::parse(texture_str.as_str())));
             }
@@ -196,4 +346,37 @@ impl AssetLoader for BlockModelLoader {
     fn extensions(&self) -> &[&str] {
         &["model.ron"]
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a face pointing up at height y, with cull_mode written as given, or left out if empty
+    fn up_face(y: f32, cull_mode: &str) -> BlockModelFace {
+        let vertices = [(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)]
+            .map(|(x, z)| format!("Vertex(pos: ({x:.1}, {y:.1}, {z:.1}), uv: ({x:.1}, {z:.1}))"))
+            .join(", ");
+        ron::de::from_str(&format!("Face(type: Quad, vertices: [{vertices}], normal: (0.0, 1.0, 0.0), texture: \"all\", {cull_mode})")).unwrap()
+    }
+
+    #[test]
+    fn cull_mode_is_inferred_for_faces_on_the_boundary() {
+        let top = up_face(1.0, "");
+        assert_eq!(top.cull_mode, CullMode::Inferred);
+        assert_eq!(top.get_cull_mode(), Some(Direction::Up));
+        // same face halfway up the block, like the top of a slab, is never covered by the block above
+        let interior = up_face(0.5, "");
+        assert_eq!(interior.get_cull_mode(), None);
+    }
+
+    #[test]
+    fn written_cull_mode_overrides_the_inferred_one() {
+        let never = up_face(1.0, "cull_mode: None");
+        assert_eq!(never.cull_mode, CullMode::Never);
+        assert_eq!(never.get_cull_mode(), None);
+        let down = up_face(0.5, "cull_mode: Some(Down)");
+        assert_eq!(down.cull_mode, CullMode::Face(Direction::Down));
+        assert_eq!(down.get_cull_mode(), Some(Direction::Down));
+    }
+}
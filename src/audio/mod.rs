@@ -0,0 +1,394 @@
+use crate::core::event::{BlockChangeSource, SetBlockEvent};
+use crate::core::state::MainGameState;
+use crate::math::block::{BlockPos, Vec3Ext};
+use crate::registry::block::Block;
+use crate::registry::{Registry, RegistryHandle};
+use crate::world::block::{BlockState, BlockStateTable, BlockWorld};
+use crate::world::player::{Player, PlayerVelocity};
+use crate::world::dimension::ActiveWorld;
+use bevy::asset::LoadState;
+use bevy::audio::Volume;
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+
+/// Plugin that handles game audio, like block sounds.
+#[derive(Default)]
+pub struct GameAudioPlugin;
+
+impl Plugin for GameAudioPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<AudioSettings>()
+            .init_resource::<BlockSounds>()
+            .add_observer(play_block_sounds)
+            .add_systems(OnEnter(MainGameState::InGame), load_block_sounds)
+            .add_systems(Update, play_footsteps.run_if(in_state(MainGameState::InGame)))
+        ;
+    }
+}
+
+#[derive(Debug, Resource)]
+pub struct AudioSettings {
+    pub master_volume: f32,
+    pub sfx_volume: f32,
+}
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master_volume: 1.0,
+            sfx_volume: 1.0,
+        }
+    }
+}
+impl AudioSettings {
+    /// The final volume sound effects are played at, with the master volume applied.
+    pub fn effective_sfx_volume(&self) -> f32 {
+        (self.master_volume * self.sfx_volume).max(0.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockSoundKind {
+    Place,
+    Break,
+    Step,
+}
+
+impl BlockSoundKind {
+    fn file_name(&self) -> &'static str {
+        match self {
+            BlockSoundKind::Place => "place",
+            BlockSoundKind::Break => "break",
+            BlockSoundKind::Step => "step",
+        }
+    }
+}
+
+/// Gets the asset path of a sound for a sound group, e.g. `sound/block/stone/break.ogg`
+pub fn block_sound_path(group: &str, kind: BlockSoundKind) -> String {
+    format!("sound/block/{}/{}.ogg", group, kind.file_name())
+}
+
+/// Block sound handles by asset path, so every sound is only loaded once.
+/// Sound groups don't have to ship every sound, a sound that fails to load (e.g. a missing file) is just never played.
+#[derive(Debug, Default, Resource)]
+pub struct BlockSounds {
+    handles: HashMap<String, Handle<AudioSource>>,
+}
+
+impl BlockSounds {
+    /// Gets a sound that's ready to play, starting to load it if it hasn't been yet.
+    /// None until it has loaded, and forever if loading failed.
+    pub fn get(&mut self, asset_server: &AssetServer, group: &str, kind: BlockSoundKind) -> Option<Handle<AudioSource>> {
+        let handle = self.handles.entry(block_sound_path(group, kind)).or_insert_with_key(|path| asset_server.load(path.clone()));
+        match asset_server.get_load_state(handle.id()) {
+            Some(LoadState::Loaded) => Some(handle.clone()),
+            _ => None,
+        }
+    }
+}
+
+// starts loading every block sound up front, so the first break or step of each isn't silent
+fn load_block_sounds(mut sounds: ResMut<BlockSounds>, asset_server: Res<AssetServer>, block_reg: Res<RegistryHandle<Block>>) {
+    for (_, block) in block_reg.iter() {
+        let Some(group) = block.get_sound_group() else {
+            continue;
+        };
+        for kind in [BlockSoundKind::Place, BlockSoundKind::Break, BlockSoundKind::Step] {
+            sounds.get(&asset_server, group, kind);
+        }
+    }
+}
+
+/// Picks which sound should be played for a block change, as a pair of the sound kind and the sound group.
+/// Returns None if no sound should be played, e.g. for programmatic edits or silent blocks.
+pub fn select_block_sound<'a>(event: &SetBlockEvent, block_reg: &'a Registry<Block>, table: &BlockStateTable) -> Option<(BlockSoundKind, &'a str)> {
+    if event.source != BlockChangeSource::Player {
+        return None;
+    }
+    // breaking plays the sound of the old block, placing plays the sound of the new block.
+    let (kind, state) = if table.is_air_state(&event.new) {
+        (BlockSoundKind::Break, &event.old)
+    } else {
+        (BlockSoundKind::Place, &event.new)
+    };
+    Some((kind, sound_group_of(state, block_reg, table)?))
+}
+
+/// Gets the sound group of a blockstate, or None if it is air or silent.
+pub fn sound_group_of<'a>(state: &BlockState, block_reg: &'a Registry<Block>, table: &BlockStateTable) -> Option<&'a str> {
+    if table.is_air_state(state) {
+        return None;
+    }
+    state.block(block_reg)?.get_sound_group()
+}
+
+fn play_block_sounds(
+    trigger: On<SetBlockEvent>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<AudioSettings>,
+    block_reg: Res<RegistryHandle<Block>>,
+    table: Res<BlockStateTable>,
+    mut sounds: ResMut<BlockSounds>,
+) {
+    let Some((kind, group)) = select_block_sound(trigger.event(), &block_reg, &table) else {
+        return;
+    };
+    let volume = settings.effective_sfx_volume();
+    if volume <= 0.0 {
+        return;
+    }
+    let Some(sound) = sounds.get(&asset_server, group, kind) else {
+        return;
+    };
+    commands.spawn((
+        AudioPlayer::new(sound),
+        PlaybackSettings::DESPAWN.with_volume(Volume::Linear(volume)),
+    ));
+}
+
+
+/// Distance in blocks covered by a single step.
+pub const FOOTSTEP_STRIDE: f32 = 1.75;
+/// Footsteps never play faster than this, no matter how fast the player moves.
+pub const MIN_FOOTSTEP_INTERVAL: f32 = 0.15;
+
+/// How many seconds between footsteps when moving at `speed` blocks per second. None if the player isn't moving.
+pub fn footstep_interval(speed: f32) -> Option<f32> {
+    if speed <= f32::EPSILON {
+        return None;
+    }
+    Some((FOOTSTEP_STRIDE / speed).max(MIN_FOOTSTEP_INTERVAL))
+}
+
+/// Whether the player is walking on the ground at `horizontal_speed`, so their steps can be heard.
+/// Standing still and being in the air are silent, whatever the game mode.
+pub fn makes_footsteps(velocity: &PlayerVelocity, horizontal_speed: f32) -> bool {
+    velocity.on_ground && horizontal_speed > f32::EPSILON
+}
+
+#[derive(Default)]
+struct FootstepState {
+    last_pos: Option<Vec3>,
+    timer: f32,
+}
+
+// plays footsteps based on the block the player is standing on, right under their feet.
+// Silent while in the air, and if the block under their feet is air or not loaded.
+fn play_footsteps(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<AudioSettings>,
+    block_reg: Res<RegistryHandle<Block>>,
+    table: Res<BlockStateTable>,
+    player: Single<(&Transform, &PlayerVelocity), With<Player>>,
+    world: Single<&BlockWorld, With<ActiveWorld>>,
+    time: Res<Time>,
+    mut state: Local<FootstepState>,
+    mut sounds: ResMut<BlockSounds>,
+) {
+    let (transform, velocity) = *player;
+    let pos = transform.translation;
+    let last_pos = state.last_pos.replace(pos).unwrap_or(pos);
+    let delta = time.delta_secs();
+    if delta <= 0.0 {
+        return;
+    }
+
+    // only horizontal movement counts as walking
+    let speed = (pos - last_pos).xz().length() / delta;
+
+    let underfoot = pos.as_block_pos().down();
+    let group = match world.get_block(&underfoot) {
+        Ok(block) => sound_group_of(&block, &block_reg, &table),
+        Err(_) => None,
+    };
+    let (Some(group), Some(interval), true) = (group, footstep_interval(speed), makes_footsteps(velocity, speed)) else {
+        state.timer = 0.0;
+        return;
+    };
+
+    state.timer += delta;
+    if state.timer < interval {
+        return;
+    }
+    state.timer = 0.0;
+
+    let volume = settings.effective_sfx_volume();
+    if volume <= 0.0 {
+        return;
+    }
+    let Some(sound) = sounds.get(&asset_server, group, BlockSoundKind::Step) else {
+        return;
+    };
+    commands.spawn((
+        AudioPlayer::new(sound),
+        PlaybackSettings::DESPAWN.with_volume(Volume::Linear(volume)),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::block::BlockAsset;
+    use crate::registry::block::test_asset;
+    use crate::world::dimension::DimensionId;
+
+    fn sound_registry() -> Registry<Block> {
+        let mut registry = Registry::<Block>::new("block");
+        registry.register(Block::from_asset(&BlockAsset { sound_group: Some(String::from("stone")), ..test_asset("stone") })).unwrap();
+        registry.register(Block::from_asset(&BlockAsset { sound_group: Some(String::from("wood")), ..test_asset("oak_planks") })).unwrap();
+        registry.register(Block::from_asset(&test_asset("barrier"))).unwrap();
+        registry.freeze();
+        registry
+    }
+
+    fn event(old: BlockState, new: BlockState, source: BlockChangeSource) -> SetBlockEvent {
+        SetBlockEvent {
+            pos: IVec3::ZERO,
+            old,
+            new,
+            source,
+            dimension: DimensionId::new("test"),
+        }
+    }
+
+    #[test]
+    fn breaking_plays_the_old_blocks_group_and_placing_the_new_ones() {
+        let registry = sound_registry();
+        let table = BlockStateTable::from_registry(&registry).unwrap();
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let planks = BlockState::new("oak_planks", &registry).unwrap();
+
+        let broken = event(stone.clone(), BlockState::air(), BlockChangeSource::Player);
+        assert_eq!(select_block_sound(&broken, &registry, &table), Some((BlockSoundKind::Break, "stone")));
+        let placed = event(BlockState::air(), planks.clone(), BlockChangeSource::Player);
+        assert_eq!(select_block_sound(&placed, &registry, &table), Some((BlockSoundKind::Place, "wood")));
+        // replacing a block sounds like the new one
+        let replaced = event(stone, planks, BlockChangeSource::Player);
+        assert_eq!(select_block_sound(&replaced, &registry, &table), Some((BlockSoundKind::Place, "wood")));
+        assert_eq!(block_sound_path("wood", BlockSoundKind::Place), "sound/block/wood/place.ogg");
+    }
+
+    #[test]
+    fn programmatic_edits_and_silent_blocks_play_nothing() {
+        let registry = sound_registry();
+        let table = BlockStateTable::from_registry(&registry).unwrap();
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let barrier = BlockState::new("barrier", &registry).unwrap();
+
+        let programmatic = event(stone, BlockState::air(), BlockChangeSource::Programmatic);
+        assert_eq!(select_block_sound(&programmatic, &registry, &table), None);
+        let silent = event(BlockState::air(), barrier, BlockChangeSource::Player);
+        assert_eq!(select_block_sound(&silent, &registry, &table), None);
+    }
+
+    #[test]
+    fn missing_sounds_are_never_played() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin {
+            file_path: format!("{}/assets", env!("CARGO_MANIFEST_DIR")),
+            ..default()
+        }))
+            .init_asset::<AudioSource>()
+            .init_asset_loader::<bevy::audio::AudioLoader>();
+        let asset_server = app.world().resource::<AssetServer>().clone();
+        let mut sounds = BlockSounds::default();
+
+        assert_eq!(sounds.get(&asset_server, "no_such_group", BlockSoundKind::Break), None);
+        let handle = asset_server.load::<AudioSource>(block_sound_path("no_such_group", BlockSoundKind::Break));
+        for _ in 0..200 {
+            if matches!(asset_server.get_load_state(handle.id()), Some(LoadState::Failed(_))) {
+                break;
+            }
+            app.update();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert!(matches!(asset_server.get_load_state(handle.id()), Some(LoadState::Failed(_))));
+        // the same handle is reused, and it stays silent once loading failed
+        assert_eq!(sounds.get(&asset_server, "no_such_group", BlockSoundKind::Break), None);
+        assert_eq!(sounds.handles.len(), 1);
+    }
+
+    #[test]
+    fn every_declared_sound_group_has_its_sounds() {
+        let assets = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        let mut groups = Vec::new();
+        for entry in std::fs::read_dir(assets.join("block")).unwrap() {
+            let path = entry.unwrap().path();
+            if !path.to_string_lossy().ends_with(".block.ron") {
+                continue;
+            }
+            let block = bevy::asset::ron::de::from_bytes::<BlockAsset>(&std::fs::read(&path).unwrap()).unwrap();
+            groups.extend(block.sound_group);
+        }
+        assert!(!groups.is_empty());
+
+        for group in groups {
+            for kind in [BlockSoundKind::Place, BlockSoundKind::Break, BlockSoundKind::Step] {
+                let path = block_sound_path(&group, kind);
+                assert!(assets.join(&path).is_file(), "missing {path}");
+            }
+        }
+    }
+
+    #[test]
+    fn footsteps_speed_up_with_speed_down_to_a_minimum() {
+        assert_eq!(footstep_interval(0.0), None);
+        assert_eq!(footstep_interval(-1.0), None);
+        assert_eq!(footstep_interval(FOOTSTEP_STRIDE), Some(1.0));
+        assert_eq!(footstep_interval(FOOTSTEP_STRIDE * 2.0), Some(0.5));
+        // running absurdly fast doesn't turn footsteps into a buzz
+        assert_eq!(footstep_interval(1000.0), Some(MIN_FOOTSTEP_INTERVAL));
+    }
+
+    #[test]
+    fn only_walking_on_the_ground_makes_footsteps() {
+        let grounded = PlayerVelocity { on_ground: true, ..default() };
+        let airborne = PlayerVelocity { on_ground: false, ..default() };
+        assert!(makes_footsteps(&grounded, 4.3));
+        assert!(!makes_footsteps(&grounded, 0.0));
+        assert!(!makes_footsteps(&airborne, 4.3));
+    }
+
+    #[test]
+    fn creative_players_walking_on_the_ground_make_footsteps() {
+        use crate::world::block::BlockWorld;
+        use crate::world::chunk::{Chunk, ChunkData};
+        use crate::world::dimension::DimensionId;
+        use crate::world::player::PlayerCollider;
+        use crate::world::shape::BlockShapes;
+
+        let registry = sound_registry();
+        let shapes = BlockShapes::new(BlockStateTable::from_registry(&registry).unwrap(), &registry, &[]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let mut data = ChunkData::single(BlockState::air());
+        for x in 0..ChunkData::CHUNK_SIZE {
+            for z in 0..ChunkData::CHUNK_SIZE {
+                data.set_block(x, 0, z, stone.clone()).unwrap();
+            }
+        }
+        let mut world = BlockWorld::new(DimensionId::new("test"), 1);
+        world.get_chunk_map_mut().add_chunk(Chunk::generated(IVec3::ZERO, data)).unwrap();
+
+        // flying in creative, but walking along the top of the stone
+        let collider = PlayerCollider::default();
+        let feet = vec3(4.5, 1.0, 4.5);
+        let walking = PlayerVelocity { on_ground: collider.is_on_ground(feet, &world, &shapes), ..default() };
+        assert!(makes_footsteps(&walking, 4.3));
+        let table = BlockStateTable::from_registry(&registry).unwrap();
+        assert_eq!(sound_group_of(&world.get_block(&feet.as_block_pos().down()).unwrap(), &registry, &table), Some("stone"));
+        // flying over it is silent
+        let flying = PlayerVelocity { on_ground: collider.is_on_ground(feet + Vec3::Y, &world, &shapes), ..default() };
+        assert!(!makes_footsteps(&flying, 4.3));
+    }
+
+    #[test]
+    fn master_volume_scales_sfx_volume() {
+        let settings = AudioSettings { master_volume: 0.5, sfx_volume: 0.5 };
+        assert_eq!(settings.effective_sfx_volume(), 0.25);
+        let muted = AudioSettings { master_volume: -1.0, sfx_volume: 1.0 };
+        assert_eq!(muted.effective_sfx_volume(), 0.0);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::core::errors::CommandError;
+use crate::core::event::{BlockChangeSource, TeleportEvent};
+use crate::registry::block::Block;
+use crate::registry::RegistryHandle;
+use crate::world::block::{BlockState, BlockWorld};
+use crate::world::chunk;
+use crate::world::chunk::ChunkPattern;
+use crate::world::dimension::ActiveWorld;
+use crate::world::generation::WorldConfig;
+use crate::world::machine::MachineWorld;
+use crate::asset::block::ToolType;
+use crate::world::player::{GameMode, HeldTool, Player, Tool};
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+/// Plugin for the dev console, and the commands it can run.
+#[derive(Default)]
+pub struct CommandPlugin;
+
+impl Plugin for CommandPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<DevConsole>()
+            .add_observer(run_command)
+        ;
+    }
+}
+
+/// State of the dev console. The UI for it lives in the ui module.
+#[derive(Debug, Default, Resource)]
+pub struct DevConsole {
+    pub open: bool,
+    /// What's currently typed in.
+    pub input: String,
+    /// Recent output, oldest first.
+    output: VecDeque<String>,
+}
+
+impl DevConsole {
+    /// How many lines of output are kept.
+    pub const MAX_OUTPUT: usize = 10;
+
+    pub fn log(&mut self, line: impl Into<String>) {
+        self.output.push_back(line.into());
+        while self.output.len() > Self::MAX_OUTPUT {
+            self.output.pop_front();
+        }
+    }
+
+    pub fn get_output(&self) -> impl Iterator<Item = &str> {
+        self.output.iter().map(|s| s.as_str())
+    }
+}
+
+/// Run condition for gameplay input, which is ignored while typing in the console.
+pub fn console_closed(console: Res<DevConsole>) -> bool {
+    !console.open
+}
+
+/// A parsed console command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameCommand {
+    /// `setblock x y z block`
+    SetBlock {
+        pos: IVec3,
+        block: String,
+    },
+    /// `fill x1 y1 z1 x2 y2 z2 block`
+    Fill {
+        min: IVec3,
+        max: IVec3,
+        block: String,
+    },
+    /// `tp x y z`
+    Teleport(Vec3),
+    /// `seed`
+    Seed,
+    /// `gamemode creative|survival|spectator`
+    GameMode(GameMode),
+    /// `tool pickaxe|axe|shovel level`, or `tool none`
+    Tool(Option<Tool>),
+    /// `pattern checkerboard|single|stairs block`, replaces the chunk the player is in
+    Pattern {
+        pattern: ChunkPattern,
+        block: String,
+    },
+    /// `machine x y z metadata`, sets the metadata of the machine at a position
+    Machine {
+        pos: IVec3,
+        metadata: u8,
+    },
+}
+
+/// Parses a line of console input into a command. A leading `/` is allowed.
+pub fn parse_command(input: &str) -> Result<GameCommand, CommandError> {
+    let input = input.trim();
+    let input = input.strip_prefix('/').unwrap_or(input);
+    let mut parts = input.split_whitespace();
+    let Some(name) = parts.next() else {
+        return Err(CommandError::Empty);
+    };
+    let args = parts.collect::<Vec<_>>();
+
+    match name {
+        "setblock" => {
+            expect_args(name, &args, 4)?;
+            Ok(GameCommand::SetBlock {
+                pos: parse_ivec3(&args[0..3])?,
+                block: String::from(args[3]),
+            })
+        }
+        "fill" => {
+            expect_args(name, &args, 7)?;
+            Ok(GameCommand::Fill {
+                min: parse_ivec3(&args[0..3])?,
+                max: parse_ivec3(&args[3..6])?,
+                block: String::from(args[6]),
+            })
+        }
+        "tp" => {
+            expect_args(name, &args, 3)?;
+            Ok(GameCommand::Teleport(vec3(parse_number(args[0])?, parse_number(args[1])?, parse_number(args[2])?)))
+        }
+        "seed" => {
+            expect_args(name, &args, 0)?;
+            Ok(GameCommand::Seed)
+        }
+        "gamemode" => {
+            expect_args(name, &args, 1)?;
+            let mode = match args[0] {
+                "creative" => GameMode::Creative,
+                "survival" => GameMode::Survival,
+                "spectator" => GameMode::Spectator,
+                other => return Err(CommandError::InvalidArgument(String::from(other))),
+            };
+            Ok(GameCommand::GameMode(mode))
+        }
+        "tool" => {
+            if args.first() == Some(&"none") {
+                expect_args(name, &args, 1)?;
+                return Ok(GameCommand::Tool(None));
+            }
+            expect_args(name, &args, 2)?;
+            let kind = match args[0] {
+                "pickaxe" => ToolType::Pickaxe,
+                "axe" => ToolType::Axe,
+                "shovel" => ToolType::Shovel,
+                other => return Err(CommandError::InvalidArgument(String::from(other))),
+            };
+            Ok(GameCommand::Tool(Some(Tool {
+                kind,
+                level: parse_number(args[1])?,
+            })))
+        }
+        "pattern" => {
+            expect_args(name, &args, 2)?;
+            let pattern = match args[0] {
+                "checkerboard" => ChunkPattern::Checkerboard,
+                "single" => ChunkPattern::Single,
+                "stairs" => ChunkPattern::Stairs,
+                other => return Err(CommandError::InvalidArgument(String::from(other))),
+            };
+            Ok(GameCommand::Pattern {
+                pattern,
+                block: String::from(args[1]),
+            })
+        }
+        "machine" => {
+            expect_args(name, &args, 4)?;
+            Ok(GameCommand::Machine {
+                pos: parse_ivec3(&args[0..3])?,
+                metadata: parse_number(args[3])?,
+            })
+        }
+        other => Err(CommandError::Unknown(String::from(other))),
+    }
+}
+
+fn expect_args(name: &str, args: &[&str], count: usize) -> Result<(), CommandError> {
+    if args.len() != count {
+        return Err(CommandError::WrongArgCount(String::from(name), count, args.len()));
+    }
+    Ok(())
+}
+
+fn parse_number<T: FromStr>(arg: &str) -> Result<T, CommandError> {
+    arg.parse::<T>().map_err(|_| CommandError::InvalidNumber(String::from(arg)))
+}
+
+fn parse_ivec3(args: &[&str]) -> Result<IVec3, CommandError> {
+    Ok(ivec3(parse_number(args[0])?, parse_number(args[1])?, parse_number(args[2])?))
+}
+
+
+/// Runs a line of console input. Output and errors are written to the [`DevConsole`].
+#[derive(Event)]
+pub struct RunCommandEvent {
+    pub input: String,
+}
+
+fn run_command(
+    trigger: On<RunCommandEvent>,
+    mut commands: Commands,
+    mut console: ResMut<DevConsole>,
+    mut world: Single<&mut BlockWorld, With<ActiveWorld>>,
+    mut machines: Single<&mut MachineWorld, With<ActiveWorld>>,
+    mut game_mode: ResMut<GameMode>,
+    mut held_tool: Single<&mut HeldTool>,
+    player: Single<&Transform, With<Player>>,
+    block_reg: Res<RegistryHandle<Block>>,
+    world_config: Res<WorldConfig>,
+) {
+    console.log(format!("> {}", trigger.input));
+    let command = match parse_command(&trigger.input) {
+        Ok(command) => command,
+        Err(e) => {
+            console.log(e.to_string());
+            return;
+        }
+    };
+
+    let result = match command {
+        GameCommand::SetBlock { pos, block } => BlockState::new(&block, &block_reg)
+            .map_err(BevyError::from)
+            .and_then(|state| Ok(world.set_block(&mut commands, &pos, state, BlockChangeSource::Programmatic)?))
+            .map(|_| format!("Set block at {pos} to {block}")),
+        GameCommand::Fill { min, max, block } => BlockState::new(&block, &block_reg)
+            .map_err(BevyError::from)
+            .and_then(|state| Ok(world.fill(&mut commands, &min, &max, state, BlockChangeSource::Programmatic)?))
+            .map(|count| format!("Filled {count} blocks with {block}")),
+        GameCommand::Teleport(pos) => {
+            commands.trigger(TeleportEvent {
+                pos,
+            });
+            Ok(format!("Teleported to {pos}"))
+        }
+        GameCommand::Seed => Ok(format!("Seed: {}", world_config.seed)),
+        GameCommand::GameMode(mode) => {
+            *game_mode = mode;
+            Ok(format!("Set game mode to {mode:?}"))
+        }
+        GameCommand::Tool(tool) => {
+            held_tool.0 = tool;
+            Ok(format!("Holding {tool:?}"))
+        }
+        GameCommand::Pattern { pattern, block } => {
+            let chunk_pos = chunk::pos_to_chunk_pos(player.translation.floor().as_ivec3());
+            BlockState::new(&block, &block_reg)
+                .map_err(BevyError::from)
+                .and_then(|state| Ok(world.fill_pattern(&mut commands, chunk_pos, pattern, state, BlockChangeSource::Programmatic)?))
+                .map(|count| format!("Replaced chunk {chunk_pos} with {pattern:?} {block}, {count} blocks changed"))
+        }
+        GameCommand::Machine { pos, metadata } => match machines.set_metadata(&pos, metadata) {
+            true => Ok(format!("Set metadata of machine at {pos} to {metadata}")),
+            false => Err(CommandError::NoMachine(pos).into()),
+        },
+    };
+    match result {
+        Ok(message) => console.log(message),
+        Err(e) => console.log(format!("Error: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn setblock_parses_position_and_block() {
+        let expected = GameCommand::SetBlock { pos: ivec3(1, -64, 300), block: String::from("stone") };
+        assert_eq!(parse_command("setblock 1 -64 300 stone").unwrap(), expected);
+        // the slash and extra whitespace are optional
+        assert_eq!(parse_command("  /setblock  1 -64   300 stone ").unwrap(), expected);
+    }
+
+    #[test]
+    fn wrong_argument_counts_are_rejected() {
+        assert!(matches!(parse_command("setblock 1 2 stone"), Err(CommandError::WrongArgCount(_, 4, 3))));
+        assert!(matches!(parse_command("setblock 1 2 3 stone extra"), Err(CommandError::WrongArgCount(_, 4, 5))));
+        assert!(matches!(parse_command("seed 5"), Err(CommandError::WrongArgCount(_, 0, 1))));
+        assert!(matches!(parse_command("tool none 2"), Err(CommandError::WrongArgCount(_, 1, 2))));
+    }
+
+    #[test]
+    fn bad_coordinates_are_rejected() {
+        assert!(matches!(parse_command("setblock 1 two 3 stone"), Err(CommandError::InvalidNumber(arg)) if arg == "two"));
+        // block positions are whole numbers, teleporting isn't
+        assert!(matches!(parse_command("setblock 1.5 2 3 stone"), Err(CommandError::InvalidNumber(_))));
+        assert_eq!(parse_command("tp 1.5 2 3").unwrap(), GameCommand::Teleport(vec3(1.5, 2.0, 3.0)));
+        assert!(matches!(parse_command("machine 0 0 0 256"), Err(CommandError::InvalidNumber(_))));
+    }
+
+    #[test]
+    fn unknown_and_empty_commands_are_rejected() {
+        assert!(matches!(parse_command(""), Err(CommandError::Empty)));
+        assert!(matches!(parse_command("/"), Err(CommandError::Empty)));
+        assert!(matches!(parse_command("explode"), Err(CommandError::Unknown(_))));
+        assert!(matches!(parse_command("gamemode hardcore"), Err(CommandError::InvalidArgument(_))));
+    }
+}
@@ -1,5 +1,5 @@
 use bevy::prelude::IVec3;
-use std::fmt::Display;
+use crate::world::chunk::ChunkGenerationStatus;
 
 #[derive(Debug, thiserror::Error)]
 pub enum RegistryError {
@@ -12,7 +12,11 @@ pub enum RegistryError {
 #[derive(Debug, thiserror::Error)]
 pub enum BlockStateError {
     #[error("Invalid block id: {0}.")]
-    InvalidId(String)
+    InvalidId(String),
+    #[error("Invalid block index: {0}.")]
+    InvalidIndex(usize),
+    #[error("Invalid state for block {0}: {1}")]
+    InvalidState(String, String),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -24,7 +28,9 @@ pub enum BlockModelError {
     #[error("Texture {0} not found in block array texture.")]
     TextureNotFound(String),
     #[error("Cannot depend on model {0}, as this creates a circular dependency.")]
-    CircularDependency(String)
+    CircularDependency(String),
+    #[error("Texture key {0} refers back to itself.")]
+    CircularTextureKey(String),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -37,8 +43,61 @@ pub enum ChunkError {
     AlreadyInitialized(IVec3),
     #[error("Chunk {0} already exists.")]
     DuplicateChunk(IVec3),
+    #[error("Chunk {0} can't go from {1:?} to {2:?}.")]
+    IllegalTransition(IVec3, ChunkGenerationStatus, ChunkGenerationStatus),
     #[error("Chunk {0} not found in chunk map.")]
     NotFound(IVec3),
+    #[error("Chunk palette is full, cannot have more than {0} entries.")]
+    PaletteFull(usize),
+    #[error("Chunk is marked as single, but has {0} palette entries instead of 1.")]
+    InvalidSinglePalette(usize),
+    #[error("Chunk data should be {0} bytes long, but is {1}.")]
+    InvalidDataLength(usize, usize),
+    #[error("Block at index {0} has palette id {1}, which isn't in the palette.")]
+    InvalidPaletteId(usize, usize),
+    #[error("Palette refcounts add up to {0}, but a chunk has {1} blocks.")]
+    RefCountTotal(usize, usize),
+    #[error("Palette entry {0} has a refcount of {1}, but {2} blocks use it.")]
+    RefCountMismatch(usize, usize, usize),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MeshError {
+    #[error("Data of chunk {0} is poisoned, a thread panicked while writing to it and left it invalid: {1}")]
+    PoisonedData(IVec3, ChunkError),
+}
+
+impl MeshError {
+    /// Whether meshing the chunk again could work. Invalid data stays invalid, so poisoned chunks aren't retried.
+    pub fn can_retry(&self) -> bool {
+        match self {
+            MeshError::PoisonedData(..) => false,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("No command given.")]
+    Empty,
+    #[error("Unknown command: {0}.")]
+    Unknown(String),
+    #[error("Command {0} expects {1} arguments, but got {2}.")]
+    WrongArgCount(String, usize, usize),
+    #[error("Invalid number: {0}.")]
+    InvalidNumber(String),
+    #[error("Invalid argument: {0}.")]
+    InvalidArgument(String),
+    #[error("There is no machine at {0}.")]
+    NoMachine(IVec3),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GenerationConfigError {
+    #[error("{0} must be positive, but is {1}.")]
+    NotPositive(&'static str, f32),
+    #[error("{0} must have at least one octave.")]
+    NoOctaves(&'static str),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -1,4 +1,5 @@
 use crate::world::block::BlockState;
+use crate::world::dimension::DimensionId;
 use bevy::prelude::{Entity, EntityEvent, Event, IVec3, Vec3};
 
 
@@ -14,7 +15,29 @@ pub struct PlayerMovedEvent {
 pub struct SetBlockEvent {
     pub pos: IVec3,
     pub old: BlockState,
-    pub new: BlockState
+    pub new: BlockState,
+    pub source: BlockChangeSource,
+    /// The dimension of the world this block was set in.
+    pub dimension: DimensionId,
+}
+
+/// Fired once per chunk for bulk edits like fills, instead of one [`SetBlockEvent`] per block.
+#[derive(Event)]
+pub struct BulkSetBlockEvent {
+    pub chunk_pos: IVec3,
+    /// World positions of every block that actually changed in this chunk.
+    pub changed_positions: Vec<IVec3>,
+    pub source: BlockChangeSource,
+    pub dimension: DimensionId,
+}
+
+/// What caused a block to change. Lets observers tell player edits apart from programmatic or bulk edits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockChangeSource {
+    /// The player placed or broke this block.
+    Player,
+    /// The block was changed by the game itself, e.g. commands or bulk edits.
+    Programmatic,
 }
 
 
@@ -23,4 +46,40 @@ pub struct JoinedWorldEvent {
     pub pos: Vec3,
     #[event_target]
     pub world: Entity,
+}
+
+
+/// Fired as a chunk moves through the generation pipeline, so progress bars, telemetry and the like can follow along without polling the world.
+/// Collected while the world runs and fired at the end of each frame, in the order they happened.
+#[derive(Event, Debug, Clone)]
+pub struct ChunkLifecycleEvent {
+    pub pos: IVec3,
+    pub dimension: DimensionId,
+    pub stage: ChunkLifecycle,
+}
+
+/// Steps of a chunk's life, in the order they happen. Remeshing a chunk fires [`ChunkLifecycle::Meshed`] again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkLifecycle {
+    /// Queued to be generated or loaded from disk.
+    Queued,
+    /// Its data is in the world, and it can be read.
+    Generated,
+    /// Its meshes were uploaded. Chunks that are all air count too, they just have no meshes.
+    Meshed,
+    /// Unloaded, or dropped from the queue before it finished generating.
+    Despawned,
+}
+
+/// Moves the player to `pos` in the world they're currently in, loading chunks around them.
+#[derive(Event)]
+pub struct TeleportEvent {
+    pub pos: Vec3,
+}
+
+/// Moves the player into another dimension, placing them at `pos`.
+#[derive(Event)]
+pub struct SwitchDimensionEvent {
+    pub dimension: DimensionId,
+    pub pos: Vec3,
 }
\ No newline at end of file
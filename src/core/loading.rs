@@ -0,0 +1,163 @@
+use crate::core::state::LoadingState;
+use bevy::prelude::{info, MessageReader, ResMut, Resource, StateTransitionEvent};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Settings for diagnosing loads that never finish.
+#[derive(Resource, Debug)]
+pub struct LoadingSettings {
+    /// How long asset loading can take before whatever it's still waiting on gets logged.
+    pub stuck_timeout: Duration,
+}
+
+impl Default for LoadingSettings {
+    fn default() -> Self {
+        Self {
+            stuck_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl LoadingSettings {
+    /// Whether the current loading phase had been running for longer than [`LoadingSettings::stuck_timeout`] at `now`.
+    pub fn is_stuck(&self, timings: &LoadingTimings, now: Instant) -> bool {
+        timings.current.as_ref().is_some_and(|(_, start)| now.saturating_duration_since(*start) >= self.stuck_timeout)
+    }
+}
+
+/// A loading phase that has finished.
+#[derive(Debug, Clone)]
+pub struct LoadingPhase {
+    pub state: LoadingState,
+    pub duration: Duration,
+    /// What was processed during this phase and how many, e.g. ("blocks", 5). None if nothing was reported.
+    pub processed: Option<(&'static str, usize)>,
+}
+
+/// Times each [`LoadingState`] and keeps track of how much work it did, so slow or stuck loads are easy to spot in the logs.
+#[derive(Resource, Debug, Default)]
+pub struct LoadingTimings {
+    current: Option<(LoadingState, Instant)>,
+    phases: Vec<LoadingPhase>,
+    counts: HashMap<LoadingState, (&'static str, usize)>,
+}
+
+impl LoadingTimings {
+    /// Reports how many things were processed in a phase. Shows up in the log when the phase ends.
+    pub fn set_count(&mut self, state: LoadingState, what: &'static str, count: usize) {
+        self.counts.insert(state, (what, count));
+    }
+
+    /// How long the current phase has been running for.
+    pub fn get_current_elapsed(&self) -> Option<Duration> {
+        self.current.as_ref().map(|(_, start)| start.elapsed())
+    }
+
+    pub fn get_total(&self) -> Duration {
+        self.phases.iter().map(|p| p.duration).sum()
+    }
+
+    fn enter(&mut self, state: LoadingState, now: Instant) {
+        self.current = Some((state, now));
+    }
+
+    // finishes the current phase, if there is one
+    fn exit(&mut self, now: Instant) -> Option<&LoadingPhase> {
+        let (state, start) = self.current.take()?;
+        let processed = self.counts.remove(&state);
+        self.phases.push(LoadingPhase {
+            duration: now - start,
+            state,
+            processed,
+        });
+        self.phases.last()
+    }
+}
+
+pub(super) fn log_loading_transitions(
+    mut transitions: MessageReader<StateTransitionEvent<LoadingState>>,
+    mut timings: ResMut<LoadingTimings>,
+) {
+    for transition in transitions.read() {
+        // identity transitions don't start a new phase
+        if transition.exited == transition.entered {
+            continue;
+        }
+        let now = Instant::now();
+        if let Some(phase) = timings.exit(now) {
+            match phase.processed {
+                Some((what, count)) => info!("Loading: {:?} took {:.2?} ({} {})", phase.state, phase.duration, count, what),
+                None => info!("Loading: {:?} took {:.2?}", phase.state, phase.duration),
+            }
+        }
+        match &transition.entered {
+            Some(LoadingState::Done) => info!("Loading: done in {:.2?}", timings.get_total()),
+            Some(state) => {
+                info!("Loading: entering {:?}", state);
+                timings.enter(state.clone(), now);
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::message::Messages;
+    use bevy::prelude::World;
+
+    #[test]
+    fn loading_that_never_finishes_is_reported_after_the_timeout() {
+        let settings = LoadingSettings { stuck_timeout: Duration::from_secs(10) };
+        let start = Instant::now();
+        let mut timings = LoadingTimings::default();
+        assert!(!settings.is_stuck(&timings, start + Duration::from_secs(60)));
+
+        // a block whose model never loads keeps asset loading going forever
+        timings.enter(LoadingState::Assets, start);
+        assert!(!settings.is_stuck(&timings, start + Duration::from_secs(9)));
+        assert!(settings.is_stuck(&timings, start + Duration::from_secs(10)));
+        assert!(settings.is_stuck(&timings, start + Duration::from_secs(60)));
+
+        // once it does load, the next phase gets its own timeout
+        timings.exit(start + Duration::from_secs(60));
+        timings.enter(LoadingState::Registries, start + Duration::from_secs(60));
+        assert!(!settings.is_stuck(&timings, start + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn every_phase_entered_gets_timed() {
+        let start = Instant::now();
+        let mut timings = LoadingTimings::default();
+        timings.enter(LoadingState::Assets, start);
+        let assets = timings.exit(start + Duration::from_millis(30)).unwrap();
+        assert_eq!((assets.state.clone(), assets.duration, assets.processed), (LoadingState::Assets, Duration::from_millis(30), None));
+        timings.set_count(LoadingState::Registries, "blocks", 5);
+        timings.enter(LoadingState::Registries, start + Duration::from_millis(30));
+        let registries = timings.exit(start + Duration::from_millis(45)).unwrap();
+        assert_eq!((registries.duration, registries.processed), (Duration::from_millis(15), Some(("blocks", 5))));
+        assert_eq!(timings.get_total(), Duration::from_millis(45));
+        // nothing running, nothing to finish
+        assert!(timings.exit(start + Duration::from_millis(50)).is_none());
+
+        // the same through state transitions, all the way to done
+        let mut world = World::new();
+        world.init_resource::<LoadingTimings>();
+        world.init_resource::<Messages<StateTransitionEvent<LoadingState>>>();
+        let states = [LoadingState::Assets, LoadingState::Registries, LoadingState::Textures, LoadingState::BlockCache, LoadingState::Done];
+        let log = world.register_system(log_loading_transitions);
+        let mut exited = None;
+        for state in states.clone() {
+            world.write_message(StateTransitionEvent { exited: exited.clone(), entered: Some(state.clone()) });
+            // re-entering the same state doesn't start a new phase
+            world.write_message(StateTransitionEvent { exited: Some(state.clone()), entered: Some(state.clone()) });
+            world.run_system(log).unwrap();
+            exited = Some(state);
+        }
+        let timings = world.resource::<LoadingTimings>();
+        assert_eq!(timings.phases.iter().map(|p| p.state.clone()).collect::<Vec<_>>(), &states[..4]);
+        assert_eq!(timings.get_total(), timings.phases.iter().map(|p| p.duration).sum());
+        assert!(timings.get_current_elapsed().is_none());
+    }
+}
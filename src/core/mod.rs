@@ -1,18 +1,20 @@
 use std::fs;
-use crate::asset::block::BlockAsset;
-use crate::core::errors::RegistryError;
-use crate::core::event::{JoinedWorldEvent, PlayerMovedEvent, SetBlockEvent};
-use crate::core::state::{LoadingState, MainGameState};
-use crate::registry::block::Block;
-use crate::registry::{Registry, RegistryHandle};
-use crate::world::camera::MainCamera;
-use crate::world::block::BlockWorld;
-use crate::{asset, registry, RunConfig};
+use crate::asset::block::{BlockAsset, BlockModelAsset};
+use crate::core::loading::{LoadingSettings, LoadingTimings};
+use crate::core::profiling::PipelineProfiler;
+use crate::core::state::{InGameState, LoadingState, MainGameState};
+use crate::render::block::BlockTextures;
+use crate::RunConfig;
 use bevy::app::{App, Plugin, Startup, Update};
-use bevy::asset::{ron, AssetServer, Assets, Handle, LoadedFolder, RecursiveDependencyLoadState};
+use bevy::asset::io::AssetSourceId;
+use bevy::asset::{ron, AssetServer, Assets, Handle, LoadState, RecursiveDependencyLoadState, UntypedAssetId};
+use bevy::tasks::futures_lite::{future, StreamExt};
+use bevy::tasks::{block_on, IoTaskPool, Task};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use bevy::log::error;
 use bevy::prelude::*;
-use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 
 #[allow(dead_code)]
@@ -21,6 +23,8 @@ pub mod state;
 pub mod errors;
 #[allow(dead_code)]
 pub mod event;
+pub mod profiling;
+pub mod loading;
 
 /// Core plugin that registers states, events, core systems, etc.
 #[derive(Default)]
@@ -31,15 +35,22 @@ impl Plugin for CoreGamePlugin {
         app
             .insert_resource(LoadedFolders::default())
             .init_resource::<AllBlockAssets>()
+            .init_resource::<PipelineProfiler>()
+            .init_resource::<LoadingTimings>()
+            .init_resource::<LoadingSettings>()
             .init_state::<MainGameState>()
             .init_state::<LoadingState>()
+            .add_sub_state::<InGameState>()
             
             .add_systems(Startup, load_folders)
             .add_systems(Startup, gen_folders_if_empty)
-            .add_systems(Update, (all_folders_loaded, check_loading_blocks)
+            .add_systems(Update, (all_folders_loaded, check_loading_blocks, report_stuck_assets)
                 .run_if(in_state(LoadingState::Assets))
             )
-            .add_systems(OnEnter(LoadingState::Done), finish_loading)
+            .add_systems(Update, finish_loading.run_if(in_state(LoadingState::Done).and(in_state(MainGameState::LoadStartup))))
+            .add_systems(Update, loading::log_loading_transitions)
+            .add_systems(First, profiling::start_profile_capture)
+            .add_systems(Last, profiling::finish_profile_capture)
             .add_systems(OnEnter(LoadingState::Done), test_writing_to_disk)
         ;
     }
@@ -57,22 +68,18 @@ fn gen_folders_if_empty(run_config: Res<RunConfig>) -> Result<(), BevyError> {
 
 
 
+// block files are listed first, then each one is loaded on its own.
+// A folder load fails as soon as one file in it does, which would stop every block from loading.
 #[derive(Resource, Default)]
 struct LoadedFolders {
-    blocks: (Handle<LoadedFolder>, bool)
+    block_paths: Option<Task<Result<Vec<PathBuf>, BevyError>>>,
+    blocks: (Vec<Handle<BlockAsset>>, bool)
 }
 
-#[derive(Resource)]
+#[derive(Resource, Default)]
 pub struct AllBlockAssets {
     pub inner: Vec<Handle<BlockAsset>>
 }
-impl Default for AllBlockAssets {
-    fn default() -> Self {
-        Self {
-            inner: Vec::new()
-        }
-    }
-}
 
 
 
@@ -81,48 +88,71 @@ fn load_folders(
     asset_server: Res<AssetServer>,
     mut loaded_folders: ResMut<LoadedFolders>,
 ) {
-    loaded_folders.blocks = (asset_server.load_folder("block"), false);
+    let asset_server = asset_server.clone();
+    loaded_folders.block_paths = Some(IoTaskPool::get().spawn(async move {
+        let reader = asset_server.get_source(AssetSourceId::Default)?.reader();
+        let paths = reader.read_directory(Path::new("block")).await?
+            .filter(|path| path.to_string_lossy().ends_with(".block.ron"))
+            .collect::<Vec<_>>().await;
+        Ok(paths)
+    }));
+    loaded_folders.blocks = (Vec::new(), false);
 }
 
 // runs during registry loading
 fn check_loading_blocks(
     asset_server: Res<AssetServer>,
-    all_folders: Res<Assets<LoadedFolder>>,
     mut loaded_folders: ResMut<LoadedFolders>,
-    mut def_list: ResMut<AllBlockAssets>
+    mut def_list: ResMut<AllBlockAssets>,
+    mut timings: ResMut<LoadingTimings>,
 ) {
 
-    let (folder_handle, already_loaded) = &loaded_folders.blocks;
-    if *already_loaded {
+    if loaded_folders.blocks.1 {
         return;
     }
 
-    let block_folder = all_folders.get(folder_handle);
-    if block_folder.is_none() {
-        return;
+    if let Some(task) = loaded_folders.block_paths.as_mut() {
+        let Some(result) = block_on(future::poll_once(task)) else {
+            return;
+        };
+        loaded_folders.block_paths = None;
+        match result {
+            Ok(paths) => loaded_folders.blocks.0 = paths.into_iter().map(|path| asset_server.load(path)).collect(),
+            Err(err) => error!("Could not read the block folder: {err}"),
+        }
     }
-    let block_folder = block_folder.unwrap();
-    match asset_server.get_recursive_dependency_load_state(folder_handle) {
-        Some(RecursiveDependencyLoadState::Loaded) => {
-
-            // we've loaded all blocks, yay! We can safely unwrap these
-            let block_handles = asset::get_handles_in::<BlockAsset>(block_folder);
-            def_list.inner = block_handles;
-
-            // if let Err(err) = registry::block::load_blocks(block_asset, block_reg, def_list.into()) {
-            //     error!("Error loading blocks: {err}")
-            // }
 
-            loaded_folders.blocks.1 = true;
-        }
-        Some(RecursiveDependencyLoadState::Failed(err)) => {
-            error!("Error loading blocks: {err}");
-            loaded_folders.blocks.1 = true;
+    // check each block file on its own, so one broken file doesn't stop every other block from loading.
+    // wait until every file is either loaded or failed first.
+    let block_files = &loaded_folders.blocks.0;
+    let still_loading = block_files.iter().any(|h| {
+        !matches!(
+            asset_server.get_recursive_dependency_load_state(h.id()),
+            Some(RecursiveDependencyLoadState::Loaded) | Some(RecursiveDependencyLoadState::Failed(_))
+        )
+    });
+    if still_loading {
+        return;
+    }
 
+    let mut block_handles = Vec::new();
+    for handle in block_files.iter() {
+        match asset_server.get_recursive_dependency_load_state(handle.id()) {
+            Some(RecursiveDependencyLoadState::Failed(err)) => {
+                let path = handle.path().map(|p| p.to_string()).unwrap_or_else(|| String::from("<unknown>"));
+                error!("Skipping block file {path}: {err}");
+            }
+            _ => block_handles.push(handle.clone()),
         }
-        // none case, or loading/notloaded
-        _ => { }
     }
+    timings.set_count(LoadingState::Assets, "block files", block_handles.len());
+    def_list.inner = block_handles;
+
+    // if let Err(err) = registry::block::load_blocks(block_asset, block_reg, def_list.into()) {
+    //     error!("Error loading blocks: {err}")
+    // }
+
+    loaded_folders.blocks.1 = true;
     //done
 }
 
@@ -130,6 +160,69 @@ fn check_loading_blocks(
 
 
 
+// only runs in asset loading state.
+// if loading takes too long, something probably never finished. Logs everything that's still pending or failed, so it's clear which file is the problem.
+fn report_stuck_assets(
+    asset_server: Res<AssetServer>,
+    loaded_folders: Res<LoadedFolders>,
+    block_assets: Res<Assets<BlockAsset>>,
+    model_assets: Res<Assets<BlockModelAsset>>,
+    timings: Res<LoadingTimings>,
+    settings: Res<LoadingSettings>,
+    mut reported: Local<bool>,
+) {
+    if *reported || !settings.is_stuck(&timings, Instant::now()) {
+        return;
+    }
+    *reported = true;
+
+    warn!("Asset loading has taken more than {:.0?}, still waiting on:", settings.stuck_timeout);
+    if loaded_folders.block_paths.is_some() {
+        warn!("  block folder: still being read");
+        return;
+    }
+    let mut visited = HashSet::new();
+    for handle in loaded_folders.blocks.0.iter() {
+        report_pending(handle.id().untyped(), &asset_server, &block_assets, &model_assets, &mut visited);
+    }
+}
+
+// logs an asset if it hasn't loaded, then does the same for its dependencies.
+// Dependencies are only known once the asset itself has loaded.
+fn report_pending(
+    id: UntypedAssetId,
+    asset_server: &AssetServer,
+    block_assets: &Assets<BlockAsset>,
+    model_assets: &Assets<BlockModelAsset>,
+    visited: &mut HashSet<UntypedAssetId>,
+) {
+    if !visited.insert(id) {
+        return;
+    }
+    // everything below this is done
+    if let Some(RecursiveDependencyLoadState::Loaded) = asset_server.get_recursive_dependency_load_state(id) {
+        return;
+    }
+    let path = asset_server.get_path(id).map(|p| p.to_string()).unwrap_or_else(|| format!("{id}"));
+    match asset_server.get_load_state(id) {
+        Some(LoadState::Loaded) => {}
+        Some(LoadState::Failed(err)) => warn!("  {path}: failed: {err}"),
+        _ => warn!("  {path}: still loading"),
+    }
+
+    let mut dependencies: Vec<UntypedAssetId> = Vec::new();
+    if let Some(block) = id.try_typed::<BlockAsset>().ok().and_then(|id| block_assets.get(id)) {
+        dependencies.extend(block.models.iter().map(|def| def.model_handle.id().untyped()));
+    }
+    if let Some(model) = id.try_typed::<BlockModelAsset>().ok().and_then(|id| model_assets.get(id)) {
+        dependencies.extend(model.parent_handle.iter().map(|h| h.id().untyped()));
+        dependencies.extend(model.texture_handles.values().map(|h| h.id().untyped()));
+    }
+    for dependency in dependencies {
+        report_pending(dependency, asset_server, block_assets, model_assets, visited);
+    }
+}
+
 // only runs in registry loading state
 fn all_folders_loaded(
     loaded_folders: Res<LoadedFolders>,
@@ -148,10 +241,21 @@ fn all_folders_loaded(
 
 
 
-// runs finally once all loading is done
+// runs finally once all loading is done, until the game starts
 fn finish_loading(
     mut next_game_state: ResMut<NextState<MainGameState>>,
+    block_textures: Res<BlockTextures>,
+    mut warned: Local<bool>,
 ) {
+    // textures load before the block cache, which loads before this, so they're normally ready by now.
+    // If they aren't, wait for them here, since chunks can't be drawn without them
+    if !block_textures.is_ready() {
+        if !*warned {
+            warn!("Finished loading, but block textures aren't ready yet. Waiting for them.");
+            *warned = true;
+        }
+        return;
+    }
     info!("Finished loading.");
     next_game_state.set(MainGameState::InGame);
 }
@@ -181,4 +285,59 @@ fn test_writing_to_disk(
     println!("Meow");
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::GameAssetPlugin;
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn a_broken_block_file_does_not_stop_the_rest_loading() {
+        let dir = std::env::temp_dir().join(format!("gtclone-blocks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("block")).unwrap();
+        for id in ["stone", "dirt"] {
+            let block = format!("Block(id: \"{id}\", hardness: 1, states: [], default_state: {{}})");
+            fs::write(dir.join("block").join(format!("{id}.block.ron")), block).unwrap();
+        }
+        fs::write(dir.join("block/broken.block.ron"), "Block(id: \"broken\", hardness: ").unwrap();
+
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin {
+            file_path: dir.to_string_lossy().into_owned(),
+            ..default()
+        }, GameAssetPlugin))
+            .init_resource::<LoadedFolders>()
+            .init_resource::<AllBlockAssets>()
+            .init_resource::<LoadingTimings>();
+        app.world_mut().run_system_once(load_folders).unwrap();
+
+        for _ in 0..400 {
+            app.update();
+            app.world_mut().run_system_once(check_loading_blocks).unwrap();
+            if app.world().resource::<LoadedFolders>().blocks.1 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert!(app.world().resource::<LoadedFolders>().blocks.1, "block folder never finished loading");
+
+        let assets = app.world().resource::<Assets<BlockAsset>>();
+        let mut ids = app.world().resource::<AllBlockAssets>().inner.iter()
+            .map(|h| assets.get(h).unwrap().id.clone())
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec!["dirt", "stone"]);
+
+        // the broken file was still tried, and is the one that failed
+        let asset_server = app.world().resource::<AssetServer>();
+        let failed = app.world().resource::<LoadedFolders>().blocks.0.iter()
+            .filter(|h| matches!(asset_server.get_load_state(h.id()), Some(LoadState::Failed(_))))
+            .map(|h| h.path().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(failed, vec!["block/broken.block.ron"]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
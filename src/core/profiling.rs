@@ -0,0 +1,163 @@
+use bevy::prelude::{info, ButtonInput, KeyCode, Res, Resource};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Stages of the chunk pipeline that get timed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum PipelineStage {
+    Generation,
+    Cull,
+    FaceGather,
+    BufferBuild,
+    Upload,
+}
+
+impl PipelineStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PipelineStage::Generation => "generation",
+            PipelineStage::Cull => "mesh: cull",
+            PipelineStage::FaceGather => "mesh: face gather",
+            PipelineStage::BufferBuild => "mesh: buffer build",
+            PipelineStage::Upload => "upload",
+        }
+    }
+}
+
+/// Total time spent and number of samples for each stage.
+#[derive(Debug, Default, Clone)]
+pub struct StageTimings {
+    stages: BTreeMap<PipelineStage, (Duration, u32)>,
+}
+
+impl StageTimings {
+    pub fn record(&mut self, stage: PipelineStage, time: Duration) {
+        let entry = self.stages.entry(stage).or_default();
+        entry.0 += time;
+        entry.1 += 1;
+    }
+
+    pub fn get_total(&self, stage: PipelineStage) -> Duration {
+        self.stages.get(&stage).map(|(t, _)| *t).unwrap_or_default()
+    }
+
+    pub fn get_count(&self, stage: PipelineStage) -> u32 {
+        self.stages.get(&stage).map(|(_, c)| *c).unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (PipelineStage, Duration, u32)> + '_ {
+        self.stages.iter().map(|(stage, (time, count))| (*stage, *time, *count))
+    }
+}
+
+/// Collects stage timings from any thread, including compute tasks. Backed by an `Arc`, so it is cheap to clone.
+/// Does nothing unless a capture is running, so it can be left in hot paths.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct PipelineProfiler {
+    enabled: Arc<AtomicBool>,
+    timings: Arc<Mutex<StageTimings>>,
+}
+
+impl PipelineProfiler {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn record(&self, stage: PipelineStage, time: Duration) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.timings.lock().unwrap().record(stage, time);
+    }
+
+    fn start_capture(&self) {
+        *self.timings.lock().unwrap() = StageTimings::default();
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    fn finish_capture(&self) -> StageTimings {
+        self.enabled.store(false, Ordering::Relaxed);
+        std::mem::take(&mut *self.timings.lock().unwrap())
+    }
+}
+
+
+//===============
+//    Systems
+//===============
+
+// runs in First. Input is only cleared in PreUpdate, so this still sees last frame's key press.
+pub fn start_profile_capture(
+    profiler: Res<PipelineProfiler>,
+    kb_input: Res<ButtonInput<KeyCode>>,
+) {
+    if kb_input.just_pressed(KeyCode::F9) {
+        profiler.start_capture();
+    }
+}
+
+// runs in Last. Logs the timings of everything that finished this frame.
+pub fn finish_profile_capture(
+    profiler: Res<PipelineProfiler>,
+) {
+    if !profiler.is_enabled() {
+        return;
+    }
+    let timings = profiler.finish_capture();
+    if timings.is_empty() {
+        info!("Pipeline timings: nothing was processed this frame.");
+        return;
+    }
+    let mut message = String::from("Pipeline timings for this frame:");
+    for (stage, time, count) in timings.iter() {
+        message.push_str(&format!("\n  {}: {:.3} ms over {} samples", stage.name(), time.as_secs_f64() * 1000.0, count));
+    }
+    info!("{message}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timings_add_up_per_stage() {
+        let ms = Duration::from_millis;
+        let profiler = PipelineProfiler::default();
+        // nothing counts outside a capture
+        profiler.record(PipelineStage::Cull, ms(100));
+        profiler.start_capture();
+        assert!(profiler.timings.lock().unwrap().is_empty());
+
+        // samples come in from other threads, like meshing tasks
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let profiler = profiler.clone();
+                scope.spawn(move || {
+                    profiler.record(PipelineStage::Cull, ms(2));
+                    profiler.record(PipelineStage::Cull, ms(3));
+                    profiler.record(PipelineStage::Upload, ms(1));
+                });
+            }
+        });
+        profiler.record(PipelineStage::Generation, ms(7));
+
+        let timings = profiler.finish_capture();
+        assert!(!profiler.is_enabled());
+        assert_eq!((timings.get_total(PipelineStage::Cull), timings.get_count(PipelineStage::Cull)), (ms(20), 8));
+        assert_eq!((timings.get_total(PipelineStage::Upload), timings.get_count(PipelineStage::Upload)), (ms(4), 4));
+        assert_eq!((timings.get_total(PipelineStage::Generation), timings.get_count(PipelineStage::Generation)), (ms(7), 1));
+        assert_eq!((timings.get_total(PipelineStage::FaceGather), timings.get_count(PipelineStage::FaceGather)), (ms(0), 0));
+        // in pipeline order
+        assert_eq!(timings.iter().map(|(stage, _, _)| stage).collect::<Vec<_>>(), vec![PipelineStage::Generation, PipelineStage::Cull, PipelineStage::Upload]);
+
+        // the next capture starts from nothing
+        profiler.start_capture();
+        assert!(profiler.finish_capture().is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use bevy::prelude::States;
+use bevy::prelude::{StateSet, States, SubStates};
 
 #[derive(States, Debug, Default, Clone, PartialEq, Eq, Hash)]
 pub enum MainGameState {
@@ -8,6 +8,15 @@ pub enum MainGameState {
     InGame
 }
 
+/// Parts of being in game. The area around spawn loads first, and the player can only move once it's done.
+#[derive(SubStates, Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[source(MainGameState = MainGameState::InGame)]
+pub enum InGameState {
+    #[default]
+    LoadingSpawn,
+    Playing,
+}
+
 
 #[derive(States, Debug, Default, Clone, PartialEq, Eq, Hash)]
 pub enum LoadingState {
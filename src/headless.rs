@@ -0,0 +1,229 @@
+//! Running the game without a window or renderer, for testing world generation, meshing and saving end to end.
+//!
+//! ```ignore
+//! let mut app = test_app(RunConfig::from_base(&temp_dir));
+//! run_until(&mut app, 600, |app| loaded_chunk_count(app) > 0);
+//! ```
+
+use crate::asset::GameAssetPlugin;
+use crate::command::CommandPlugin;
+use crate::core::state::InGameState;
+use crate::core::CoreGamePlugin;
+use crate::registry::RegistryPlugin;
+use crate::render::GameRenderPlugin;
+use crate::world::block::BlockWorld;
+use crate::world::GameWorldPlugin;
+use crate::RunConfig;
+use bevy::image::{CompressedImageFormats, ImageLoader};
+use bevy::input::InputPlugin;
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+
+/// Builds an app with the world plugins and nothing that needs a window or GPU.
+/// Nothing runs until it's stepped, see [`step`].
+pub fn test_app(run_config: RunConfig) -> App {
+    let mut app = App::new();
+    app
+        .add_plugins((
+            MinimalPlugins,
+            StatesPlugin,
+            AssetPlugin::default(),
+            ImagePlugin::default_nearest(),
+            TransformPlugin,
+            InputPlugin,
+        ))
+        .init_asset::<Mesh>()
+        .add_plugins((
+            CoreGamePlugin,
+            GameAssetPlugin,
+            RegistryPlugin,
+            GameWorldPlugin,
+            GameRenderPlugin { headless: true },
+            CommandPlugin,
+        ))
+        // the image loader normally comes from the renderer, and the block textures can't load without it
+        .register_asset_loader(ImageLoader::new(CompressedImageFormats::NONE))
+        .insert_resource(run_config)
+    ;
+    app
+}
+
+/// Runs `frames` updates of the app.
+pub fn step(app: &mut App, frames: usize) {
+    for _ in 0..frames {
+        app.update();
+    }
+}
+
+/// Steps the app until `done` returns true, or `max_frames` have run. Returns how many frames it took, or `None` if it never finished.
+/// Asset loading and chunk generation happen on other threads, so this is more reliable than a fixed number of frames.
+pub fn run_until(app: &mut App, max_frames: usize, mut done: impl FnMut(&mut App) -> bool) -> Option<usize> {
+    for frame in 0..max_frames {
+        if done(app) {
+            return Some(frame);
+        }
+        app.update();
+    }
+    done(app).then_some(max_frames)
+}
+
+/// Total chunks loaded across every dimension.
+pub fn loaded_chunk_count(app: &mut App) -> usize {
+    let world = app.world_mut();
+    let mut q_world = world.query::<&BlockWorld>();
+    q_world.iter(world).map(|w| w.get_chunk_map().len()).sum()
+}
+
+/// Whether the spawn chunks have finished loading and the player can move.
+pub fn is_playing(app: &App) -> bool {
+    app.world().get_resource::<State<InGameState>>()
+        .is_some_and(|state| *state.get() == InGameState::Playing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::event::{BlockChangeSource, ChunkLifecycle, ChunkLifecycleEvent, TeleportEvent};
+    use crate::registry::block::{test_asset, Block};
+    use crate::registry::{RegisterBuiltinBlock, RegistryHandle};
+    use crate::world::block::BlockState;
+    use crate::world::camera::MainCamera;
+    use crate::world::chunk;
+    use crate::world::dimension::ActiveWorld;
+    use bevy::ecs::system::RunSystemOnce;
+    use crate::world::player::{Player, PlayerCollider};
+    use crate::world::save;
+
+    #[test]
+    fn a_new_world_loads_its_spawn_and_saves_on_exit() {
+        let base = std::env::temp_dir().join(format!("gtclone-headless-{}", std::process::id()));
+        let run_config = RunConfig::from_base(&base);
+        let data_dir = run_config.data_dir.clone();
+        let mut app = test_app(run_config);
+
+        // generation runs on other threads, so give it real time instead of only frames
+        let playing = run_until(&mut app, 3000, |app| {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            is_playing(app)
+        });
+        assert!(playing.is_some(), "spawn never finished loading");
+        assert!(loaded_chunk_count(&mut app) > 0);
+
+        app.world_mut().write_message(AppExit::Success);
+        app.update();
+        let saved = save::load_metadata(&data_dir);
+        let _ = std::fs::remove_dir_all(&base);
+        assert!(saved.unwrap().is_some(), "exiting should write the world metadata");
+    }
+
+    #[test]
+    fn the_camera_sits_at_the_players_eyes() {
+        let base = std::env::temp_dir().join(format!("gtclone-eyes-{}", std::process::id()));
+        let mut app = test_app(RunConfig::from_base(&base));
+        let playing = run_until(&mut app, 3000, |app| {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            is_playing(app)
+        });
+        let _ = std::fs::remove_dir_all(&base);
+        assert!(playing.is_some(), "spawn never finished loading");
+
+        let world = app.world_mut();
+        let (feet, collider) = world.query_filtered::<(&GlobalTransform, &PlayerCollider), With<Player>>().single(world).unwrap();
+        let expected = feet.translation() + collider.get_eye_offset();
+        let camera = world.query_filtered::<&GlobalTransform, With<MainCamera>>().single(world).unwrap();
+        assert!(camera.translation().abs_diff_eq(expected, 1e-4), "camera at {}, eyes at {}", camera.translation(), expected);
+    }
+
+    #[derive(Resource, Default)]
+    struct Lifecycle(Vec<(IVec3, ChunkLifecycle)>);
+
+    #[test]
+    fn chunks_are_generated_then_meshed_then_despawned() {
+        let base = std::env::temp_dir().join(format!("gtclone-lifecycle-{}", std::process::id()));
+        let mut app = test_app(RunConfig::from_base(&base));
+        app.init_resource::<Lifecycle>();
+        app.add_observer(|event: On<ChunkLifecycleEvent>, mut seen: ResMut<Lifecycle>| seen.0.push((event.pos, event.stage)));
+
+        let playing = run_until(&mut app, 3000, |app| {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            is_playing(app)
+        });
+        assert!(playing.is_some(), "spawn never finished loading");
+
+        // going far away unloads everything around spawn
+        app.world_mut().trigger(TeleportEvent { pos: vec3(10000.0, 100.0, 10000.0) });
+        let unloaded = run_until(&mut app, 3000, |app| {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            app.world().resource::<Lifecycle>().0.iter().any(|(pos, stage)| *pos == IVec3::ZERO && *stage == ChunkLifecycle::Despawned)
+        });
+        let _ = std::fs::remove_dir_all(&base);
+        assert!(unloaded.is_some(), "the spawn chunk never unloaded");
+
+        let stages = app.world().resource::<Lifecycle>().0.iter()
+            .filter(|(pos, _)| *pos == IVec3::ZERO)
+            .map(|(_, stage)| *stage)
+            .collect::<Vec<_>>();
+        let first = |stage| stages.iter().position(|s| *s == stage).unwrap_or_else(|| panic!("no {stage:?} in {stages:?}"));
+        assert!(first(ChunkLifecycle::Queued) < first(ChunkLifecycle::Generated), "{stages:?}");
+        assert!(first(ChunkLifecycle::Generated) < first(ChunkLifecycle::Meshed), "{stages:?}");
+        assert_eq!(stages.last(), Some(&ChunkLifecycle::Despawned), "{stages:?}");
+        assert_eq!(stages.iter().filter(|s| **s == ChunkLifecycle::Despawned).count(), 1);
+    }
+
+    #[test]
+    fn an_edited_chunk_is_loaded_from_disk_instead_of_regenerated() {
+        let base = std::env::temp_dir().join(format!("gtclone-reload-{}", std::process::id()));
+        let mut app = test_app(RunConfig::from_base(&base));
+        app.init_resource::<Lifecycle>();
+        app.add_observer(|event: On<ChunkLifecycleEvent>, mut seen: ResMut<Lifecycle>| seen.0.push((event.pos, event.stage)));
+        let playing = run_until(&mut app, 3000, |app| {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            is_playing(app)
+        });
+        assert!(playing.is_some(), "spawn never finished loading");
+
+        // somewhere above the player's head, which generation leaves as air
+        let feet = app.world_mut().query_filtered::<&Transform, With<Player>>().single(app.world()).unwrap().translation;
+        let edited = feet.floor().as_ivec3() + ivec3(0, 3, 0);
+        let chunk_pos = chunk::pos_to_chunk_pos(edited);
+        let before = app.world_mut().run_system_once(move |mut commands: Commands, mut world: Single<&mut BlockWorld, With<ActiveWorld>>, registry: Res<RegistryHandle<Block>>| {
+            let stone = BlockState::new("stone", &registry).unwrap();
+            world.set_block(&mut commands, &edited, stone, BlockChangeSource::Player).unwrap()
+        }).unwrap();
+        assert!(before.is_air());
+
+        let count = |app: &App, stage: ChunkLifecycle| app.world().resource::<Lifecycle>().0.iter().filter(|(pos, s)| *pos == chunk_pos && *s == stage).count();
+        app.world_mut().trigger(TeleportEvent { pos: vec3(10000.0, 100.0, 10000.0) });
+        let unloaded = run_until(&mut app, 3000, |app| {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            count(app, ChunkLifecycle::Despawned) > 0
+        });
+        assert!(unloaded.is_some(), "the edited chunk never unloaded");
+
+        app.world_mut().trigger(TeleportEvent { pos: feet });
+        let reloaded = run_until(&mut app, 3000, |app| {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            count(app, ChunkLifecycle::Generated) > 1
+        });
+        let after = app.world_mut().run_system_once(move |world: Single<&BlockWorld, With<ActiveWorld>>| world.get_block(&edited).unwrap()).unwrap();
+        let _ = std::fs::remove_dir_all(&base);
+        assert!(reloaded.is_some(), "the edited chunk never came back");
+        assert_eq!(after.get_id(), "stone");
+    }
+
+    #[test]
+    fn a_builtin_block_sharing_an_id_with_a_block_file_exits() {
+        let base = std::env::temp_dir().join(format!("gtclone-collision-{}", std::process::id()));
+        let mut app = test_app(RunConfig::from_base(&base));
+        app.register_builtin_block(Block::from_asset(&test_asset("stone")));
+
+        let exited = run_until(&mut app, 3000, |app| {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            app.should_exit().is_some()
+        });
+        let _ = std::fs::remove_dir_all(&base);
+        assert!(exited.is_some(), "a duplicate block id should stop loading");
+        assert!(app.should_exit().unwrap().is_error());
+        assert!(!is_playing(&app));
+    }
+}
@@ -0,0 +1,110 @@
+// bevy systems and queries naturally take lots of parameters
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
+pub mod asset;
+pub mod audio;
+pub mod core;
+pub mod registry;
+pub mod world;
+pub mod render;
+pub mod ui;
+pub mod command;
+pub mod headless;
+
+#[allow(dead_code)]
+pub mod math;
+
+use std::fmt::Formatter;
+use std::path;
+use std::path::{Path, PathBuf};
+use bevy::prelude::Resource;
+use directories::ProjectDirs;
+
+pub const ORG_NAME: &str = "vertexcubed";
+pub const APP_NAME: &str = "GTClone";
+
+#[derive(Resource, Debug)]
+pub struct RunConfig {
+    pub data_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    pub config_dir: PathBuf,
+}
+impl RunConfig {
+    /// Where everything goes if there's no home directory to find the usual folders from, e.g. on CI. Relative to the working directory.
+    pub const FALLBACK_DIR: &'static str = "run";
+
+    /// Puts the data, cache and config folders all in one folder.
+    pub fn from_base(base: &Path) -> Self {
+        Self {
+            data_dir: base.join("data"),
+            cache_dir: base.join("cache"),
+            config_dir: base.join("config"),
+        }
+    }
+
+    /// Picks the folders to use. An `output` folder always wins, otherwise the platform's usual folders are used.
+    /// If those can't be found either, falls back to [`RunConfig::FALLBACK_DIR`].
+    pub fn resolve(output: Option<PathBuf>, project_dirs: Option<ProjectDirs>) -> Self {
+        if let Some(output) = output {
+            let base = if output.is_absolute() { output } else {
+                path::absolute(output).expect("Error: could not access provided file path.")
+            };
+            return Self::from_base(&base);
+        }
+        match project_dirs {
+            Some(dirs) => Self {
+                data_dir: PathBuf::from(dirs.data_dir()),
+                cache_dir: PathBuf::from(dirs.cache_dir()),
+                config_dir: PathBuf::from(dirs.config_dir()),
+            },
+            None => {
+                // logging isn't set up yet
+                eprintln!("Warning: could not find home directory, using ./{} instead.", Self::FALLBACK_DIR);
+                let base = path::absolute(Self::FALLBACK_DIR).unwrap_or_else(|_| PathBuf::from(Self::FALLBACK_DIR));
+                Self::from_base(&base)
+            }
+        }
+    }
+}
+impl std::fmt::Display for RunConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f,
+               "{{ Data directory: {:?}, Cache directory: {:?}, Config directory: {:?} }}",
+               self.data_dir, self.cache_dir, self.config_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_output_folder_always_wins() {
+        let base = std::env::temp_dir().join("gtclone-output");
+        let config = RunConfig::resolve(Some(base.clone()), ProjectDirs::from("", ORG_NAME, APP_NAME));
+        assert_eq!(config.data_dir, base.join("data"));
+        assert_eq!(config.cache_dir, base.join("cache"));
+        assert_eq!(config.config_dir, base.join("config"));
+
+        // relative ones are relative to the working directory
+        let config = RunConfig::resolve(Some(PathBuf::from("out")), None);
+        assert!(config.data_dir.is_absolute());
+        assert_eq!(config.data_dir, path::absolute("out").unwrap().join("data"));
+    }
+
+    #[test]
+    fn without_an_output_folder_the_platform_folders_are_used() {
+        if let Some(dirs) = ProjectDirs::from("", ORG_NAME, APP_NAME) {
+            let config = RunConfig::resolve(None, Some(dirs.clone()));
+            assert_eq!(config.data_dir, dirs.data_dir());
+            assert_eq!(config.cache_dir, dirs.cache_dir());
+            assert_eq!(config.config_dir, dirs.config_dir());
+        }
+
+        // and without those, the fallback folder
+        let config = RunConfig::resolve(None, None);
+        let fallback = path::absolute(RunConfig::FALLBACK_DIR).unwrap();
+        assert_eq!(config.data_dir, fallback.join("data"));
+        assert_eq!(config.config_dir, fallback.join("config"));
+    }
+}
@@ -1,22 +1,14 @@
-mod asset;
-mod core;
-mod registry;
-mod world;
-mod render;
-mod ui;
-
-#[allow(dead_code)]
-mod math;
-
-use std::fmt::Formatter;
-use std::path;
 use std::path::PathBuf;
-use crate::registry::RegistryPlugin;
-use crate::render::pipeline::GameRenderPipelinePlugin;
-use crate::render::GameRenderPlugin;
-use crate::ui::GameUiPlugin;
-use crate::world::GameWorldPlugin;
-use asset::GameAssetPlugin;
+use gtclone::audio::GameAudioPlugin;
+use gtclone::command::CommandPlugin;
+use gtclone::registry::RegistryPlugin;
+use gtclone::render::pipeline::GameRenderPipelinePlugin;
+use gtclone::render::GameRenderPlugin;
+use gtclone::ui::GameUiPlugin;
+use gtclone::world::GameWorldPlugin;
+use gtclone::asset::GameAssetPlugin;
+use gtclone::core::CoreGamePlugin;
+use gtclone::{RunConfig, APP_NAME, ORG_NAME};
 use bevy::diagnostic::FrameTimeDiagnosticsPlugin;
 use bevy::pbr::wireframe::WireframePlugin;
 use bevy::prelude::*;
@@ -26,13 +18,9 @@ use bevy::render::RenderPlugin;
 use bevy::window::PresentMode;
 use clap::Parser;
 use directories::ProjectDirs;
-use core::CoreGamePlugin;
-
 
 
 
-pub const ORG_NAME: &'static str = "vertexcubed";
-pub const APP_NAME: &'static str = "GTClone";
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -44,43 +32,11 @@ struct CliConfigRaw {
     output: Option<PathBuf>
 }
 
-#[derive(Resource, Debug)]
-pub struct RunConfig {
-    pub data_dir: PathBuf,
-    pub cache_dir: PathBuf,
-    pub config_dir: PathBuf,
-}
 impl From<CliConfigRaw> for RunConfig {
     fn from(value: CliConfigRaw) -> Self {
-
-        let project_dirs = ProjectDirs::from("", ORG_NAME, APP_NAME).expect("Could not find home directory!");
-
-        let (data_dir, cache_dir, config_dir) = match value.output {
-            None => {
-                (PathBuf::from(project_dirs.data_dir()), PathBuf::from(project_dirs.cache_dir()), PathBuf::from(project_dirs.config_dir()))
-            }
-            Some(old) => {
-                let base = if old.is_absolute() { old } else {
-                    path::absolute(old).expect("Error: could not access provided file path.")
-                };
-                (base.join("data"), base.join("cache"), base.join("config"))
-            }
-        };
-        Self {
-            data_dir,
-            cache_dir,
-            config_dir
-        }
-    }
-}
-impl std::fmt::Display for RunConfig {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f,
-               "{{ Data directory: {:?}, Cache directory: {:?}, Config directory: {:?} }}",
-               self.data_dir, self.cache_dir, self.config_dir)
+        RunConfig::resolve(value.output, ProjectDirs::from("", ORG_NAME, APP_NAME))
     }
 }
-
 fn main() {
 
     let run_config: RunConfig = CliConfigRaw::parse().into();
@@ -110,13 +66,15 @@ fn main() {
             FrameTimeDiagnosticsPlugin::default(),
 
 
-            CoreGamePlugin::default(),
-            GameAssetPlugin::default(),
-            RegistryPlugin::default(),
-            GameWorldPlugin::default(),
+            CoreGamePlugin,
+            GameAssetPlugin,
+            RegistryPlugin,
+            GameWorldPlugin,
             GameRenderPlugin::default(),
-            GameRenderPipelinePlugin::default(),
-            GameUiPlugin::default(),
+            GameRenderPipelinePlugin,
+            GameUiPlugin,
+            GameAudioPlugin,
+            CommandPlugin,
         ))
        .insert_resource(run_config)
 
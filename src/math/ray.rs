@@ -1,5 +1,5 @@
 use crate::math::block::Vec3Ext;
-use bevy::prelude::{IVec3, Vec3};
+use bevy::prelude::{vec2, IVec3, Vec2, Vec3};
 use crate::world::block::Direction;
 
 /// Performs a raycast from a starting position to a direction in the world.
@@ -11,96 +11,174 @@ pub fn block_raycast(
     max_distance: f32,
     mut test_function: impl FnMut(&RayContext, Vec3, Direction, IVec3) -> Result<bool, Box<dyn std::error::Error>>,
 ) -> Result<RayResult, Box<dyn std::error::Error>> {
-
-
-    // println!("Raycasting from {} in direction {}", start, direction);
-
-
-
-    // prevents division by zero
-    let mut direction = direction.normalize_or_zero();
-    if direction.x == 0.0 {
-        // prevent division by zero issues
-        direction.x = 0.000000001;
-    }
-    if direction.y == 0.0 {
-        direction.y = 0.000000001;
-    }
-    if direction.z == 0.0 {
-        direction.z = 0.000000001;
+    let steps = BlockSteps::new(start, direction, max_distance);
+    let context = steps.get_context().clone();
+    for step in steps {
+        if test_function(&context, step.point, step.face, step.pos)? {
+            return Ok(RayResult::Hit(step.point, step.face, step.pos, step.distance))
+        }
     }
+    Ok(RayResult::Miss)
+}
 
-    // following distances are based on the formula p + t * d, where p is the origin, d is the dir vector, and t is the amount
-
-    let context = RayContext {
-        start,
-        direction,
-    };
-
+/// One block a ray passes into: the point it entered at, the face it entered through, the block pos, and the distance traveled so far.
+#[derive(Debug, Clone)]
+pub struct RayStep {
+    pub point: Vec3,
+    pub face: Direction,
+    pub pos: IVec3,
+    pub distance: f32,
+}
 
+/// Walks the blocks a ray passes through in order, see [`block_raycast`].
+/// Handy when the caller needs to look ahead, e.g. to group steps by chunk.
+#[derive(Debug, Clone)]
+pub struct BlockSteps {
+    context: RayContext,
+    max_distance: f32,
     // the step vectors. the signs tell you which way to step
-    let step = direction.signum();
-
-    // direction = opposite of the direction step is going
-    let x_face = if step.x > 0.0 {
-        Direction::West
-    } else {
-        Direction::East
-    };
-
-    let y_face = if step.y > 0.0 {
-        Direction::Down
-    } else {
-        Direction::Up
-    };
-    let z_face = if step.z > 0.0 {
-        Direction::South
-    } else {
-        Direction::North
-    };
-
-
-
-
-    // println!("Step vec: {}", step);
-
-    // the delta vector, i.e. delta_t.x * direction will have an x length of 1
-    let delta_t = 1.0 / direction.abs();
-
-    // Get current voxel position
-    let mut grid_pos = start.floor();
-
-    // max distance to travel to reach the next grid line.
-    // let mut max_t = (grid_pos + step - start) / direction;
-    let mut max_t = ( ((step + 1.0) / 2.0) + (grid_pos - start) ) / direction;
+    step: Vec3,
+    // face hit when stepping along each axis
+    faces: [Direction; 3],
+    delta_t: Vec3,
+    grid_pos: Vec3,
+    max_t: Vec3,
+    traveled_distance: f32,
+}
+impl BlockSteps {
+    pub fn new(start: Vec3, direction: Vec3, max_distance: f32) -> Self {
+        // prevents division by zero
+        let mut direction = direction.normalize_or_zero();
+        if direction.x == 0.0 {
+            // prevent division by zero issues
+            direction.x = 0.000000001;
+        }
+        if direction.y == 0.0 {
+            direction.y = 0.000000001;
+        }
+        if direction.z == 0.0 {
+            direction.z = 0.000000001;
+        }
 
-    // keep track of how
-    let mut traveled_distance = 0.0;
+        // following distances are based on the formula p + t * d, where p is the origin, d is the dir vector, and t is the amount
 
-    while traveled_distance < max_distance {
-        let axis = argmin(max_t);
+        let step = direction.signum();
 
-        let face = match axis {
-            0 => x_face,
-            1 => y_face,
-            2 => z_face,
-            _ => panic!("Dead branch")
+        // direction = opposite of the direction step is going
+        let x_face = if step.x > 0.0 {
+            Direction::West
+        } else {
+            Direction::East
         };
 
+        let y_face = if step.y > 0.0 {
+            Direction::Down
+        } else {
+            Direction::Up
+        };
+        let z_face = if step.z > 0.0 {
+            Direction::South
+        } else {
+            Direction::North
+        };
 
+        // Get current voxel position
+        let grid_pos = start.floor();
+
+        Self {
+            context: RayContext {
+                start,
+                direction,
+            },
+            max_distance,
+            step,
+            faces: [x_face, y_face, z_face],
+            // the delta vector, i.e. delta_t.x * direction will have an x length of 1
+            delta_t: 1.0 / direction.abs(),
+            grid_pos,
+            // max distance to travel to reach the next grid line.
+            max_t: ( ((step + 1.0) / 2.0) + (grid_pos - start) ) / direction,
+            traveled_distance: 0.0,
+        }
+    }
 
-        grid_pos[axis] += step[axis];
+    pub fn get_context(&self) -> &RayContext {
+        &self.context
+    }
+}
+impl Iterator for BlockSteps {
+    type Item = RayStep;
 
-        let is_hit = test_function(&context, start + (max_t[axis] * direction), face, grid_pos.as_block_pos())?;
-        if is_hit {
-            return Ok(RayResult::Hit(start + (max_t[axis] * direction), face, grid_pos.as_block_pos()))
+    fn next(&mut self) -> Option<RayStep> {
+        if self.traveled_distance >= self.max_distance {
+            return None;
         }
-        traveled_distance = max_t[axis];
-        // println!("Distance traveled: {}", traveled_distance);
-        max_t[axis] += delta_t[axis];
+        let axis = argmin(self.max_t);
+        self.grid_pos[axis] += self.step[axis];
+
+        let t = self.max_t[axis];
+        // direction is normalized, so t is also the distance traveled
+        self.traveled_distance = t;
+        self.max_t[axis] += self.delta_t[axis];
+        Some(RayStep {
+            point: self.context.start + (t * self.context.direction),
+            face: self.faces[axis],
+            pos: self.grid_pos.as_block_pos(),
+            distance: t,
+        })
     }
+}
 
-    Ok(RayResult::Miss)
+/// Gets the position of a hit point on the face it hit, as (u, v) in [0, 1).
+/// For north/south faces this is (x, y), for east/west faces (z, y), and for up/down faces (x, z), all in block local coordinates.
+pub fn face_uv(point: Vec3, face: Direction) -> Vec2 {
+    let local = point - point.floor();
+    match face {
+        Direction::North | Direction::South => vec2(local.x, local.y),
+        Direction::East | Direction::West => vec2(local.z, local.y),
+        Direction::Up | Direction::Down => vec2(local.x, local.z),
+    }
+}
+
+/// Intersects a ray with an axis aligned box, returning the distance to where the ray enters it and the face it enters through.
+/// Faces use the same convention as [`block_raycast`]. If the ray starts inside the box the distance is 0 and there's no face.
+pub fn ray_box_intersection(start: Vec3, direction: Vec3, min: Vec3, max: Vec3) -> Option<(f32, Option<Direction>)> {
+    let direction = direction.normalize_or_zero();
+    let mut t_enter = f32::NEG_INFINITY;
+    let mut t_exit = f32::INFINITY;
+    let mut enter_axis = None;
+    for axis in 0..3 {
+        if direction[axis] == 0.0 {
+            // parallel to this pair of planes, so it has to already be between them
+            if start[axis] < min[axis] || start[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let t1 = (min[axis] - start[axis]) / direction[axis];
+        let t2 = (max[axis] - start[axis]) / direction[axis];
+        let (near, far) = if t1 < t2 { (t1, t2) } else { (t2, t1) };
+        if near > t_enter {
+            t_enter = near;
+            enter_axis = Some(axis);
+        }
+        t_exit = t_exit.min(far);
+    }
+    if t_enter > t_exit || t_exit < 0.0 {
+        return None;
+    }
+    if t_enter <= 0.0 {
+        return Some((0.0, None));
+    }
+    let face = enter_axis.map(|axis| match (axis, direction[axis] > 0.0) {
+        (0, true) => Direction::West,
+        (0, false) => Direction::East,
+        (1, true) => Direction::Down,
+        (1, false) => Direction::Up,
+        (_, true) => Direction::South,
+        (_, false) => Direction::North,
+    });
+    Some((t_enter, face))
 }
 
 // gets the minimum value, returns 0 1 or 2 for x y and z respectively.
@@ -119,10 +197,10 @@ fn argmin(vec: Vec3) -> usize {
 
 /// The result of a raycast. 
 /// Either a hit containing the Vec3 representing the point 
-/// on the block the ray intersected and block pos of the raycast, or a miss.
+/// on the block the ray intersected, the face hit, the block pos, and the distance the ray traveled, or a miss.
 #[derive(Debug, Clone)]
 pub enum RayResult {
-    Hit(Vec3, Direction, IVec3),
+    Hit(Vec3, Direction, IVec3, f32),
     Miss
 }
 
@@ -131,4 +209,43 @@ pub enum RayResult {
 pub struct RayContext {
     pub start: Vec3,
     pub direction: Vec3,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::{ivec3, vec3};
+
+    // casts through empty space until it reaches `target`
+    fn cast_at(start: Vec3, direction: Vec3, target: IVec3) -> RayResult {
+        block_raycast(start, direction, 20.0, |_, _, _, pos| Ok(pos == target)).unwrap()
+    }
+
+    #[test]
+    fn hit_distance_is_how_far_the_ray_went() {
+        let start = vec3(0.25, 1.5, 0.75);
+        let RayResult::Hit(point, face, pos, distance) = cast_at(start, vec3(3.0, 1.0, 2.0), ivec3(4, 2, 3)) else {
+            panic!("expected a hit");
+        };
+        assert_eq!(pos, ivec3(4, 2, 3));
+        assert_eq!(face, Direction::West);
+        assert!((distance - start.distance(point)).abs() < 1e-4, "{distance} vs {}", start.distance(point));
+        // straight along an axis it's just the gap to the top of the block
+        let RayResult::Hit(_, _, _, distance) = cast_at(vec3(0.5, 0.5, 0.5), Vec3::NEG_Y, ivec3(0, -3, 0)) else {
+            panic!("expected a hit");
+        };
+        assert!((distance - 2.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn the_middle_of_a_face_is_half_way_across() {
+        // straight at the middle of the north face of the block at the origin
+        let RayResult::Hit(point, face, _, _) = cast_at(vec3(0.5, 0.5, 4.5), Vec3::NEG_Z, IVec3::ZERO) else {
+            panic!("expected a hit");
+        };
+        assert_eq!(face, Direction::North);
+        assert!(face_uv(point, face).abs_diff_eq(vec2(0.5, 0.5), 1e-5));
+
+        assert_eq!(face_uv(vec3(-2.75, 7.0, 3.5), Direction::Up), vec2(0.25, 0.5));
+        assert_eq!(face_uv(vec3(1.0, 2.125, -0.25), Direction::West), vec2(0.75, 0.125));
+    }
+}
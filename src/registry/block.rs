@@ -1,15 +1,24 @@
-use crate::asset::block::{BlockAsset, BlockStateAsset};
-use crate::registry::RegistryObject;
+use crate::asset::block::{BlockAsset, BlockStateAsset, CollisionBox, ToolType};
+use crate::registry::{Registry, RegistryObject};
+use crate::world::player::Tool;
 use bevy::prelude::*;
 use std::collections::BTreeMap;
 
+/// Id of air, the block registry's initial value. Nothing else can be registered with this id.
+pub const AIR_ID: &str = "air";
+
 /// The registry representation of a Block. Contains its id, state information, etc.
-#[derive(Debug, Hash, PartialEq, Eq)]
+#[derive(Debug, PartialEq)]
 pub struct Block {
     id: String,
     hardness: u32,
     default_state: BTreeMap<String, String>,
-    states: Vec<BlockStateAsset>
+    states: Vec<BlockStateAsset>,
+    sound_group: Option<String>,
+    tool: Option<ToolType>,
+    harvest_level: u32,
+    collision_shape: Option<Vec<CollisionBox>>,
+    tags: Vec<String>,
 }
 
 impl Block {
@@ -20,12 +29,22 @@ impl Block {
             id: asset.id.clone(),
             hardness: asset.hardness,
             default_state: asset.default_state.clone(),
-            states: asset.states.clone()
+            states: asset.states.clone(),
+            sound_group: asset.sound_group.clone(),
+            tool: asset.tool,
+            harvest_level: asset.harvest_level,
+            collision_shape: asset.collision_shape.clone(),
+            tags: asset.tags.clone(),
         }
     }
     pub fn get_hardness(&self) -> u32 {
         self.hardness
     }
+
+    /// Changes how long this block takes to break. Only possible before the registry is frozen, see [`crate::registry::overrides`].
+    pub fn set_hardness(&mut self, hardness: u32) {
+        self.hardness = hardness;
+    }
     pub fn get_default_state(&self) -> &BTreeMap<String, String> {
         &self.default_state
     }
@@ -33,6 +52,36 @@ impl Block {
     pub fn get_states(&self) -> &Vec<BlockStateAsset> {
         &self.states
     }
+
+    /// The sound group of this block, used to pick place and break sounds. None if this block is silent.
+    pub fn get_sound_group(&self) -> Option<&str> {
+        self.sound_group.as_deref()
+    }
+
+    /// The boxes this block declared it collides with. None means each state collides with the box around its model,
+    /// see [`crate::world::shape::BlockShapes`].
+    pub fn get_collision_shape(&self) -> Option<&[CollisionBox]> {
+        self.collision_shape.as_deref()
+    }
+
+    /// Tags this block was declared with. Use [`crate::registry::tag::BlockTags`] to look up every block with a tag.
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// The tool needed to harvest this block, if any.
+    pub fn get_tool(&self) -> Option<ToolType> {
+        self.tool
+    }
+
+    /// Whether breaking this block with `tool` (None for bare hands) gives the block back.
+    pub fn can_harvest(&self, tool: Option<&Tool>) -> bool {
+        match (self.tool, tool) {
+            (None, _) => true,
+            (Some(required), Some(tool)) => tool.kind == required && tool.level >= self.harvest_level,
+            (Some(_), None) => false,
+        }
+    }
 }
 impl RegistryObject for Block {
     fn get_id(&self) -> &str {
@@ -41,10 +90,81 @@ impl RegistryObject for Block {
 
     fn make_initial() -> Option<Self> {
         Some(Self {
-            id: String::from("air"),
+            id: String::from(AIR_ID),
             hardness: 0,
             default_state: BTreeMap::new(),
             states: vec![],
+            sound_group: None,
+            tool: None,
+            harvest_level: 0,
+            collision_shape: Some(vec![]),
+            tags: vec![],
         })
     }
-}
\ No newline at end of file
+}
+
+impl Registry<Block> {
+    /// Gets the canonical air block.
+    pub fn air(&self) -> &Block {
+        self.get_initial().expect("Block registry is always created with air.")
+    }
+}
+
+/// A plain full cube block with this id, for tests that need blocks without loading assets.
+#[cfg(test)]
+pub(crate) fn test_asset(id: &str) -> BlockAsset {
+    BlockAsset {
+        id: String::from(id),
+        hardness: 1,
+        states: vec![],
+        default_state: BTreeMap::new(),
+        models: vec![],
+        sound_group: None,
+        tool: None,
+        harvest_level: 0,
+        render_layer: Default::default(),
+        emissive: false,
+        collision_shape: None,
+        tags: vec![],
+    }
+}
+
+/// A frozen block registry with air and a [`test_asset`] block for each id.
+#[cfg(test)]
+pub(crate) fn test_registry(ids: &[&str]) -> Registry<Block> {
+    let mut registry = Registry::<Block>::new("block");
+    for id in ids {
+        registry.register(Block::from_asset(&test_asset(id))).unwrap();
+    }
+    registry.freeze();
+    registry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn harvesting_needs_the_right_tool_at_a_high_enough_level() {
+        // needs a stone pickaxe or better
+        let mut asset = test_asset("iron_ore");
+        asset.tool = Some(ToolType::Pickaxe);
+        asset.harvest_level = 1;
+        let ore = Block::from_asset(&asset);
+
+        let wood = Tool { kind: ToolType::Pickaxe, level: 0 };
+        let stone = Tool { kind: ToolType::Pickaxe, level: 1 };
+        let iron = Tool { kind: ToolType::Pickaxe, level: 2 };
+        let stone_axe = Tool { kind: ToolType::Axe, level: 1 };
+        assert!(!ore.can_harvest(None));
+        assert!(!ore.can_harvest(Some(&wood)));
+        assert!(ore.can_harvest(Some(&stone)));
+        assert!(ore.can_harvest(Some(&iron)));
+        assert!(!ore.can_harvest(Some(&stone_axe)));
+
+        // blocks without a tool drop whatever they're broken with
+        let dirt = Block::from_asset(&test_asset("dirt"));
+        assert!(dirt.can_harvest(None));
+        assert!(dirt.can_harvest(Some(&wood)));
+    }
+}
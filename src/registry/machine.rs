@@ -1,5 +1,6 @@
 
 /// represents a "Machine", i.e. a component that can interact with other machines.
+#[allow(dead_code)]
 pub trait Machine {
     
 }
\ No newline at end of file
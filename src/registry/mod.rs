@@ -1,11 +1,18 @@
 pub mod block;
+pub mod tag;
+pub mod overrides;
 mod machine;
 
 use crate::asset::block::BlockAsset;
 use crate::core::errors::RegistryError;
+use crate::core::loading::LoadingTimings;
 use crate::core::state::LoadingState;
 use crate::core::AllBlockAssets;
 use crate::registry::block::Block;
+use crate::registry::overrides::BlockOverrideConfig;
+use crate::registry::tag::BlockTags;
+use crate::world::block::BlockStateTable;
+use crate::RunConfig;
 use bevy::prelude::*;
 use std::collections::hash_map::Iter;
 use std::collections::HashMap;
@@ -22,7 +29,8 @@ impl Plugin for RegistryPlugin {
         app
             // .insert_resource(BlockRegistry::new())
             .insert_resource(Registry::<Block>::new("block"))
-            .add_systems(OnEnter(LoadingState::Registries), create_block_registry)
+            .init_resource::<BuiltinBlocks>()
+            .add_systems(OnEnter(LoadingState::Registries), create_block_registry.pipe(exit_on_error))
             .add_systems(OnExit(LoadingState::Registries), freeze_registries)
         ;
     }
@@ -49,22 +57,35 @@ pub trait RegistryObject {
 pub struct Registry<T: RegistryObject> {
     name: String,
     map: HashMap<String, T>,
+    /// Ids sorted by name, so each object has a stable numeric index. Only filled in once the registry is frozen.
+    indices: Vec<String>,
+    /// Id of the value from [`RegistryObject::make_initial`], if there is one.
+    initial: Option<String>,
     frozen: bool,
 }
 
 impl <T: RegistryObject> Registry<T> {
     pub fn new(name: &str) -> Self {
         let mut map = HashMap::new();
+        let mut initial_id = None;
         if let Some(initial) = T::make_initial() {
+            initial_id = Some(initial.get_id().to_string());
             map.insert(initial.get_id().to_string(), initial);
         }
         Self {
             name: name.to_string(),
             map,
+            indices: Vec::new(),
+            initial: initial_id,
             frozen: false,
         }
     }
 
+    /// Gets the initial value this registry was created with, e.g. air for blocks.
+    pub fn get_initial(&self) -> Option<&T> {
+        self.map.get(self.initial.as_ref()?)
+    }
+
     pub fn register(&mut self, obj: T) -> std::result::Result<(), RegistryError> {
         let id = obj.get_id();
         if self.frozen {
@@ -83,6 +104,14 @@ impl <T: RegistryObject> Registry<T> {
         self.map.get(id)
     }
 
+    /// Gets an object to change it. None if the id doesn't exist, or the registry is frozen.
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut T> {
+        if self.frozen {
+            return None;
+        }
+        self.map.get_mut(id)
+    }
+
     pub fn iter(&self) -> Iter<'_, String, T> {
         self.map.iter()
     }
@@ -92,8 +121,55 @@ impl <T: RegistryObject> Registry<T> {
     }
 
     pub fn freeze(&mut self) {
+        let mut indices = self.map.keys().cloned().collect::<Vec<_>>();
+        indices.sort();
+        self.indices = indices;
         self.frozen = true;
     }
+
+    /// Gets the numeric index of an id. None if the id doesn't exist or the registry isn't frozen yet.
+    pub fn get_index(&self, id: &str) -> Option<usize> {
+        self.indices.binary_search_by(|i| i.as_str().cmp(id)).ok()
+    }
+
+    /// Gets an object by its numeric index. None if the index doesn't exist or the registry isn't frozen yet.
+    pub fn get_by_index(&self, index: usize) -> Option<&T> {
+        self.map.get(self.indices.get(index)?)
+    }
+}
+
+
+/// Blocks that come from code instead of a `.block.ron` file. They're registered before the asset blocks,
+/// and an asset block with the same id fails loading like any other duplicate.
+/// Built-in blocks have no models, since models are only read from block assets.
+#[derive(Resource, Debug, Default)]
+pub struct BuiltinBlocks {
+    blocks: Vec<Block>,
+}
+impl BuiltinBlocks {
+    pub fn add(&mut self, block: Block) {
+        self.blocks.push(block);
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+/// Lets plugins add built-in blocks while the app is being built, see [`BuiltinBlocks`].
+pub trait RegisterBuiltinBlock {
+    fn register_builtin_block(&mut self, block: Block) -> &mut Self;
+}
+impl RegisterBuiltinBlock for App {
+    fn register_builtin_block(&mut self, block: Block) -> &mut Self {
+        // works whether or not the registry plugin has been added yet
+        self.world_mut().get_resource_or_init::<BuiltinBlocks>().add(block);
+        self
+    }
 }
 
 
@@ -150,29 +226,62 @@ impl <T: RegistryObject> Deref for RegistryHandle<T> {
 //===============
 fn create_block_registry(
     mut block_reg: ResMut<Registry<Block>>,
+    mut builtin_blocks: ResMut<BuiltinBlocks>,
     all_block_handles: Res<AllBlockAssets>,
     block_asset: Res<Assets<BlockAsset>>,
     mut next_load_state: ResMut<NextState<LoadingState>>,
+    mut timings: ResMut<LoadingTimings>,
 ) -> Result<(), BevyError> {
 
-    info!("Creating block registry.");
-
+    // built-in blocks first, so a block file reusing one of their ids is the one reported as a duplicate
+    let builtin = std::mem::take(&mut builtin_blocks.blocks);
+    let builtin_count = builtin.len();
+    for block in builtin {
+        block_reg.register(block)?;
+    }
     for h in all_block_handles.inner.iter() {
         let block = Block::from_asset(block_asset.get(h).unwrap());
         block_reg.register(block)?;
     }
+    timings.set_count(LoadingState::Registries, "blocks registered", builtin_count + all_block_handles.inner.len());
     next_load_state.set(LoadingState::Textures);
 
     Ok(())
 }
 
+// loading can't continue with a broken registry, so quit instead of sitting in the loading state forever
+fn exit_on_error(
+    In(result): In<Result<(), BevyError>>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    if let Err(e) = result {
+        error!("Could not create the block registry: {}", e);
+        exit.write(AppExit::error());
+    }
+}
 
-// freezes registries, moving them to ReadOnlyRegistry resources which are backed by an arc
+
+// freezes registries, moving them to ReadOnlyRegistry resources which are backed by an arc.
+// Also applies block overrides from the config, interns every blockstate now that block indices are known, and groups blocks by tag.
 fn freeze_registries(
     world: &mut World
-) {
+) -> Result<(), BevyError> {
     // old writeable registry is removed from the world, and replaced with a Read Only Registry that is backed by an arc.
     let mut old_reg = world.remove_resource::<Registry<Block>>().unwrap();
+    if let Some(run_config) = world.get_resource::<RunConfig>() {
+        let overrides = BlockOverrideConfig::load_or_default(&run_config.config_dir);
+        let changed = overrides.apply(&mut old_reg);
+        if changed > 0 {
+            info!("Overrode {} blocks from the config.", changed);
+        }
+    }
     old_reg.freeze();
+    let table = BlockStateTable::from_registry(&old_reg)?;
+    info!("Interned {} blockstates.", table.len());
+    let tags = BlockTags::from_registry(&old_reg);
+    info!("Found {} block tags.", tags.len());
+    world.insert_resource(table);
+    world.insert_resource(tags);
     world.insert_resource(RegistryHandle::new(old_reg));
+    Ok(())
 }
\ No newline at end of file
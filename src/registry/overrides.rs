@@ -0,0 +1,117 @@
+use crate::registry::block::Block;
+use crate::registry::Registry;
+use bevy::asset::ron;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Changes to blocks read from the config folder, so mining times can be tuned without editing block files.
+/// Applied to the block registry right before it's frozen, over whatever the block assets say.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlockOverrideConfig {
+    /// Block id to the hardness it should have instead.
+    #[serde(default)]
+    pub hardness: BTreeMap<String, u32>,
+    /// Blocks that break straight away in survival, as if their hardness was 0. Takes precedence over `hardness`.
+    #[serde(default)]
+    pub instant_mine: Vec<String>,
+}
+
+impl BlockOverrideConfig {
+    pub const FILE_NAME: &'static str = "block_overrides.ron";
+
+    /// Reads the overrides from the config folder. Overrides nothing if there isn't a file.
+    pub fn load(config_dir: &Path) -> Result<Self, BevyError> {
+        let file = config_dir.join(Self::FILE_NAME);
+        if !file.exists() {
+            return Ok(Self::default());
+        }
+        let bytes = fs::read(file)?;
+        Ok(ron::de::from_bytes(bytes.as_slice())?)
+    }
+
+    /// Like [`BlockOverrideConfig::load`], but overrides nothing if the file can't be used.
+    pub fn load_or_default(config_dir: &Path) -> Self {
+        match Self::load(config_dir) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Could not load block overrides, using none: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Changes the blocks in a registry that isn't frozen yet. Ids that aren't registered are skipped with a warning.
+    /// Returns how many blocks were changed.
+    pub fn apply(&self, registry: &mut Registry<Block>) -> usize {
+        let mut changed = 0;
+        for (id, hardness) in self.hardness.iter() {
+            if self.instant_mine.contains(id) {
+                continue;
+            }
+            match registry.get_mut(id) {
+                Some(block) => {
+                    block.set_hardness(*hardness);
+                    changed += 1;
+                }
+                None => warn!("Can't override the hardness of {}, as it isn't a registered block.", id),
+            }
+        }
+        for id in self.instant_mine.iter() {
+            match registry.get_mut(id) {
+                Some(block) => {
+                    block.set_hardness(0);
+                    changed += 1;
+                }
+                None => warn!("Can't make {} instant mine, as it isn't a registered block.", id),
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::block::BlockAsset;
+    use crate::registry::block::test_asset;
+    use crate::world::player::GameMode;
+
+    #[test]
+    fn overrides_apply_before_freezing() {
+        let mut registry = Registry::<Block>::new("block");
+        for id in ["stone", "dirt", "grass"] {
+            registry.register(Block::from_asset(&BlockAsset { hardness: 4, ..test_asset(id) })).unwrap();
+        }
+        let config = BlockOverrideConfig {
+            hardness: BTreeMap::from([(String::from("stone"), 2), (String::from("dirt"), 9), (String::from("missing"), 1)]),
+            instant_mine: vec![String::from("dirt")],
+        };
+        assert_eq!(config.apply(&mut registry), 2);
+        registry.freeze();
+
+        let stone = registry.get("stone").unwrap();
+        let grass = registry.get("grass").unwrap();
+        let dirt = registry.get("dirt").unwrap();
+        assert_eq!(stone.get_hardness(), 2);
+        assert_eq!(grass.get_hardness(), 4);
+        assert!(GameMode::Survival.get_break_duration(stone, None) < GameMode::Survival.get_break_duration(grass, None));
+        // instant mine wins over the hardness override
+        assert_eq!(dirt.get_hardness(), 0);
+        assert_eq!(GameMode::Survival.get_break_duration(dirt, None), 0.0);
+    }
+
+    #[test]
+    fn loading_without_a_file_writes_nothing() {
+        let config_dir = std::env::temp_dir().join(format!("gtclone-overrides-{}", std::process::id()));
+        fs::create_dir_all(&config_dir).unwrap();
+        let file = config_dir.join(BlockOverrideConfig::FILE_NAME);
+        let _ = fs::remove_file(&file);
+
+        assert_eq!(BlockOverrideConfig::load(&config_dir).unwrap(), BlockOverrideConfig::default());
+        assert!(!file.exists());
+        let _ = fs::remove_dir_all(&config_dir);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::registry::block::Block;
+use crate::registry::{Registry, RegistryObject};
+use crate::world::block::{BlockState, BlockStateTable};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// Every block tag, and the ids of the blocks that have it. Built from the `tags` of each block when the registry is frozen,
+/// so it's only available as a resource once registries are done loading.
+///
+/// Tags are plain strings and don't need declaring anywhere, a tag exists as soon as one block has it.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct BlockTags {
+    tags: HashMap<String, HashSet<String>>,
+}
+
+impl BlockTags {
+    /// Blocks that get overwritten when placing a block into them, like tall grass. Air always is, without needing the tag.
+    pub const REPLACEABLE: &'static str = "replaceable";
+    /// Blocks that get a machine when placed, see [`crate::world::machine::MachineWorld`].
+    pub const MACHINE: &'static str = "machine";
+
+    /// Groups every block in the registry by its tags.
+    pub fn from_registry(registry: &Registry<Block>) -> Self {
+        let mut tags = BlockTags::default();
+        for (id, block) in registry.iter() {
+            for tag in block.get_tags() {
+                tags.add(tag, id);
+            }
+        }
+        tags
+    }
+
+    pub fn add(&mut self, tag: &str, id: &str) {
+        self.tags.entry(tag.to_string()).or_default().insert(id.to_string());
+    }
+
+    /// Whether the block with this id has the tag. False for unknown tags.
+    pub fn contains(&self, tag: &str, id: &str) -> bool {
+        self.tags.get(tag).is_some_and(|ids| ids.contains(id))
+    }
+
+    /// Shorthand for [`BlockTags::contains`] with a block instead of an id.
+    pub fn has_tag(&self, tag: &str, block: &Block) -> bool {
+        self.contains(tag, block.get_id())
+    }
+
+    /// Whether placing a block into this state overwrites it. True for air and anything tagged [`BlockTags::REPLACEABLE`].
+    pub fn is_replaceable(&self, state: &BlockState, table: &BlockStateTable) -> bool {
+        table.is_air_state(state) || self.contains(Self::REPLACEABLE, state.get_id())
+    }
+
+    /// Gets the ids of every block with this tag, or None if no block has it.
+    pub fn get(&self, tag: &str) -> Option<&HashSet<String>> {
+        self.tags.get(tag)
+    }
+
+    /// Iterates over every tag name, in no particular order.
+    pub fn tag_names(&self) -> impl Iterator<Item = &str> {
+        self.tags.keys().map(String::as_str)
+    }
+
+    /// How many different tags there are.
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::block::BlockAsset;
+    use crate::registry::block::test_asset;
+
+    #[test]
+    fn tags_group_blocks_from_the_registry() {
+        let tagged = |id: &str, tags: &[&str]| Block::from_asset(&BlockAsset { tags: tags.iter().map(|t| t.to_string()).collect(), ..test_asset(id) });
+        let mut registry = Registry::<Block>::new("block");
+        registry.register(tagged("tall_grass", &[BlockTags::REPLACEABLE, "plant"])).unwrap();
+        registry.register(tagged("flower", &["plant"])).unwrap();
+        registry.register(tagged("stone", &[])).unwrap();
+        registry.freeze();
+        let tags = BlockTags::from_registry(&registry);
+
+        assert_eq!(tags.len(), 2);
+        assert!(tags.contains("plant", "tall_grass"));
+        assert!(tags.contains("plant", "flower"));
+        assert!(tags.contains(BlockTags::REPLACEABLE, "tall_grass"));
+        assert!(!tags.contains(BlockTags::REPLACEABLE, "flower"));
+        assert!(!tags.contains("plant", "stone"));
+        // unknown tags and blocks are just false
+        assert!(!tags.contains("liquid", "stone"));
+        assert!(!tags.contains("plant", "cactus"));
+        assert_eq!(tags.get("plant").unwrap().len(), 2);
+        assert!(tags.get("liquid").is_none());
+    }
+}
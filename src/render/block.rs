@@ -1,44 +1,102 @@
-use crate::asset::block::{BlockModelAsset, BlockModelFace, FaceType};
+use crate::asset::block::{BlockModelAsset, BlockModelFace, FaceType, RenderLayer, Tint};
 use crate::core::errors::BlockModelError;
-use crate::core::errors::BlockModelError::{CircularDependency, InvalidFace, KeyNotFound};
+use crate::core::errors::BlockModelError::{CircularDependency, CircularTextureKey, InvalidFace, KeyNotFound};
 use crate::render::material::BlockMaterial;
-use arc_swap::ArcSwap;
-use bevy::asset::{Assets, Handle};
+use bevy::asset::{Assets, Handle, RenderAssetUsages};
 use bevy::image::Image;
-use bevy::math::{Vec2, Vec3};
-use bevy::prelude::Resource;
+use bevy::math::{IVec3, UVec3, Vec2, Vec3};
+use bevy::mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
+use bevy::prelude::{Mesh, Resource};
 use std::collections::{HashMap, HashSet};
 use std::slice::Iter;
 use std::sync::Arc;
-use crate::world::block::{BlockState, Direction};
+use crate::world::block::{BlockState, BlockStateId, BlockStateTable, Direction};
+use crate::world::chunk::ChunkData;
 
 #[derive(Debug, Clone)]
 pub struct BlockModelMinimal {
     faces: Vec<FaceMinimal>,
     full_sides: u8,
+    render_layer: RenderLayer,
+    /// (min, max) corners of a box around every face vertex, in block local coordinates.
+    bounds: (Vec3, Vec3),
 }
 
 impl BlockModelMinimal {
+    /// Creates an opaque model straight from faces, without going through model assets.
+    pub fn new(faces: Vec<FaceMinimal>, full_sides: &[Direction]) -> Self {
+        Self {
+            bounds: face_bounds(&faces),
+            faces,
+            full_sides: full_sides.iter().fold(0, |acc, dir| acc | full_side_bit(*dir)),
+            render_layer: RenderLayer::Opaque,
+        }
+    }
+
     pub fn face_iter(&self) -> Iter<'_, FaceMinimal> {
         self.faces.iter()
     }
 
+    pub fn get_faces(&self) -> &[FaceMinimal] {
+        &self.faces
+    }
+
+    pub fn get_render_layer(&self) -> RenderLayer {
+        self.render_layer
+    }
+
+    /// Gets the (min, max) corners of a box around the whole model, in block local coordinates.
+    /// Computed once when the model is created. Models without any faces have an empty box at the origin.
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        self.bounds
+    }
+
+    /// Copy of this model drawn in another layer. Models are shared between blocks, so the layer comes from the block, not the model.
+    pub fn with_render_layer(&self, render_layer: RenderLayer) -> Self {
+        Self {
+            render_layer,
+            ..self.clone()
+        }
+    }
+
+    /// Copy of this model with every face emissive, for blocks that glow as a whole.
+    pub fn with_emissive(&self) -> Self {
+        Self {
+            faces: self.faces.iter().map(|f| FaceMinimal { emissive: true, ..f.clone() }).collect(),
+            ..self.clone()
+        }
+    }
+
     // inner func. includes a set of visited models to track circular dependencies
     fn from_asset_rec(
         model_handle: &Handle<BlockModelAsset>, 
         block_model_asset: &Assets<BlockModelAsset>, 
         block_textures: &BlockTextures, 
         visited_models: &mut HashSet<Handle<BlockModelAsset>>,
-        texture_map: &mut HashMap<String, (Handle<Image>, String)>
+        texture_map: &mut HashMap<String, (Handle<Image>, String)>,
+        texture_aliases: &mut HashMap<String, String>,
+        smooth_normals: &mut Option<bool>,
     ) -> Result<BlockModelMinimal, BlockModelError> {
         let model = block_model_asset.get(model_handle).unwrap();
+        // same as textures, the child's setting wins
+        if smooth_normals.is_none() {
+            *smooth_normals = model.smooth_normals;
+        }
         
-        // add to texture map.
-        for (k, v) in model.texture_handles.iter() {
-            if texture_map.contains_key(k) {
+        // add to texture map. Children go first, so a key they set (as a texture or a `#key`) wins over the parent's.
+        for (k, v) in model.textures.iter() {
+            if texture_map.contains_key(k) || texture_aliases.contains_key(k) {
                 continue;
             }
-            texture_map.insert(k.clone(), (v.clone(), model.textures.get(k).unwrap().clone()));
+            match v.strip_prefix('#') {
+                Some(target) => {
+                    texture_aliases.insert(k.clone(), String::from(target));
+                }
+                None => {
+                    let handle = model.texture_handles.get(k).unwrap();
+                    texture_map.insert(k.clone(), (handle.clone(), v.clone()));
+                }
+            }
         }
         let mut face_vec = vec![];
         let mut full_sides: u8 = 0;
@@ -46,10 +104,10 @@ impl BlockModelMinimal {
         if let Some(parent) = &model.parent_handle {
             // if true, circular dependency, cannot create model.
             if visited_models.contains(parent) {
-                let parent_str = (&model.parent.as_ref().unwrap().clone()).clone();
+                let parent_str = model.parent.as_ref().unwrap().clone();
                 return Err(CircularDependency(parent_str));
             }
-            let rec = Self::from_asset_rec(parent, block_model_asset, block_textures, visited_models, texture_map)?;
+            let rec = Self::from_asset_rec(parent, block_model_asset, block_textures, visited_models, texture_map, texture_aliases, smooth_normals)?;
             (face_vec, full_sides) = (rec.faces, rec.full_sides);
         }
 
@@ -58,17 +116,12 @@ impl BlockModelMinimal {
         
         // construct new faces
         for face in model.faces.iter() {
-            // chcek if face texture is OK or not
-            let texture_handle = texture_map.get(&face.texture);
-            if texture_handle.is_none() {
-                return Err(KeyNotFound(face.texture.clone()).into());
-            }
-            let array_id = block_textures.get_texture_id(&texture_handle.unwrap().0.clone());
-            if array_id.is_none() {
-                let texture_path = texture_map.get(&face.texture).unwrap().1.clone();
-                return Err(KeyNotFound(texture_path).into());
-            }
-            let face = FaceMinimal::from_asset(face, array_id.unwrap())?;
+            let array_id = Self::resolve_texture(&face.texture, texture_map, texture_aliases, block_textures)?;
+            let overlay_id = match &face.overlay {
+                Some(overlay) => Some(Self::resolve_texture(overlay, texture_map, texture_aliases, block_textures)?),
+                None => None,
+            };
+            let face = FaceMinimal::from_asset(face, array_id, overlay_id)?;
             face_vec.push(face);
         }
         
@@ -84,40 +137,102 @@ impl BlockModelMinimal {
         for side in model.full_sides.iter() {
             match side {
                 Direction::Up => {
-                    full_sides = full_sides | (0b1)
+                    full_sides |= 0b1
                 }
                 Direction::Down => {
-                    full_sides = full_sides | (0b1 << 1)
+                    full_sides |= 0b1 << 1
                 }
                 Direction::North => {
-                    full_sides = full_sides | (0b1 << 2)
+                    full_sides |= 0b1 << 2
                 }
                 Direction::South => {
-                    full_sides = full_sides | (0b1 << 3)
+                    full_sides |= 0b1 << 3
                 }
                 Direction::East => {
-                    full_sides = full_sides | (0b1 << 4)
+                    full_sides |= 0b1 << 4
                 }
                 Direction::West => {
-                    full_sides = full_sides | (0b1 << 5)
+                    full_sides |= 0b1 << 5
                 }
             }
         }
 
 
         Ok(BlockModelMinimal {
+            bounds: face_bounds(&face_vec),
             faces: face_vec,
-            full_sides
+            full_sides,
+            render_layer: RenderLayer::Opaque,
         })
     }
 
+    // finds the array texture index of a texture key, following `#key` references
+    fn resolve_texture(
+        key: &str,
+        texture_map: &HashMap<String, (Handle<Image>, String)>,
+        texture_aliases: &HashMap<String, String>,
+        block_textures: &BlockTextures,
+    ) -> Result<u32, BlockModelError> {
+        let mut key = key.strip_prefix('#').unwrap_or(key);
+        // a chain longer than the number of aliases must go in a circle
+        let mut hops = 0;
+        while let Some(target) = texture_aliases.get(key) {
+            if hops == texture_aliases.len() {
+                return Err(CircularTextureKey(String::from(key)));
+            }
+            key = target;
+            hops += 1;
+        }
+        // chcek if face texture is OK or not
+        let Some((handle, texture_path)) = texture_map.get(key) else {
+            return Err(KeyNotFound(String::from(key)));
+        };
+        block_textures.get_texture_id(handle).ok_or_else(|| KeyNotFound(texture_path.clone()))
+    }
+
     pub fn from_asset(model: &Handle<BlockModelAsset>, block_model_asset: &Assets<BlockModelAsset>, block_textures: &BlockTextures) -> Result<Self, BlockModelError> {
         let mut set = HashSet::new();
         set.insert(model.clone());
-        Self::from_asset_rec(model, block_model_asset, block_textures, &mut set, &mut HashMap::new())
+        let mut smooth_normals = None;
+        let model = Self::from_asset_rec(model, block_model_asset, block_textures, &mut set, &mut HashMap::new(), &mut HashMap::new(), &mut smooth_normals)?;
+        Ok(match smooth_normals.unwrap_or(false) {
+            true => model.with_smooth_normals(),
+            false => model,
+        })
+    }
+
+    /// Copy of this model where every vertex gets the average normal of the faces touching it, so rounded models don't look faceted.
+    /// Vertices count as touching if they land on the same spot once packed, see [`POSITION_STEPS`].
+    pub fn with_smooth_normals(&self) -> Self {
+        let key = |pos: Vec3| (pos * POSITION_STEPS).round().as_ivec3();
+        let mut sums: HashMap<IVec3, Vec3> = HashMap::new();
+        for face in self.faces.iter() {
+            let normal = face.normal.normalize_or_zero();
+            // a face with the same corner in more than one polygon still only counts once there
+            let corners = face.vertices.iter().map(|v| key(v.position)).collect::<HashSet<_>>();
+            for corner in corners {
+                *sums.entry(corner).or_default() += normal;
+            }
+        }
+        let faces = self.faces.iter().map(|f| FaceMinimal {
+            vertices: f.vertices.iter().map(|v| Vertex {
+                // opposite faces can cancel out, those keep the face normal
+                normal: sums[&key(v.position)].try_normalize(),
+                ..v.clone()
+            }).collect(),
+            ..f.clone()
+        }).collect();
+        Self {
+            faces,
+            ..self.clone()
+        }
     }
     
     pub fn is_full(&self, direction: Direction) -> bool {
+        // neighbours can be seen through anything that isn't opaque, so it never culls them
+        if self.render_layer != RenderLayer::Opaque {
+            return false;
+        }
         match direction {
             Direction::Up => {
                 self.full_sides & (0b1) != 0
@@ -143,6 +258,54 @@ impl BlockModelMinimal {
     pub fn full_raw(&self) -> u8 {
         self.full_sides
     }
+
+    /// Creates a copy of this model rotated clockwise around Y (looking down) by 90° `quarter_turns` times,
+    /// so a model facing north faces east after one turn.
+    pub fn rotated_y(&self, quarter_turns: u32) -> Self {
+        let mut full_sides = 0;
+        for dir in Direction::ALL {
+            if self.full_sides & full_side_bit(dir) != 0 {
+                full_sides |= full_side_bit(dir.rotate_y(quarter_turns));
+            }
+        }
+        let faces = self.faces.iter().map(|f| f.rotated_y(quarter_turns)).collect::<Vec<_>>();
+        Self {
+            bounds: face_bounds(&faces),
+            faces,
+            full_sides,
+            render_layer: self.render_layer,
+        }
+    }
+}
+
+// box around every vertex of these faces
+fn face_bounds(faces: &[FaceMinimal]) -> (Vec3, Vec3) {
+    let mut vertices = faces.iter().flat_map(|f| f.vertices.iter().map(|v| v.position));
+    let Some(first) = vertices.next() else {
+        return (Vec3::ZERO, Vec3::ZERO);
+    };
+    vertices.fold((first, first), |(min, max), v| (min.min(v), max.max(v)))
+}
+
+// the bit of a side in full_sides
+fn full_side_bit(direction: Direction) -> u8 {
+    match direction {
+        Direction::Up => 0b1,
+        Direction::Down => 0b1 << 1,
+        Direction::North => 0b1 << 2,
+        Direction::South => 0b1 << 3,
+        Direction::East => 0b1 << 4,
+        Direction::West => 0b1 << 5,
+    }
+}
+
+// rotates a vector clockwise around Y in exact 90° steps, so no float error creeps into model vertices.
+fn rotate_y(v: Vec3, quarter_turns: u32) -> Vec3 {
+    let mut v = v;
+    for _ in 0..quarter_turns % 4 {
+        v = Vec3::new(v.z, v.y, -v.x);
+    }
+    v
 }
 
 #[derive(Debug, Clone)]
@@ -151,19 +314,40 @@ pub struct FaceMinimal {
     normal: Vec3,
     indices: Vec<u32>,
     texture_index: u32,
+    overlay_index: Option<u32>,
+    /// packed RGBA8, see [`tint_color`]
+    tint: u32,
     cull_mode: Option<Direction>,
+    emissive: bool,
+    /// Set if the uvs follow the vertex positions, so the face can take its uvs from where it is in the chunk instead, see [`UvTiling`].
+    tiling: Option<UvTiling>,
 }
 
 impl FaceMinimal {
     pub fn from_asset(
         face: &BlockModelFace,
         texture_id: u32,
+        overlay_id: Option<u32>,
     ) -> Result<Self, BlockModelError> {
         let mut vertices = vec![];
         let mut indices = vec![];
+        if !face.uv_rotation.is_multiple_of(90) || face.uv_rotation >= 360 {
+            let message = format!("Expected uv rotation of 0, 90, 180 or 270, but found {}", face.uv_rotation);
+            return Err(InvalidFace(message));
+        }
+        // these would get clamped when packed, and the mesh would quietly come out wrong
+        if let Some(v) = face.vertices.iter().find(|v| !v.pos.cmpge(Vec3::ZERO).all() || !v.pos.cmple(Vec3::splat(MAX_MODEL_POSITION)).all()) {
+            let message = format!("Vertex position {} is outside of 0 to {}", v.pos, MAX_MODEL_POSITION);
+            return Err(InvalidFace(message));
+        }
+        if let Some(v) = face.vertices.iter().find(|v| !v.uv.cmpge(Vec2::ZERO).all() || !v.uv.cmple(Vec2::ONE).all()) {
+            let message = format!("Vertex uv {} is outside of 0 to 1", v.uv);
+            return Err(InvalidFace(message));
+        }
+        let uv = |uv: Vec2| transform_uv(uv, face.uv_rotation / 90, face.uv_flip);
         match face.face_type {
             FaceType::Quad => {
-                if face.vertices.len() % 4 != 0 {
+                if !face.vertices.len().is_multiple_of(4) {
                     //todo: make Err type
                     let message = format!("Expected multiple of 4 vertices for face type quad, but found {} vertices", face.vertices.len());
                     return Err(InvalidFace(message));
@@ -171,18 +355,19 @@ impl FaceMinimal {
                 vertices = face.vertices
                     .iter()
                     .map(|v|
-                        Vertex { position: v.pos, uv0: v.uv }
+                        Vertex { position: v.pos, uv0: uv(v.uv), normal: None }
                     )
                     .collect::<Vec<Vertex>>();
                 for i in 0..face.vertices.len() / 4 {
+                    let i = i as u32 * 4;
                     let mut one_quad = vec![
-                        i as u32, i as u32 + 1, i as u32 + 2, i as u32, i as u32 + 2, i as u32 + 3
+                        i, i + 1, i + 2, i, i + 2, i + 3
                     ];
                     indices.append(&mut one_quad);
                 }
             }
             FaceType::Triangle => {
-                if face.vertices.len() % 3 != 0 {
+                if !face.vertices.len().is_multiple_of(3) {
                     //todo: make Err type
                     let message = format!("Expected multiple of 3 vertices for face type triangle, but found {} vertices", face.vertices.len());
                     return Err(InvalidFace(message));
@@ -190,7 +375,7 @@ impl FaceMinimal {
                 vertices = face.vertices
                     .iter()
                     .map(|v|
-                        Vertex { position: v.pos, uv0: v.uv }
+                        Vertex { position: v.pos, uv0: uv(v.uv), normal: None }
                     )
                     .collect::<Vec<Vertex>>();
                 indices = (0..vertices.len())
@@ -198,12 +383,17 @@ impl FaceMinimal {
                     .collect::<Vec<u32>>();
             }
         }
+        let (vertices, indices) = dedup_face_vertices(vertices, indices);
         Ok(Self {
+            tiling: UvTiling::find(&vertices),
             vertices,
             indices,
             normal: face.normal,
             texture_index: texture_id,
-            cull_mode: face.cull_mode
+            overlay_index: overlay_id,
+            tint: tint_color(face.tint),
+            cull_mode: face.get_cull_mode(),
+            emissive: face.emissive,
         })
     }
 
@@ -211,20 +401,275 @@ impl FaceMinimal {
         self.cull_mode
     }
 
-    /// Constructs a tuple for mesh creation of (position, uv0, normal, indices, texture_indices)
-    pub fn get_face_data(&self, chunk_pos: Vec3, index_offset: u32) -> (Vec<[f32; 3]>, Vec<[f32; 2]>, Vec<[f32; 3]>, Vec<u32>, Vec<u32>) {
-        let mut pos = vec![];
-        let mut uv0 = vec![];
-        let mut normal = vec![];
-        let indices = self.indices.iter().map(|n| n + index_offset).collect::<Vec<u32>>();
-        let mut texture_indices = vec![];
+    pub fn get_texture_index(&self) -> u32 {
+        self.texture_index
+    }
+
+    pub fn get_overlay_index(&self) -> Option<u32> {
+        self.overlay_index
+    }
+
+    pub fn get_tint(&self) -> u32 {
+        self.tint
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emissive
+    }
+
+    /// Appends this face's mesh data to existing buffers. Vertices with their own normal (see [`BlockModelMinimal::with_smooth_normals`])
+    /// use it for shading, the rest use the face's.
+    pub fn append_face_data(
+        &self,
+        chunk_pos: Vec3,
+        index_offset: u32,
+        out: &mut BlockVertexBuffers,
+    ) {
+        out.indices.extend(self.indices.iter().map(|n| n + index_offset));
+        let overlay = self.overlay_index.unwrap_or(BlockMaterial::NO_OVERLAY);
+        let normal = pack_normal(self.normal);
+        let light = match self.emissive {
+            true => pack_emissive_light(),
+            false => pack_light(baked_light(self.normal)),
+        };
         for vertex in self.vertices.iter() {
-            pos.push([chunk_pos.x + vertex.position.x, chunk_pos.y + vertex.position.y, chunk_pos.z + vertex.position.z]);
-            uv0.push([vertex.uv0.x, vertex.uv0.y]);
-            normal.push([self.normal.x, self.normal.y, self.normal.z]);
-            texture_indices.push(self.texture_index);
+            let position = chunk_pos + vertex.position;
+            out.positions.push(pack_position(position));
+            out.uv0s.push(pack_uv(self.tiling.map_or(vertex.uv0, |tiling| tiling.uv_at(position))));
+            match vertex.normal {
+                Some(n) => {
+                    out.normals.push(pack_normal(n));
+                    out.lights.push(if self.emissive { light } else { pack_light(baked_light(n)) });
+                }
+                None => {
+                    out.normals.push(normal);
+                    out.lights.push(light);
+                }
+            }
+            out.texture_ids.push(self.texture_index);
+            out.overlay_ids.push(overlay);
+            out.tints.push(self.tint);
         }
-        (pos, uv0, normal, indices, texture_indices)
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len()
+    }
+
+    /// Whether this face takes its uvs from its position in the chunk, see [`UvTiling`].
+    pub fn is_tiled(&self) -> bool {
+        self.tiling.is_some()
+    }
+
+    /// Rotates this face around the vertical center line of the block. See [`BlockModelMinimal::rotated_y`].
+    pub fn rotated_y(&self, quarter_turns: u32) -> Self {
+        let center = Vec3::new(0.5, 0.0, 0.5);
+        let vertices = self.vertices.iter()
+            .map(|v| Vertex {
+                position: rotate_y(v.position - center, quarter_turns) + center,
+                uv0: v.uv0,
+                normal: v.normal.map(|n| rotate_y(n, quarter_turns)),
+            })
+            .collect::<Vec<_>>();
+        Self {
+            // the uvs stay put while the positions turn, so they may not line up anymore
+            tiling: UvTiling::find(&vertices),
+            vertices,
+            normal: rotate_y(self.normal, quarter_turns),
+            indices: self.indices.clone(),
+            texture_index: self.texture_index,
+            overlay_index: self.overlay_index,
+            tint: self.tint,
+            cull_mode: self.cull_mode.map(|d| d.rotate_y(quarter_turns)),
+            emissive: self.emissive,
+        }
+    }
+}
+
+/// How a face's uvs follow its vertex positions: u and v are each one axis of the position, possibly mirrored (1 - position).
+/// Faces like this look the same if their uvs are taken from their position in the chunk instead, as the texture repeats every block.
+/// Neighbouring faces then have the same uvs where their corners meet, so chunk meshes can share those vertices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UvTiling {
+    u: (usize, bool),
+    v: (usize, bool),
+}
+
+impl UvTiling {
+    /// Mirrored axes count down from here, so uvs stay positive anywhere a chunk vertex can be. A whole number, so the texture lines up.
+    const MIRROR_ORIGIN: f32 = 64.0;
+
+    /// Finds the axes the uvs of these vertices follow, if they follow any.
+    fn find(vertices: &[Vertex]) -> Option<Self> {
+        // compared at the precision uvs are packed with
+        let matches = |axis: usize, mirrored: bool, uv: fn(&Vertex) -> f32| vertices.iter().all(|v| {
+            let expected = if mirrored { 1.0 - v.position[axis] } else { v.position[axis] };
+            (expected - uv(v)).abs() < 0.5 / UV_STEPS
+        });
+        let candidates = (0..3).flat_map(|axis| [(axis, false), (axis, true)]);
+        let u = candidates.clone().find(|&(axis, mirrored)| matches(axis, mirrored, |v| v.uv0.x))?;
+        let v = candidates.filter(|(axis, _)| *axis != u.0).find(|&(axis, mirrored)| matches(axis, mirrored, |v| v.uv0.y))?;
+        Some(Self { u, v })
+    }
+
+    /// Uv of a vertex at this position in the chunk. Only differs from the model's uv by whole textures.
+    fn uv_at(&self, position: Vec3) -> Vec2 {
+        let along = |(axis, mirrored): (usize, bool)| if mirrored { Self::MIRROR_ORIGIN - position[axis] } else { position[axis] };
+        Vec2::new(along(self.u), along(self.v))
+    }
+}
+
+// Merges the corners a face repeats, like the shared edge of two quads in one face, and remaps the indices to match.
+// Vertices of different faces are merged later, by the chunk mesh, see [`UvTiling`].
+// Positions and uvs are compared at the precision they're packed with, so vertices that end up identical in the mesh count as the same.
+fn dedup_face_vertices(vertices: Vec<Vertex>, indices: Vec<u32>) -> (Vec<Vertex>, Vec<u32>) {
+    let mut seen: HashMap<(IVec3, [u16; 2]), u32> = HashMap::new();
+    let mut unique = Vec::with_capacity(vertices.len());
+    let remap = vertices.into_iter().map(|v| {
+        *seen.entry(((v.position * POSITION_STEPS).round().as_ivec3(), pack_uv(v.uv0))).or_insert_with(|| {
+            unique.push(v);
+            unique.len() as u32 - 1
+        })
+    }).collect::<Vec<u32>>();
+    let indices = indices.into_iter().map(|i| remap[i as usize]).collect();
+    (unique, indices)
+}
+
+/// Mirrors `uv` horizontally if `flip` is set, then rotates it clockwise around the middle of the texture.
+pub fn transform_uv(uv: Vec2, quarter_turns: u32, flip: bool) -> Vec2 {
+    let mut uv = if flip { Vec2::new(1.0 - uv.x, uv.y) } else { uv };
+    for _ in 0..quarter_turns % 4 {
+        uv = Vec2::new(1.0 - uv.y, uv.x);
+    }
+    uv
+}
+
+/// Brightness of faces pointing straight up, down, along x and along z.
+/// Gives blocks some shading without a lighting pass: tops are lit like the sun is overhead, and sides are darker.
+pub const LIGHT_UP: f32 = 1.0;
+pub const LIGHT_DOWN: f32 = 0.5;
+pub const LIGHT_X: f32 = 0.6;
+pub const LIGHT_Z: f32 = 0.8;
+
+/// Gets the baked light of a face from its normal. Slanted faces blend between the axis values.
+pub fn baked_light(normal: Vec3) -> f32 {
+    let n = normal.normalize_or_zero();
+    // faces without a normal just get full light
+    if n == Vec3::ZERO {
+        return LIGHT_UP;
+    }
+    let vertical = if n.y > 0.0 { LIGHT_UP } else { LIGHT_DOWN };
+    n.x * n.x * LIGHT_X + n.y * n.y * vertical + n.z * n.z * LIGHT_Z
+}
+
+/// How many steps packed positions have per block. Model vertices get snapped to the nearest step. Must match the shader.
+pub const POSITION_STEPS: f32 = 16.0;
+const POSITION_BITS: u32 = 10;
+const POSITION_MASK: u32 = (1 << POSITION_BITS) - 1;
+/// Largest coordinate a model vertex can have and still be packed, for a block on the far edge of a chunk.
+pub const MAX_MODEL_POSITION: f32 = POSITION_MASK as f32 / POSITION_STEPS - (ChunkData::CHUNK_SIZE - 1) as f32;
+
+/// Packs a position inside a chunk into a single u32, 10 bits per axis with x in the lowest bits.
+/// Each axis is stored in [`POSITION_STEPS`] of a block, so 0 to [`ChunkData::CHUNK_SIZE`](crate::world::chunk::ChunkData::CHUNK_SIZE) fits with room to spare.
+/// Anything that doesn't fit is clamped, models with vertices that wouldn't fit are rejected when they load. Unpacked again in the vertex shader.
+pub fn pack_position(pos: Vec3) -> u32 {
+    let steps = (pos * POSITION_STEPS).round().clamp(Vec3::ZERO, Vec3::splat(POSITION_MASK as f32)).as_uvec3();
+    steps.x | steps.y << POSITION_BITS | steps.z << (2 * POSITION_BITS)
+}
+
+/// Unpacks a position packed by [`pack_position`]. Same as `unpack_position` in the block shader.
+pub fn unpack_position(packed: u32) -> Vec3 {
+    let steps = UVec3::new(packed & POSITION_MASK, (packed >> POSITION_BITS) & POSITION_MASK, (packed >> (2 * POSITION_BITS)) & POSITION_MASK);
+    steps.as_vec3() / POSITION_STEPS
+}
+
+/// Packs a normal as snorm8, the last component is unused.
+pub fn pack_normal(normal: Vec3) -> [i8; 4] {
+    let n = (normal.normalize_or_zero() * 127.0).round();
+    [n.x as i8, n.y as i8, n.z as i8, 0]
+}
+
+/// How many steps packed uvs have per texture. Must match the shader.
+pub const UV_STEPS: f32 = 512.0;
+
+/// Packs a uv into two u16s, in [`UV_STEPS`] of a texture. Uvs past 1 repeat the texture, which tiled faces use (see [`UvTiling`]).
+/// Anything outside 0 to `u16::MAX / UV_STEPS` is clamped. Unpacked again in the vertex shader.
+pub fn pack_uv(uv: Vec2) -> [u16; 2] {
+    let uv = (uv * UV_STEPS).round().clamp(Vec2::ZERO, Vec2::splat(u16::MAX as f32));
+    [uv.x as u16, uv.y as u16]
+}
+
+/// Unpacks a uv packed by [`pack_uv`]. Same as the block shader.
+pub fn unpack_uv(packed: [u16; 2]) -> Vec2 {
+    Vec2::new(packed[0] as f32, packed[1] as f32) / UV_STEPS
+}
+
+/// Packs baked light as an unorm8 color, see [`baked_light`]. Alpha marks emissive faces, so it's 0 here.
+pub fn pack_light(light: f32) -> [u8; 4] {
+    let l = (light.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [l, l, l, 0]
+}
+
+/// Light of emissive faces. Full alpha tells the shader to skip lighting for them.
+pub fn pack_emissive_light() -> [u8; 4] {
+    [u8::MAX; 4]
+}
+
+/// Tint of untinted faces. Multiplying by white changes nothing.
+pub const NO_TINT: u32 = 0xFFFFFFFF;
+
+/// Gets the color of a tint, packed as RGBA8 with red in the lowest byte.
+/// There are no biomes yet, so every biome uses the same colors for now.
+pub fn tint_color(tint: Option<Tint>) -> u32 {
+    let rgb = match tint {
+        None => return NO_TINT,
+        Some(Tint::Grass) => [0x91, 0xBD, 0x59],
+        Some(Tint::Foliage) => [0x77, 0xAB, 0x2F],
+    };
+    u32::from_le_bytes([rgb[0], rgb[1], rgb[2], 0xFF])
+}
+
+/// Vertex data of a chunk mesh, in the packed layout [`BlockMaterial`] expects. Faces are added with [`FaceMinimal::append_face_data`].
+#[derive(Debug, Default, Clone)]
+pub struct BlockVertexBuffers {
+    /// Positions relative to the chunk, see [`pack_position`].
+    pub positions: Vec<u32>,
+    pub uv0s: Vec<[u16; 2]>,
+    pub normals: Vec<[i8; 4]>,
+    pub indices: Vec<u32>,
+    pub texture_ids: Vec<u32>,
+    pub overlay_ids: Vec<u32>,
+    pub tints: Vec<u32>,
+    /// Baked light as a vertex color, see [`baked_light`].
+    pub lights: Vec<[u8; 4]>,
+}
+
+impl BlockVertexBuffers {
+    pub fn clear(&mut self) {
+        self.positions.clear();
+        self.uv0s.clear();
+        self.normals.clear();
+        self.indices.clear();
+        self.texture_ids.clear();
+        self.overlay_ids.clear();
+        self.tints.clear();
+        self.lights.clear();
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn into_mesh(self) -> Mesh {
+        Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD)
+            .with_inserted_attribute(BlockMaterial::ATTRIBUTE_PACKED_POSITION, self.positions)
+            .with_inserted_attribute(BlockMaterial::ATTRIBUTE_ARRAY_ID, self.texture_ids)
+            .with_inserted_attribute(BlockMaterial::ATTRIBUTE_PACKED_UV, VertexAttributeValues::Uint16x2(self.uv0s))
+            .with_inserted_attribute(BlockMaterial::ATTRIBUTE_PACKED_NORMAL, VertexAttributeValues::Snorm8x4(self.normals))
+            .with_inserted_attribute(BlockMaterial::ATTRIBUTE_OVERLAY_ID, self.overlay_ids)
+            .with_inserted_attribute(BlockMaterial::ATTRIBUTE_TINT, self.tints)
+            .with_inserted_attribute(BlockMaterial::ATTRIBUTE_LIGHT, VertexAttributeValues::Unorm8x4(self.lights))
+            .with_inserted_indices(Indices::U32(self.indices))
     }
 }
 
@@ -232,22 +677,369 @@ impl FaceMinimal {
 struct Vertex {
     position: Vec3,
     uv0: Vec2,
+    /// Overrides the face normal when set, for smooth shading.
+    normal: Option<Vec3>,
 }
 
 #[derive(Resource, Debug, Clone, Default)]
 pub struct MeshDataCache {
-    pub inner: Arc<HashMap<BlockState, BlockModelMinimal>>
+    /// Models indexed by [`BlockStateId`]. None for states without a model, like air.
+    models: Arc<Vec<Option<BlockModelMinimal>>>,
+    table: BlockStateTable,
+    /// Goes up every time the models are swapped out, so systems can tell when chunks need to be meshed again.
+    version: u64,
+}
+
+impl MeshDataCache {
+    pub fn new(table: BlockStateTable, models: Vec<Option<BlockModelMinimal>>) -> Self {
+        Self {
+            models: Arc::new(models),
+            table,
+            version: 0,
+        }
+    }
+
+    /// Replaces every model, e.g. after they were reloaded, and bumps the version.
+    /// Meshing tasks that already cloned the cache keep using the old models.
+    pub fn swap(&mut self, table: BlockStateTable, models: Vec<Option<BlockModelMinimal>>) {
+        self.models = Arc::new(models);
+        self.table = table;
+        self.version += 1;
+    }
+
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    /// The table the models are indexed by.
+    pub fn get_table(&self) -> &BlockStateTable {
+        &self.table
+    }
+
+    /// Gets the model of a blockstate. Prefer [`MeshDataCache::get_model_by_id`] when the id is already known.
+    /// None for air and for invisible blocks, which have no models at all.
+    pub fn get_model(&self, state: &BlockState) -> Option<&BlockModelMinimal> {
+        self.get_model_by_id(self.table.get_id(state)?)
+    }
+
+    pub fn get_model_by_id(&self, id: BlockStateId) -> Option<&BlockModelMinimal> {
+        self.models.get(id.get_index())?.as_ref()
+    }
+
+    /// Checks if a blockstate has a model, i.e. it's visible when placed.
+    pub fn has_model(&self, state: &BlockState) -> bool {
+        self.get_model(state).is_some()
+    }
 }
 
 #[derive(Debug, Default, Clone, Resource)]
 pub struct BlockTextures {
+    /// Array texture layer of each texture. Keyed by the texture alone: uv rotation and flipping only change a face's uvs,
+    /// so faces showing the same texture mirrored or turned still share one layer.
     pub map: HashMap<Handle<Image>, u32>,
     pub array_texture: Handle<Image>,
-    pub material: Handle<BlockMaterial>,
+    pub materials: HashMap<RenderLayer, Handle<BlockMaterial>>,
+    /// Average luminance of each texture, indexed by array texture id.
+    pub luminance: Vec<f32>,
 }
 
 impl BlockTextures {
+    /// The material chunk meshes of this layer are drawn with.
+    pub fn get_material(&self, layer: RenderLayer) -> Handle<BlockMaterial> {
+        self.materials.get(&layer).cloned().unwrap_or_default()
+    }
+
+    /// Checks if the array texture and a material for every layer have been created.
+    /// Until then [`BlockTextures::get_material`] hands out default handles, which draw nothing.
+    pub fn is_ready(&self) -> bool {
+        self.array_texture != Handle::default()
+            && RenderLayer::ALL.iter().all(|layer| self.materials.get(layer).is_some_and(|m| *m != Handle::default()))
+    }
+
+    pub fn get_luminance(&self, texture_id: u32) -> Option<f32> {
+        self.luminance.get(texture_id as usize).copied()
+    }
+
     pub fn get_texture_id(&self, name: &Handle<Image>) -> Option<u32> {
         self.map.get(name).cloned()
     }
-}
\ No newline at end of file
+}
+/// A [`BlockModelFace`] with the given vertices and every optional field left out, textured with `"all"`.
+#[cfg(test)]
+pub(crate) fn test_face(face_type: FaceType, vertices: &[(Vec3, Vec2)], normal: Vec3) -> BlockModelFace {
+    use crate::asset::block::{CullMode, ModelVertex};
+
+    BlockModelFace {
+        face_type,
+        vertices: vertices.iter().map(|&(pos, uv)| ModelVertex { pos, uv }).collect(),
+        normal,
+        texture: String::from("all"),
+        cull_mode: CullMode::Inferred,
+        overlay: None,
+        tint: None,
+        uv_rotation: 0,
+        uv_flip: false,
+        emissive: false,
+    }
+}
+
+/// A full unit cube, each face flush with its side so it can be culled.
+#[cfg(test)]
+pub(crate) fn test_cube_model() -> BlockModelMinimal {
+    use crate::math::block::BlockPos;
+    use bevy::math::vec2;
+
+    let faces = Direction::ALL.map(|dir| {
+        let normal = IVec3::ZERO.offset(dir).as_vec3();
+        let base = normal.max(Vec3::ZERO);
+        let (u, v) = match dir {
+            Direction::Up | Direction::Down => (Vec3::X, Vec3::Z),
+            Direction::North | Direction::South => (Vec3::X, Vec3::Y),
+            Direction::East | Direction::West => (Vec3::Y, Vec3::Z),
+        };
+        let vertices = [(base, vec2(0.0, 0.0)), (base + u, vec2(1.0, 0.0)), (base + u + v, vec2(1.0, 1.0)), (base + v, vec2(0.0, 1.0))];
+        FaceMinimal::from_asset(&test_face(FaceType::Quad, &vertices, normal), 0, None).unwrap()
+    });
+    BlockModelMinimal::new(faces.to_vec(), &Direction::ALL)
+}
+
+/// A [`MeshDataCache`] for the states of `registry`, where the listed blocks (in their default state) get the given models.
+#[cfg(test)]
+pub(crate) fn test_mesh_cache(registry: &crate::registry::Registry<crate::registry::block::Block>, models: &[(&str, BlockModelMinimal)]) -> MeshDataCache {
+    let table = BlockStateTable::from_registry(registry).unwrap();
+    let mut by_id = vec![None; table.len()];
+    for (id, model) in models {
+        let state = BlockState::new(id, registry).unwrap();
+        by_id[table.get_id(&state).unwrap().get_index()] = Some(model.clone());
+    }
+    MeshDataCache::new(table, by_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::asset::ron;
+    use bevy::math::{vec2, vec3};
+
+    #[test]
+    fn quads_in_one_face_share_their_edge() {
+        // two quads side by side on the top of a block, sharing the edge at x = 0.5
+        let face = test_face(FaceType::Quad, &[
+            (vec3(0.0, 1.0, 1.0), vec2(0.0, 1.0)), (vec3(0.5, 1.0, 1.0), vec2(0.5, 1.0)), (vec3(0.5, 1.0, 0.0), vec2(0.5, 0.0)), (vec3(0.0, 1.0, 0.0), vec2(0.0, 0.0)),
+            (vec3(0.5, 1.0, 1.0), vec2(0.5, 1.0)), (vec3(1.0, 1.0, 1.0), vec2(1.0, 1.0)), (vec3(1.0, 1.0, 0.0), vec2(1.0, 0.0)), (vec3(0.5, 1.0, 0.0), vec2(0.5, 0.0)),
+        ], Vec3::Y);
+        let face = FaceMinimal::from_asset(&face, 0, None).unwrap();
+        assert_eq!(face.vertex_count(), 6);
+        assert_eq!(face.indices.len(), 12);
+        // the second quad still covers the right half
+        let second = face.indices[6..].iter().map(|&i| face.vertices[i as usize].position).collect::<Vec<_>>();
+        assert!(second.iter().all(|p| p.x >= 0.5));
+        assert!(second.contains(&vec3(1.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn different_uvs_are_not_merged() {
+        let face = test_face(FaceType::Triangle, &[
+            (vec3(0.0, 0.0, 0.0), vec2(0.0, 0.0)), (vec3(1.0, 0.0, 0.0), vec2(1.0, 0.0)), (vec3(0.0, 0.0, 1.0), vec2(0.0, 1.0)),
+            (vec3(0.0, 0.0, 0.0), vec2(1.0, 1.0)), (vec3(1.0, 0.0, 0.0), vec2(1.0, 0.0)), (vec3(1.0, 0.0, 1.0), vec2(1.0, 1.0)),
+        ], Vec3::NEG_Y);
+        let face = FaceMinimal::from_asset(&face, 0, None).unwrap();
+        // only (1, 0, 0) with the same uv repeats
+        assert_eq!(face.vertex_count(), 5);
+        assert_eq!(face.indices, vec![0, 1, 2, 3, 1, 4]);
+    }
+
+    #[test]
+    fn vertices_that_cannot_be_packed_are_rejected() {
+        let outside = test_face(FaceType::Triangle, &[(vec3(-0.5, 0.0, 0.0), Vec2::ZERO), (Vec3::X, Vec2::X), (Vec3::Z, Vec2::Y)], Vec3::Y);
+        assert!(matches!(FaceMinimal::from_asset(&outside, 0, None), Err(InvalidFace(_))));
+        let stretched = test_face(FaceType::Triangle, &[(Vec3::ZERO, Vec2::ZERO), (Vec3::X, vec2(2.0, 0.0)), (Vec3::Z, Vec2::Y)], Vec3::Y);
+        assert!(matches!(FaceMinimal::from_asset(&stretched, 0, None), Err(InvalidFace(_))));
+
+        // the furthest a vertex can go still packs exactly, even on the edge of a chunk
+        let far = Vec3::splat(MAX_MODEL_POSITION);
+        let edge = test_face(FaceType::Triangle, &[(far, Vec2::ZERO), (Vec3::X, Vec2::X), (Vec3::Z, Vec2::Y)], Vec3::Y);
+        assert!(FaceMinimal::from_asset(&edge, 0, None).is_ok());
+        let corner = Vec3::splat((ChunkData::CHUNK_SIZE - 1) as f32) + far;
+        assert_ne!(pack_position(corner - Vec3::splat(1.0 / POSITION_STEPS)), pack_position(corner));
+        assert_eq!(pack_position(corner), POSITION_MASK | POSITION_MASK << POSITION_BITS | POSITION_MASK << (2 * POSITION_BITS));
+    }
+
+    #[test]
+    fn positions_round_trip_through_packing() {
+        let last_step = ChunkData::CHUNK_SIZE as u32 * POSITION_STEPS as u32;
+        for x in 0..=last_step {
+            for y in 0..=last_step {
+                for z in 0..=last_step {
+                    let pos = UVec3::new(x, y, z).as_vec3() / POSITION_STEPS;
+                    assert_eq!(unpack_position(pack_position(pos)), pos);
+                }
+            }
+        }
+
+        // the furthest a model vertex can go on the edge of a chunk is the largest position that packs
+        let corner = Vec3::splat((ChunkData::CHUNK_SIZE - 1) as f32 + MAX_MODEL_POSITION);
+        assert_eq!(unpack_position(pack_position(corner)), corner);
+        assert_eq!(unpack_position(pack_position(corner + Vec3::splat(1.0 / POSITION_STEPS))), corner);
+    }
+
+    #[test]
+    fn tiled_faces_only_shift_their_uvs_by_whole_textures() {
+        let out_of = |face: &FaceMinimal, chunk_pos: Vec3| {
+            let mut out = BlockVertexBuffers::default();
+            face.append_face_data(chunk_pos, 0, &mut out);
+            out.uv0s.iter().map(|&uv| unpack_uv(uv)).collect::<Vec<_>>()
+        };
+        // north side of a full block: u follows x, v runs down y
+        let side = test_face(FaceType::Quad, &[
+            (vec3(0.0, 0.0, 1.0), vec2(0.0, 1.0)), (vec3(1.0, 0.0, 1.0), vec2(1.0, 1.0)), (vec3(1.0, 1.0, 1.0), vec2(1.0, 0.0)), (vec3(0.0, 1.0, 1.0), vec2(0.0, 0.0)),
+        ], Vec3::Z);
+        let side = FaceMinimal::from_asset(&side, 0, None).unwrap();
+        assert!(side.is_tiled());
+        let at_origin = out_of(&side, Vec3::ZERO);
+        let moved = out_of(&side, vec3(5.0, 17.0, 3.0));
+        for (model, (a, b)) in side.vertices.iter().zip(at_origin.iter().zip(moved.iter())) {
+            let shift = *b - model.uv0;
+            assert_eq!(shift, shift.round(), "{shift}");
+            assert_eq!(*a - model.uv0, (*a - model.uv0).round());
+        }
+        // the shared edge of two neighbours gets the same uvs from both
+        assert_eq!(out_of(&side, Vec3::ZERO)[1], out_of(&side, Vec3::X)[0]);
+
+        // still tiled once turned, just along another axis
+        assert!(side.rotated_y(1).is_tiled());
+
+        // uvs from the middle of the texture don't follow the position
+        let inset = test_face(FaceType::Quad, &[
+            (vec3(0.0, 0.0, 1.0), vec2(0.25, 0.75)), (vec3(1.0, 0.0, 1.0), vec2(0.75, 0.75)), (vec3(1.0, 1.0, 1.0), vec2(0.75, 0.25)), (vec3(0.0, 1.0, 1.0), vec2(0.25, 0.25)),
+        ], Vec3::Z);
+        let inset = FaceMinimal::from_asset(&inset, 0, None).unwrap();
+        assert!(!inset.is_tiled());
+        assert_eq!(out_of(&inset, vec3(5.0, 17.0, 3.0)), inset.vertices.iter().map(|v| v.uv0).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn quarter_turned_uvs_rotate_clockwise() {
+        let vertices = [(vec3(0.0, 0.0, 1.0), vec2(0.0, 1.0)), (vec3(1.0, 0.0, 1.0), vec2(1.0, 1.0)), (vec3(1.0, 1.0, 1.0), vec2(1.0, 0.0)), (vec3(0.0, 1.0, 1.0), vec2(0.0, 0.0))];
+        let turned = |uv_rotation: u32, uv_flip: bool| {
+            let face = BlockModelFace { uv_rotation, uv_flip, ..test_face(FaceType::Quad, &vertices, Vec3::Z) };
+            FaceMinimal::from_asset(&face, 0, None).unwrap()
+        };
+        let uvs = |face: &FaceMinimal| face.vertices.iter().map(|v| (v.position, v.uv0)).collect::<Vec<_>>();
+
+        // the bottom left corner of the texture goes to the top left, the top left to the top right, and so on
+        let expected = [vec2(0.0, 0.0), vec2(0.0, 1.0), vec2(1.0, 1.0), vec2(1.0, 0.0)];
+        let quarter = turned(90, false);
+        assert_eq!(uvs(&quarter), vertices.iter().map(|v| v.0).zip(expected).collect::<Vec<_>>());
+        // four quarter turns are none at all, and mirroring goes first
+        assert_eq!(uvs(&turned(0, false)), vertices.to_vec());
+        assert!(vertices.iter().all(|&(_, uv)| transform_uv(uv, 4, false) == uv));
+        assert_eq!(uvs(&turned(90, true)), vertices.iter().map(|&(pos, uv)| (pos, transform_uv(vec2(1.0 - uv.x, uv.y), 1, false))).collect::<Vec<_>>());
+
+        // the chunk mesh keeps the turn, only shifted by whole textures
+        let mut out = BlockVertexBuffers::default();
+        quarter.append_face_data(vec3(3.0, 4.0, 5.0), 0, &mut out);
+        for (packed, uv) in out.uv0s.iter().zip(expected) {
+            let shift = unpack_uv(*packed) - uv;
+            assert_eq!(shift, shift.round());
+        }
+    }
+
+    #[test]
+    fn grass_sides_carry_an_overlay_and_the_grass_tint() {
+        let model = ron::de::from_str::<BlockModelAsset>(include_str!("../../assets/model/block/grass_block.model.ron")).unwrap();
+        for face in model.faces.iter() {
+            let side = face.get_cull_mode().unwrap();
+            let expected_tint = match side {
+                Direction::Down => None,
+                _ => Some(Tint::Grass),
+            };
+            assert_eq!(face.tint, expected_tint, "{side:?}");
+            assert_eq!(face.overlay.is_some(), !matches!(side, Direction::Up | Direction::Down), "{side:?}");
+
+            let overlay_id = face.overlay.as_ref().map(|_| 7);
+            let minimal = FaceMinimal::from_asset(face, 3, overlay_id).unwrap();
+            assert_eq!(minimal.get_texture_index(), 3);
+            assert_eq!(minimal.get_overlay_index(), overlay_id);
+            assert_eq!(minimal.get_tint(), tint_color(expected_tint));
+        }
+    }
+
+    #[test]
+    fn emissive_faces_are_marked_full_bright() {
+        let lights_of = |face: &FaceMinimal| {
+            let mut out = BlockVertexBuffers::default();
+            face.append_face_data(Vec3::ZERO, 0, &mut out);
+            out.lights
+        };
+        let vertices = [(vec3(0.0, 0.0, 1.0), vec2(0.0, 1.0)), (vec3(1.0, 0.0, 1.0), vec2(1.0, 1.0)), (vec3(1.0, 1.0, 1.0), vec2(1.0, 0.0)), (vec3(0.0, 1.0, 1.0), vec2(0.0, 0.0))];
+        let normal = FaceMinimal::from_asset(&test_face(FaceType::Quad, &vertices, Vec3::Z), 0, None).unwrap();
+        let glowing = FaceMinimal::from_asset(&BlockModelFace { emissive: true, ..test_face(FaceType::Quad, &vertices, Vec3::Z) }, 0, None).unwrap();
+        assert!(!normal.is_emissive());
+        assert!(glowing.is_emissive());
+
+        // the alpha is what tells the shader to skip lighting
+        assert!(lights_of(&normal).iter().all(|&light| light == pack_light(baked_light(Vec3::Z)) && light[3] == 0));
+        assert!(lights_of(&glowing).iter().all(|&light| light == pack_emissive_light() && light[3] == u8::MAX));
+
+        // and blocks that glow as a whole mark every face
+        let model = BlockModelMinimal::new(vec![normal], &[]).with_emissive();
+        assert!(model.face_iter().all(|face| face.is_emissive() && lights_of(face).iter().all(|light| light[3] == u8::MAX)));
+    }
+
+    #[test]
+    fn tops_bake_brighter_than_bottoms() {
+        let model = test_cube_model();
+        let light_of = |dir: Direction| {
+            let face = model.face_iter().find(|f| f.get_cull_mode() == Some(dir)).unwrap();
+            let mut out = BlockVertexBuffers::default();
+            face.append_face_data(Vec3::ZERO, 0, &mut out);
+            // flat faces are lit the same all over
+            assert!(out.lights.iter().all(|&light| light == out.lights[0]), "{dir:?}");
+            out.lights[0][0]
+        };
+        let (top, bottom) = (light_of(Direction::Up), light_of(Direction::Down));
+        assert_eq!(top, u8::MAX);
+        for side in [Direction::North, Direction::South, Direction::East, Direction::West] {
+            assert!(top > light_of(side) && light_of(side) > bottom, "{side:?}");
+        }
+        // a slope facing up sits between the top and its side
+        let slope = baked_light(vec3(0.0, 1.0, 1.0));
+        assert!(slope < LIGHT_UP && slope > LIGHT_Z);
+    }
+
+    #[test]
+    fn rotating_a_smooth_model_rotates_its_normals() {
+        let north = test_face(FaceType::Quad, &[
+            (vec3(0.0, 0.0, 1.0), vec2(0.0, 1.0)), (vec3(1.0, 0.0, 1.0), vec2(1.0, 1.0)), (vec3(1.0, 1.0, 1.0), vec2(1.0, 0.0)), (vec3(0.0, 1.0, 1.0), vec2(0.0, 0.0)),
+        ], Vec3::Z);
+        let top = test_face(FaceType::Quad, &[
+            (vec3(0.0, 1.0, 1.0), vec2(0.0, 1.0)), (vec3(1.0, 1.0, 1.0), vec2(1.0, 1.0)), (vec3(1.0, 1.0, 0.0), vec2(1.0, 0.0)), (vec3(0.0, 1.0, 0.0), vec2(0.0, 0.0)),
+        ], Vec3::Y);
+        let faces = vec![FaceMinimal::from_asset(&north, 0, None).unwrap(), FaceMinimal::from_asset(&top, 0, None).unwrap()];
+        let model = BlockModelMinimal {
+            bounds: face_bounds(&faces),
+            faces,
+            full_sides: 0,
+            render_layer: RenderLayer::Opaque,
+        };
+
+        let rotated = model.with_smooth_normals().rotated_y(1);
+        // the north face now faces east
+        assert_eq!(rotated.faces[0].normal, Vec3::X);
+        assert_eq!(rotated.faces[1].normal, Vec3::Y);
+        let normal_at = |model: &BlockModelMinimal, pos: Vec3| {
+            model.faces.iter().flat_map(|f| f.vertices.iter()).find(|v| v.position == pos).and_then(|v| v.normal).unwrap()
+        };
+        // the top north edge went to the top east edge, and its normal went with it
+        assert!(normal_at(&rotated, vec3(1.0, 1.0, 1.0)).abs_diff_eq((Vec3::X + Vec3::Y).normalize(), 1e-6));
+        assert!(normal_at(&rotated, vec3(1.0, 0.0, 1.0)).abs_diff_eq(Vec3::X, 1e-6));
+        assert!(normal_at(&rotated, vec3(0.0, 1.0, 1.0)).abs_diff_eq(Vec3::Y, 1e-6));
+
+        // same as smoothing after rotating
+        let smoothed_after = model.rotated_y(1).with_smooth_normals();
+        for vertex in rotated.faces.iter().flat_map(|f| f.vertices.iter()) {
+            assert!(vertex.normal.unwrap().abs_diff_eq(normal_at(&smoothed_after, vertex.position), 1e-6), "{}", vertex.position);
+        }
+    }
+}
@@ -1,184 +1,202 @@
-use crate::render::block::{BlockModelMinimal, FaceMinimal, MeshDataCache};
-use crate::render::material::BlockMaterial;
-use crate::world::chunk;
+use crate::asset::block::RenderLayer;
+use crate::render::block::{BlockModelMinimal, BlockVertexBuffers, MeshDataCache};
+use crate::core::profiling::{PipelineProfiler, PipelineStage};
 use crate::world::chunk::ChunkData;
-use bevy::asset::RenderAssetUsages;
 use bevy::log::info_span;
-use bevy::math::{vec3, IVec3};
-use bevy::prelude::{info, ivec3, Mesh};
+use bevy::math::IVec3;
+use bevy::prelude::{debug, ivec3, Mesh};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::time::Instant;
-use bevy::mesh::{Indices, PrimitiveTopology};
-use crate::world::block::{BlockState, Direction};
-
-#[derive(Debug, Copy, Clone, PartialEq)]
-enum Facing {
-    North, // +z
-    South, // -z
-    East, // +x
-    West, // -x
-    Up, // +y
-    Down, // -y
+use crate::world::block::Direction;
+
+
+/// The six chunks around the one being meshed, indexed by [`Direction`] like [`crate::world::block::ChunkMap::neighbors`].
+pub type NeighborData<'a> = [&'a ChunkData; 6];
+
+thread_local! {
+    // meshing runs on the compute pool, so every thread gets its own scratch buffers. No locking needed.
+    static MESH_SCRATCH: RefCell<MeshScratch> = RefCell::new(MeshScratch::default());
 }
 
-impl From<Direction> for Facing {
-    fn from(value: Direction) -> Self {
-        match value {
-            Direction::Up => Facing::Up,
-            Direction::Down => Facing::Down,
-            Direction::North => Facing::North,
-            Direction::South => Facing::South,
-            Direction::East => Facing::East,
-            Direction::West => Facing::West,
+/// Buffers reused between calls of [`create_chunk_mesh_with`], so meshing doesn't reallocate them for every chunk.
+/// Contents are meaningless between calls, they're cleared at the start of every mesh.
+#[derive(Debug, Default)]
+pub struct MeshScratch {
+    vertices: BlockVertexBuffers,
+    /// (local pos, palette id, culled sides)
+    cull_info: Vec<(IVec3, usize, u8)>,
+    /// (local pos, palette id, face index)
+    faces: Vec<(IVec3, usize, usize)>,
+    seen: HashMap<VertexKey, u32>,
+    remap: Vec<u32>,
+}
+
+impl MeshScratch {
+    fn clear(&mut self) {
+        self.cull_info.clear();
+        self.faces.clear();
+        self.clear_vertices();
+    }
+
+    // only clears what's needed to build another mesh from the same faces
+    fn clear_vertices(&mut self) {
+        self.vertices.clear();
+        self.seen.clear();
+        self.remap.clear();
+    }
+
+    /// Merges identical vertices (same position, uv, normal, textures, tint and light) of the whole chunk into one, and remaps indices to match.
+    /// Tiled faces of neighbouring blocks share their corners this way, see [`crate::render::block::UvTiling`].
+    /// Order of the remaining vertices is kept, so the first occurrence of a vertex is the one that's kept.
+    fn dedup_vertices(&mut self) -> BlockVertexBuffers {
+        let mut out = BlockVertexBuffers::default();
+        let v = &mut self.vertices;
+
+        // everything is already packed into integers, so vertices can be hashed directly
+        for i in 0..v.vertex_count() {
+            let key = (v.positions[i], v.uv0s[i], v.normals[i], v.texture_ids[i], v.overlay_ids[i], v.tints[i], v.lights[i]);
+            let new_index = *self.seen.entry(key).or_insert_with(|| {
+                out.positions.push(v.positions[i]);
+                out.uv0s.push(v.uv0s[i]);
+                out.normals.push(v.normals[i]);
+                out.texture_ids.push(v.texture_ids[i]);
+                out.overlay_ids.push(v.overlay_ids[i]);
+                out.tints.push(v.tints[i]);
+                out.lights.push(v.lights[i]);
+                out.positions.len() as u32 - 1
+            });
+            self.remap.push(new_index);
         }
+
+        // mesh takes ownership of the index buffer, so it's moved out rather than copied.
+        out.indices = std::mem::take(&mut v.indices);
+        for index in out.indices.iter_mut() {
+            *index = self.remap[*index as usize];
+        }
+        out
     }
 }
 
-pub type NeighborData<'a> = (&'a ChunkData, &'a ChunkData, &'a ChunkData, &'a ChunkData, &'a ChunkData, &'a ChunkData);
+type VertexKey = (u32, [u16; 2], [i8; 4], u32, u32, u32, [u8; 4]);
 
+/// Creates a chunk's meshes, using this thread's scratch buffers.
+/// There's one mesh per render layer, layers without any faces are left out.
 pub fn create_chunk_mesh(
     chunk: &ChunkData,
     cache: &MeshDataCache,
-    neighbors: NeighborData
-) -> Mesh {
+    neighbors: NeighborData,
+    profiler: &PipelineProfiler,
+) -> Vec<(RenderLayer, Mesh)> {
+    MESH_SCRATCH.with_borrow_mut(|scratch| create_chunk_mesh_with(chunk, cache, neighbors, scratch, profiler))
+}
 
-    let _span = info_span!("create_chunk_mesh").entered();
+pub fn create_chunk_mesh_with(
+    chunk: &ChunkData,
+    cache: &MeshDataCache,
+    neighbors: NeighborData,
+    scratch: &mut MeshScratch,
+    profiler: &PipelineProfiler,
+) -> Vec<(RenderLayer, Mesh)> {
 
-    let model_map = cache.inner.as_ref();
-    
-    let mut positions = Vec::<[f32; 3]>::with_capacity(ChunkData::BLOCKS_PER_CHUNK);
-    let mut uv0s = Vec::<[f32; 2]>::with_capacity(ChunkData::BLOCKS_PER_CHUNK);
-    let mut normals = Vec::<[f32; 3]>::with_capacity(ChunkData::BLOCKS_PER_CHUNK);
-    let mut indices = Vec::<u32>::with_capacity(ChunkData::BLOCKS_PER_CHUNK);
-    let mut texture_ids = Vec::<u32>::with_capacity(ChunkData::BLOCKS_PER_CHUNK);
+    let _span = info_span!("create_chunk_mesh").entered();
 
+    scratch.clear();
 
     //TODO: optimize in the case of single chunks (chunks made up of just one block)
 
     let now = Instant::now();
 
 
-    let mut indices_offset = 0;
-
-    let mut cull_info = Vec::new();
-
-    let mut faces: Vec<(IVec3, &FaceMinimal)> = Vec::with_capacity(1024);
-
-    // let (north, south, east, west, up, down) = (neighbors.0.clone(), neighbors.1.clone(), neighbors.2.clone(), neighbors.3.clone(), neighbors.4.clone(), neighbors.5.clone());
-    let (north, south, east, west, up, down) = neighbors;
 
     let _model_cache = info_span!("model_cache").entered();
 
-
-    // let _test_block_lookups = info_span!("test_block_lookups").entered();
-    // let mut something = 0;
-    // for i in 0..ChunkData::BLOCKS_PER_CHUNK {
-    //     for _ in 0..6 {
-    //         let id = chunk.block_at_index(i);
-    //         something += id;
-    //     }
-    // }
-    // drop(_test_block_lookups);
-    // // prevent all this from being otpimized out
-    // if something > 32768 {
-    //     info!("Something")
-    // }
-
     // precompute models for the palettes of this chunk and neighboring chunks.
     // Reduces number of CPU cache misses and time spent hashing BlockStates 
     // as indexing a linear data structure is significantly faster. 
-    let mut models: [Vec<Option<&BlockModelMinimal>>; 7] = [
-        Vec::with_capacity(chunk.palette_len()),
-        Vec::with_capacity(north.palette_len()),
-        Vec::with_capacity(south.palette_len()),
-        Vec::with_capacity(east.palette_len()),
-        Vec::with_capacity(west.palette_len()),
-        Vec::with_capacity(up.palette_len()),
-        Vec::with_capacity(down.palette_len()),
-    ];
-    setup_model_cache(&chunk, &mut models[0], &model_map);
-    setup_model_cache(&north, &mut models[1], &model_map);
-    setup_model_cache(&south, &mut models[2], &model_map);
-    setup_model_cache(&east, &mut models[3], &model_map);
-    setup_model_cache(&west, &mut models[4], &model_map);
-    setup_model_cache(&up, &mut models[5], &model_map);
-    setup_model_cache(&down, &mut models[6], &model_map);
-    let _after_model_cache = now.elapsed().as_secs_f64() * 1000.;
+    // Index 0 is this chunk, then each neighbor at 1 + its direction.
+    let mut models: [Vec<Option<&BlockModelMinimal>>; 7] = Default::default();
+    for (data, cache_models) in std::iter::once(chunk).chain(neighbors).zip(models.iter_mut()) {
+        cache_models.reserve(data.palette_len());
+        setup_model_cache(data, cache_models, cache);
+    }
 
     drop(_model_cache);
 
     let _cull_info = info_span!("cull_info").entered();
-    // Figures out cull info for non air blocks.
+    // Figures out cull info for blocks with a model.
+    // Everything past this point only indexes the precomputed models by palette id, BlockStates are never touched.
     for i in 0..ChunkData::BLOCKS_PER_CHUNK {
         let id = chunk.block_at_index(i);
 
-        let block_id = chunk.lookup_palette(id).unwrap();
-        if block_id.block.is_air() {
+        // air and other blocks without a model never make faces
+        if models[0][id].is_none() {
             continue;
         }
         let (x, y, z) = index_to_xyz(i);
         // let culled_sides = 0b00111111;
-        cull_info.push((ivec3(x as i32, y as i32, z as i32), &block_id.block, culled_sides(&chunk, x, y, z, neighbors, &models)));
+        scratch.cull_info.push((ivec3(x as i32, y as i32, z as i32), id, culled_sides(chunk, x, y, z, neighbors, &models)));
     }
-    let _after_first_loop = now.elapsed().as_secs_f64() * 1000.;
+    let after_cull = now.elapsed();
 
     drop(_cull_info);
 
     let _grab_faces = info_span!("grab_faces").entered();
     // grabs faces for non air blocks that shouldn't be culled
-    for (pos, block, cull_info) in cull_info {
-        let Some(block_model) = model_map.get(block) else {
+    for (pos, id, cull_info) in scratch.cull_info.iter() {
+        let Some(block_model) = models[0][*id] else {
             continue;
         };
-        for face in block_model.face_iter() {
-            if let Some(dir) = face.get_cull_mode() {
-                if should_skip(dir, cull_info) {
-                    continue;
-                }
+        for (face_idx, face) in block_model.face_iter().enumerate() {
+            if let Some(dir) = face.get_cull_mode()
+                && should_skip(dir, *cull_info)
+            {
+                continue;
             }
-            faces.push((pos, face));
+            scratch.faces.push((*pos, *id, face_idx));
         }
     }
-    let _after_second_loop = now.elapsed().as_secs_f64() * 1000.;
+    let after_face_gather = now.elapsed();
 
     drop(_grab_faces);
 
-    let _make_face_data = info_span!("make_face_data").entered();
-    // creates face data and sticks it into vecs
-    for (pos, face) in faces {
-        let (
-            mut face_pos,
-            mut face_uv0,
-            mut face_normal,
-            mut face_index,
-            mut face_texture_ids
-        ) = face.get_face_data(pos.as_vec3(), indices_offset);
-
-        indices_offset += face_pos.len() as u32;
-
-        positions.append(&mut face_pos);
-        uv0s.append(&mut face_uv0);
-        normals.append(&mut face_normal);
-        indices.append(&mut face_index);
-        texture_ids.append(&mut face_texture_ids);
-    }
-    let _after_third_loop = now.elapsed().as_secs_f64() * 1000.;
-
-    drop(_make_face_data);
-    
-    // creates the chunk mesh
-    let ret = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD)
-        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
-        .with_inserted_attribute(BlockMaterial::ATTRIBUTE_ARRAY_ID, texture_ids)
-        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uv0s)
-        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
-        .with_inserted_indices(Indices::U32(indices));
-
-    let end = now.elapsed().as_secs_f64() * 1000.0;
-    if end > 10.0 {
-        // info!("Took {end} ms to mesh.\nModel cache took {}.First loop took {}, second loop took {}, third loop took {}.", after_model_cache, after_first_loop - after_model_cache, after_second_loop - after_first_loop, after_third_loop - after_second_loop);    
+    let mut ret = Vec::with_capacity(RenderLayer::ALL.len());
+    for layer in RenderLayer::ALL {
+        scratch.clear_vertices();
+        let mut indices_offset = 0;
+
+        let _make_face_data = info_span!("make_face_data").entered();
+        // creates face data for this layer and sticks it into vecs
+        for (pos, id, face_idx) in scratch.faces.iter() {
+            let model = models[0][*id].unwrap();
+            if model.get_render_layer() != layer {
+                continue;
+            }
+            let face = &model.get_faces()[*face_idx];
+            face.append_face_data(pos.as_vec3(), indices_offset, &mut scratch.vertices);
+            indices_offset += face.vertex_count() as u32;
+        }
+        drop(_make_face_data);
+
+        let vertices_before = scratch.vertices.vertex_count();
+        if vertices_before == 0 {
+            continue;
+        }
+
+        let _dedup = info_span!("dedup_vertices").entered();
+        let vertices = scratch.dedup_vertices();
+        debug!("Vertex dedup ({:?}): {} -> {} vertices", layer, vertices_before, vertices.vertex_count());
+        drop(_dedup);
+
+        // creates the chunk mesh for this layer
+        ret.push((layer, vertices.into_mesh()));
     }
 
+    let end = now.elapsed();
+    profiler.record(PipelineStage::Cull, after_cull);
+    profiler.record(PipelineStage::FaceGather, after_face_gather - after_cull);
+    profiler.record(PipelineStage::BufferBuild, end - after_face_gather);
+
     ret
 }
 
@@ -196,147 +214,50 @@ fn should_skip(dir: Direction, cull_info: u8) -> bool {
 fn setup_model_cache<'a>(
     chunk: &ChunkData,
     list: &mut Vec<Option<&'a BlockModelMinimal>>,
-    model_map: &'a HashMap<BlockState, BlockModelMinimal>
+    cache: &'a MeshDataCache,
 ) {
     for entry in 0..chunk.palette_len() {
         let state = &chunk.lookup_palette(entry).unwrap().block;
-        let model = model_map.get(&state);
+        let model = cache.get_model(state);
         list.push(model);
     }
 }
 
 
 
-type FaceData = ([[f32; 3]; 4], [[f32; 2]; 4], [[f32; 3]; 4], [u32; 6]);
-
-// outputs vertex specific data for this block and face
-#[deprecated]
-fn face_data(facing: Facing) -> FaceData {
-    match facing {
-        Facing::North => (
-            [ [0., 0., 1.], [0., 1., 1.], [1., 1., 1.], [1., 0., 1.], ],
-            [ [0.0, 1.0], [0.0, 0.0], [1.0, 0.0], [1.0, 1.0], ],
-            [ [0.0, 0.0, 1.0], [0.0, 0.0, 1.0], [0.0, 0.0, 1.0], [0.0, 0.0, 1.0], ],
-            [ 0,3,1, 1,3,2, ],
-        ),
-        Facing::South => (
-            [ [0., 0., 0.], [0., 1., 0.], [1., 1., 0.], [1., 0., 0.], ],
-            [ [0.0, 1.0], [0.0, 0.0], [1.0, 0.0], [1.0, 1.0], ],
-            [ [0.0, 0.0, -1.0], [0.0, 0.0, -1.0], [0.0, 0.0, -1.0], [0.0, 0.0, -1.0], ],
-            [ 0,1,3, 1,2,3, ],
-        ),
-        Facing::East => (
-            [ [1., 0., 0.], [1., 0., 1.], [1., 1., 1.], [1., 1., 0.], ],
-            [ [1.0, 1.0], [0.0, 1.0], [0.0, 0.0], [1.0, 0.0], ],
-            [ [1.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0, 0.0], ],
-            [ 0,3,1, 1,3,2, ],
-        ),
-        Facing::West => (
-            [ [0., 0., 0.], [0., 0., 1.], [0., 1., 1.], [0., 1., 0.], ],
-            [ [1.0, 1.0], [0.0, 1.0], [0.0, 0.0], [1.0, 0.0], ],
-            [ [-1.0, 0.0, 0.0], [-1.0, 0.0, 0.0], [-1.0, 0.0, 0.0], [-1.0, 0.0, 0.0], ],
-            [ 0,1,3, 1,2,3 ],
-        ),
-        Facing::Up => (
-            [ [0., 1., 0.], [1., 1., 0.], [1., 1., 1.], [0., 1., 1.], ],
-            [ [0.0, 1.0], [0.0, 0.0], [1.0, 0.0], [1.0, 1.0] ],
-            [ [0.0, 1.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 0.0], [0.0, 1.0, 0.0], ],
-            [ 0,3,1, 1,3,2 ],
-        ),
-        Facing::Down => (
-            [ [0., 0., 0.], [1., 0., 0.], [1., 0., 1.], [0., 0., 1.], ],
-            [ [0.0, 1.0], [0.0, 0.0], [1.0, 0.0], [1.0, 1.0], ],
-            [ [0.0, -1.0, 0.0], [0.0, -1.0, 0.0], [0.0, -1.0, 0.0], [0.0, -1.0, 0.0], ],
-            [ 0,1,3, 1,2,3 ]
-        ),
-    }
-}
 
-#[deprecated]
-fn should_make_face(
-    facing: Facing,
+/// Palette id of the block next to (x, y, z) in `dir`, looking into the neighbouring chunk if it's across the chunk boundary.
+/// Also returns which chunk the id belongs to: 0 for `chunk`, then 1 + the direction for neighbors,
+/// the same order as the model cache in [`create_chunk_mesh_with`].
+#[inline]
+fn block_in_direction(
     chunk: &ChunkData,
+    neighbors: NeighborData,
     x: usize, y: usize, z: usize,
-    neighbors: Option<NeighborData>,
-    model_map: &HashMap<BlockState, BlockModelMinimal>
-) -> bool {
-
+    dir: Direction,
+) -> (usize, usize) {
     let last = ChunkData::CHUNK_SIZE - 1;
-    
-    if neighbors.is_none() {
-        match facing {
-            Facing::North => {
-                if(z == last) { return true; };
-            }
-            Facing::South => {
-                if(z == 0) { return true; };
-            }
-            Facing::East => {
-                if(x == last) { return true; };
-            }
-            Facing::West => {
-                if(x == 0) { return true; };
-            }
-            Facing::Up => {
-                if(y == last) { return true; };
-            }
-            Facing::Down => {
-                if(y == 0) { return true; };
-            }
-        };
-    };
-
-    // get the value at new_pos
-    let (new_x, new_y, new_z) = new_block(facing, x as isize, y as isize, z as isize);
-
-    let (block, queried_chunk) = if neighbors.is_some() {
-        let (north, south, east, west, up, down) = neighbors.unwrap();
-
-        if new_z < 0 {
-            (south.block_at(new_x as usize, new_y as usize, last), south)
-        }
-        else if new_z > last as isize {
-            (north.block_at(new_x as usize, new_y as usize, 0), north)
-        }
-        else if new_x < 0 {
-            (west.block_at(last, new_y as usize, new_z as usize), west)
-        }
-        else if new_x > last as isize {
-            (east.block_at(0, new_y as usize, new_z as usize), east)
-        }
-        else if new_y < 0 {
-            (down.block_at(new_x as usize, last, new_z as usize), down)
-        }
-        else if new_y > last as isize {
-            (up.block_at(new_x as usize, 0, new_z as usize), up)
-        }
-        else {
-            (chunk.block_at(new_x as usize, new_y as usize, new_z as usize), chunk)
-        }
-    }
-    else {
-        (chunk.block_at(new_x as usize, new_y as usize, new_z as usize), chunk)
-    };
-    
-    let queried_side = match facing {
-        Facing::North => Direction::South,
-        Facing::South => Direction::North,
-        Facing::East => Direction::West,
-        Facing::West => Direction::East,
-        Facing::Up => Direction::Down,
-        Facing::Down => Direction::Up,
-    };
-    
-    let state = &queried_chunk.lookup_palette(block).unwrap().block;
-    let model = model_map.get(&state);
-    // no model? treat like air
-    if model.is_none() {
-        return true;
+    let neighbor = neighbors[dir as usize];
+    let source = 1 + dir as usize;
+    match dir {
+        Direction::North if z == last => (neighbor.block_at(x, y, 0), source),
+        Direction::North => (chunk.block_at(x, y, z + 1), 0),
+        Direction::South if z == 0 => (neighbor.block_at(x, y, last), source),
+        Direction::South => (chunk.block_at(x, y, z - 1), 0),
+        Direction::East if x == last => (neighbor.block_at(0, y, z), source),
+        Direction::East => (chunk.block_at(x + 1, y, z), 0),
+        Direction::West if x == 0 => (neighbor.block_at(last, y, z), source),
+        Direction::West => (chunk.block_at(x - 1, y, z), 0),
+        Direction::Up if y == last => (neighbor.block_at(x, 0, z), source),
+        Direction::Up => (chunk.block_at(x, y + 1, z), 0),
+        Direction::Down if y == 0 => (neighbor.block_at(x, last, z), source),
+        Direction::Down => (chunk.block_at(x, y - 1, z), 0),
     }
-    
-    !model.unwrap().is_full(queried_side)
 }
 
+// order of the bits in culled sides, same as should_skip
+const CULL_ORDER: [Direction; 6] = [Direction::North, Direction::South, Direction::East, Direction::West, Direction::Up, Direction::Down];
+
 // TODO: Current bottleneck when it comes to meshing. Speed this up dramatically somehow.
 fn culled_sides(
     chunk: &ChunkData,
@@ -344,98 +265,30 @@ fn culled_sides(
     neighbors: NeighborData,
     model_map: &[Vec<Option<&BlockModelMinimal>>; 7]
 ) -> u8 {
-    let last = ChunkData::CHUNK_SIZE - 1;
-    let (north, south, east, west, up, down) = neighbors;
-
-    let (id_north, q_north) = if z == last {
-        (north.block_at(x, y, 0), 1)
-    } else {
-        (chunk.block_at(x, y, z + 1), 0)
-    };
-
-    let (id_south, q_south) = if z == 0 {
-        (south.block_at(x, y, last), 2)
-    } else {
-        (chunk.block_at(x, y, z - 1), 0)
-    };
-
-    let (id_east, q_east) = if x == last {
-        (east.block_at(0, y, z), 3)
-    } else {
-        (chunk.block_at(x + 1, y, z), 0)
-    };
-
-    let (id_west, q_west) = if x == 0 {
-        (west.block_at(last, y, z), 4)
-    } else {
-        (chunk.block_at(x - 1, y, z), 0)
-    };
-
-    let (id_up, q_up) = if y == last {
-        (up.block_at(x, 0, z), 5)
-    } else {
-        (chunk.block_at(x, y + 1, z), 0)
-    };
-
-    let (id_down, q_down) = if y == 0 {
-        (down.block_at(x, last, z), 6)
-    } else {
-        (chunk.block_at(x, y - 1, z), 0)
-    };
-    
-    let m_north = &model_map[q_north][id_north];
-    let m_south = &model_map[q_south][id_south];
-    let m_east = &model_map[q_east][id_east];
-    let m_west = &model_map[q_west][id_west];
-    let m_up = &model_map[q_up][id_up];
-    let m_down = &model_map[q_down][id_down];
-
-
-
-    let cull_north = match m_north {
-        Some(model) => model.is_full(Direction::South),
-        None => false,
-    } as u8;
-    let cull_south = match m_south {
-        Some(model) => model.is_full(Direction::North),
-        None => false,
-    } as u8;
-    let cull_east = match m_east {
-        Some(model) => model.is_full(Direction::West),
-        None => false,
-    } as u8;
-    let cull_west = match m_west {
-        Some(model) => model.is_full(Direction::East),
-        None => false,
-    } as u8;
-    let cull_up = match m_up {
-        Some(model) => model.is_full(Direction::Down),
-        None => false,
-    } as u8;
-    let cull_down = match m_down {
-        Some(model) => model.is_full(Direction::Up),
-        None => false,
-    } as u8;
-    
-    (cull_north) | (cull_south << 1) | (cull_east << 2) | (cull_west << 3) | (cull_up << 4) | (cull_down << 5)
+    let mut culled = 0;
+    for (bit, dir) in CULL_ORDER.into_iter().enumerate() {
+        let (id, source) = block_in_direction(chunk, neighbors, x, y, z, dir);
+        // the neighbour's side that touches this block
+        let touching = match dir {
+            Direction::North => Direction::South,
+            Direction::South => Direction::North,
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+        };
+        // blocks without a model (air, but also invisible blocks like barriers) never hide their neighbours
+        if model_map[source][id].is_some_and(|model| model.is_full(touching)) {
+            culled |= 1 << bit;
+        }
+    }
+    culled
 }
 
 
 
 
 
-// no guarantee these are in bounds
-#[deprecated]
-fn new_block(facing: Facing, x: isize, y: isize, z: isize) -> (isize, isize, isize) {
-    match facing {
-        Facing::North => (x, y, z + 1),
-        Facing::South => (x, y, z - 1),
-        Facing::East =>  (x + 1, y, z),
-        Facing::West =>  (x - 1, y, z),
-        Facing::Up =>    (x, y + 1, z),
-        Facing::Down =>  (x, y - 1, z),
-    }
-}
 
 fn index_to_xyz(i: usize) -> (usize, usize, usize) {
     (
@@ -443,4 +296,184 @@ fn index_to_xyz(i: usize) -> (usize, usize, usize) {
         i / (ChunkData::CHUNK_SIZE * ChunkData::CHUNK_SIZE),
         i % ChunkData::CHUNK_SIZE
     )
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::block::BlockModelAsset;
+    use crate::registry::block::{test_registry, Block};
+    use crate::registry::Registry;
+    use crate::render::block::{test_cube_model, test_mesh_cache, FaceMinimal};
+    use crate::math::block::BlockPos;
+    use crate::world::block::BlockState;
+    use crate::world::generation::SineHeightMap;
+    use crate::world::pipeline::GenerationPipeline;
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+
+    // the models generated terrain uses, baked from the real model files. Every texture key of a model gets its own id
+    fn terrain_models(registry: &Registry<Block>) -> MeshDataCache {
+        let parse = |src: &str| bevy::asset::ron::de::from_str::<BlockModelAsset>(src).unwrap();
+        let full = parse(include_str!("../../assets/model/block/base/full.model.ron"));
+        let grass = parse(include_str!("../../assets/model/block/grass_block.model.ron"));
+        let bake = |model: &BlockModelAsset, first_id: u32| {
+            let keys = model.faces.iter().flat_map(|f| std::iter::once(&f.texture).chain(&f.overlay)).collect::<BTreeSet<_>>();
+            let id = |key: &String| first_id + keys.iter().position(|k| *k == key).unwrap() as u32;
+            let faces = model.faces.iter().map(|f| FaceMinimal::from_asset(f, id(&f.texture), f.overlay.as_ref().map(id)).unwrap()).collect();
+            BlockModelMinimal::new(faces, &model.full_sides)
+        };
+
+        let models = ["stone", "oak_planks", "dirt", "grass_block"].into_iter().enumerate()
+            .map(|(i, id)| (id, bake(if id == "grass_block" { &grass } else { &full }, i as u32 * 8)))
+            .collect::<Vec<_>>();
+        test_mesh_cache(registry, &models)
+    }
+
+    #[test]
+    fn a_flat_plane_shares_the_corners_of_neighbouring_faces() {
+        let registry = test_registry(&["stone", "oak_planks", "dirt", "grass_block"]);
+        let cache = terrain_models(&registry);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let air = ChunkData::single(BlockState::air());
+        let mut chunk = ChunkData::single(BlockState::air());
+        let size = ChunkData::CHUNK_SIZE;
+        for x in 0..size {
+            for z in 0..size {
+                chunk.set_block(x, 0, z, stone.clone()).unwrap();
+            }
+        }
+
+        let meshes = create_chunk_mesh_with(&chunk, &cache, [&air; 6], &mut MeshScratch::default(), &PipelineProfiler::default());
+        let [(RenderLayer::Opaque, mesh)] = meshes.as_slice() else { panic!("expected one opaque mesh") };
+        let face_count = mesh.indices().unwrap().len() / 6;
+        assert_eq!(face_count, 2 * size * size + 4 * size);
+        assert!(mesh.count_vertices() < 4 * face_count);
+        // the top and bottom are one grid of corners each, every side a strip of them
+        assert_eq!(mesh.count_vertices(), 2 * (size + 1) * (size + 1) + 4 * 2 * (size + 1));
+    }
+
+    #[test]
+    fn generated_terrain_shares_vertices() {
+        let registry = test_registry(&["stone", "oak_planks", "dirt", "grass_block"]);
+        let cache = terrain_models(&registry);
+        // the sine height map goes up and down through this chunk, so there's a surface, caves and sea level planks
+        let pipeline = GenerationPipeline::noise(Arc::new(SineHeightMap::new()));
+        let generate = |pos: IVec3| pipeline.generate(pos, 7, &registry).into_data();
+        let chunk = generate(IVec3::X);
+        let neighbors = Direction::ALL.map(|dir| generate(IVec3::X.offset(dir)));
+
+        let meshes = create_chunk_mesh_with(&chunk, &cache, neighbors.each_ref(), &mut MeshScratch::default(), &PipelineProfiler::default());
+        let [(RenderLayer::Opaque, mesh)] = meshes.as_slice() else { panic!("expected one opaque mesh") };
+        let face_count = mesh.indices().unwrap().len() / 6;
+        assert!(face_count > 0);
+        assert!(mesh.count_vertices() < 4 * face_count, "{} vertices for {face_count} faces", mesh.count_vertices());
+    }
+
+    #[test]
+    fn blocks_across_the_edge_come_from_the_neighbor_in_that_direction() {
+        let ids = ["up", "down", "north", "south", "east", "west"];
+        let registry = test_registry(&ids);
+        let last = ChunkData::CHUNK_SIZE as i32 - 1;
+        for dir in Direction::ALL {
+            // the block each lookup should land on is the only block of this direction's state
+            let marker = BlockState::new(ids[dir as usize], &registry).unwrap();
+            // on the near edge, in the middle, and on the far edge of the axis the direction points along
+            for along in [0, 5, last] {
+                let pos = ivec3(7, 7, 7) * (IVec3::ONE - IVec3::ZERO.offset(dir).abs()) + IVec3::splat(along) * IVec3::ZERO.offset(dir).abs();
+                let target = pos.offset(dir);
+                let inside = target.cmpge(IVec3::ZERO).all() && target.cmple(IVec3::splat(last)).all();
+
+                let mut chunk = ChunkData::single(BlockState::air());
+                let mut neighbors = [(); 6].map(|_| ChunkData::single(BlockState::air()));
+                let marked = if inside { &mut chunk } else { &mut neighbors[dir as usize] };
+                let local = target.rem_euclid(IVec3::splat(ChunkData::CHUNK_SIZE as i32)).as_uvec3();
+                marked.set_block(local.x as usize, local.y as usize, local.z as usize, marker.clone()).unwrap();
+
+                let (id, source) = block_in_direction(&chunk, neighbors.each_ref(), pos.x as usize, pos.y as usize, pos.z as usize, dir);
+                assert_eq!(source, if inside { 0 } else { 1 + dir as usize }, "{dir:?} from {pos}");
+                let from = if inside { &chunk } else { &neighbors[dir as usize] };
+                assert_eq!(from.lookup_palette(id).unwrap().block, marker, "{dir:?} from {pos}");
+            }
+        }
+    }
+
+    #[test]
+    fn glass_does_not_cull_its_opaque_neighbors() {
+        let registry = test_registry(&["stone", "glass"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let glass = BlockState::new("glass", &registry).unwrap();
+        let cube = test_cube_model();
+        let cache = test_mesh_cache(&registry, &[("stone", cube.clone()), ("glass", cube.with_render_layer(RenderLayer::Translucent))]);
+
+        let air = ChunkData::single(BlockState::air());
+        let mut chunk = ChunkData::single(BlockState::air());
+        chunk.set_block(5, 5, 5, stone.clone()).unwrap();
+        chunk.set_block(6, 5, 5, glass).unwrap();
+        let meshes = create_chunk_mesh_with(&chunk, &cache, [&air; 6], &mut MeshScratch::default(), &PipelineProfiler::default());
+        let face_count = |layer| meshes.iter().find(|(l, _)| *l == layer).map_or(0, |(_, mesh)| mesh.indices().unwrap().len() / 6);
+        // the stone shows through the glass, but the glass side against the stone is hidden
+        assert_eq!(face_count(RenderLayer::Opaque), 6);
+        assert_eq!(face_count(RenderLayer::Translucent), 5);
+
+        // two stone blocks hide the faces between them
+        chunk.set_block(6, 5, 5, stone).unwrap();
+        let meshes = create_chunk_mesh_with(&chunk, &cache, [&air; 6], &mut MeshScratch::default(), &PipelineProfiler::default());
+        let [(RenderLayer::Opaque, mesh)] = meshes.as_slice() else { panic!("expected one opaque mesh") };
+        assert_eq!(mesh.indices().unwrap().len() / 6, 10);
+    }
+
+    #[test]
+    fn meshing_only_hashes_each_palette_entry_once() {
+        use crate::world::block::STATE_HASHES;
+
+        let registry = test_registry(&["stone", "dirt"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let dirt = BlockState::new("dirt", &registry).unwrap();
+        let cache = test_mesh_cache(&registry, &[("stone", test_cube_model()), ("dirt", test_cube_model())]);
+        let air = ChunkData::single(BlockState::air());
+
+        // the same three states, with a few blocks or a lot of them
+        let mesh = |blocks: usize| {
+            let mut chunk = ChunkData::single(BlockState::air());
+            for i in 0..blocks {
+                let state = if i % 3 == 0 { &dirt } else { &stone };
+                chunk.set_block(i % 32, (i / 32) % 32, i / 1024, state.clone()).unwrap();
+            }
+            let before = STATE_HASHES.with(|count| count.get());
+            let meshes = create_chunk_mesh_with(&chunk, &cache, [&air; 6], &mut MeshScratch::default(), &PipelineProfiler::default());
+            (STATE_HASHES.with(|count| count.get()) - before, meshes)
+        };
+        let (few, _) = mesh(3);
+        let (lots, meshes) = mesh(5000);
+        // one lookup per palette entry of the chunk and its six neighbors, however many blocks there are
+        assert_eq!(few, 3 + 6);
+        assert_eq!(lots, few);
+        assert!(!meshes.is_empty());
+    }
+
+    #[test]
+    fn reused_scratch_meshes_the_same_without_growing() {
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let cache = test_mesh_cache(&registry, &[("stone", test_cube_model())]);
+        let air = ChunkData::single(BlockState::air());
+        // a checkerboard, so nothing gets culled
+        let mut chunk = ChunkData::single(BlockState::air());
+        for i in (0..ChunkData::BLOCKS_PER_CHUNK).filter(|i| (i % 32 + i / 32 % 32 + i / 1024) % 2 == 0).take(2000) {
+            chunk.set_block(i % 32, i / 32 % 32, i / 1024, stone.clone()).unwrap();
+        }
+        let profiler = PipelineProfiler::default();
+        let fresh = format!("{:?}", create_chunk_mesh_with(&chunk, &cache, [&air; 6], &mut MeshScratch::default(), &profiler));
+
+        let mut scratch = MeshScratch::default();
+        create_chunk_mesh_with(&chunk, &cache, [&air; 6], &mut scratch, &profiler);
+        let capacities = |s: &MeshScratch| (s.cull_info.capacity(), s.faces.capacity(), s.vertices.positions.capacity(), s.seen.capacity(), s.remap.capacity());
+        let after_first = capacities(&scratch);
+        for _ in 0..3 {
+            let reused = format!("{:?}", create_chunk_mesh_with(&chunk, &cache, [&air; 6], &mut scratch, &profiler));
+            assert_eq!(reused, fresh);
+            // nothing had to be reallocated
+            assert_eq!(capacities(&scratch), after_first);
+        }
+    }
+}
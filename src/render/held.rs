@@ -0,0 +1,198 @@
+use crate::registry::block::Block;
+use crate::registry::RegistryHandle;
+use crate::render::block::{BlockModelMinimal, BlockTextures, BlockVertexBuffers, MeshDataCache};
+use crate::render::material::BlockMaterial;
+use crate::world::block::BlockState;
+use crate::world::camera::MainCamera;
+use crate::world::player::{BlockPicker, Player, PlayerVelocity};
+use bevy::camera::primitives::Aabb;
+use bevy::camera::visibility::RenderLayers;
+use bevy::pbr::wireframe::NoWireframe;
+use bevy::prelude::*;
+
+/// Settings for the block shown in the player's hand.
+#[derive(Debug, Resource)]
+pub struct HeldBlockSettings {
+    pub enabled: bool,
+    /// Where the block sits relative to the view, in front of and below the camera.
+    pub offset: Vec3,
+    pub scale: f32,
+    /// How far the block bobs while walking.
+    pub bob_amount: f32,
+    /// Bobs per block walked.
+    pub bob_frequency: f32,
+}
+impl Default for HeldBlockSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            offset: vec3(0.55, -0.45, -0.9),
+            scale: 0.4,
+            bob_amount: 0.03,
+            bob_frequency: 0.35,
+        }
+    }
+}
+
+/// Render layer only the held block and its camera are on. Keeps the held block drawn over the world,
+/// so it never clips into walls.
+pub const HELD_BLOCK_LAYER: usize = 1;
+
+/// The block model in the player's hand. Follows the selected block of the [`BlockPicker`].
+#[derive(Debug, Component, Default)]
+pub struct HeldBlock {
+    state: Option<BlockState>,
+    /// Progress through the bob animation, in radians.
+    bob_phase: f32,
+}
+impl HeldBlock {
+    /// Gets the state currently shown, if any.
+    pub fn get_state(&self) -> Option<&BlockState> {
+        self.state.as_ref()
+    }
+}
+
+/// Marks the camera that draws the held block on top of the main camera.
+#[derive(Component)]
+pub struct HeldBlockCamera;
+
+// gives every new main camera a second camera for the held block. It's a child, so it always looks the same way
+pub fn spawn_held_block_camera(
+    mut commands: Commands,
+    q_camera: Query<Entity, Added<MainCamera>>,
+) {
+    for camera in q_camera.iter() {
+        commands.entity(camera).with_child((
+            Camera3d::default(),
+            Camera {
+                // drawn after the main camera, without clearing what it drew
+                order: 1,
+                clear_color: ClearColorConfig::None,
+                ..default()
+            },
+            // fixed, so fov changes from settings or sprinting don't stretch the held block
+            Projection::Perspective(PerspectiveProjection {
+                fov: 70.0_f32.to_radians(),
+                near: 0.01,
+                ..default()
+            }),
+            RenderLayers::layer(HELD_BLOCK_LAYER),
+            HeldBlockCamera,
+            Transform::default(),
+            children![(
+                HeldBlock::default(),
+                Transform::default(),
+                Visibility::Hidden,
+                RenderLayers::layer(HELD_BLOCK_LAYER),
+                // the mesh has packed positions, so bevy can't work out its bounds by itself
+                Aabb::from_min_max(Vec3::ZERO, Vec3::ONE),
+                NoWireframe,
+            )],
+        ));
+    }
+}
+
+// swaps the held model when the selection changes, and bobs it while walking
+pub fn update_held_block(
+    mut commands: Commands,
+    settings: Res<HeldBlockSettings>,
+    picker: Single<&BlockPicker>,
+    player: Single<&PlayerVelocity, With<Player>>,
+    held: Single<(Entity, &mut HeldBlock, &mut Transform, &mut Visibility)>,
+    block_reg: Res<RegistryHandle<Block>>,
+    cache: Res<MeshDataCache>,
+    block_textures: Res<BlockTextures>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    time: Res<Time>,
+) {
+    let (entity, mut held, mut transform, mut visibility) = held.into_inner();
+
+    let state = match settings.enabled && !picker.block_order.is_empty() {
+        true => picker.get_selected_state(&block_reg).ok(),
+        false => None,
+    };
+    if state != held.state {
+        let model = state.as_ref().and_then(|s| cache.get_model(s));
+        match model {
+            Some(model) => {
+                commands.entity(entity).insert((
+                    Mesh3d(meshes.add(held_block_mesh(model))),
+                    MeshMaterial3d(block_textures.get_material(model.get_render_layer())),
+                ));
+                *visibility = Visibility::Inherited;
+            }
+            // nothing selected, or an invisible block
+            None => {
+                commands.entity(entity).remove::<(Mesh3d, MeshMaterial3d<BlockMaterial>)>();
+                *visibility = Visibility::Hidden;
+            }
+        }
+        held.state = state;
+    }
+
+    // bobs with distance walked, so it speeds up when sprinting and stops when standing still or in the air
+    let speed = if player.on_ground { player.velocity.with_y(0.0).length() } else { 0.0 };
+    if speed > 0.0 {
+        held.bob_phase = (held.bob_phase + speed * settings.bob_frequency * std::f32::consts::TAU * time.delta_secs()) % std::f32::consts::TAU;
+    } else {
+        // settle back down. The bob is symmetric, so easing the phase to the nearest rest point looks smooth
+        let rest = (held.bob_phase / std::f32::consts::PI).round() * std::f32::consts::PI;
+        held.bob_phase = held.bob_phase.lerp(rest, (time.delta_secs() * 10.0).min(1.0));
+    }
+    let bob = vec3(held.bob_phase.sin(), -held.bob_phase.sin().abs(), 0.0) * settings.bob_amount;
+
+    transform.rotation = Quat::from_euler(EulerRot::YXZ, 45.0_f32.to_radians(), 0.0, 0.0);
+    transform.scale = Vec3::splat(settings.scale);
+    // models go from 0 to 1, so shift them to turn around their center
+    transform.translation = settings.offset + bob - transform.rotation * Vec3::splat(settings.scale * 0.5);
+}
+
+/// Creates a mesh of a single block model, from 0 to 1 like in a chunk. Every face is kept, since nothing can cull them.
+pub fn held_block_mesh(model: &BlockModelMinimal) -> Mesh {
+    let mut buffers = BlockVertexBuffers::default();
+    let mut index_offset = 0;
+    for face in model.face_iter() {
+        face.append_face_data(Vec3::ZERO, index_offset, &mut buffers);
+        index_offset += face.vertex_count() as u32;
+    }
+    buffers.into_mesh()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::block::test_registry;
+    use crate::render::block::{test_cube_model, test_mesh_cache};
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[test]
+    fn the_held_model_follows_the_selected_block() {
+        let registry = test_registry(&["stone", "dirt", "barrier"]);
+        let mut world = World::new();
+        // barriers have no model
+        world.insert_resource(test_mesh_cache(&registry, &[("stone", test_cube_model()), ("dirt", test_cube_model())]));
+        world.insert_resource(RegistryHandle::new(registry));
+        world.init_resource::<HeldBlockSettings>();
+        world.init_resource::<BlockTextures>();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Time>();
+        let block_order = ["stone", "dirt", "barrier"].map(String::from).to_vec();
+        let player = world.spawn((Player, PlayerVelocity::default(), BlockPicker { block_order, ..default() })).id();
+        let held = world.spawn((HeldBlock::default(), Transform::default(), Visibility::Hidden)).id();
+
+        let shown = |world: &mut World, selected: &str| {
+            world.get_mut::<BlockPicker>(player).unwrap().select(selected);
+            world.run_system_once(update_held_block).unwrap();
+            let entity = world.entity(held);
+            let state = entity.get::<HeldBlock>().unwrap().get_state().map(|s| s.get_id().to_string());
+            (state, entity.contains::<Mesh3d>(), *entity.get::<Visibility>().unwrap())
+        };
+
+        assert_eq!(shown(&mut world, "stone"), (Some(String::from("stone")), true, Visibility::Inherited));
+        let stone_mesh = world.get::<Mesh3d>(held).unwrap().0.clone();
+        assert_eq!(shown(&mut world, "dirt"), (Some(String::from("dirt")), true, Visibility::Inherited));
+        assert_ne!(world.get::<Mesh3d>(held).unwrap().0, stone_mesh);
+        // selected, but nothing to draw
+        assert_eq!(shown(&mut world, "barrier"), (Some(String::from("barrier")), false, Visibility::Hidden));
+    }
+}
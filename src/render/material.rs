@@ -1,23 +1,76 @@
-use bevy::asset::{ron, LoadedFolder};
 use bevy::pbr::{MaterialPipeline, MaterialPipelineKey};
 use bevy::prelude::*;
 use bevy::mesh::{MeshVertexAttribute, MeshVertexBufferLayoutRef, VertexFormat};
-use bevy::render::render_resource::{AsBindGroup, RenderPipelineDescriptor, SpecializedMeshPipelineError};
-use std::any::TypeId;
+use bevy::render::render_resource::{AsBindGroup, PolygonMode, RenderPipelineDescriptor, SpecializedMeshPipelineError};
 use bevy::shader::ShaderRef;
 
 const SHADER_ASSET_PATH: &str = "shader/block.wgsl";
 
 
 #[derive(Debug, Clone, Asset, TypePath, AsBindGroup)]
+#[bind_group_data(BlockMaterialKey)]
 pub struct BlockMaterial {
     #[texture(0, dimension = "2d_array")]   
     #[sampler(1)]
     pub array_texture: Handle<Image>,
+    /// Fragments with less alpha than this are discarded. Only non-zero for [`AlphaMode::Mask`].
+    #[uniform(2)]
+    pub alpha_cutoff: f32,
+    pub alpha_mode: AlphaMode,
+    /// Draws triangle edges instead of filled faces. Chunk meshes pack their positions, which bevy's wireframe
+    /// pipeline can't read, so they get wireframes from their own material instead.
+    pub wireframe: bool,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockMaterialKey {
+    wireframe: bool,
+}
+impl From<&BlockMaterial> for BlockMaterialKey {
+    fn from(material: &BlockMaterial) -> Self {
+        Self {
+            wireframe: material.wireframe,
+        }
+    }
+}
+
 impl BlockMaterial {
+    pub fn new(array_texture: Handle<Image>, alpha_mode: AlphaMode) -> Self {
+        let alpha_cutoff = match alpha_mode {
+            AlphaMode::Mask(cutoff) => cutoff,
+            _ => 0.0,
+        };
+        Self {
+            array_texture,
+            alpha_cutoff,
+            alpha_mode,
+            wireframe: false,
+        }
+    }
+
+    /// Position relative to the chunk, see [`pack_position`](crate::render::block::pack_position).
+    /// Meshes without it, like particles, use [`Mesh::ATTRIBUTE_POSITION`] instead.
+    pub const ATTRIBUTE_PACKED_POSITION: MeshVertexAttribute =
+        MeshVertexAttribute::new("PackedPosition", 988540920, VertexFormat::Uint32);
+    pub const ATTRIBUTE_PACKED_NORMAL: MeshVertexAttribute =
+        MeshVertexAttribute::new("PackedNormal", 988540921, VertexFormat::Snorm8x4);
+    /// Uv in steps of a texture, see [`pack_uv`](crate::render::block::pack_uv).
+    pub const ATTRIBUTE_PACKED_UV: MeshVertexAttribute =
+        MeshVertexAttribute::new("PackedUv", 988540922, VertexFormat::Uint16x2);
+    /// Baked light, see [`baked_light`](crate::render::block::baked_light).
+    pub const ATTRIBUTE_LIGHT: MeshVertexAttribute =
+        MeshVertexAttribute::new("Light", 988540923, VertexFormat::Unorm8x4);
     pub const ATTRIBUTE_ARRAY_ID: MeshVertexAttribute =
         MeshVertexAttribute::new("ArrayId", 988540917, VertexFormat::Uint32);
+    /// Array index of a texture composited over the base texture, or [`BlockMaterial::NO_OVERLAY`].
+    pub const ATTRIBUTE_OVERLAY_ID: MeshVertexAttribute =
+        MeshVertexAttribute::new("OverlayId", 988540918, VertexFormat::Uint32);
+    /// Tint as packed RGBA8. Tints the overlay if there is one, otherwise the base texture.
+    pub const ATTRIBUTE_TINT: MeshVertexAttribute =
+        MeshVertexAttribute::new("Tint", 988540919, VertexFormat::Uint32);
+
+    /// Overlay id of faces without an overlay. Must match the shader.
+    pub const NO_OVERLAY: u32 = u32::MAX;
 
 }
 
@@ -29,14 +82,61 @@ impl Material for BlockMaterial {
         SHADER_ASSET_PATH.into()
     }
 
-    fn specialize(pipeline: &MaterialPipeline, descriptor: &mut RenderPipelineDescriptor, layout: &MeshVertexBufferLayoutRef, key: MaterialPipelineKey<Self>) -> Result<(), SpecializedMeshPipelineError> {
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn specialize(_pipeline: &MaterialPipeline, descriptor: &mut RenderPipelineDescriptor, layout: &MeshVertexBufferLayoutRef, key: MaterialPipelineKey<Self>) -> Result<(), SpecializedMeshPipelineError> {
+        // chunk meshes pack their positions, anything else (e.g. particles) uses regular float positions
+        let position = if layout.0.contains(BlockMaterial::ATTRIBUTE_PACKED_POSITION) {
+            descriptor.vertex.shader_defs.push("PACKED_POSITION".into());
+            BlockMaterial::ATTRIBUTE_PACKED_POSITION.at_shader_location(0)
+        } else {
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0)
+        };
         let vertex_layout = layout.0.get_layout(&[
-            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
-            Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+            position,
+            BlockMaterial::ATTRIBUTE_PACKED_UV.at_shader_location(1),
             BlockMaterial::ATTRIBUTE_ARRAY_ID.at_shader_location(2),
-            Mesh::ATTRIBUTE_NORMAL.at_shader_location(3)
+            BlockMaterial::ATTRIBUTE_PACKED_NORMAL.at_shader_location(3),
+            BlockMaterial::ATTRIBUTE_OVERLAY_ID.at_shader_location(4),
+            BlockMaterial::ATTRIBUTE_TINT.at_shader_location(5),
+            BlockMaterial::ATTRIBUTE_LIGHT.at_shader_location(6),
         ])?;
         descriptor.vertex.buffers = vec![vertex_layout];
+        if key.bind_group_data.wireframe {
+            descriptor.primitive.polygon_mode = PolygonMode::Line;
+        }
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::render_resource::encase::UniformBuffer;
+    use bevy::render::render_resource::ShaderType;
+
+    #[test]
+    fn the_alpha_cutoff_uniform_matches_the_shader() {
+        let cutout = BlockMaterial::new(Handle::default(), AlphaMode::Mask(0.4));
+        assert_eq!(cutout.alpha_cutoff, 0.4);
+        // anything that isn't cutout discards nothing
+        for mode in [AlphaMode::Opaque, AlphaMode::Blend] {
+            assert_eq!(BlockMaterial::new(Handle::default(), mode).alpha_cutoff, 0.0);
+        }
+
+        // uploaded as a lone f32, the way the shader reads it
+        assert_eq!(f32::min_size().get(), 4);
+        let mut buffer = UniformBuffer::new(Vec::<u8>::new());
+        buffer.write(&cutout.alpha_cutoff).unwrap();
+        assert_eq!(buffer.into_inner()[..4], 0.4_f32.to_le_bytes());
+        let shader = include_str!("../../assets/shader/block.wgsl");
+        for binding in [
+            "@binding(0) var my_array_texture: texture_2d_array<f32>;",
+            "@binding(1) var my_array_texture_sampler: sampler;",
+            "@binding(2) var<uniform> alpha_cutoff: f32;",
+        ] {
+            assert!(shader.contains(binding), "{binding}");
+        }
+    }
+}
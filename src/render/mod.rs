@@ -1,41 +1,64 @@
-use crate::asset::block::{BlockAsset, BlockModelAsset};
+use crate::asset::block::{BlockAsset, BlockModelAsset, RenderLayer};
+use crate::core::loading::LoadingTimings;
 use crate::core::state::LoadingState;
 use crate::core::AllBlockAssets;
 use crate::registry::block::Block;
 use crate::registry::{RegistryHandle, RegistryObject};
 use crate::render::material::BlockMaterial;
-use crate::world::block::BlockState;
+use crate::world::block::{BlockState, BlockStateId, BlockStateTable, Direction};
+use crate::world::shape::BlockShapes;
 use bevy::app::{App, Plugin};
-use bevy::asset::{AssetContainer, Assets, RenderAssetUsages};
+use bevy::asset::{AssetApp, Assets, RenderAssetUsages};
 use bevy::color::palettes::basic::WHITE;
-use bevy::image::{Image, ImageSampler};
+use bevy::color::{Alpha, Luminance};
+use bevy::image::{Image, ImageAddressMode, ImageSampler, ImageSamplerDescriptor};
 use bevy::input::ButtonInput;
 use bevy::pbr::wireframe::{NoWireframe, WireframeConfig};
 use bevy::pbr::MaterialPlugin;
-use bevy::prelude::{info, BevyError, Gizmos, Handle, KeyCode, Mesh3d, NextState, OnEnter, Query, Res, ResMut, Resource, Transform, Update, Visibility, With, Without};
+use bevy::prelude::{debug, AlphaMode, AppGizmoBuilder, IntoScheduleConfigs, warn, BevyError, Handle, KeyCode, Mesh3d, NextState, OnEnter, Query, Res, ResMut, Update, Visibility, With, Without};
 use bevy::render::mesh::allocator::MeshAllocatorSettings;
-use bevy::render::render_resource::{Extent3d, TextureDataOrder, TextureDescriptor, TextureDimension, TextureUsages, TextureViewDescriptor, TextureViewDimension};
+use bevy::render::render_resource::{Extent3d, TextureDataOrder, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor, TextureViewDimension};
 use bevy::render::RenderApp;
 use bevy::utils::default;
 use block::BlockTextures;
+use outline::{OutlineGizmos, OutlineSettings};
 use block::{BlockModelMinimal, MeshDataCache};
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
-use bevy::color::palettes::css;
-use bevy::math::Vec3;
-use crate::math::block::BlockPos;
-use crate::world::player::LookAtData;
+use particle::ParticleSettings;
+use held::HeldBlockSettings;
+use sky::SkySettings;
+use crate::command::console_closed;
 
 pub mod material;
 pub mod pipeline;
 pub mod block;
 pub mod chunk;
+pub mod particle;
+pub mod outline;
+pub mod held;
+pub mod sky;
 
 #[derive(Default)]
-pub struct GameRenderPlugin;
+pub struct GameRenderPlugin {
+    /// Only sets up what the world needs to load and mesh chunks, without anything that draws.
+    /// For running without a window or renderer, see [`crate::headless`].
+    pub headless: bool,
+}
 
 impl Plugin for GameRenderPlugin {
     fn build(&self, app: &mut App) {
+        app
+            .init_resource::<BlockTextures>()
+            .init_resource::<MeshDataCache>()
+            .init_resource::<BlockShapes>()
+            .add_systems(OnEnter(LoadingState::BlockCache), create_block_data_cache)
+            .add_systems(OnEnter(LoadingState::Textures), create_block_array_texture)
+        ;
+        if self.headless {
+            // chunks still get a material handle, there's just nothing to draw it
+            app.init_asset::<BlockMaterial>();
+            return;
+        }
         app
             .add_plugins(
                 MaterialPlugin::<BlockMaterial>::default(),
@@ -49,16 +72,18 @@ impl Plugin for GameRenderPlugin {
                 // Can be changed per mesh using the `WireframeColor` component.
                 default_color: WHITE.into(),
             })
-            .init_resource::<BlockTextures>()
-            .init_resource::<MeshDataCache>()
+            .init_resource::<ParticleSettings>()
+            .init_resource::<OutlineSettings>()
+            .init_resource::<HeldBlockSettings>()
+            .init_resource::<SkySettings>()
+            .init_gizmo_group::<OutlineGizmos>()
             .insert_resource(MeshAllocatorSettings {
                 ..default()
             })
-            .add_systems(Update, (toggle_wireframe, render_look_at_outline))
-            .add_systems(OnEnter(LoadingState::BlockCache), create_block_data_cache)
-            .add_systems(OnEnter(LoadingState::Textures), create_block_array_texture)
+            .add_systems(Update, (toggle_wireframe.run_if(console_closed), outline::render_look_at_outline, particle::update_particles, (held::spawn_held_block_camera, held::update_held_block).chain(), (sky::spawn_sky, sky::update_sky).chain()))
+            .add_observer(particle::spawn_block_break_particles)
         ;
-        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+        if let Some(_render_app) = app.get_sub_app_mut(RenderApp) {
             // render_app.add_systems(Startup, update_mesh_allocator);
         }
     }
@@ -67,12 +92,17 @@ impl Plugin for GameRenderPlugin {
 fn toggle_wireframe(
     kb_input: Res<ButtonInput<KeyCode>>,
     mut config: ResMut<WireframeConfig>,
-    mut to_toggle: Query<&mut Visibility, (With<Mesh3d>, Without<NoWireframe>)>,
+    mut materials: ResMut<Assets<BlockMaterial>>,
+    _to_toggle: Query<&mut Visibility, (With<Mesh3d>, Without<NoWireframe>)>,
 ) {
 
     // toggles on and off wireframe
     if kb_input.just_pressed(KeyCode::KeyZ) {
         config.global = !config.global;
+        // chunks opt out of the global wireframe, their material draws it instead
+        for (_, material) in materials.iter_mut() {
+            material.wireframe = config.global;
+        }
         // for mut vis in to_toggle.iter_mut() {
         //     *vis = match config.global {
         //         true => Visibility::Hidden,
@@ -83,54 +113,109 @@ fn toggle_wireframe(
 }
 
 
+// the textures that get a layer in the block array texture, each once, in layer order. Only the texture handle matters:
+// uv rotation and flipping just change a face's uvs, so faces showing a texture turned or mirrored share its layer
+fn layer_textures<'a>(models: impl IntoIterator<Item = &'a BlockModelAsset>) -> Vec<(&'a String, &'a Handle<Image>)> {
+    let mut visited = HashSet::new();
+    models.into_iter()
+        .flat_map(|model| model.texture_handles.iter())
+        .filter(|(_, handle)| visited.insert((*handle).clone()))
+        .collect()
+}
+
+// average brightness of an image's visible pixels. Used to pick outline colors that stand out against a block.
+fn average_luminance(image: &Image) -> f32 {
+    let (mut total, mut weight) = (0.0, 0.0);
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let Ok(color) = image.get_color_at(x, y) else {
+                continue;
+            };
+            total += color.luminance() * color.alpha();
+            weight += color.alpha();
+        }
+    }
+    if weight > 0.0 { total / weight } else { 0.0 }
+}
+
 // creates an atomic cache of all block model and texture data.
 // Needed to send to other threads
 fn create_block_data_cache(
     all_block_defs: Res<AllBlockAssets>,
     mut cache: ResMut<MeshDataCache>,
+    mut shapes: ResMut<BlockShapes>,
     block_reg: Res<RegistryHandle<Block>>,
     block_asset: Res<Assets<BlockAsset>>,
     block_model_asset: Res<Assets<BlockModelAsset>>,
     block_textures: Res<BlockTextures>,
-    mut next_load: ResMut<NextState<LoadingState>>
+    state_table: Res<BlockStateTable>,
+    mut next_load: ResMut<NextState<LoadingState>>,
+    mut timings: ResMut<LoadingTimings>,
 ) -> Result<(), BevyError> {
-    let mut map: HashMap<BlockState, BlockModelMinimal> = HashMap::new();
+    let mut map: Vec<Option<BlockModelMinimal>> = vec![None; state_table.len()];
     let reg = block_reg.as_ref().as_ref();
 
     // info!("Creating block data cache.");
 
 
-    // stores a maps of block model handles to blockstates, the blockstate being the FIRST blockstate to use this exact model handle.
+    // stores a maps of block model handles to blockstate ids, the blockstate being the FIRST blockstate to use this exact model handle.
     // Done like this to avoid mutable and immutable borrows at the same time
-    let mut models: HashMap<Handle<BlockModelAsset>, BlockState> = HashMap::new();
+    let mut models: HashMap<Handle<BlockModelAsset>, BlockStateId> = HashMap::new();
 
     for h in all_block_defs.inner.iter() {
         let block = block_asset.get(h).unwrap();
+        let render_layer = block.render_layer;
+        let emissive = block.emissive;
         for def in block.models.iter() {
             // TODO: add support for parents + overrides
             let block = reg.get(block.id.as_str()).unwrap();
-            let state = BlockState::with_state(block.get_id(), def.state.clone(), &block_reg)?;
 
-            let model = block_model_asset.get(&def.model_handle).unwrap();
-
-            // if we've already visited this model, clone it and skip
-            if models.contains_key(&def.model_handle) {
-                map.insert(state, map.get(models.get(&def.model_handle).unwrap()).unwrap().clone());
-                continue;
+            // if we've already visited this model, clone it instead of building it again
+            let base = match models.get(&def.model_handle) {
+                Some(first) => map[first.get_index()].clone(),
+                None => Some(BlockModelMinimal::from_asset(&def.model_handle, &block_model_asset, &block_textures)?),
+            };
+
+            // rotated models are authored facing north, other facings are the same model turned around Y
+            let variants = match &def.rotate {
+                Some(property) => Direction::HORIZONTAL.iter().enumerate()
+                    .map(|(turns, dir)| {
+                        let mut state = def.state.clone();
+                        state.insert(property.clone(), String::from(dir.get_name()));
+                        (state, turns as u32)
+                    })
+                    .collect::<Vec<_>>(),
+                None => vec![(def.state.clone(), 0)],
+            };
+
+            for (state, turns) in variants {
+                let state = BlockState::with_state(block.get_id(), state, &block_reg)?;
+                let Some(id) = state_table.get_id(&state) else {
+                    warn!("Model for {} has an invalid state {:?}, skipping.", block.get_id(), state.get_state());
+                    continue;
+                };
+                // only unrotated models get reused
+                if turns == 0 {
+                    models.entry(def.model_handle.clone()).or_insert(id);
+                }
+                map[id.get_index()] = base.as_ref().map(|model| {
+                    let model = model.rotated_y(turns).with_render_layer(render_layer);
+                    if emissive { model.with_emissive() } else { model }
+                });
             }
-            let minimal = BlockModelMinimal::from_asset(&def.model_handle, &block_model_asset, &block_textures)?;
-
-            map.insert(state.clone(), minimal);
-            // cloning blockstates are cheap
-            models.insert(def.model_handle.clone(), state);
         }
 
     }
-    cache.inner = Arc::new(map);
+    timings.set_count(LoadingState::BlockCache, "block models cached", map.iter().flatten().count());
+    *shapes = BlockShapes::new(state_table.clone(), reg, &map);
+    cache.swap(state_table.clone(), map);
     next_load.set(LoadingState::Done);
     Ok(())
 }
 
+/// Single white RGBA texel, used as the only layer of the block array texture when no block has a texture.
+const PLACEHOLDER_TEXEL: [u8; 4] = [255, 255, 255, 255];
+
 // runs once on entering Textures state.
 // All of these textures are guaranteed to be loaded
 fn create_block_array_texture(
@@ -141,6 +226,7 @@ fn create_block_array_texture(
     mut image_asset: ResMut<Assets<Image>>,
     mut next_load_state: ResMut<NextState<LoadingState>>,
     mut materials: ResMut<Assets<BlockMaterial>>,
+    mut timings: ResMut<LoadingTimings>,
 ) {
 
     // info!("Creating block array textures.");
@@ -155,112 +241,102 @@ fn create_block_array_texture(
     let mut new_data = Vec::new();
 
     let mut visited_models = HashSet::new();
-    let mut visited_textures = HashSet::new();
-    for h in all_block_defs.inner.iter() {
+    let models = all_block_defs.inner.iter()
+        .flat_map(|h| block_asset.get(h).unwrap().models.iter())
+        // blocks can share models, each only needs looking at once
+        .filter(|model| visited_models.insert(model.model_handle.clone()))
+        .map(|model| block_model_asset.get(&model.model_handle).unwrap());
+    for (k, texture_handle) in layer_textures(models) {
+        let image = image_asset.get(texture_handle).unwrap();
+        let descriptor = &image.texture_descriptor;
+        block_textures.luminance.push(average_luminance(image));
+
+        debug!("{:?} // {:?}", descriptor, image.texture_view_descriptor);
+        if let Some(d) = &image.data {
+            debug!("Data length: {:?}", d.len());
+        }
 
-        for model in block_asset.get(h).unwrap().models.iter() {
-            // if we've already visited this model handle? continue on.
-            if visited_models.contains(&model.model_handle) {
-                continue;
+        let mut should_convert = false;
+        match (size, format, data_order, mip_count, sample_count) {
+            (None, None, None, None, None) => {
+                size = Some(descriptor.size);
+                format = Some(descriptor.format);
+                data_order = Some(image.data_order);
+                mip_count = Some(descriptor.mip_level_count);
+                sample_count = Some(descriptor.sample_count);
             }
-            visited_models.insert(model.model_handle.clone());
-
-            let model =
-                block_model_asset.get(
-                    &model.model_handle
-                ).unwrap();
-            for (k, texture_handle) in model.texture_handles.iter() {
-                // if we've already added this texture to the array texture? continue on.
-                if visited_textures.contains(texture_handle) {
-                    continue;
+            (Some(s), Some(f), Some(o), Some(mi), Some(sa)) => {
+                if descriptor.size != s {
+                    panic!("Block array texture requires size {:?}, but texture {:?} has size {:?}",
+                           s,
+                           k,
+                           descriptor.size
+                    );
                 }
-                visited_textures.insert(texture_handle.clone());
-
-
-                let image = image_asset.get(texture_handle).unwrap();
-                let descriptor = &image.texture_descriptor;
-
-                info!("{:?} // {:?}", descriptor, image.texture_view_descriptor);
-                if let Some(d) = &image.data {
-                    info!("Data length: {:?}", d.len());
+                if descriptor.format != f {
+                    should_convert = true;
                 }
-
-                let mut should_convert = false;
-                match (size, format, data_order, mip_count, sample_count) {
-                    (None, None, None, None, None) => {
-                        size = Some(descriptor.size);
-                        format = Some(descriptor.format);
-                        data_order = Some(image.data_order);
-                        mip_count = Some(descriptor.mip_level_count);
-                        sample_count = Some(descriptor.sample_count);
-                    }
-                    (Some(s), Some(f), Some(o), Some(mi), Some(sa)) => {
-                        if descriptor.size != s {
-                            panic!("Block array texture requires size {:?}, but texture {:?} has size {:?}",
-                                   s,
-                                   k,
-                                   descriptor.size
-                            );
-                        }
-                        if descriptor.format != f {
-                            should_convert = true;
-                        }
-                        if o != image.data_order {
-                            panic!("Block array texture requires data ordered {:?}, but texture {:?} has it ordered {:?}",
-                                   o,
-                                   k,
-                                   image.data_order
-                            )
-                        }
-                        if mi != descriptor.mip_level_count {
-                            panic!("Block array texture requires {:?} mipmap levels, but texture {:?} has {:?}",
-                                   mi,
-                                   k,
-                                   descriptor.mip_level_count
-                            );
-                        }
-                        if sa != descriptor.sample_count {
-                            panic!("Block array texture requires {:?} samplers, but texture {:?} has {:?}",
-                                   sa,
-                                   k,
-                                   descriptor.sample_count
-                            );
-                        }
-                    }
-                    _ => {
-                        panic!("Dead branch");
-                    }
+                if o != image.data_order {
+                    panic!("Block array texture requires data ordered {:?}, but texture {:?} has it ordered {:?}",
+                           o,
+                           k,
+                           image.data_order
+                    )
+                }
+                if mi != descriptor.mip_level_count {
+                    panic!("Block array texture requires {:?} mipmap levels, but texture {:?} has {:?}",
+                           mi,
+                           k,
+                           descriptor.mip_level_count
+                    );
                 }
+                if sa != descriptor.sample_count {
+                    panic!("Block array texture requires {:?} samplers, but texture {:?} has {:?}",
+                           sa,
+                           k,
+                           descriptor.sample_count
+                    );
+                }
+            }
+            _ => {
+                panic!("Dead branch");
+            }
+        }
 
-                // get around dropped references and stuff
-                let data = if should_convert {
-                    &image.convert(format.unwrap()).expect("Valid texture format.").data
-                } else {
-                    &image.data
-                };
+        // get around dropped references and stuff
+        let data = if should_convert {
+            &image.convert(format.unwrap()).expect("Valid texture format.").data
+        } else {
+            &image.data
+        };
 
 
 
-                match data {
-                    None => { panic!("Should not happen")}
-                    Some(d) => {
-                        for p in d.iter() {
-                            new_data.push(*p);
-                        }
-                    }
+        match data {
+            None => { panic!("Should not happen")}
+            Some(d) => {
+                for p in d.iter() {
+                    new_data.push(*p);
                 }
+            }
+        }
 
 
-                block_textures.map.insert(texture_handle.clone(), i);
+        block_textures.map.insert(texture_handle.clone(), i);
 
-                i += 1;
-            }
-
-        }
+        i += 1;
     }
 
-    if visited_textures.len() == 0 {
-        panic!("Cannot create Array texture for zero textures.")
+    // nothing has a texture (e.g. there's only air), but an array texture needs at least one layer
+    if i == 0 {
+        warn!("No block textures found, using a placeholder.");
+        size = Some(Extent3d { width: 1, height: 1, depth_or_array_layers: 1 });
+        format = Some(TextureFormat::Rgba8UnormSrgb);
+        mip_count = Some(1);
+        sample_count = Some(1);
+        new_data.extend_from_slice(&PLACEHOLDER_TEXEL);
+        block_textures.luminance.push(1.0);
+        i = 1;
     }
 
     let size = Extent3d {
@@ -285,7 +361,13 @@ fn create_block_array_texture(
                 | TextureUsages::COPY_SRC,
             view_formats: &[],
         },
-        sampler: ImageSampler::nearest(),
+        // nearest with no mip filtering, so cutout edges stay sharp instead of blending into half-transparent texels.
+        // Repeats, since tiled faces have uvs past 1
+        sampler: ImageSampler::Descriptor(ImageSamplerDescriptor {
+            address_mode_u: ImageAddressMode::Repeat,
+            address_mode_v: ImageAddressMode::Repeat,
+            ..ImageSamplerDescriptor::nearest()
+        }),
         texture_view_descriptor: Some(TextureViewDescriptor {
             dimension: Some(TextureViewDimension::D2Array),
             ..default()
@@ -307,9 +389,17 @@ fn create_block_array_texture(
     // new_image.data_order = data_order.unwrap();
 
     block_textures.array_texture = image_asset.add(new_image);
-    block_textures.material = materials.add(BlockMaterial {
-        array_texture: block_textures.array_texture.clone(),
-    });
+    // every layer samples the same array texture, they only differ in how alpha is handled
+    for layer in RenderLayer::ALL {
+        let alpha_mode = match layer {
+            RenderLayer::Opaque => AlphaMode::Opaque,
+            RenderLayer::Cutout => AlphaMode::Mask(0.5),
+            RenderLayer::Translucent => AlphaMode::Blend,
+        };
+        let material = materials.add(BlockMaterial::new(block_textures.array_texture.clone(), alpha_mode));
+        block_textures.materials.insert(layer, material);
+    }
+    timings.set_count(LoadingState::Textures, "textures packed", block_textures.map.len());
     next_load_state.set(LoadingState::BlockCache);
 
 
@@ -317,15 +407,60 @@ fn create_block_array_texture(
 
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::block::BlockModelFace;
+    use bevy::asset::{ron, uuid_handle};
 
-fn render_look_at_outline(
-    look_info: Query<&LookAtData>,
-    mut gizmos: Gizmos,
-) {
-    for look in look_info.iter() {
-        let Some(pos) = look.look_pos else {
-            continue;
+    #[test]
+    fn turned_and_mirrored_faces_share_a_layer() {
+        let stone: Handle<Image> = uuid_handle!("6a3c1f0e-2b7d-4e8a-9c51-0d4f7a2e8b13");
+        let dirt: Handle<Image> = uuid_handle!("1e9b4d72-5f3a-4c06-8e2d-7b0a9f6c4d21");
+        let full = ron::de::from_str::<BlockModelAsset>(include_str!("../../assets/model/block/base/full.model.ron")).unwrap();
+        let model = |textures: &[(&str, &Handle<Image>)], rotation: u32, flip: bool| BlockModelAsset {
+            faces: full.faces.iter().map(|face| BlockModelFace { uv_rotation: rotation, uv_flip: flip, ..face.clone() }).collect(),
+            texture_handles: textures.iter().map(|(k, h)| (String::from(*k), (*h).clone())).collect(),
+            ..full.clone()
         };
-        gizmos.cuboid(Transform::from_translation(pos.center()).with_scale(Vec3::splat(1.0)), css::BLACK);
+        let models = [
+            model(&[("all", &stone)], 0, false),
+            model(&[("all", &stone)], 90, false),
+            model(&[("side", &stone), ("top", &dirt)], 180, true),
+        ];
+
+        let layers = layer_textures(&models).into_iter().map(|(_, h)| h.clone()).collect::<Vec<_>>();
+        assert_eq!(layers, vec![stone, dirt]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn no_textures_still_make_a_one_layer_array_texture() {
+        use bevy::ecs::system::RunSystemOnce;
+        use bevy::prelude::World;
+
+        // only air, which has no model
+        let mut world = World::new();
+        world.init_resource::<AllBlockAssets>();
+        world.init_resource::<BlockTextures>();
+        world.init_resource::<Assets<BlockAsset>>();
+        world.init_resource::<Assets<BlockModelAsset>>();
+        world.init_resource::<Assets<Image>>();
+        world.init_resource::<Assets<BlockMaterial>>();
+        world.init_resource::<NextState<LoadingState>>();
+        world.init_resource::<LoadingTimings>();
+        world.run_system_once(create_block_array_texture).unwrap();
+
+        let block_textures = world.resource::<BlockTextures>();
+        assert!(block_textures.is_ready());
+        assert_eq!(block_textures.luminance, vec![1.0]);
+        let image = world.resource::<Assets<Image>>().get(&block_textures.array_texture).unwrap();
+        let descriptor = &image.texture_descriptor;
+        assert_eq!(descriptor.size, Extent3d { width: 1, height: 1, depth_or_array_layers: 1 });
+        assert_eq!(descriptor.format, TextureFormat::Rgba8UnormSrgb);
+        assert_eq!((descriptor.mip_level_count, descriptor.sample_count), (1, 1));
+        assert_eq!(image.data.as_deref(), Some(&PLACEHOLDER_TEXEL[..]));
+        assert_eq!(image.texture_view_descriptor.as_ref().unwrap().dimension, Some(TextureViewDimension::D2Array));
+        // and loading moves on
+        assert!(matches!(world.resource::<NextState<LoadingState>>(), NextState::Pending(LoadingState::BlockCache)));
+    }
+}
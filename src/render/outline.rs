@@ -0,0 +1,108 @@
+use crate::render::block::{BlockTextures, MeshDataCache};
+use crate::world::player::{BreakProgress, LookAtData};
+use bevy::prelude::*;
+
+/// Settings for the outline drawn around the block the player is looking at.
+#[derive(Debug, Resource)]
+pub struct OutlineSettings {
+    /// Color used when `auto_contrast` is off.
+    pub color: Color,
+    /// Line thickness in pixels.
+    pub width: f32,
+    /// Picks black or white depending on how bright the targeted block is, so the outline is always visible.
+    pub auto_contrast: bool,
+    /// How fast the outline pulses while a block is being broken, in radians per second.
+    pub pulse_speed: f32,
+}
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            color: Color::BLACK,
+            width: 2.0,
+            auto_contrast: true,
+            pulse_speed: 8.0,
+        }
+    }
+}
+
+/// Gizmo group for the block outline, so its line width doesn't affect other gizmos.
+#[derive(Default, Reflect, GizmoConfigGroup)]
+pub struct OutlineGizmos;
+
+/// Blocks brighter than this get a dark outline, darker ones get a light outline.
+const CONTRAST_THRESHOLD: f32 = 0.5;
+
+/// Picks the outline color for a block with the given average luminance.
+/// Falls back to the configured color if auto contrast is off or the luminance isn't known.
+pub fn pick_outline_color(settings: &OutlineSettings, luminance: Option<f32>) -> Color {
+    match luminance {
+        Some(l) if settings.auto_contrast => {
+            if l > CONTRAST_THRESHOLD { Color::BLACK } else { Color::WHITE }
+        }
+        _ => settings.color,
+    }
+}
+
+pub fn render_look_at_outline(
+    look_info: Query<(&LookAtData, Option<&BreakProgress>)>,
+    mut gizmos: Gizmos<OutlineGizmos>,
+    mut config_store: ResMut<GizmoConfigStore>,
+    settings: Res<OutlineSettings>,
+    cache: Res<MeshDataCache>,
+    block_textures: Res<BlockTextures>,
+) {
+    for (look, progress) in look_info.iter() {
+        let Some(pos) = look.look_pos else {
+            continue;
+        };
+
+        // average brightness of the block's faces
+        let luminance = look.look_block.as_ref()
+            .and_then(|state| cache.get_model(state))
+            .and_then(|model| {
+                let faces = model.get_faces();
+                let total: f32 = faces.iter().filter_map(|f| block_textures.get_luminance(f.get_texture_index())).sum();
+                (!faces.is_empty()).then(|| total / faces.len() as f32)
+            });
+        let mut color = pick_outline_color(&settings, luminance);
+        let mut width = settings.width;
+
+        // pulse while this block is being broken
+        if let Some(progress) = progress.filter(|p| p.pos == Some(pos) && p.elapsed > 0.0) {
+            let pulse = 0.5 + 0.5 * (progress.elapsed * settings.pulse_speed).sin();
+            color = color.with_alpha(0.4 + 0.6 * pulse);
+            width *= 1.0 + pulse;
+        }
+        let (config, _) = config_store.config_mut::<OutlineGizmos>();
+        config.line.width = width;
+
+        // hug the model, so e.g. slabs get a half height outline
+        let (min, max) = look.look_block.as_ref()
+            .and_then(|state| cache.get_model(state))
+            .map(|model| model.bounds())
+            .filter(|(min, max)| min != max)
+            .unwrap_or((Vec3::ZERO, Vec3::ONE));
+        let center = pos.as_vec3() + (min + max) / 2.0;
+        gizmos.cuboid(Transform::from_translation(center).with_scale(max - min), color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outlines_contrast_with_the_targeted_block() {
+        let settings = OutlineSettings { color: Color::srgb(1.0, 0.0, 0.0), ..default() };
+        // light blocks like sand get a dark outline, dark ones like obsidian a light one
+        assert_eq!(pick_outline_color(&settings, Some(0.9)), Color::BLACK);
+        assert_eq!(pick_outline_color(&settings, Some(0.1)), Color::WHITE);
+        assert_eq!(pick_outline_color(&settings, Some(CONTRAST_THRESHOLD)), Color::WHITE);
+
+        // nothing to go on, or turned off
+        assert_eq!(pick_outline_color(&settings, None), settings.color);
+        let fixed = OutlineSettings { auto_contrast: false, ..settings };
+        assert_eq!(pick_outline_color(&fixed, Some(0.9)), fixed.color);
+        assert_eq!(pick_outline_color(&fixed, Some(0.1)), fixed.color);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::asset::block::RenderLayer;
+use crate::core::event::{BlockChangeSource, SetBlockEvent};
+use crate::math::block::BlockPos;
+use crate::render::block::{pack_light, pack_normal, pack_uv, BlockTextures, MeshDataCache, NO_TINT};
+use crate::render::material::BlockMaterial;
+use crate::world::camera::MainCamera;
+use bevy::asset::RenderAssetUsages;
+use bevy::mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
+use bevy::pbr::wireframe::NoWireframe;
+use bevy::prelude::*;
+use rand::Rng;
+
+/// Settings for particles. Particles can be turned off entirely for performance.
+#[derive(Debug, Resource)]
+pub struct ParticleSettings {
+    pub enabled: bool,
+    /// How many particles spawn when a block is broken.
+    pub block_break_count: u32,
+    /// How long particles live for, in seconds.
+    pub lifetime: f32,
+}
+impl Default for ParticleSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            block_break_count: 16,
+            lifetime: 0.8,
+        }
+    }
+}
+
+/// A simple CPU particle. Falls with gravity and despawns once its lifetime runs out.
+#[derive(Debug, Component)]
+pub struct Particle {
+    pub velocity: Vec3,
+    /// Seconds left before this particle despawns.
+    pub remaining: f32,
+}
+
+/// Downwards acceleration of particles, in blocks per second squared.
+pub const PARTICLE_GRAVITY: f32 = 16.0;
+const PARTICLE_SIZE: f32 = 0.125;
+
+// spawns a burst of particles textured like the block that was broken
+pub fn spawn_block_break_particles(
+    trigger: On<SetBlockEvent>,
+    mut commands: Commands,
+    settings: Res<ParticleSettings>,
+    cache: Res<MeshDataCache>,
+    block_textures: Res<BlockTextures>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    if !settings.enabled || settings.block_break_count == 0 {
+        return;
+    }
+    let table = cache.get_table();
+    if trigger.source != BlockChangeSource::Player || !table.is_air_state(&trigger.new) || table.is_air_state(&trigger.old) {
+        return;
+    }
+    // particles use the texture of the first face of the broken block's model
+    let Some(face) = cache.get_model(&trigger.old).and_then(|model| model.face_iter().next()) else {
+        return;
+    };
+    // overlays aren't drawn on particles, and their tint only belongs on the overlay
+    let tint = if face.get_overlay_index().is_some() { NO_TINT } else { face.get_tint() };
+
+    // all particles of a burst share one mesh. It's freed once they all despawn.
+    let mesh = meshes.add(particle_mesh(face.get_texture_index(), tint));
+    let center = trigger.pos.center();
+    let mut rng = rand::rng();
+    for _ in 0..settings.block_break_count {
+        let offset = vec3(rng.random_range(-0.3..0.3), rng.random_range(-0.3..0.3), rng.random_range(-0.3..0.3));
+        let velocity = vec3(offset.x * 4.0, rng.random_range(1.5..4.0), offset.z * 4.0);
+        commands.spawn((
+            Particle {
+                velocity,
+                remaining: settings.lifetime,
+            },
+            Mesh3d(mesh.clone()),
+            MeshMaterial3d(block_textures.get_material(RenderLayer::Opaque)),
+            Transform::from_translation(center + offset),
+            NoWireframe,
+        ));
+    }
+}
+
+// small quad facing +z, showing a small patch from the middle of a block texture.
+// Particles are centered on their origin, so unlike chunks they keep full float positions.
+fn particle_mesh(texture_index: u32, tint: u32) -> Mesh {
+    let s = PARTICLE_SIZE / 2.0;
+    let (uv_min, uv_max) = (0.375, 0.625);
+    let uvs = [vec2(uv_min, uv_max), vec2(uv_max, uv_max), vec2(uv_max, uv_min), vec2(uv_min, uv_min)].map(pack_uv);
+    Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, vec![[-s, -s, 0.0], [s, -s, 0.0], [s, s, 0.0], [-s, s, 0.0]])
+        .with_inserted_attribute(BlockMaterial::ATTRIBUTE_ARRAY_ID, vec![texture_index; 4])
+        .with_inserted_attribute(BlockMaterial::ATTRIBUTE_PACKED_UV, VertexAttributeValues::Uint16x2(uvs.to_vec()))
+        .with_inserted_attribute(BlockMaterial::ATTRIBUTE_PACKED_NORMAL, VertexAttributeValues::Snorm8x4(vec![pack_normal(Vec3::Z); 4]))
+        .with_inserted_attribute(BlockMaterial::ATTRIBUTE_OVERLAY_ID, vec![BlockMaterial::NO_OVERLAY; 4])
+        .with_inserted_attribute(BlockMaterial::ATTRIBUTE_TINT, vec![tint; 4])
+        .with_inserted_attribute(BlockMaterial::ATTRIBUTE_LIGHT, VertexAttributeValues::Unorm8x4(vec![pack_light(1.0); 4]))
+        .with_inserted_indices(Indices::U32(vec![0, 1, 2, 0, 2, 3]))
+}
+
+pub fn update_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    camera: Single<&GlobalTransform, With<MainCamera>>,
+    mut q_particles: Query<(Entity, &mut Particle, &mut Transform)>,
+) {
+    let delta = time.delta_secs();
+    for (entity, mut particle, mut transform) in q_particles.iter_mut() {
+        particle.remaining -= delta;
+        if particle.remaining <= 0.0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        particle.velocity.y -= PARTICLE_GRAVITY * delta;
+        transform.translation += particle.velocity * delta;
+        // always face the camera
+        transform.rotation = camera.rotation();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::block::test_registry;
+    use crate::render::block::{test_cube_model, test_mesh_cache};
+    use crate::world::block::BlockState;
+    use crate::world::dimension::DimensionId;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::time::Duration;
+
+    fn particle_world(settings: ParticleSettings) -> (World, BlockState) {
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let cache = test_mesh_cache(&registry, &[("stone", test_cube_model())]);
+
+        let mut world = World::new();
+        world.insert_resource(settings);
+        world.insert_resource(cache);
+        world.init_resource::<BlockTextures>();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Time>();
+        world.spawn((MainCamera, GlobalTransform::default()));
+        world.add_observer(spawn_block_break_particles);
+        (world, stone)
+    }
+
+    fn set_block(world: &mut World, old: BlockState, new: BlockState, source: BlockChangeSource) {
+        world.trigger(SetBlockEvent { pos: ivec3(3, 4, 5), old, new, source, dimension: DimensionId::new("test") });
+        world.flush();
+    }
+
+    fn particle_count(world: &mut World) -> usize {
+        world.query::<&Particle>().iter(world).count()
+    }
+
+    #[test]
+    fn breaking_spawns_particles_that_despawn_after_their_lifetime() {
+        let (mut world, stone) = particle_world(ParticleSettings { enabled: true, block_break_count: 5, lifetime: 0.5 });
+        // placing and programmatic breaking don't make any
+        set_block(&mut world, BlockState::air(), stone.clone(), BlockChangeSource::Player);
+        set_block(&mut world, stone.clone(), BlockState::air(), BlockChangeSource::Programmatic);
+        assert_eq!(particle_count(&mut world), 0);
+
+        set_block(&mut world, stone, BlockState::air(), BlockChangeSource::Player);
+        assert_eq!(particle_count(&mut world), 5);
+        let near_block = world.query_filtered::<&Transform, With<Particle>>().iter(&world)
+            .all(|t| t.translation.distance(vec3(3.5, 4.5, 5.5)) < 0.6);
+        assert!(near_block);
+
+        let step = |world: &mut World| {
+            world.resource_mut::<Time>().advance_by(Duration::from_secs_f32(0.3));
+            world.run_system_once(update_particles).unwrap();
+            particle_count(world)
+        };
+        assert_eq!(step(&mut world), 5);
+        assert_eq!(step(&mut world), 0);
+    }
+
+    #[test]
+    fn disabled_particles_never_spawn() {
+        let (mut world, stone) = particle_world(ParticleSettings { enabled: false, block_break_count: 5, lifetime: 0.5 });
+        set_block(&mut world, stone, BlockState::air(), BlockChangeSource::Player);
+        assert_eq!(particle_count(&mut world), 0);
+    }
+}
@@ -5,13 +5,9 @@ use bevy::render::RenderApp;
 pub struct GameRenderPipelinePlugin;
 impl Plugin for GameRenderPipelinePlugin {
     fn build(&self, app: &mut App) {
-        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+        let Some(_render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
-        render_app
-        
-        
-        ;
     }
 }
 
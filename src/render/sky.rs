@@ -0,0 +1,178 @@
+use crate::world::camera::MainCamera;
+use bevy::camera::visibility::RenderLayers;
+use bevy::pbr::wireframe::NoWireframe;
+use bevy::prelude::*;
+
+/// Settings for the sky behind the world.
+/// Change the colors at runtime (e.g. from a day/night cycle) and the sky follows.
+#[derive(Debug, Resource)]
+pub struct SkySettings {
+    /// Draws the gradient. When off, the background is just the horizon color.
+    pub enabled: bool,
+    /// Color at and below the horizon. Also the background past the sky, and what fog should fade to.
+    pub horizon: Color,
+    /// Color straight up.
+    pub zenith: Color,
+    /// Shapes the gradient. Below 1 the zenith color takes over quickly, above 1 the horizon color reaches higher.
+    pub falloff: f32,
+}
+impl Default for SkySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            horizon: Color::srgb(0.72, 0.84, 1.0),
+            zenith: Color::srgb(0.33, 0.53, 0.92),
+            falloff: 0.6,
+        }
+    }
+}
+impl SkySettings {
+    /// Gets the sky color at a view elevation in radians, 0 being the horizon and pi / 2 straight up.
+    /// Anything below the horizon gets the horizon color.
+    pub fn color_at(&self, elevation: f32) -> Color {
+        sky_color(self.horizon, self.zenith, self.falloff, elevation)
+    }
+
+    /// The color distant things should fade into, so they blend with the sky at the edge of the render distance.
+    pub fn get_fog_color(&self) -> Color {
+        self.horizon
+    }
+}
+
+/// Blends from `horizon` to `zenith` by the sine of `elevation`, raised to `falloff`. See [`SkySettings::color_at`].
+pub fn sky_color(horizon: Color, zenith: Color, falloff: f32, elevation: f32) -> Color {
+    let t = elevation.sin().max(0.0).powf(falloff.max(0.0));
+    let color = LinearRgba::from(horizon).mix(&LinearRgba::from(zenith), t);
+    Color::from(color)
+}
+
+/// Render layer only the sky and its camera are on.
+pub const SKY_LAYER: usize = 2;
+
+/// Rings and segments of the sky dome. The gradient only changes with elevation, so it doesn't need many.
+const DOME_RESOLUTION: (u32, u32) = (32, 18);
+
+/// Marks the camera that draws the sky before the main camera.
+#[derive(Component)]
+pub struct SkyCamera;
+
+/// The sphere the sky gradient is drawn on. Follows the camera around, so it's always the same distance away.
+#[derive(Component)]
+pub struct SkyDome;
+
+// gives every new main camera a sky drawn underneath it, and stops the main camera from clearing over it
+pub fn spawn_sky(
+    mut commands: Commands,
+    settings: Res<SkySettings>,
+    mut q_camera: Query<(Entity, &mut Camera), Added<MainCamera>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (entity, mut camera) in q_camera.iter_mut() {
+        camera.clear_color = ClearColorConfig::None;
+        commands.entity(entity).with_child((
+            Camera3d::default(),
+            Camera {
+                // drawn before the main camera, which draws over it
+                order: -1,
+                clear_color: ClearColorConfig::Custom(settings.get_fog_color()),
+                ..default()
+            },
+            RenderLayers::layer(SKY_LAYER),
+            SkyCamera,
+            Transform::default(),
+        ));
+        commands.spawn((
+            Mesh3d(meshes.add(sky_dome_mesh(&settings))),
+            MeshMaterial3d(materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                unlit: true,
+                // seen from the inside
+                cull_mode: None,
+                fog_enabled: false,
+                ..default()
+            })),
+            Transform::default(),
+            Visibility::Visible,
+            RenderLayers::layer(SKY_LAYER),
+            SkyDome,
+            NoWireframe,
+        ));
+    }
+}
+
+// keeps the sky centered on the camera and matching its view, and recolors it when the settings change
+pub fn update_sky(
+    settings: Res<SkySettings>,
+    main_camera: Single<(&GlobalTransform, &Projection), (With<MainCamera>, Without<SkyCamera>)>,
+    sky_camera: Single<(&mut Camera, &mut Projection), With<SkyCamera>>,
+    dome: Single<(&Mesh3d, &mut Transform, &mut Visibility), With<SkyDome>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let (main_transform, main_projection) = main_camera.into_inner();
+    let (mut camera, mut projection) = sky_camera.into_inner();
+    let (mesh, mut transform, mut visibility) = dome.into_inner();
+
+    // same fov as the main camera, so the horizon lines up with the terrain
+    *projection = main_projection.clone();
+    transform.translation = main_transform.translation();
+
+    if !settings.is_changed() {
+        return;
+    }
+    camera.clear_color = ClearColorConfig::Custom(settings.get_fog_color());
+    *visibility = if settings.enabled { Visibility::Visible } else { Visibility::Hidden };
+    if let Some(mesh) = meshes.get_mut(&mesh.0) {
+        let colors = sky_colors(mesh, &settings);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    }
+}
+
+/// Creates the sky dome: a unit sphere with the gradient in its vertex colors.
+pub fn sky_dome_mesh(settings: &SkySettings) -> Mesh {
+    let mut mesh = Sphere::new(1.0).mesh().uv(DOME_RESOLUTION.0, DOME_RESOLUTION.1);
+    let colors = sky_colors(&mesh, settings);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh
+}
+
+// sky color of every vertex of a unit sphere, from how high up it is
+fn sky_colors(mesh: &Mesh, settings: &SkySettings) -> Vec<[f32; 4]> {
+    let Some(positions) = mesh.attribute(Mesh::ATTRIBUTE_POSITION).and_then(|a| a.as_float3()) else {
+        return vec![];
+    };
+    positions.iter()
+        .map(|pos| {
+            let elevation = pos[1].clamp(-1.0, 1.0).asin();
+            LinearRgba::from(settings.color_at(elevation)).to_f32_array()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_6};
+
+    #[test]
+    fn the_sky_blends_from_horizon_to_zenith() {
+        let (horizon, zenith) = (Color::linear_rgb(1.0, 1.0, 1.0), Color::linear_rgb(0.0, 0.0, 0.5));
+        let close = |a: Color, b: Color| LinearRgba::from(a).to_vec4().abs_diff_eq(LinearRgba::from(b).to_vec4(), 1e-5);
+
+        assert!(close(sky_color(horizon, zenith, 1.0, 0.0), horizon));
+        assert!(close(sky_color(horizon, zenith, 1.0, FRAC_PI_2), zenith));
+        // below the horizon stays the horizon color
+        assert!(close(sky_color(horizon, zenith, 1.0, -FRAC_PI_6), horizon));
+
+        // 30 degrees up is halfway, then the falloff moves it towards one end or the other
+        assert!(close(sky_color(horizon, zenith, 1.0, FRAC_PI_6), Color::linear_rgb(0.5, 0.5, 0.75)));
+        let quick = LinearRgba::from(sky_color(horizon, zenith, 0.5, FRAC_PI_6));
+        let slow = LinearRgba::from(sky_color(horizon, zenith, 2.0, FRAC_PI_6));
+        assert!(close(quick.into(), Color::linear_rgb(1.0 - 0.5_f32.sqrt(), 1.0 - 0.5_f32.sqrt(), 1.0 - 0.5 * 0.5_f32.sqrt())));
+        assert!(close(slow.into(), Color::linear_rgb(0.75, 0.75, 0.875)));
+
+        // the settings use the same blend
+        let settings = SkySettings { horizon, zenith, falloff: 2.0, ..default() };
+        assert!(close(settings.color_at(FRAC_PI_6), slow.into()));
+    }
+}
@@ -1,11 +1,19 @@
 use crate::math::block::Vec3Ext;
+use crate::world::block::BlockWorld;
 use crate::world::camera::MainCamera;
 use crate::world::chunk;
 use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
 use bevy::prelude::*;
 use std::collections::VecDeque;
 use std::time::Duration;
-use crate::world::player::{BlockPicker, LookAtData};
+use crate::world::player::{BlockPicker, GameMode, Inventory, LookAtData, Player};
+use crate::world::save::SaveWorker;
+use crate::world::{SPAWN_CHUNKS_READY, SPAWN_CHUNKS_TOTAL};
+use crate::core::state::InGameState;
+use crate::command::{DevConsole, RunCommandEvent};
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::ButtonState;
+use bevy::window::{CursorGrabMode, CursorOptions, PrimaryWindow};
 
 #[derive(Default)]
 pub struct GameUiPlugin;
@@ -14,7 +22,10 @@ impl Plugin for GameUiPlugin {
         app
 
             .add_systems(Startup, (build_debug_ui, build_hud))
-            .add_systems(Update, (update_fps_text, update_position, update_look_target, update_block_picker_text))
+            .add_systems(Update, (update_fps_text, update_position, update_look_target, update_block_picker_text, update_saving_text))
+            .add_systems(Update, (console_input, update_console_text).chain())
+            .add_systems(OnEnter(InGameState::LoadingSpawn), build_loading_screen)
+            .add_systems(Update, update_loading_text.run_if(in_state(InGameState::LoadingSpawn)))
         ;
     }
 }
@@ -31,6 +42,15 @@ struct LookTarget;
 #[derive(Component)]
 struct BlockPickerText;
 
+#[derive(Component)]
+struct SavingText;
+
+#[derive(Component)]
+struct ConsoleText;
+
+#[derive(Component)]
+struct LoadingText;
+
 
 fn build_hud(
     mut commands: Commands,
@@ -88,6 +108,51 @@ fn build_hud(
         ));
     });
 
+    // saving indicator, top right
+    commands.spawn(
+        Node {
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            justify_content: JustifyContent::FlexEnd,
+            align_items: AlignItems::FlexStart,
+            padding: UiRect::all(Val::Px(8.)),
+            ..default()
+        }
+    ).with_children(|parent| {
+        parent.spawn((
+            Text::new(""),
+            TextFont {
+                font_size: 16.0,
+                ..default()
+            },
+            SavingText,
+        ));
+    });
+
+    // dev console, bottom left
+    commands.spawn(
+        Node {
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::FlexEnd,
+            align_items: AlignItems::FlexStart,
+            padding: UiRect::all(Val::Px(8.)),
+            ..default()
+        }
+    ).with_children(|parent| {
+        parent.spawn((
+            Text::new(""),
+            TextFont {
+                font_size: 16.0,
+                ..default()
+            },
+            BackgroundColor(Color::BLACK.with_alpha(0.5)),
+            Visibility::Hidden,
+            ConsoleText,
+        ));
+    });
+
 
 }
 
@@ -97,9 +162,38 @@ fn build_hud(
 
 
 
+// covers the screen until the spawn area is loaded, removed automatically when the state changes
+fn build_loading_screen(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        Node {
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BackgroundColor(Color::srgb(0.1, 0.1, 0.1)),
+        // draw over the rest of the hud
+        GlobalZIndex(1),
+        DespawnOnExit(InGameState::LoadingSpawn),
+    )).with_children(|parent| {
+        parent.spawn((
+            Text::new("Loading world..."),
+            TextFont {
+                font_size: 24.0,
+                ..default()
+            },
+            LoadingText,
+        ));
+    });
+}
+
 fn build_debug_ui(
     mut commands: Commands,
-    asset_server: Res<AssetServer>
+    _asset_server: Res<AssetServer>
 ) {
     let root = commands.spawn(
         Node {
@@ -184,25 +278,26 @@ fn update_fps_text(
     let mut frame_time = time.delta_secs_f64();
     if let Some(frame_time_diagnostic) =
         diagnostics.get(&FrameTimeDiagnosticsPlugin::FRAME_TIME)
+        && let Some(frame_time_smoothed) = frame_time_diagnostic.smoothed()
     {
-        if let Some(frame_time_smoothed) = frame_time_diagnostic.smoothed() {
-            frame_time = frame_time_smoothed;
-        }
+        frame_time = frame_time_smoothed;
     }
 
     *writer.text(entity, 0) = format!("{avg_fps:.1} fps | {frame_time:.1} ms");
 
 }
 
+// shows where the player's feet are, and where their eyes are looking
 fn update_position(
-    camera: Single<&Transform, With<MainCamera>>,
+    player: Single<&Transform, With<Player>>,
+    camera: Single<&Transform, (With<MainCamera>, Without<Player>)>,
     position: Single<Entity, With<Position>>,
     mut writer: TextUiWriter,
 ) {
-    let pos = camera.translation;
+    let pos = player.translation;
     let chunk_pos = chunk::pos_to_chunk_pos(pos.as_block_pos());
     let (x, y, z) = (pos.x, pos.y, pos.z);
-    let view = camera.forward().as_vec3();
+    let view = player.mul_transform(**camera).forward().as_vec3();
     let (vx, vy, vz) = (view.x, view.y, view.z);
     let (ix, iy, iz) = (chunk_pos.x, chunk_pos.y, chunk_pos.z);
     *writer.text(position.into_inner(), 0) = format!("x: {x:.4}, y: {y:.4}, z: {z:.4} [{ix}, {iy}, {iz}]\nLook direction: ({vx:.4}, {vy:.4}, {vz:.4})");
@@ -235,14 +330,131 @@ fn update_look_target(
 
 
 fn update_block_picker_text(
-    picker: Single<&BlockPicker>,
+    picker: Single<(&BlockPicker, &Inventory)>,
+    game_mode: Res<GameMode>,
     q_text: Single<Entity, With<BlockPickerText>>,
     mut writer: TextUiWriter,
 ) {
-    if picker.block_order.len() == 0 {
+    let (picker, inventory) = picker.into_inner();
+    if picker.block_order.is_empty() {
         *writer.text(q_text.into_inner(), 0) = String::from("");
         return;
     }
-    let text = picker.block_order[picker.index].clone();
+    let id = picker.get_selected_id();
+    // survival only has what's in the inventory
+    let mut text = match *game_mode {
+        GameMode::Survival => format!("{id} ({})", inventory.get_count(id)),
+        _ => String::from(id),
+    };
+    // show the properties of a picked state, e.g. facing=east
+    if let Some(state) = picker.picked_state.as_ref().filter(|s| !s.get_state().is_empty()) {
+        let props = state.get_state().iter().map(|(k, v)| format!("{k}={v}")).collect::<Vec<_>>();
+        text.push_str(&format!(" [{}]", props.join(", ")));
+    }
     *writer.text(q_text.into_inner(), 0) = text;
-}
\ No newline at end of file
+}
+
+fn update_saving_text(
+    worker: Res<SaveWorker>,
+    q_world: Query<&BlockWorld>,
+    q_text: Single<Entity, With<SavingText>>,
+    mut writer: TextUiWriter,
+) {
+    let unsaved = q_world.iter().map(|w| w.dirty_chunk_count()).sum::<usize>();
+    let text = match (worker.is_saving(), unsaved) {
+        (true, _) => String::from("Saving..."),
+        (false, 0) => String::new(),
+        (false, n) => format!("{n} unsaved chunks"),
+    };
+    let mut current = writer.text(q_text.into_inner(), 0);
+    // only write on change, so the text isn't re-laid out every frame
+    if *current != text {
+        *current = text;
+    }
+}
+
+// typing into the dev console. Backquote opens and closes it, and the cursor is released while it's open.
+fn console_input(
+    mut commands: Commands,
+    mut console: ResMut<DevConsole>,
+    mut key_events: MessageReader<KeyboardInput>,
+    mut cursor_options: Single<&mut CursorOptions, With<PrimaryWindow>>,
+) {
+    for event in key_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        if event.key_code == KeyCode::Backquote {
+            console.open = !console.open;
+            console.input.clear();
+            continue;
+        }
+        if !console.open {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Enter => {
+                let input = std::mem::take(&mut console.input);
+                if !input.trim().is_empty() {
+                    commands.trigger(RunCommandEvent {
+                        input,
+                    });
+                }
+            }
+            Key::Backspace => {
+                console.input.pop();
+            }
+            Key::Escape => {
+                console.open = false;
+                console.input.clear();
+            }
+            _ => {
+                if let Some(text) = &event.text {
+                    console.input.extend(text.chars().filter(|c| !c.is_control()));
+                }
+            }
+        }
+    }
+
+    if console.is_changed() {
+        if console.open {
+            cursor_options.grab_mode = CursorGrabMode::None;
+            cursor_options.visible = true;
+        }
+        else {
+            cursor_options.grab_mode = CursorGrabMode::Locked;
+            cursor_options.visible = false;
+        }
+    }
+}
+
+fn update_console_text(
+    console: Res<DevConsole>,
+    q_text: Single<(Entity, &mut Visibility), With<ConsoleText>>,
+    mut writer: TextUiWriter,
+) {
+    if !console.is_changed() {
+        return;
+    }
+    let (entity, mut vis) = q_text.into_inner();
+    // output stays visible after closing, the input line only shows while open
+    let mut lines = console.get_output().collect::<Vec<_>>().join("\n");
+    if console.open {
+        if !lines.is_empty() {
+            lines.push('\n');
+        }
+        lines.push_str(&format!("> {}_", console.input));
+    }
+    *vis = if lines.is_empty() { Visibility::Hidden } else { Visibility::Inherited };
+    *writer.text(entity, 0) = lines;
+}
+
+fn update_loading_text(
+    diagnostics: Res<DiagnosticsStore>,
+    q_text: Single<Entity, With<LoadingText>>,
+    mut writer: TextUiWriter,
+) {
+    let count = |path| diagnostics.get_measurement(path).map_or(0.0, |m| m.value);
+    let (ready, total) = (count(&SPAWN_CHUNKS_READY), count(&SPAWN_CHUNKS_TOTAL));
+    *writer.text(q_text.into_inner(), 0) = format!("Loading world... {ready} / {total} chunks");
+}
@@ -1,67 +1,219 @@
+use crate::asset::block::RenderLayer;
 use crate::core::errors::ChunkError::{DuplicateChunk, NotFound};
-use crate::core::errors::{BlockStateError, ChunkError, WorldError};
-use crate::core::event::SetBlockEvent;
-use crate::registry::block::Block;
-use crate::registry::{Registry, RegistryHandle};
+use crate::core::errors::{BlockStateError, ChunkError, MeshError, WorldError};
+use crate::core::event::{BlockChangeSource, BulkSetBlockEvent, ChunkLifecycle, ChunkLifecycleEvent, SetBlockEvent};
+use crate::core::profiling::{PipelineProfiler, PipelineStage};
+use crate::core::state::MainGameState;
+use crate::math::block::{BlockPos, Vec3Ext};
+use crate::math::ray;
+use crate::math::ray::RayResult;
+use crate::registry::block::{Block, AIR_ID};
+use crate::registry::{Registry, RegistryHandle, RegistryObject};
 use crate::render;
 use crate::render::block::BlockTextures;
 use crate::render::block::MeshDataCache;
-use crate::world::chunk::{Chunk, ChunkData, ChunkMarker, ChunkMeshMarker, ChunkNeedsMeshing};
-use crate::world::{chunk, noise_gen_function, temp_gen_function};
+use crate::world::shape::BlockShapes;
+use crate::world::chunk::{Chunk, ChunkData, ChunkGenerationStatus, ChunkStorage, ChunkMarker, ChunkMeshMarker, ChunkNeedsMeshing, ChunkPattern};
+use crate::world::pipeline::GeneratedChunk;
+use crate::world::{chunk, save};
+use crate::RunConfig;
 use bevy::app::PostUpdate;
 use bevy::asset::Assets;
-use bevy::ecs::system::SystemState;
 use bevy::log::info_span;
+use bevy::mesh::Indices;
 use bevy::math::{ivec3, Vec3};
 use bevy::pbr::MeshMaterial3d;
-use bevy::prelude::{error, info, warn, App, Children, Commands, Component, Entity, EventWriter, Events, First, IVec3, IntoScheduleConfigs, Mesh, Mesh3d, PreUpdate, Query, QueryState, Res, ResMut, Single, Visibility, With};
+use bevy::pbr::wireframe::NoWireframe;
+use bevy::prelude::{debug, error, in_state, DetectChanges, warn, App, BevyError, ChildOf, Children, Commands, Component, Entity, Has, Last, IVec3, IntoScheduleConfigs, Local, Mesh, Mesh3d, PreUpdate, Query, Res, ResMut, Resource, Transform, Visibility, With};
 use bevy::tasks::futures_lite::future;
 use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
-use std::collections::hash_map::Iter;
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
-use std::rc::Rc;
-use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use bevy::platform::collections::{hash_map as fast_hash_map, HashMap as FastHashMap};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::path::Path;
+use std::time::Instant;
 use bevy::camera::primitives::Aabb;
 use serde::{Deserialize, Serialize};
 use crate::core::errors::BlockStateError::InvalidId;
-use crate::world::generation::{SineHeightMap, WorldGenerator};
+use crate::world::dimension::{ActiveWorld, DimensionId};
+use crate::world::generation::WorldGenerator;
+use crate::world::camera::CameraSettings;
+use crate::world::player::Player;
 
 /// A component that represents a world that can be read/written from. Stores the actual Chunk map,
 /// along with information about the world status (i.e. chunk generation status)
 #[derive(Component)]
 pub struct BlockWorld {
+    dimension: DimensionId,
     map: ChunkMap,
     chunk_queue: ChunkQueue,
+    /// Chunks with edits that haven't been saved to disk yet.
+    dirty_chunks: HashSet<IVec3>,
+    /// Chunks taken out of `dirty_chunks` for a save that hasn't finished writing yet.
+    saving_chunks: HashSet<IVec3>,
+    /// Chunks that have a save on disk, and should be loaded instead of generated.
+    saved_chunks: HashSet<IVec3>,
+    /// A chunk position and despawn distance every loaded chunk is known to be in range of, see [`BlockWorld::get_keep_center`].
+    keep_center: Option<(IVec3, u32)>,
+    /// Squared chunk distance from the player that chunks stop being generated at, once the memory budget has unloaded
+    /// chunks inside render distance. See [`BlockWorld::in_budget_range`].
+    budget_distance: Option<i64>,
 }
 
 
 
 /// A bunch of queues and maps representing chunks that are currently being generated, and chunks that are currently being meshed.
 /// Stores the tasks for these jobs too.
+///
+/// Chunks start generating in the order they were queued, and finished ones are picked up in the order they started.
+/// Which tasks are done by a given frame still depends on the thread pool, unless the `deterministic_order` feature is on
+/// (it always is in unit tests). Then a chunk isn't handed over until every chunk that started before it has been.
 #[derive(Debug, Default)]
 pub struct ChunkQueue {
     to_generate: VecDeque<IVec3>,
+    /// Chunks that are saved on disk. These always go before anything in `to_generate`.
+    to_load: VecDeque<IVec3>,
+    /// Everything in `to_generate` and `to_load`, so checking if a chunk is queued doesn't have to search the queues.
+    pending: HashSet<IVec3>,
     to_despawn: VecDeque<IVec3>,
-    currently_generating: HashMap<IVec3, Task<ChunkData>>,
-    finished_generating: VecDeque<(IVec3, ChunkData)>,
-    currently_meshing: HashMap<IVec3, Task<Option<Mesh>>>,
-    finished_meshing: VecDeque<(IVec3, Option<Mesh>)>,
+    currently_generating: FastHashMap<IVec3, Task<GeneratedChunk>>,
+    /// Everything in `currently_generating`, in the order it started.
+    generation_order: VecDeque<IVec3>,
+    finished_generating: VecDeque<(IVec3, GeneratedChunk)>,
+    currently_meshing: FastHashMap<IVec3, Task<MeshResult>>,
+    finished_meshing: VecDeque<(IVec3, MeshResult)>,
+    /// How many times each chunk has failed to mesh in a row.
+    mesh_failures: FastHashMap<IVec3, u32>,
+    /// Lifecycle steps since the last time they were sent out as [`ChunkLifecycleEvent`]s, oldest first.
+    lifecycle: Vec<(IVec3, ChunkLifecycle)>,
+}
+
+/// How many times a chunk can fail to mesh before it's given up on.
+pub const MAX_MESH_RETRIES: u32 = 3;
+
+/// The result of a meshing task.
+#[derive(Debug)]
+pub enum MeshResult {
+    /// The chunk is all air, so there's nothing to draw.
+    Empty,
+    /// One mesh per render layer that has any faces.
+    Meshes(Vec<(RenderLayer, Mesh)>),
+    /// Meshing went wrong. If the error can clear (see [`MeshError::can_retry`]) the chunk gets queued for meshing again,
+    /// up to [`MAX_MESH_RETRIES`] times.
+    Failed(MeshError),
+}
+
+impl ChunkQueue {
+    /// Removes a chunk from every generation and meshing queue. Dropping a task cancels it, so any in-flight work is thrown away.
+    /// Returns true if the chunk was in any of the queues.
+    fn cancel(&mut self, pos: IVec3) -> bool {
+        let mut found = false;
+        found |= self.remove_pending(&HashSet::from([pos]));
+        found |= self.currently_generating.remove(&pos).is_some();
+        remove_from_queue(&mut self.generation_order, |p| *p == pos);
+        found |= remove_from_queue(&mut self.finished_generating, |(p, _)| *p == pos);
+        found |= self.currently_meshing.remove(&pos).is_some();
+        found |= remove_from_queue(&mut self.finished_meshing, |(p, _)| *p == pos);
+        self.mesh_failures.remove(&pos);
+        found
+    }
+
+    // counts a failed mesh, and decides if it's worth trying again
+    fn should_retry_meshing(&mut self, pos: IVec3, error: &MeshError) -> bool {
+        let failures = self.mesh_failures.entry(pos).or_insert(0);
+        *failures += 1;
+        if !error.can_retry() {
+            error!("Chunk {pos} failed to mesh, giving up: {error}");
+        }
+        else if *failures > MAX_MESH_RETRIES {
+            error!("Chunk {pos} failed to mesh {failures} times, giving up: {error}");
+        }
+        else {
+            warn!("Chunk {pos} failed to mesh, retrying: {error}");
+            return true;
+        }
+        self.mesh_failures.remove(&pos);
+        false
+    }
+
+    fn start_generating(&mut self, pos: IVec3, task: Task<GeneratedChunk>) {
+        self.currently_generating.insert(pos, task);
+        self.generation_order.push_back(pos);
+    }
+
+    fn push_pending(&mut self, pos: IVec3, saved: bool) {
+        self.pending.insert(pos);
+        match saved {
+            true => self.to_load.push_back(pos),
+            false => self.to_generate.push_back(pos),
+        }
+    }
+
+    /// Takes the next chunk to generate, saved chunks first. Returns it, and whether it's saved on disk.
+    fn pop_pending(&mut self) -> Option<(IVec3, bool)> {
+        let next = match self.to_load.pop_front() {
+            Some(pos) => (pos, true),
+            None => (self.to_generate.pop_front()?, false),
+        };
+        self.pending.remove(&next.0);
+        Some(next)
+    }
+
+    // moves the `n` queued chunks nearest to `center` to the front of their queues, so whatever is around the player generates first.
+    // Saved chunks still go before everything else.
+    fn prioritize(&mut self, center: IVec3, n: usize) {
+        for queue in [&mut self.to_load, &mut self.to_generate] {
+            let nearest = chunk::nearest_n(center, queue.iter().copied(), n);
+            let front = nearest.iter().copied().collect::<HashSet<_>>();
+            queue.retain(|p| !front.contains(p));
+            for pos in nearest.into_iter().rev() {
+                queue.push_front(pos);
+            }
+        }
+    }
+
+    // drops these chunks from the generation queues in one pass, returns true if any were queued
+    fn remove_pending(&mut self, positions: &HashSet<IVec3>) -> bool {
+        // checking the set first means chunks that aren't queued don't cost a walk through the queues
+        let removed = positions.iter().filter(|p| self.pending.remove(*p)).count();
+        if removed == 0 {
+            return false;
+        }
+        self.to_generate.retain(|p| !positions.contains(p));
+        self.to_load.retain(|p| !positions.contains(p));
+        true
+    }
+}
+
+// removes everything matching from a queue, returns true if anything was removed
+fn remove_from_queue<T>(queue: &mut VecDeque<T>, matches: impl Fn(&T) -> bool) -> bool {
+    let len = queue.len();
+    queue.retain(|t| !matches(t));
+    queue.len() != len
 }
 
 
 
 impl BlockWorld {
-    pub fn new() -> Self {
+    /// Creates an empty world. `chunk_capacity` is how many chunks the chunk map has room for up front,
+    /// see [`CameraSettings::get_chunk_capacity`].
+    pub fn new(dimension: DimensionId, chunk_capacity: usize) -> Self {
         BlockWorld {
-            map: ChunkMap::default(),
+            dimension,
+            map: ChunkMap::with_capacity(chunk_capacity),
             chunk_queue: ChunkQueue::default(),
+            dirty_chunks: HashSet::new(),
+            saving_chunks: HashSet::new(),
+            saved_chunks: HashSet::new(),
+            keep_center: None,
+            budget_distance: None,
         }
     }
 
     /// Gets a block at a given Block position.
     /// Note: this creates and discards a `RwLockReadGuard`, which may be slow if doing large amounts of reads. In this case, consider accessing the chunk map directly.
     pub fn get_block(&self, pos: &IVec3) -> Result<BlockState, WorldError> {
-        let pos = pos.clone();
+        let pos = *pos;
         let chunk_pos = chunk::pos_to_chunk_pos(pos);
         let chunk_local = chunk::pos_to_chunk_local(pos);
         let Some(chunk) = self.map.get_chunk(&chunk_pos) else {
@@ -70,32 +222,240 @@ impl BlockWorld {
         Ok(chunk.get_block(chunk_local)?)
     }
 
-    /// Sets a block at a given Block position.
+    /// Sets a block at a given Block position. `source` is passed along to the fired [`SetBlockEvent`].
     /// Note: this creates and discards a `RwLockWriteGuard`, which may be slow if doing large amounts of writes. In this case, consider accessing the chunk map directly.
-    pub fn set_block(&mut self, commands: &mut Commands, pos: &IVec3, block: BlockState) -> Result<BlockState, WorldError> {
-        let pos = pos.clone();
+    pub fn set_block(&mut self, commands: &mut Commands, pos: &IVec3, block: BlockState, source: BlockChangeSource) -> Result<BlockState, WorldError> {
+        let pos = *pos;
         let chunk_pos = chunk::pos_to_chunk_pos(pos);
         let chunk_local = chunk::pos_to_chunk_local(pos);
         let Some(chunk) = self.map.get_chunk_mut(&chunk_pos) else {
             return Err(WorldError::UnloadedChunk(chunk_pos));
         };
         let res = chunk.set_block(chunk_local, block.clone())?;
+        if res != block {
+            self.dirty_chunks.insert(chunk_pos);
+        }
 
         commands.trigger(SetBlockEvent {
-            pos: pos,
+            pos,
             old: res.clone(),
             new: block,
+            source,
+            dimension: self.dimension.clone(),
         });
 
         Ok(res)
     }
 
+    /// Fills every block in the region between `min` and `max` (inclusive) with `block`.
+    /// Fires one [`BulkSetBlockEvent`] per chunk that changed, rather than one [`SetBlockEvent`] per block.
+    /// Fails without changing anything if any chunk in the region isn't loaded. Returns the number of blocks changed.
+    /// If setting a block fails partway through, the blocks changed before it stay changed, and still get their events and remesh.
+    pub fn fill(&mut self, commands: &mut Commands, min: &IVec3, max: &IVec3, block: BlockState, source: BlockChangeSource) -> Result<usize, WorldError> {
+        let (min, max) = (min.min(*max), min.max(*max));
+        let (min_chunk, max_chunk) = (chunk::pos_to_chunk_pos(min), chunk::pos_to_chunk_pos(max));
+
+        // make sure everything is loaded before we touch anything
+        let mut chunks = Vec::new();
+        for x in min_chunk.x..=max_chunk.x {
+            for y in min_chunk.y..=max_chunk.y {
+                for z in min_chunk.z..=max_chunk.z {
+                    let chunk_pos = ivec3(x, y, z);
+                    let Some(chunk) = self.map.get_chunk(&chunk_pos).filter(|c| c.is_initialized()) else {
+                        return Err(WorldError::UnloadedChunk(chunk_pos));
+                    };
+                    chunks.push((chunk_pos, chunk.get_data()?));
+                }
+            }
+        }
+
+        let mut total = 0;
+        for (chunk_pos, data) in chunks {
+            // clamp the region to this chunk
+            let chunk_min = chunk::chunk_pos_to_world_pos(chunk_pos);
+            let chunk_max = chunk_min + IVec3::splat(ChunkData::CHUNK_SIZE as i32 - 1);
+            let (from, to) = (min.max(chunk_min), max.min(chunk_max));
+
+            // one write lock per chunk
+            let mut changed_positions = Vec::new();
+            let region = (from.x..=to.x)
+                .flat_map(|x| (from.y..=to.y).flat_map(move |y| (from.z..=to.z).map(move |z| ivec3(x, y, z))))
+                .map(|pos| (pos, &block));
+            let result = set_blocks_in_chunk(&mut data.write().unwrap(), region, &mut changed_positions);
+
+            total += self.finish_bulk_edit(commands, chunk_pos, changed_positions, source);
+            result?;
+        }
+        Ok(total)
+    }
+
+    // marks a chunk changed by a bulk edit dirty and fires its event. Returns how many blocks changed.
+    fn finish_bulk_edit(&mut self, commands: &mut Commands, chunk_pos: IVec3, changed_positions: Vec<IVec3>, source: BlockChangeSource) -> usize {
+        if changed_positions.is_empty() {
+            return 0;
+        }
+        let total = changed_positions.len();
+        self.dirty_chunks.insert(chunk_pos);
+        commands.trigger(BulkSetBlockEvent {
+            chunk_pos,
+            changed_positions,
+            source,
+            dimension: self.dimension.clone(),
+        });
+        total
+    }
+
+    /// Replaces a whole chunk with a test pattern of `block` and air, writing straight into its data.
+    /// Fires a [`BulkSetBlockEvent`] like [`BlockWorld::fill`], so the chunk gets remeshed. Returns the number of blocks changed.
+    /// Like `fill`, blocks changed before an error stay changed and still fire the event.
+    pub fn fill_pattern(&mut self, commands: &mut Commands, chunk_pos: IVec3, pattern: ChunkPattern, block: BlockState, source: BlockChangeSource) -> Result<usize, WorldError> {
+        let Some(chunk) = self.map.get_chunk(&chunk_pos).filter(|c| c.is_initialized()) else {
+            return Err(WorldError::UnloadedChunk(chunk_pos));
+        };
+        let data = chunk.get_data()?;
+        let origin = chunk::chunk_pos_to_world_pos(chunk_pos);
+        let air = BlockState::air();
+
+        let mut changed_positions = Vec::new();
+        let size = ChunkData::CHUNK_SIZE as i32;
+        let blocks = (0..size)
+            .flat_map(|x| (0..size).flat_map(move |y| (0..size).map(move |z| ivec3(x, y, z))))
+            .map(|local| (origin + local, if pattern.is_filled(local) { &block } else { &air }));
+        let result = set_blocks_in_chunk(&mut data.write().unwrap(), blocks, &mut changed_positions);
+
+        let total = self.finish_bulk_edit(commands, chunk_pos, changed_positions, source);
+        result?;
+        Ok(total)
+    }
+
+    /// Raycasts against the blocks in this world, hitting the first block whose hit box (see [`BlockShapes`]) the ray passes through.
+    /// Partial blocks like slabs are only hit where their model is, and blocks without a model (like barriers) on their collision shape.
+    /// Unloaded chunks are treated as empty. For custom hit tests, use [`ray::block_raycast`] directly.
+    /// Each chunk the ray passes through is only looked up and locked once, see [`ChunkReader`].
+    pub fn raycast(&self, start: Vec3, direction: Vec3, max_distance: f32, shapes: &BlockShapes) -> RayResult {
+        self.raycast_with(&mut ChunkReader::new(&self.map), start, direction, max_distance, shapes)
+    }
+
+    /// [`BlockWorld::raycast`], reading blocks through `reader`.
+    pub fn raycast_with(&self, reader: &mut ChunkReader, start: Vec3, direction: Vec3, max_distance: f32, shapes: &BlockShapes) -> RayResult {
+        let steps = ray::BlockSteps::new(start, direction, max_distance);
+        let context = steps.get_context().clone();
+        reader.scan(steps.map(|step| (step.pos, step.face)), |b_pos, face, block| {
+            let hit_box = shapes.get_hit_box(block)?;
+            let origin = b_pos.as_vec3();
+            let (distance, box_face) = ray::ray_box_intersection(context.start, context.direction, origin + hit_box.min, origin + hit_box.max)?;
+            if distance > max_distance {
+                return None;
+            }
+            Some(RayResult::Hit(context.start + context.direction * distance, box_face.unwrap_or(face), b_pos, distance))
+        }).unwrap_or(RayResult::Miss)
+    }
+
+    pub fn get_dimension(&self) -> &DimensionId {
+        &self.dimension
+    }
+
+    /// Whether a chunk has edits that haven't been saved yet.
+    pub fn is_dirty(&self, pos: &IVec3) -> bool {
+        self.dirty_chunks.contains(pos)
+    }
+
+    /// Takes every dirty chunk, marking them all as clean. The caller is responsible for actually saving them,
+    /// and calling [`BlockWorld::finish_saving`] once they're written.
+    /// Sorted by position, so chunks are always saved in the same order.
+    pub fn take_dirty_chunks(&mut self) -> Vec<IVec3> {
+        let mut dirty = std::mem::take(&mut self.dirty_chunks).into_iter().collect::<Vec<_>>();
+        self.saving_chunks.extend(dirty.iter().copied());
+        dirty.sort_by_key(|pos| pos.to_array());
+        dirty
+    }
+
+    /// Called once the chunks from [`BlockWorld::take_dirty_chunks`] have been written, marking them as saved on disk.
+    /// If writing failed they count as dirty again, so the next save retries them.
+    pub fn finish_saving(&mut self, success: bool) {
+        let saving = std::mem::take(&mut self.saving_chunks);
+        if success {
+            self.saved_chunks.extend(saving);
+        }
+        else {
+            self.dirty_chunks.extend(saving);
+        }
+    }
+
+    /// Drops a chunk from the save in progress without writing it, e.g. because it was unloaded before it could be packed.
+    pub fn cancel_saving(&mut self, pos: &IVec3) {
+        self.saving_chunks.remove(pos);
+    }
+
+    /// Every chunk with edits that aren't on disk yet, including ones a save is still writing. In no particular order.
+    pub fn dirty_chunks(&self) -> impl Iterator<Item = &IVec3> {
+        self.dirty_chunks.union(&self.saving_chunks)
+    }
+
+    /// How many chunks have edits that aren't on disk yet, see [`BlockWorld::dirty_chunks`].
+    pub fn dirty_chunk_count(&self) -> usize {
+        self.dirty_chunks().count()
+    }
+
+    /// Whether a chunk has a save on disk.
+    pub fn is_saved(&self, pos: &IVec3) -> bool {
+        self.saved_chunks.contains(pos)
+    }
+
+    pub fn set_saved_chunks(&mut self, saved_chunks: HashSet<IVec3>) {
+        self.saved_chunks = saved_chunks;
+    }
+
     pub fn get_chunk_map(&self) -> &ChunkMap {
         &self.map
     }
 
     pub fn is_queued_for_generation(&self, pos: &IVec3) -> bool {
-        self.chunk_queue.currently_generating.contains_key(pos)
+        self.chunk_queue.currently_generating.contains_key(pos) || self.chunk_queue.pending.contains(pos)
+    }
+
+    /// All chunks that are waiting to generate or load. Chunks are added to the chunk map once they start generating,
+    /// so these are the only ones not in it yet.
+    pub fn get_pending_generation(&self) -> impl Iterator<Item = &IVec3> {
+        self.chunk_queue.to_load.iter().chain(self.chunk_queue.to_generate.iter())
+    }
+
+    /// Resolves chunks that are queued for both generation and despawning, which can happen when the player moves
+    /// away and back before the queues are processed. If `keep` says a chunk should stay, the despawn is dropped.
+    /// Otherwise the generation is dropped, and the despawn too if there's nothing loaded to despawn.
+    pub fn reconcile_queues(&mut self, keep: impl Fn(IVec3) -> bool) {
+        let (map, queue) = (&self.map, &mut self.chunk_queue);
+        let conflicts = queue.to_despawn.iter()
+            .filter(|pos| queue.pending.contains(pos))
+            .copied()
+            .collect::<HashSet<_>>();
+        if conflicts.is_empty() {
+            return;
+        }
+
+        // sorted so lifecycle events come out in the same order every run
+        let mut conflicts = conflicts.into_iter().collect::<Vec<_>>();
+        conflicts.sort_by_key(|pos| pos.to_array());
+
+        // work out what to drop first, so each queue is only walked once
+        let mut drop_despawn = HashSet::new();
+        let mut drop_generation = HashSet::new();
+        for pos in conflicts {
+            if keep(pos) {
+                drop_despawn.insert(pos);
+            }
+            else {
+                drop_generation.insert(pos);
+                if map.get_chunk(&pos).is_none() {
+                    drop_despawn.insert(pos);
+                    queue.lifecycle.push((pos, ChunkLifecycle::Despawned));
+                }
+            }
+        }
+        queue.remove_pending(&drop_generation);
+        if !drop_despawn.is_empty() {
+            queue.to_despawn.retain(|p| !drop_despawn.contains(p));
+        }
     }
 
     pub fn is_queued_for_meshing(&self, pos: &IVec3) -> bool {
@@ -116,9 +476,96 @@ impl BlockWorld {
     }
 
 
+    /// Counts how many chunks within `radius` chunks (a cube) of `center` are generated and meshed.
+    /// `needs_meshing` should say if a chunk entity is still waiting to be meshed. Returns (meshed, total).
+    pub fn count_meshed_around(&self, center: IVec3, radius: i32, needs_meshing: impl Fn(Entity) -> bool) -> (usize, usize) {
+        let mut meshed = 0;
+        let mut total = 0;
+        for x in -radius..radius + 1 {
+            for z in -radius..radius + 1 {
+                for y in -radius..radius + 1 {
+                    let pos = ivec3(x, y, z) + center;
+                    total += 1;
+                    let Some(chunk) = self.map.get_chunk(&pos) else {
+                        continue;
+                    };
+                    if chunk.is_initialized() && !needs_meshing(chunk.get_entity()) && !self.is_queued_for_meshing(&pos) {
+                        meshed += 1;
+                    }
+                }
+            }
+        }
+        (meshed, total)
+    }
+
+    /// Queues every chunk within `radius` chunks (a cube, not a sphere) of `pos` that isn't loaded or queued yet.
+    /// Returns the number of chunks queued.
+    pub fn load_around(&mut self, pos: Vec3, radius: i32) -> usize {
+        let center = chunk::pos_to_chunk_pos(pos.as_block_pos());
+        let mut queued = 0;
+        for x in -radius..radius + 1 {
+            for z in -radius..radius + 1 {
+                for y in -radius..radius + 1 {
+                    let coord = ivec3(x, y, z) + center;
+                    if self.map.get_chunk(&coord).is_some() || self.is_queued_for_generation(&coord) {
+                        continue;
+                    }
+                    self.queue_chunk_generation(coord);
+                    queued += 1;
+                }
+            }
+        }
+        queued
+    }
+
+    /// The chunk position and despawn distance of the last despawn check, if every loaded and pending chunk is still within that distance of it.
+    /// Moving from there only needs to check the chunks that leave range, instead of every loaded chunk.
+    /// Cleared whenever a chunk is queued from anywhere else, since it could be out of range.
+    pub fn get_keep_center(&self) -> Option<(IVec3, u32)> {
+        self.keep_center
+    }
+
+    pub fn set_keep_center(&mut self, center: IVec3, despawn_distance: u32) {
+        self.keep_center = Some((center, despawn_distance));
+    }
+
+    /// Whether a chunk is close enough to `center`, the player's chunk, to be generated under the memory budget.
+    /// Once the budget has unloaded chunks, chunks as far away as the nearest of them aren't generated again
+    /// until the budget changes, so they don't get generated and unloaded over and over.
+    pub fn in_budget_range(&self, center: IVec3, pos: IVec3) -> bool {
+        self.budget_distance.is_none_or(|limit| chunk::chunk_distance_squared(center, pos) < limit)
+    }
+
+    // stops generating chunks this far from `center` or further, including ones already queued
+    fn limit_to_budget(&mut self, center: IVec3, distance: i64) {
+        let limit = self.budget_distance.map_or(distance, |d| d.min(distance));
+        self.budget_distance = Some(limit);
+        let too_far = self.chunk_queue.pending.iter()
+            .filter(|pos| chunk::chunk_distance_squared(center, **pos) >= limit)
+            .copied()
+            .collect::<HashSet<_>>();
+        let mut sorted = too_far.iter().copied().collect::<Vec<_>>();
+        sorted.sort_by_key(|pos| pos.to_array());
+        for pos in sorted {
+            self.chunk_queue.lifecycle.push((pos, ChunkLifecycle::Despawned));
+        }
+        self.chunk_queue.remove_pending(&too_far);
+    }
+
+    /// Queues a chunk to be generated. Chunks that are saved on disk are loaded from there instead,
+    /// ahead of every chunk that needs fresh terrain, so the player sees their builds first.
     pub fn queue_chunk_generation(&mut self, pos: IVec3) {
-        self.chunk_queue.to_generate.push_back(pos);
+        // chunks can wait in the queue for a few frames if the task budget is used up, so don't queue them twice
+        if self.chunk_queue.pending.contains(&pos) {
+            return;
+        }
+        self.keep_center = None;
+        let saved = self.saved_chunks.contains(&pos);
+        self.chunk_queue.push_pending(pos, saved);
+        self.chunk_queue.lifecycle.push((pos, ChunkLifecycle::Queued));
     }
+    /// Queues a chunk to be despawned. Generation or meshing still running for it is cancelled once the despawn queue is processed,
+    /// so the chunk stays in the generation queues until then.
     pub fn queue_chunk_despawn(&mut self, pos: IVec3) {
         self.chunk_queue.to_despawn.push_back(pos);
     }
@@ -132,22 +579,62 @@ impl BlockWorld {
 
 
 
+// A poisoned lock means some other thread panicked mid-write. Poison never clears by itself, so if the data is still consistent
+// the lock is recovered and used as normal. Otherwise it can't be trusted, and meshing the chunk fails.
+fn read_or_recover(pos: IVec3, lock: &RwLock<ChunkData>) -> Result<RwLockReadGuard<'_, ChunkData>, MeshError> {
+    let guard = match lock.read() {
+        Ok(guard) => return Ok(guard),
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if let Err(e) = guard.validate() {
+        return Err(MeshError::PoisonedData(pos, e));
+    }
+    warn!("Data of chunk {pos} was poisoned but is still valid, recovering it.");
+    lock.clear_poison();
+    Ok(guard)
+}
+
+// sets (world pos, block) pairs that are all in one chunk, collecting the positions that actually changed.
+// Stops at the first error, but blocks set before it stay set and are still in `changed`.
+fn set_blocks_in_chunk<'a>(data: &mut ChunkData, blocks: impl Iterator<Item = (IVec3, &'a BlockState)>, changed: &mut Vec<IVec3>) -> Result<(), ChunkError> {
+    for (pos, block) in blocks {
+        let local = chunk::pos_to_chunk_local(pos);
+        let old = data.set_block(local.x as usize, local.y as usize, local.z as usize, block.clone())?;
+        if old != *block {
+            changed.push(pos);
+        }
+    }
+    Ok(())
+}
+
 /// Stores entity ids for all chunks currently loaded in the world / in memory.
 /// Backed by an Arc, so can be cloned and sent to other threads.
 /// All operations will require you to acquire a LockGuard first.
+///
+/// Uses bevy's fixed hasher (foldhash) instead of std's SipHash. `get_chunk` is called constantly during meshing
+/// and neighbor checks, and chunk positions don't need DoS resistance.
+///
+/// Iteration order: [`ChunkMap::iter`] is in hash map order. The hasher isn't seeded, so the same inserts and removes give the same
+/// order every run, but it's otherwise meaningless and changes whenever the map does. [`ChunkMap::iter_by_distance`] and
+/// [`ChunkMap::nearest_n`] are fully ordered, with ties broken by position. Anything where order matters should use those.
 #[derive(Debug)]
 pub struct ChunkMap {
-    data: HashMap<IVec3, Chunk>
+    data: FastHashMap<IVec3, Chunk>
 }
 impl Default for ChunkMap {
     fn default() -> Self {
-        Self {
-            data: HashMap::with_capacity(1000)
-        }
+        Self::with_capacity(ChunkMap::DEFAULT_CAPACITY)
     }
 }
 
 impl ChunkMap {
+    pub const DEFAULT_CAPACITY: usize = 1000;
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            data: FastHashMap::with_capacity(capacity)
+        }
+    }
 
     // gets the chunk entity at this position. Cheap to clone.
     pub fn get_chunk(&self, pos: &IVec3) -> Option<&Chunk> {
@@ -158,10 +645,35 @@ impl ChunkMap {
         self.data.get_mut(pos)
     }
     
-    pub fn iter(&self) -> Iter<'_, IVec3, Chunk> {
+    pub fn iter(&self) -> fast_hash_map::Iter<'_, IVec3, Chunk> {
         self.data.iter()
     }
 
+    /// How many chunks are loaded.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Iterates over all chunks from nearest to furthest from `center`, a chunk position, see [`chunk::distance_order`].
+    /// Sorts every chunk, so use [`ChunkMap::nearest_n`] if only the closest few are needed.
+    pub fn iter_by_distance(&self, center: IVec3) -> impl DoubleEndedIterator<Item = (&IVec3, &Chunk)> {
+        let mut chunks = self.data.iter().collect::<Vec<_>>();
+        chunks.sort_by_key(|(pos, _)| chunk::distance_order(center, **pos));
+        chunks.into_iter()
+    }
+
+    /// Gets the `n` chunks nearest to `center`, nearest first. Only keeps `n` chunks around while searching.
+    pub fn nearest_n(&self, center: IVec3, n: usize) -> Vec<(&IVec3, &Chunk)> {
+        chunk::nearest_n(center, self.data.keys().copied(), n)
+            .into_iter()
+            .filter_map(|pos| self.data.get_key_value(&pos))
+            .collect()
+    }
+
     pub fn add_chunk(&mut self, chunk: Chunk) -> Result<(), ChunkError> {
         let pos = chunk.get_pos();
         if self.data.contains_key(&pos) {
@@ -179,214 +691,720 @@ impl ChunkMap {
         }
         Ok(self.data.remove(&pos).unwrap())
     }
+
+    /// Gets the six chunks next to a chunk pos, indexed by [`Direction`] (see [`Direction::ALL`]).
+    pub fn neighbors(&self, pos: &IVec3) -> [Option<&Chunk>; 6] {
+        Direction::ALL.map(|dir| self.get_chunk(&pos.offset(dir)))
+    }
 }
 
+/// Reads blocks from a [`ChunkMap`] a chunk at a time. Runs of blocks in the same chunk share one lookup and one read lock,
+/// held until the blocks move into another chunk, so walking blocks in order (like a raycast) only fetches each chunk once.
+/// Packed chunks are read as they are, without unpacking them.
+pub struct ChunkReader<'a> {
+    map: &'a ChunkMap,
+    fetches: usize,
+}
+impl<'a> ChunkReader<'a> {
+    pub fn new(map: &'a ChunkMap) -> Self {
+        Self {
+            map,
+            fetches: 0,
+        }
+    }
 
+    /// Runs `f` on each block in order, along with whatever was passed with its position, until it returns Some.
+    /// Blocks in chunks that aren't loaded or generated are skipped.
+    pub fn scan<T, R>(&mut self, blocks: impl IntoIterator<Item = (IVec3, T)>, mut f: impl FnMut(IVec3, T, &BlockState) -> Option<R>) -> Option<R> {
+        let mut blocks = blocks.into_iter().peekable();
+        while let Some(&(first, _)) = blocks.peek() {
+            let chunk_pos = chunk::pos_to_chunk_pos(first);
+            let in_chunk = std::iter::from_fn(|| blocks.next_if(|(pos, _)| chunk::pos_to_chunk_pos(*pos) == chunk_pos));
+            self.fetches += 1;
+            let Some(storage) = self.map.get_chunk(&chunk_pos).filter(|c| c.is_initialized()).and_then(|c| c.read_storage()) else {
+                in_chunk.for_each(drop);
+                continue;
+            };
+            match &*storage {
+                ChunkStorage::Unpacked(data) => {
+                    let data = data.read().unwrap();
+                    for (pos, item) in in_chunk {
+                        let local = chunk::pos_to_chunk_local(pos);
+                        let Ok(entry) = data.lookup_palette(data.block_at(local.x as usize, local.y as usize, local.z as usize)) else {
+                            continue;
+                        };
+                        if let Some(result) = f(pos, item, &entry.block) {
+                            return Some(result);
+                        }
+                    }
+                }
+                ChunkStorage::Packed(packed) => {
+                    for (pos, item) in in_chunk {
+                        let local = chunk::pos_to_chunk_local(pos);
+                        let Some(block) = packed.get_block_state(local.x as usize, local.y as usize, local.z as usize) else {
+                            continue;
+                        };
+                        if let Some(result) = f(pos, item, block) {
+                            return Some(result);
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// How many times a chunk has been looked up in the map.
+    pub fn get_fetch_count(&self) -> usize {
+        self.fetches
+    }
+}
 
 
 
 
+
+
+/// Which kind of task gets first pick of the task budget when both are waiting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TaskPriority {
+    Meshing,
+    Generation,
+}
+
+/// Despawned chunk entities, kept around to be reused by new chunks instead of spawning fresh ones.
+/// Pooled entities have all of their components and mesh children removed, so nothing carries over between chunks.
+#[derive(Debug, Resource)]
+pub struct ChunkEntityPool {
+    free: Vec<Entity>,
+    /// Max entities kept in the pool. Anything past this is despawned like normal.
+    pub capacity: usize,
+}
+impl Default for ChunkEntityPool {
+    fn default() -> Self {
+        Self {
+            free: Vec::new(),
+            capacity: 1024,
+        }
+    }
+}
+impl ChunkEntityPool {
+    /// Gets an empty entity from the pool, or spawns a new one if it's empty.
+    pub fn take(&mut self, commands: &mut Commands) -> Entity {
+        match self.free.pop() {
+            Some(entity) => entity,
+            None => commands.spawn_empty().id(),
+        }
+    }
+
+    /// Clears out a chunk entity and its mesh children, and keeps it for reuse.
+    pub fn recycle(&mut self, commands: &mut Commands, entity: Entity) {
+        if self.free.len() >= self.capacity {
+            commands.entity(entity).despawn();
+            return;
+        }
+        commands.entity(entity).despawn_related::<Children>().clear();
+        self.free.push(entity);
+    }
+}
+
+/// Keeps chunks far from the player packed in memory, see [`ChunkStorage`](crate::world::chunk::ChunkStorage).
+/// Packed chunks are unpacked again as soon as something reads, edits or meshes them.
+#[derive(Debug, Resource)]
+pub struct ChunkPackingSettings {
+    /// Chunks further than this many chunks from the player get packed. None keeps every chunk unpacked.
+    /// Should be past the render distance, or chunks on screen get unpacked again every time they or a neighbour are remeshed.
+    pub pack_distance: Option<f32>,
+    /// How many chunks can be packed in one frame, so a big jump in position doesn't pack everything behind the player at once.
+    pub max_per_frame: usize,
+}
+impl Default for ChunkPackingSettings {
+    fn default() -> Self {
+        Self {
+            // off, since it depends on the render distance
+            pack_distance: None,
+            max_per_frame: 16,
+        }
+    }
+}
+
+/// Caps how much memory loaded chunk data can take up, across every world. See [`Chunk::memory_footprint`].
+/// Going over the budget unloads the chunks furthest from the player, even inside render distance, and pauses generation.
+/// Chunks that far from the player then aren't generated again until the budget is changed, see [`BlockWorld::in_budget_range`].
+#[derive(Debug, Resource)]
+pub struct ChunkMemorySettings {
+    /// Max bytes of chunk data. None means no limit.
+    pub budget: Option<usize>,
+    /// Unloading stops once usage is under this fraction of the budget, and generation only runs while it stays under it.
+    /// The gap keeps chunks at the edge from being unloaded and generated again every frame.
+    pub resume_fraction: f32,
+}
+impl Default for ChunkMemorySettings {
+    fn default() -> Self {
+        Self {
+            budget: Some(1024 * 1024 * 1024),
+            resume_fraction: 0.9,
+        }
+    }
+}
+
+/// Caps how much chunk mesh data gets handed to the GPU each frame, so a burst of finished meshes doesn't stall one frame.
+#[derive(Debug, Resource)]
+pub struct MeshUploadSettings {
+    /// Bytes of vertex and index data uploaded per frame, shared between every world. Meshes keep getting uploaded until this is used up,
+    /// so the last one can go over. A mesh bigger than the whole budget still gets uploaded, just on its own.
+    pub bytes_per_frame: usize,
+}
+impl Default for MeshUploadSettings {
+    fn default() -> Self {
+        Self {
+            // 1 MiB
+            bytes_per_frame: 1024 * 1024,
+        }
+    }
+}
+
+/// Caps how many generation and meshing tasks can be running on the [`AsyncComputeTaskPool`] at once.
+#[derive(Debug, Resource)]
+pub struct TaskBudget {
+    /// Max tasks running at once, of both kinds combined.
+    pub total: usize,
+    pub max_generation: usize,
+    pub max_meshing: usize,
+    pub priority: TaskPriority,
+}
+impl Default for TaskBudget {
+    fn default() -> Self {
+        Self {
+            total: 64,
+            max_generation: 48,
+            max_meshing: 32,
+            // meshing is what the player actually sees, so it goes first
+            priority: TaskPriority::Meshing,
+        }
+    }
+}
+impl TaskBudget {
+    /// Figures out how many new (generation, meshing) tasks can be started, given how many are running and waiting.
+    /// The kind with priority takes as many slots as it can first, and the other kind gets what's left.
+    pub fn allowance(&self, running_generation: usize, running_meshing: usize, pending_generation: usize, pending_meshing: usize) -> (usize, usize) {
+        let mut free = self.total.saturating_sub(running_generation + running_meshing);
+        let mut take = |running: usize, pending: usize, max: usize| {
+            let n = pending.min(max.saturating_sub(running)).min(free);
+            free -= n;
+            n
+        };
+        match self.priority {
+            TaskPriority::Meshing => {
+                let meshing = take(running_meshing, pending_meshing, self.max_meshing);
+                let generation = take(running_generation, pending_generation, self.max_generation);
+                (generation, meshing)
+            }
+            TaskPriority::Generation => {
+                let generation = take(running_generation, pending_generation, self.max_generation);
+                let meshing = take(running_meshing, pending_meshing, self.max_meshing);
+                (generation, meshing)
+            }
+        }
+    }
+}
+
+/// How many tasks of each kind may still be started this frame. Filled in by `plan_task_budget`.
+#[derive(Debug, Default, Resource)]
+struct TaskAllowance {
+    generation: usize,
+    meshing: usize,
+}
+
 // ===================================
 // Systems that require private access
 // ===================================
 pub fn add_systems(app: &mut App) {
     app
-        .add_systems(PostUpdate, (process_generate_queue, process_despawn_queue, receive_generated_chunks, insert_chunk_data, queue_mesh_creation).chain())
+        .init_resource::<TaskBudget>()
+        .init_resource::<TaskAllowance>()
+        .init_resource::<ChunkEntityPool>()
+        .init_resource::<ChunkPackingSettings>()
+        .init_resource::<ChunkMemorySettings>()
+        .init_resource::<MeshUploadSettings>()
+        // generation needs the frozen block registry, which isn't there until the game starts
+        .add_systems(PostUpdate, (plan_task_budget, enforce_memory_budget, process_generate_queue.run_if(in_state(MainGameState::InGame)), process_despawn_queue, receive_generated_chunks, insert_chunk_data, remesh_on_cache_swap, queue_mesh_creation, pack_distant_chunks).chain())
         .add_systems(PreUpdate, (receive_generated_meshes, upload_meshes))
+        .add_systems(Last, send_lifecycle_events)
     ;
 }
 
+// fires the lifecycle steps every world collected this frame, in order
+fn send_lifecycle_events(
+    mut q_world: Query<&mut BlockWorld>,
+    mut commands: Commands,
+) {
+    for mut world in q_world.iter_mut() {
+        if world.chunk_queue.lifecycle.is_empty() {
+            continue;
+        }
+        let dimension = world.dimension.clone();
+        for (pos, stage) in world.chunk_queue.lifecycle.drain(..) {
+            commands.trigger(ChunkLifecycleEvent {
+                pos,
+                dimension: dimension.clone(),
+                stage,
+            });
+        }
+    }
+}
+
+// packs far away chunks that nothing is using. Runs after meshing tasks start, so chunks they just borrowed are left alone.
+fn pack_distant_chunks(
+    q_world: Query<&BlockWorld>,
+    player: Query<&Transform, With<Player>>,
+    needs_meshing: Query<(), With<ChunkNeedsMeshing>>,
+    settings: Res<ChunkPackingSettings>,
+) {
+    let (Some(distance), Ok(player)) = (settings.pack_distance, player.single()) else {
+        return;
+    };
+    let center = chunk::pos_to_chunk_pos(player.translation.as_block_pos()).as_vec3();
+    let mut packed = 0;
+    'worlds: for world in q_world.iter() {
+        for (pos, chunk) in world.map.iter() {
+            if packed >= settings.max_per_frame {
+                break 'worlds;
+            }
+            // chunks waiting to be meshed would just get unpacked again
+            if !chunk.is_initialized() || chunk.is_packed() || needs_meshing.contains(chunk.get_entity()) {
+                continue;
+            }
+            if center.distance_squared(pos.as_vec3()) <= distance * distance {
+                continue;
+            }
+            // and so would their neighbours, which meshing reads too
+            if world.map.neighbors(pos).into_iter().flatten().any(|neighbor| needs_meshing.contains(neighbor.get_entity())) {
+                continue;
+            }
+            if chunk.pack() {
+                packed += 1;
+            }
+        }
+    }
+    if packed > 0 {
+        debug!("Packed {} distant chunks.", packed);
+    }
+}
+
+// splits the task budget between generation and meshing for this frame
+fn plan_task_budget(
+    q_world: Query<&BlockWorld>,
+    chunks_to_mesh: Query<(), With<ChunkNeedsMeshing>>,
+    budget: Res<TaskBudget>,
+    mut allowance: ResMut<TaskAllowance>,
+) {
+    let (mut running_generation, mut running_meshing, mut pending_generation) = (0, 0, 0);
+    for world in q_world.iter() {
+        running_generation += world.chunk_queue.currently_generating.len();
+        running_meshing += world.chunk_queue.currently_meshing.len();
+        pending_generation += world.chunk_queue.pending.len();
+    }
+    let pending_meshing = chunks_to_mesh.iter().count();
+
+    let (generation, meshing) = budget.allowance(running_generation, running_meshing, pending_generation, pending_meshing);
+    allowance.generation = generation;
+    allowance.meshing = meshing;
+}
+
+// unloads the furthest chunks while over the memory budget, and holds off generation until there's room again.
+// Chunks of inactive worlds count as furthest. Chunks with unsaved edits are skipped, they can go once an autosave has written them.
+// Chunks unloaded from the active world also stop anything that far from the player being generated again, see BlockWorld::in_budget_range.
+fn enforce_memory_budget(
+    mut q_world: Query<(Entity, &mut BlockWorld, Has<ActiveWorld>)>,
+    player: Query<&Transform, With<Player>>,
+    settings: Res<ChunkMemorySettings>,
+    mut allowance: ResMut<TaskAllowance>,
+) {
+    // a different budget might fit more chunks again
+    if settings.is_changed() {
+        for (_, mut world, _) in q_world.iter_mut() {
+            world.budget_distance = None;
+        }
+    }
+    let Some(budget) = settings.budget else {
+        return;
+    };
+    let resume = (budget as f64 * settings.resume_fraction as f64) as usize;
+    let mut usage = q_world.iter()
+        .map(|(_, world, _)| world.map.iter().map(|(_, chunk)| chunk.memory_footprint()).sum::<usize>())
+        .sum::<usize>();
+
+    if usage > budget {
+        let center = player.single().ok().map(|t| chunk::pos_to_chunk_pos(t.translation.as_block_pos())).unwrap_or(IVec3::ZERO);
+        // (distance, world, pos, bytes)
+        let mut candidates = Vec::new();
+        for (entity, world, active) in q_world.iter() {
+            for (pos, chunk) in world.map.iter() {
+                if world.is_dirty(pos) {
+                    continue;
+                }
+                let distance = if active { chunk::chunk_distance_squared(center, *pos) } else { i64::MAX };
+                candidates.push((distance, entity, *pos, chunk.memory_footprint()));
+            }
+        }
+        // furthest first, ties broken by position so the same chunks go every run
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.2.to_array().cmp(&b.2.to_array())));
+
+        let mut unloaded = 0;
+        // the active world and the distance of the nearest chunk unloaded from it
+        let mut nearest_unloaded = None;
+        for (distance, entity, pos, bytes) in candidates {
+            if usage <= resume {
+                break;
+            }
+            let Ok((_, mut world, active)) = q_world.get_mut(entity) else {
+                continue;
+            };
+            if active {
+                nearest_unloaded = Some((entity, distance));
+            }
+            world.queue_chunk_despawn(pos);
+            usage = usage.saturating_sub(bytes);
+            unloaded += 1;
+        }
+        if let Some((entity, distance)) = nearest_unloaded && let Ok((_, mut world, _)) = q_world.get_mut(entity) {
+            world.limit_to_budget(center, distance);
+        }
+        if unloaded > 0 {
+            warn!("Chunk data is over its memory budget ({} bytes), unloading {} chunks.", budget, unloaded);
+        }
+    }
+
+    if usage > resume {
+        allowance.generation = 0;
+    }
+}
+
 fn process_generate_queue(
-    mut single: Single<(&mut BlockWorld, &mut WorldGenerator)>,
+    mut q_world: Query<(Entity, &mut BlockWorld, &WorldGenerator)>,
     mut commands: Commands,
-    block_reg: Res<RegistryHandle<Block>>
+    block_reg: Res<RegistryHandle<Block>>,
+    profiler: Res<PipelineProfiler>,
+    run_config: Res<RunConfig>,
+    mut allowance: ResMut<TaskAllowance>,
+    player: Query<&Transform, With<Player>>,
+    camera_settings: Res<CameraSettings>,
+    mut pool: ResMut<ChunkEntityPool>,
+) {
+    // chunks queued both ways are kept if they're still close enough to the player
+    let center = player.single().ok().map(|t| chunk::pos_to_chunk_pos(t.translation.as_block_pos()));
+    let keep = |pos: IVec3| center.is_none_or(|c| camera_settings.in_keep_range(c, pos));
+
+    for (world_entity, mut world, generator) in q_world.iter_mut() {
+        world.reconcile_queues(keep);
+        if let Some(center) = center {
+            world.chunk_queue.prioritize(center, allowance.generation);
+        }
+        process_world_generate_queue(world_entity, world.as_mut(), generator, &mut commands, &block_reg, &profiler, &run_config.data_dir, &mut allowance.generation, &mut pool);
+    }
+}
+
+fn process_world_generate_queue(
+    world_entity: Entity,
+    world: &mut BlockWorld,
+    generator: &WorldGenerator,
+    commands: &mut Commands,
+    block_reg: &RegistryHandle<Block>,
+    profiler: &PipelineProfiler,
+    data_dir: &Path,
+    allowance: &mut usize,
+    pool: &mut ChunkEntityPool,
 ) {
-    let mut single = single.into_inner();
-    //rust rover not showing me types so gonna specify here
-    let (world, generator): (&mut BlockWorld, &mut WorldGenerator) = (single.0.as_mut(), single.1.as_mut());
     let (map, chunk_queue) = (&mut world.map, &mut world.chunk_queue);
     
     
     
-    if chunk_queue.to_generate.is_empty() {
+    if chunk_queue.pending.is_empty() {
         return;
     }
 
-    while !chunk_queue.to_generate.is_empty() {
-        let pos = chunk_queue.to_generate.pop_front().unwrap();
+    // anything left over stays queued until a later frame. Saved chunks go first.
+    while *allowance > 0 {
+        let Some((pos, saved)) = chunk_queue.pop_pending() else {
+            break;
+        };
+        let saved_file = saved.then(|| save::chunk_file(data_dir, &world.dimension, pos));
+        *allowance -= 1;
 
 
         // info!("Generating chunk {pos}");
 
-        // Create chunk entity
-        let chunk_entity = commands.spawn((
+        // Create chunk entity, reusing an old one if there is one. Chunks are children of their world, so hiding a world hides all of its chunks.
+        let chunk_entity = pool.take(commands);
+        commands.entity(chunk_entity).insert((
             ChunkMarker::new(pos),
             chunk::chunk_pos_to_transform(pos),
-            Visibility::Visible,
-            )).id();
+            Visibility::Inherited,
+            ChildOf(world_entity),
+            ));
 
         let chunk = Chunk::new(pos, chunk_entity);
 
         if let Err(e) = map.add_chunk(chunk) {
             error!("Failed to add chunk: {}", e);
+            pool.recycle(commands, chunk_entity);
             continue;
         }
+
         // create chunk generation task
 
         let reg = block_reg.clone();
 
-        let height_map = generator.borrow_height_map();
-        
+        let pipeline = generator.borrow_pipeline();
+        let ceiling = generator.borrow_ceiling();
+        let seed = generator.get_seed();
+        let profiler = profiler.clone();
         
         let task = AsyncComputeTaskPool::get().spawn(async move {
+            // if a save can't be read, fall back to generating it fresh
+            if let Some(file) = saved_file {
+                match save::load_chunk(&file) {
+                    Ok(data) => return data.into(),
+                    Err(e) => warn!("Could not load saved chunk {pos}, generating it instead: {e}"),
+                }
+            }
+            // nothing is ever generated up here, so skip the pipeline and hand it air straight away
+            if ceiling.is_above_terrain(pos) {
+                return ChunkData::single(BlockState::air()).into();
+            }
+            let _span = info_span!("generate_chunk").entered();
+            let start = Instant::now();
             // make_box(reg.as_ref())
             // temp_gen_function(pos, reg.as_ref())
-            noise_gen_function(pos, reg.as_ref(), height_map)
+            let generated = pipeline.generate(pos, seed, reg.as_ref());
+            profiler.record(PipelineStage::Generation, start.elapsed());
+            if cfg!(debug_assertions)
+                && let Err(e) = generated.terrain.validate()
+            {
+                error!("Generated invalid data for chunk {pos}: {e}");
+            }
+            generated
         });
 
-        chunk_queue.currently_generating.insert(pos, task);
+        chunk_queue.start_generating(pos, task);
     }
 }
 
 fn process_despawn_queue(
-    mut world: Single<&mut BlockWorld>,
+    mut q_world: Query<&mut BlockWorld>,
     mut commands: Commands,
+    mut pool: ResMut<ChunkEntityPool>,
+    run_config: Res<RunConfig>,
 ) {
-    let world = world.as_mut();
-    let (map, chunk_queue) = (&mut world.map, &mut world.chunk_queue);
-
-    if chunk_queue.to_despawn.is_empty() {
-        return;
-    }
-
+    for mut world in q_world.iter_mut() {
+        let world = world.as_mut();
 
-    while !chunk_queue.to_despawn.is_empty() {
-        let pos = chunk_queue.to_despawn.pop_front().unwrap();
-        let old_chunk = match map.remove_chunk(pos) {
-            Ok(o) => o,
-            Err(e) => {
-                error!("Error despawning chunks: {}", e);
+        while let Some(pos) = world.chunk_queue.to_despawn.pop_front() {
+            if !save_before_unload(world, pos, &run_config.data_dir) {
+                // stays loaded for now. Forgetting the keep center means the next move checks every chunk, so it gets another try then
+                world.keep_center = None;
                 continue;
             }
-        };
-        commands.entity(old_chunk.get_entity()).despawn();
+            let (map, chunk_queue) = (&mut world.map, &mut world.chunk_queue);
+            // drop any tasks for this chunk, so their results don't get processed after it's gone
+            let was_pending = chunk_queue.cancel(pos);
+            let old_chunk = match map.remove_chunk(pos) {
+                Ok(o) => o,
+                // chunks still waiting to generate aren't in the map yet
+                Err(_) if was_pending => {
+                    chunk_queue.lifecycle.push((pos, ChunkLifecycle::Despawned));
+                    continue;
+                }
+                Err(e) => {
+                    error!("Error despawning chunks: {}", e);
+                    continue;
+                }
+            };
+            pool.recycle(&mut commands, old_chunk.get_entity());
+            chunk_queue.lifecycle.push((pos, ChunkLifecycle::Despawned));
 
+        }
     }
 
 }
 
 
 
+// Writes a chunk's unsaved edits to disk right away, so unloading it doesn't lose them. Only edited chunks get written here.
+// Returns false if the chunk has to stay loaded: a save is still writing it (and could finish after a newer write here),
+// or writing failed and it would be the only copy of the edits.
+fn save_before_unload(world: &mut BlockWorld, pos: IVec3, data_dir: &Path) -> bool {
+    if world.saving_chunks.contains(&pos) {
+        return false;
+    }
+    if !world.dirty_chunks.contains(&pos) {
+        return true;
+    }
+    let Some(chunk) = world.map.get_chunk(&pos) else {
+        return true;
+    };
+    let file = save::chunk_file(data_dir, &world.dimension, pos);
+    let result = chunk.to_packed().map_err(BevyError::from).and_then(|packed| save::write_chunk(&file, &packed));
+    if let Err(e) = result {
+        error!("Couldn't save chunk {pos} in {} before unloading it, keeping it loaded: {e}", world.dimension);
+        return false;
+    }
+    world.dirty_chunks.remove(&pos);
+    world.saved_chunks.insert(pos);
+    true
+}
+
 // receives chunks that have finished generating.
 fn receive_generated_chunks(
-    mut world: Single<&mut BlockWorld>
+    mut q_world: Query<&mut BlockWorld>
 ) {
-    let mut chunk_queue = &mut world.chunk_queue;
-    let mut finished = VecDeque::new();
-    // this needs to be in a separate scope so the first mutable reference can be dropped.
-    {
-        for (coord, task) in chunk_queue.currently_generating.iter_mut() {
+    for mut world in q_world.iter_mut() {
+        let chunk_queue = &mut world.chunk_queue;
+        let mut still_running = VecDeque::with_capacity(chunk_queue.generation_order.len());
+        while let Some(coord) = chunk_queue.generation_order.pop_front() {
+            let Some(task) = chunk_queue.currently_generating.get_mut(&coord) else {
+                continue;
+            };
             let Some(data) = block_on(future::poll_once(task)) else {
+                still_running.push_back(coord);
+                // everything after this waits for it too
+                if cfg!(any(test, feature = "deterministic_order")) {
+                    break;
+                }
                 continue;
             };
-            finished.push_back((coord.clone(), data));
+            chunk_queue.currently_generating.remove(&coord);
+            chunk_queue.finished_generating.push_back((coord, data));
         }
-    }
-
-    while !finished.is_empty() {
-        let (coord, data) = finished.pop_front().unwrap();
-        chunk_queue.currently_generating.remove(&coord);
-        chunk_queue.finished_generating.push_back((coord.clone(), data));
+        // whatever wasn't looked at goes back after what's still running, so the order is kept
+        still_running.append(&mut chunk_queue.generation_order);
+        chunk_queue.generation_order = still_running;
     }
 }
 
 // Inserts chunk data for chunks that have finished generating, initializing their data.
 fn insert_chunk_data(
-    mut world: Single<&mut BlockWorld>,
+    mut q_world: Query<&mut BlockWorld>,
     mut commands: Commands,
 ) {
-    let world = world.as_mut();
+    for mut world in q_world.iter_mut() {
+        let world = world.as_mut();
 
-    let (map, chunk_queue) = (&mut world.map, &mut world.chunk_queue);
+        let (map, chunk_queue) = (&mut world.map, &mut world.chunk_queue);
 
-    if chunk_queue.finished_generating.is_empty() {
-        return;
-    }
+        // println!("Inserting {} chunk data.", chunk_queue.finished_generating.len());
+        // let mut write_guard = world.map.write_guard();
+        while !chunk_queue.finished_generating.is_empty() {
+            let (pos, GeneratedChunk { terrain, decorations }) = chunk_queue.finished_generating.pop_front().unwrap();
 
-    // println!("Inserting {} chunk data.", chunk_queue.finished_generating.len());
-    // let mut write_guard = world.map.write_guard();
-    while !chunk_queue.finished_generating.is_empty() {
-        let (pos, data) = chunk_queue.finished_generating.pop_front().unwrap();
+            // info!("Finished generating chunk {pos}, inserting...");
 
-        // info!("Finished generating chunk {pos}, inserting...");
 
+            let Some(chunk) = map.get_chunk_mut(&pos) else {
+                error!("Chunk {pos} doesn't exist!");
+                continue;
+            };
+            if let Err(e) = chunk.init_data(terrain) {
+                error!("Error initializing chunk: {e}");
+                continue;
+            }
+            // loaded chunks were saved fully generated, so they have no decorations left to place
+            if let Err(e) = chunk.place_decorations(&decorations) {
+                error!("Error decorating chunk: {e}");
+                continue;
+            }
+            // there's no lighting yet, so nothing else needs to happen before the chunk can be read and meshed
+            if let Err(e) = chunk.set_generation_status(ChunkGenerationStatus::Generated) {
+                error!("Error finishing chunk generation: {e}");
+                continue;
+            }
+            if chunk.is_initialized() {
+                chunk_queue.lifecycle.push((pos, ChunkLifecycle::Generated));
+            }
 
-        let Some(chunk) = map.get_chunk_mut(&pos) else {
-            error!("Chunk {pos} doesn't exist!");
-            continue;
-        };
-        if let Err(e) = chunk.init_data(data) {
-            error!("Error initializing chunk: {e}")
+            let _ = info_span!("insert_needs_meshing").entered();
+            let entity = chunk.get_entity();
+            commands.entity(entity).insert(ChunkNeedsMeshing);
         }
+    }
+}
 
-        let _ = info_span!("insert_needs_meshing").entered();
-        let entity = chunk.get_entity();
+// meshes every loaded chunk again when the block models change, so they don't keep showing the old ones
+fn remesh_on_cache_swap(
+    mesh_cache: Res<MeshDataCache>,
+    mut last_version: Local<u64>,
+    q_chunks: Query<Entity, With<ChunkMarker>>,
+    mut commands: Commands,
+) {
+    if mesh_cache.get_version() == *last_version {
+        return;
+    }
+    *last_version = mesh_cache.get_version();
+    for entity in q_chunks.iter() {
         commands.entity(entity).insert(ChunkNeedsMeshing);
     }
 }
 
 fn queue_mesh_creation(
-    mut world: Single<&mut BlockWorld>,
-    chunks_to_mesh: Query<(Entity, &ChunkMarker), With<ChunkNeedsMeshing>>,
+    mut q_world: Query<&mut BlockWorld>,
+    chunks_to_mesh: Query<(Entity, &ChunkMarker, &ChildOf), With<ChunkNeedsMeshing>>,
     mut commands: Commands,
 
-    mut mesh_cache: Res<MeshDataCache>,
+    mesh_cache: Res<MeshDataCache>,
+    profiler: Res<PipelineProfiler>,
+    mut allowance: ResMut<TaskAllowance>,
+    player: Query<&Transform, With<Player>>,
 ) {
 
     if chunks_to_mesh.is_empty() {
         return;
     }
-    let world = world.as_mut();
-    let (map, chunk_queue) = (&world.map, &mut world.chunk_queue);
 
-    let iter = chunks_to_mesh.iter();
+    // nearest to the player first, same order as generation
+    let center = player.single().ok().map(|t| chunk::pos_to_chunk_pos(t.translation.as_block_pos())).unwrap_or(IVec3::ZERO);
+    let mut to_mesh = chunks_to_mesh.iter().collect::<Vec<_>>();
+    to_mesh.sort_by_key(|(_, marker, _)| chunk::distance_order(center, marker.get_pos()));
 
-    for (entity, marker) in iter {
+    for (entity, marker, child_of) in to_mesh {
+        // out of budget, the rest keep their ChunkNeedsMeshing and get picked up next frame
+        if allowance.meshing == 0 {
+            break;
+        }
         let pos = marker.get_pos();
 
+        // chunk entities are always children of the world they belong to
+        let Ok(mut world) = q_world.get_mut(child_of.parent()) else {
+            error!("Chunk entity {pos} is not attached to a world!");
+            continue;
+        };
+        let world = world.as_mut();
+        let (map, chunk_queue) = (&world.map, &mut world.chunk_queue);
+
+        // already being meshed, but that mesh might be missing the newest edits.
+        // It keeps its ChunkNeedsMeshing, so it gets meshed once more after the current task finishes, no matter how many edits came in.
+        if chunk_queue.currently_meshing.contains_key(&pos) {
+            continue;
+        }
+
         // info!("Meshing chunk {pos}...");
 
         let chunk = map.get_chunk(&pos).expect("Leaked chunk entity found - chunk entity exists, but is not present in chunk map!");
 
-        let north = map.get_chunk(&(pos + ivec3(0, 0, 1)));
-        let south = map.get_chunk(&(pos + ivec3(0, 0, -1)));
-        let east = map.get_chunk(&(pos + ivec3(1, 0, 0)));
-        let west = map.get_chunk(&(pos + ivec3(-1, 0, 0)));
-        let up = map.get_chunk(&(pos + ivec3(0, 1, 0)));
-        let down = map.get_chunk(&(pos + ivec3(0, -1, 0)));
-        if let (Some(north), Some(south), Some(east), Some(west), Some(up), Some(down)) = (north, south, east, west, up, down) {
+        // every neighbor has to be loaded and generated, since meshing culls against them
+        let neighbors = map.neighbors(&pos);
+        if let [Some(up), Some(down), Some(north), Some(south), Some(east), Some(west)] = neighbors {
 
             // moved into thread
             let cache = mesh_cache.clone();
-            if !(
-                chunk.is_initialized() &&
-                north.is_initialized() &&
-                south.is_initialized() &&
-                east.is_initialized() &&
-                west.is_initialized() &&
-                up.is_initialized() &&
-                down.is_initialized()
-            ) {
+            let profiler = profiler.clone();
+            let neighbors = [up, down, north, south, east, west];
+            if !chunk.is_initialized() || !neighbors.iter().all(|c| c.is_initialized()) {
                 continue;
             }
 
             let data_arc = chunk.get_data().unwrap();
-            let north_arc = north.get_data().unwrap();
-            let south_arc = south.get_data().unwrap();
-            let east_arc = east.get_data().unwrap();
-            let west_arc = west.get_data().unwrap();
-            let up_arc = up.get_data().unwrap();
-            let down_arc = down.get_data().unwrap();
+            let neighbor_arcs = neighbors.map(|c| c.get_data().unwrap());
 
 
 
@@ -394,33 +1412,31 @@ fn queue_mesh_creation(
                 // read the data
 
 
-                let data = data_arc.read().unwrap();
-                let north_data = north_arc.read().unwrap();
-                let south_data = south_arc.read().unwrap();
-                let east_data = east_arc.read().unwrap();
-                let west_data = west_arc.read().unwrap();
-                let up_data = up_arc.read().unwrap();
-                let down_data = down_arc.read().unwrap();
-                let neighbors: render::chunk::NeighborData = (
-                    &north_data,
-                    &south_data,
-                    &east_data,
-                    &west_data,
-                    &up_data,
-                    &down_data,
-                );
-
-
-                if data.is_empty() {
-                    None
+                let data = match read_or_recover(pos, &data_arc) {
+                    Ok(data) => data,
+                    Err(e) => return MeshResult::Failed(e),
+                };
+                let mut neighbor_data = Vec::with_capacity(6);
+                for arc in neighbor_arcs.iter() {
+                    match read_or_recover(pos, arc) {
+                        Ok(guard) => neighbor_data.push(guard),
+                        Err(e) => return MeshResult::Failed(e),
+                    }
+                }
+                let neighbors: render::chunk::NeighborData = std::array::from_fn(|i| &*neighbor_data[i]);
+
+
+                if data.is_empty(cache.get_table()) {
+                    MeshResult::Empty
                 }
                 else {
                     // create the mesh
-                    Some(render::chunk::create_chunk_mesh(&data, &cache, neighbors))
+                    MeshResult::Meshes(render::chunk::create_chunk_mesh(&data, &cache, neighbors, &profiler))
                 }
 
             });
             chunk_queue.currently_meshing.insert(pos, task);
+            allowance.meshing -= 1;
 
             // info!("Submitted mesh job for {pos}");
             commands.entity(entity).remove::<ChunkNeedsMeshing>();
@@ -431,49 +1447,63 @@ fn queue_mesh_creation(
 
 
 fn receive_generated_meshes(
-    mut world: Single<&mut BlockWorld>,
+    mut q_world: Query<&mut BlockWorld>,
 ) {
-    let mut chunk_queue = &mut world.chunk_queue;
+    for mut world in q_world.iter_mut() {
+        let chunk_queue = &mut world.chunk_queue;
 
-    let mut finished = VecDeque::new();
-    {
-        for (coord, task) in chunk_queue.currently_meshing.iter_mut() {
+        let mut finished = VecDeque::new();
+        {
+            for (coord, task) in chunk_queue.currently_meshing.iter_mut() {
 
-            let Some(mesh) = block_on(future::poll_once(task)) else {
-                continue;
-            };
-            finished.push_back((coord.clone(), mesh));
+                let Some(mesh) = block_on(future::poll_once(task)) else {
+                    continue;
+                };
+                finished.push_back((*coord, mesh));
+            }
         }
-    }
-    while !finished.is_empty() {
-        let (coord, mesh) = finished.pop_front().unwrap();
-        chunk_queue.currently_meshing.remove(&coord);
-        chunk_queue.finished_meshing.push_back((coord.clone(), mesh));
+        while !finished.is_empty() {
+            let (coord, mesh) = finished.pop_front().unwrap();
+            chunk_queue.currently_meshing.remove(&coord);
+            chunk_queue.finished_meshing.push_back((coord, mesh));
 
+        }
     }
 }
 
 
 
 
-// how many MiB per frame can we upload to the GPU? Default 1.
-const MIB_PER_FRAME: i32 = 1024 * 1024 * 1;
-
+/// Bytes of vertex and index data a mesh takes up on the GPU.
+pub fn mesh_upload_size(mesh: &Mesh) -> usize {
+    let index_size = match mesh.indices() {
+        Some(Indices::U16(indices)) => indices.len() * size_of::<u16>(),
+        Some(Indices::U32(indices)) => indices.len() * size_of::<u32>(),
+        None => 0,
+    };
+    mesh.get_vertex_buffer_size() + index_size
+}
 
 fn upload_meshes(
     mut commands: Commands,
-    mut world: Single<&mut BlockWorld>,
+    mut q_world: Query<&mut BlockWorld>,
     q_children: Query<&Children, With<ChunkMarker>>,
     q_chunk_meshes: Query<&ChunkMeshMarker>,
     mut meshes: ResMut<Assets<Mesh>>,
     block_textures: Res<BlockTextures>,
+    upload_settings: Res<MeshUploadSettings>,
+    profiler: Res<PipelineProfiler>,
+    mut warned: Local<bool>,
 ) {
     let _span = info_span!("upload_meshes").entered();
+    let start = Instant::now();
 
-    let world = world.as_mut();
-    let (map, mut chunk_queue) = (&world.map, &mut world.chunk_queue);
-
-    if chunk_queue.finished_meshing.is_empty() {
+    // meshes would get spawned with a material that doesn't exist yet. They stay queued until it does
+    if !block_textures.is_ready() {
+        if !*warned {
+            warn!("Block textures aren't ready yet, holding back chunk meshes.");
+            *warned = true;
+        }
         return;
     }
 
@@ -485,72 +1515,120 @@ fn upload_meshes(
 
 
     // let mut new_entities = Vec::new();
-    let mut hard_process_limit = MIB_PER_FRAME;
-    while !chunk_queue.finished_meshing.is_empty() && hard_process_limit > 0 {
-
-        let (coord, Some(mesh)) = chunk_queue.finished_meshing.pop_front().unwrap() else {
-            // air - we don't need to make a mesh and can just move on
-            continue;
-        };
-
-        // info!("Uploading mesh {coord}");
-
+    // the upload budget is shared between all worlds
+    let mut bytes_uploaded = 0;
+    let mut uploaded = 0;
+    for mut world in q_world.iter_mut() {
+        let world = world.as_mut();
+        let (map, chunk_queue) = (&world.map, &mut world.chunk_queue);
+
+        while !chunk_queue.finished_meshing.is_empty() && bytes_uploaded < upload_settings.bytes_per_frame {
+
+            let (coord, result) = chunk_queue.finished_meshing.pop_front().unwrap();
+            let layer_meshes = match result {
+                MeshResult::Meshes(layer_meshes) => layer_meshes,
+                // air - nothing new to upload, but any old meshes still need removing
+                MeshResult::Empty => Vec::new(),
+                MeshResult::Failed(e) => {
+                    if chunk_queue.should_retry_meshing(coord, &e)
+                        && let Some(chunk) = map.get_chunk(&coord)
+                    {
+                        commands.entity(chunk.get_entity()).insert(ChunkNeedsMeshing);
+                    }
+                    continue;
+                }
+            };
+            chunk_queue.mesh_failures.remove(&coord);
 
-        // println!("Indices: {mesh_size}");
+            // info!("Uploading mesh {coord}");
 
-        // println!("Buffer size: {}, vertex size: {}, num vertices: {}", mesh.get_vertex_buffer_size(), mesh.get_vertex_size(), mesh.count_vertices());
 
-        // scales the amount of "work" done by how big this mesh is
-        // if the mesh is very big, less meshes will be uploaded this frame.
-        let to_sub = mesh.get_vertex_buffer_size();
+            // println!("Indices: {mesh_size}");
 
-        // println!("Coord: {}, count: {}", coord, counter.count);
+            // println!("Buffer size: {}, vertex size: {}, num vertices: {}", mesh.get_vertex_buffer_size(), mesh.get_vertex_size(), mesh.count_vertices());
 
-        let Some(chunk) = map.get_chunk(&coord) else {
-            warn!("Chunk {coord} no longer exists in Chunk Map, discarding mesh...");
-            continue;
-        };
-        let chunk_entity = chunk.get_entity();
-        // let mut component = q_chunks.get_mut(entity).expect("Invalid entity id");
+            // scales the amount of "work" done by how big the meshes are
+            // if the meshes are very big, less chunks will be uploaded this frame.
+            let mesh_size: usize = layer_meshes.iter().map(|(_, mesh)| mesh_upload_size(mesh)).sum();
 
+            // println!("Coord: {}, count: {}", coord, counter.count);
 
+            let Some(chunk) = map.get_chunk(&coord) else {
+                if !layer_meshes.is_empty() {
+                    warn!("Chunk {coord} no longer exists in Chunk Map, discarding mesh...");
+                }
+                continue;
+            };
+            let chunk_entity = chunk.get_entity();
+            // let mut component = q_chunks.get_mut(entity).expect("Invalid entity id");
+
+            // chunk may or may not already have meshes, one child per layer.
+            let mut old_meshes = Vec::new();
+            if let Ok(children) = q_children.get(chunk_entity) {
+                for child in children.iter() {
+                    if let Ok(ChunkMeshMarker(layer)) = q_chunk_meshes.get(*child) {
+                        old_meshes.push((*layer, *child));
+                    }
+                }
+            }
 
-        // create the mesh handle
-        let mesh_handle = meshes.add(mesh);
+            for (layer, mesh) in layer_meshes {
+                // create the mesh handle
+                let mesh_handle = meshes.add(mesh);
 
-        let mut needs_new_mesh = true;
-        // chunk may or may not already have a mesh.
-        if let Ok(children) = q_children.get(chunk_entity) {
-            //iter over all the children.
-            for child in children.iter() {
-                // does this child have a mesh?
-                if q_chunk_meshes.contains(child.clone()) {
-                    commands.entity(child.clone()).insert(Mesh3d(mesh_handle.clone()));
-                    needs_new_mesh = false;
+                if let Some(i) = old_meshes.iter().position(|(l, _)| *l == layer) {
+                    let (_, child) = old_meshes.swap_remove(i);
+                    commands.entity(child).insert(Mesh3d(mesh_handle));
+                    continue;
                 }
+                let child = commands.spawn((
+                    Visibility::Inherited,
+                    Mesh3d(mesh_handle),
+                    ChunkMeshMarker(layer),
+                    MeshMaterial3d(block_textures.get_material(layer)),
+                    // packed positions can't be read by the wireframe pipeline, or used to compute bounds
+                    NoWireframe,
+                    Aabb::from_min_max(Vec3::ZERO, Vec3::splat(ChunkData::CHUNK_SIZE as f32))
+                )).id();
+
+                commands.entity(chunk_entity).add_child(child);
             }
+            // layers the chunk no longer has any blocks in
+            for (_, child) in old_meshes {
+                commands.entity(child).despawn();
+            }
+            chunk_queue.lifecycle.push((coord, ChunkLifecycle::Meshed));
+            bytes_uploaded += mesh_size;
+            uploaded += 1;
         }
-        if needs_new_mesh {
-            let child = commands.spawn((
-                Visibility::Inherited,
-                Mesh3d(mesh_handle.clone()),
-                ChunkMeshMarker,
-                MeshMaterial3d(block_textures.material.clone()),
-                Aabb::from_min_max(Vec3::ZERO, Vec3::splat(ChunkData::CHUNK_SIZE as f32))
-            )).id();
-
-            commands.entity(chunk_entity).add_child(child);
-        }
-        hard_process_limit -= to_sub as i32;
+    }
+    if uploaded > 0 {
+        profiler.record(PipelineStage::Upload, start.elapsed());
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BlockState {
     block: String,
     state: BTreeMap<String, String>
 }
 
+#[cfg(test)]
+thread_local! {
+    /// How many times a BlockState was hashed on this thread, so tests can check hot loops don't hash them.
+    pub(crate) static STATE_HASHES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+// same as the derived impl, but counted in tests
+impl std::hash::Hash for BlockState {
+    fn hash<H: std::hash::Hasher>(&self, hasher: &mut H) {
+        #[cfg(test)]
+        STATE_HASHES.with(|count| count.set(count.get() + 1));
+        self.block.hash(hasher);
+        self.state.hash(hasher);
+    }
+}
+
 impl BlockState {
     pub fn new(id: &str, block_reg: &Registry<Block>) -> Result<Self, BlockStateError> {
         match block_reg.get(id) {
@@ -591,8 +1669,205 @@ impl BlockState {
         &self.state
     }
 
+    /// Gets the block this is a state of. None if the id isn't registered.
+    pub fn block<'a>(&self, block_reg: &'a Registry<Block>) -> Option<&'a Block> {
+        block_reg.get(self.get_id())
+    }
+
+    /// The canonical air state. Air has no properties, so this doesn't need the registry.
+    pub fn air() -> Self {
+        Self {
+            block: String::from(AIR_ID),
+            state: BTreeMap::new(),
+        }
+    }
+
+    /// Checks if this is air by its block id. Prefer [`BlockStateTable::is_air_state`], which goes through the registry's air instead
+    /// of the id string, wherever the table is at hand.
     pub fn is_air(&self) -> bool {
-        self.block == "air"
+        self.block == AIR_ID
+    }
+
+    /// Converts to a compact form, with the block stored as its registry index and each property stored as the index of its value.
+    /// Falls back to the full string form if the registry has no indices yet, or the state can't be represented with indices.
+    pub fn to_compact(&self, block_reg: &Registry<Block>) -> CompactBlockState {
+        let fallback = CompactBlockState::Named(self.clone());
+        let (Some(index), Some(block)) = (block_reg.get_index(&self.block), block_reg.get(&self.block)) else {
+            return fallback;
+        };
+        // every property must be declared on the block
+        if self.state.keys().any(|k| !block.get_states().iter().any(|s| &s.name == k)) {
+            return fallback;
+        }
+
+        let mut state = Vec::with_capacity(block.get_states().len());
+        for def in block.get_states().iter() {
+            let Some(value) = self.state.get(&def.name) else {
+                return fallback;
+            };
+            match def.values.iter().position(|v| v == value) {
+                Some(i) if i <= u8::MAX as usize => state.push(i as u8),
+                _ => return fallback,
+            }
+        }
+        CompactBlockState::Indexed {
+            block: index as u32,
+            state,
+        }
+    }
+
+    /// Converts back from the compact form. Errors if an index doesn't exist in the registry or block.
+    pub fn from_compact(compact: &CompactBlockState, block_reg: &Registry<Block>) -> Result<Self, BlockStateError> {
+        match compact {
+            CompactBlockState::Named(state) => {
+                Self::with_state(state.get_id(), state.get_state().clone(), block_reg)
+            }
+            CompactBlockState::Indexed { block: index, state: values } => {
+                let block = block_reg.get_by_index(*index as usize).ok_or(BlockStateError::InvalidIndex(*index as usize))?;
+                let id = block.get_id();
+                if values.len() != block.get_states().len() {
+                    let message = format!("Expected {} properties, but found {}", block.get_states().len(), values.len());
+                    return Err(BlockStateError::InvalidState(String::from(id), message));
+                }
+
+                let mut state = BTreeMap::new();
+                for (def, value) in block.get_states().iter().zip(values.iter()) {
+                    let Some(value) = def.values.get(*value as usize) else {
+                        let message = format!("Value index {} does not exist for state {}", value, def.name);
+                        return Err(BlockStateError::InvalidState(String::from(id), message));
+                    };
+                    state.insert(def.name.clone(), value.clone());
+                }
+                Ok(Self {
+                    block: String::from(id),
+                    state,
+                })
+            }
+        }
+    }
+}
+
+/// A smaller serialized form of a [`BlockState`].
+/// Indices depend on which blocks are registered and the order their values are declared in, so this must never outlive
+/// the registry it came from. Saves store the full [`BlockState`] instead.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CompactBlockState {
+    /// The block's registry index, then the index of each property's value, in the order the block declares them.
+    Indexed {
+        block: u32,
+        state: Vec<u8>,
+    },
+    /// Full string form, used when indices aren't available.
+    Named(BlockState),
+}
+
+/// Numeric id of an interned [`BlockState`]. Equal blockstates always share the same id.
+/// Like registry indices, ids depend on the blocks that are registered.
+///
+/// These are global, and unrelated to the palette ids chunks store their blocks as (see [`ChunkData`]),
+/// so the number of blockstates in the game isn't limited by how many fit in a chunk's palette.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockStateId(u32);
+
+impl BlockStateId {
+    pub fn get_index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Interning table of every possible [`BlockState`], built when the block registry is frozen.
+/// Lets code that looks up the same states over and over (like meshing) index a `Vec` by [`BlockStateId`]
+/// instead of hashing full blockstates every time.
+///
+/// Backed by arcs, so it is cheap to clone and can be shared with multiple threads.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct BlockStateTable {
+    states: Arc<Vec<BlockState>>,
+    ids: Arc<HashMap<BlockState, BlockStateId>>,
+    /// Id of the registry's air block, resolved when the table is built.
+    air: BlockStateId,
+}
+
+impl BlockStateTable {
+    /// Creates a table with every combination of state values for every block. Blocks are visited in registry index order
+    /// and properties in the order the block declares them, so ids are stable for the same set of blocks.
+    /// Fails if the registry's air block has no state in the table, e.g. if it was overridden with properties but no default.
+    pub fn from_registry(block_reg: &Registry<Block>) -> Result<Self, BlockStateError> {
+        let mut states = Vec::new();
+        let mut index = 0;
+        while let Some(block) = block_reg.get_by_index(index) {
+            index += 1;
+            // mixed radix counter over the value of each property
+            let defs = block.get_states();
+            let mut counter = vec![0_usize; defs.len()];
+            'combos: loop {
+                let state = defs.iter().zip(counter.iter())
+                    .filter_map(|(def, i)| Some((def.name.clone(), def.values.get(*i)?.clone())))
+                    .collect::<BTreeMap<_, _>>();
+                states.push(BlockState {
+                    block: String::from(block.get_id()),
+                    state,
+                });
+
+                for (i, def) in defs.iter().enumerate() {
+                    counter[i] += 1;
+                    if counter[i] < def.values.len() {
+                        continue 'combos;
+                    }
+                    counter[i] = 0;
+                }
+                break;
+            }
+        }
+
+        let ids = states.iter().enumerate()
+            .map(|(i, state)| (state.clone(), BlockStateId(i as u32)))
+            .collect::<HashMap<_, _>>();
+        let air_state = BlockState::new(block_reg.air().get_id(), block_reg)?;
+        let air = ids.get(&air_state).copied()
+            .ok_or_else(|| BlockStateError::InvalidState(air_state.block.clone(), format!("{:?}", air_state.state)))?;
+        Ok(Self {
+            states: Arc::new(states),
+            ids: Arc::new(ids),
+            air,
+        })
+    }
+
+    /// Gets the interned id of air.
+    pub fn get_air_id(&self) -> BlockStateId {
+        self.air
+    }
+
+    /// Checks if an interned id is air, without any string comparisons.
+    pub fn is_air(&self, id: BlockStateId) -> bool {
+        id == self.air
+    }
+
+    /// Checks if a blockstate is the registry's air, by its interned id. States that aren't in the table never are.
+    pub fn is_air_state(&self, state: &BlockState) -> bool {
+        self.get_id(state).is_some_and(|id| self.is_air(id))
+    }
+
+    /// Gets the interned id of a blockstate. None if the state isn't valid for its block.
+    pub fn get_id(&self, state: &BlockState) -> Option<BlockStateId> {
+        self.ids.get(state).copied()
+    }
+
+    pub fn get_state(&self, id: BlockStateId) -> Option<&BlockState> {
+        self.states.get(id.get_index())
+    }
+
+    /// Every state in the table, in id order.
+    pub fn iter(&self) -> impl Iterator<Item = (BlockStateId, &BlockState)> {
+        self.states.iter().enumerate().map(|(i, state)| (BlockStateId(i as u32), state))
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
     }
 }
 
@@ -604,4 +1879,868 @@ pub enum Direction {
     South,
     East,
     West
-}
\ No newline at end of file
+}
+
+impl Direction {
+    /// Every direction, in declaration order, so `Direction::ALL[dir as usize] == dir`.
+    pub const ALL: [Direction; 6] = [Direction::Up, Direction::Down, Direction::North, Direction::South, Direction::East, Direction::West];
+
+    /// Horizontal directions, clockwise starting from north. Each direction's index is how many 90° turns around Y it is from north.
+    pub const HORIZONTAL: [Direction; 4] = [Direction::North, Direction::East, Direction::South, Direction::West];
+
+    /// Rotates this direction clockwise around the Y axis (looking down) by 90° `quarter_turns` times. Up and Down don't change.
+    pub fn rotate_y(self, quarter_turns: u32) -> Direction {
+        match Self::HORIZONTAL.iter().position(|d| *d == self) {
+            Some(i) => Self::HORIZONTAL[(i + quarter_turns as usize) % 4],
+            None => self,
+        }
+    }
+
+    /// Gets the horizontal direction closest to a vector, ignoring its y.
+    pub fn nearest_horizontal(v: Vec3) -> Direction {
+        if v.x.abs() > v.z.abs() {
+            if v.x > 0.0 { Direction::East } else { Direction::West }
+        }
+        else if v.z > 0.0 { Direction::North } else { Direction::South }
+    }
+
+    /// Lowercase name, as used for values of blockstate properties like `facing`.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Direction::Up => "up",
+            Direction::Down => "down",
+            Direction::North => "north",
+            Direction::South => "south",
+            Direction::East => "east",
+            Direction::West => "west",
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::block::test_registry;
+    use bevy::prelude::{On, World};
+
+    // a world with an all air chunk at each position
+    fn air_world(chunks: &[IVec3]) -> BlockWorld {
+        let mut world = BlockWorld::new(DimensionId::new("test"), chunks.len());
+        for pos in chunks {
+            world.map.add_chunk(Chunk::generated(*pos, ChunkData::single(BlockState::air()))).unwrap();
+        }
+        world
+    }
+
+    #[test]
+    fn spawn_progress_counts_generated_and_meshed_chunks() {
+        let around = (-1..=1).flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| ivec3(x, y, z))));
+        // one chunk isn't there yet, one is still generating
+        let chunks = around.filter(|pos| *pos != ivec3(1, 1, 1)).collect::<Vec<_>>();
+        let mut world = air_world(&chunks[1..]);
+        world.map.add_chunk(Chunk::new(chunks[0], Entity::PLACEHOLDER)).unwrap();
+
+        assert_eq!(world.count_meshed_around(IVec3::ZERO, 1, |_| false), (25, 27));
+        assert_eq!(world.count_meshed_around(IVec3::ZERO, 1, |_| true), (0, 27));
+        // chunks outside the radius don't count
+        assert_eq!(world.count_meshed_around(ivec3(3, 0, 0), 1, |_| false), (0, 27));
+    }
+
+    #[derive(Resource, Default)]
+    struct FiredEvents {
+        bulk: Vec<IVec3>,
+        single: usize,
+    }
+
+    // an ECS world that records which block events were fired
+    fn event_world() -> World {
+        let mut world = World::new();
+        world.init_resource::<FiredEvents>();
+        world.add_observer(|event: On<BulkSetBlockEvent>, mut fired: ResMut<FiredEvents>| fired.bulk.push(event.chunk_pos));
+        world.add_observer(|_: On<SetBlockEvent>, mut fired: ResMut<FiredEvents>| fired.single += 1);
+        world
+    }
+
+    #[test]
+    fn fill_fires_one_event_per_chunk() {
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let mut block_world = air_world(&[ivec3(0, 0, 0), ivec3(1, 0, 0)]);
+        let mut events = event_world();
+
+        let changed = block_world.fill(&mut events.commands(), &ivec3(0, 0, 0), &ivec3(63, 3, 3), stone, BlockChangeSource::Programmatic).unwrap();
+        events.flush();
+
+        assert_eq!(changed, 64 * 4 * 4);
+        let mut fired = events.resource::<FiredEvents>().bulk.clone();
+        fired.sort_by_key(|p| p.to_array());
+        assert_eq!(fired, vec![ivec3(0, 0, 0), ivec3(1, 0, 0)]);
+        assert_eq!(events.resource::<FiredEvents>().single, 0);
+        assert_eq!(block_world.take_dirty_chunks().len(), 2);
+    }
+
+    #[test]
+    fn a_checkerboard_pattern_alternates_and_shows_every_face() {
+        use crate::core::profiling::PipelineProfiler;
+        use crate::render::block::{test_cube_model, test_mesh_cache};
+        use crate::render::chunk::{create_chunk_mesh_with, MeshScratch};
+
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let mut block_world = air_world(&[IVec3::ZERO]);
+        let mut events = event_world();
+
+        let changed = block_world.fill_pattern(&mut events.commands(), IVec3::ZERO, ChunkPattern::Checkerboard, stone.clone(), BlockChangeSource::Programmatic).unwrap();
+        events.flush();
+        assert_eq!(changed, ChunkData::BLOCKS_PER_CHUNK / 2);
+        assert_eq!(events.resource::<FiredEvents>().bulk, vec![IVec3::ZERO]);
+
+        let data = block_world.map.get_chunk(&IVec3::ZERO).unwrap().get_data().unwrap();
+        let data = data.read().unwrap();
+        for (x, y, z) in [(0, 0, 0), (1, 1, 0), (31, 0, 1), (5, 6, 7)] {
+            assert_eq!(data.get_block(x, y, z).unwrap(), stone, "({x}, {y}, {z})");
+        }
+        for (x, y, z) in [(1, 0, 0), (0, 0, 1), (31, 31, 31), (5, 6, 8)] {
+            assert!(data.get_block(x, y, z).unwrap().is_air(), "({x}, {y}, {z})");
+        }
+
+        // no block touches another, so none of their faces get culled
+        let cache = test_mesh_cache(&registry, &[("stone", test_cube_model())]);
+        let air = ChunkData::single(BlockState::air());
+        let meshes = create_chunk_mesh_with(&data, &cache, [&air; 6], &mut MeshScratch::default(), &PipelineProfiler::default());
+        let [(RenderLayer::Opaque, mesh)] = meshes.as_slice() else { panic!("expected one opaque mesh") };
+        assert_eq!(mesh.indices().unwrap().len() / 6, 6 * ChunkData::BLOCKS_PER_CHUNK / 2);
+    }
+
+    #[test]
+    fn fill_over_unloaded_chunk_changes_nothing() {
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let mut block_world = air_world(&[ivec3(0, 0, 0)]);
+        let mut events = event_world();
+
+        let result = block_world.fill(&mut events.commands(), &ivec3(0, 0, 0), &ivec3(40, 0, 0), stone, BlockChangeSource::Programmatic);
+        events.flush();
+
+        assert!(matches!(result, Err(WorldError::UnloadedChunk(pos)) if pos == ivec3(1, 0, 0)));
+        assert!(block_world.get_block(&ivec3(0, 0, 0)).unwrap().is_air());
+        assert!(events.resource::<FiredEvents>().bulk.is_empty());
+    }
+
+    #[test]
+    fn neighbors_are_indexed_by_direction() {
+        let center = ivec3(2, -1, 5);
+        let around = Direction::ALL.map(|dir| center.offset(dir));
+        let block_world = air_world(&around);
+
+        let neighbors = block_world.map.neighbors(&center);
+        for dir in Direction::ALL {
+            assert_eq!(neighbors[dir as usize].map(|c| c.get_pos()), Some(center.offset(dir)), "{dir:?}");
+        }
+        // center itself was never loaded, and neither was anything further out
+        let outer = block_world.map.neighbors(&center.up());
+        assert!(outer[Direction::Up as usize].is_none());
+        assert!(outer[Direction::Down as usize].is_none());
+    }
+
+    #[test]
+    fn nearest_chunks_come_first() {
+        let center = ivec3(0, 0, 0);
+        let far = [ivec3(5, 0, 0), ivec3(0, -4, 0), ivec3(3, 3, 3)];
+        let near = [ivec3(1, 0, 0), ivec3(0, 0, -1), ivec3(1, 1, 0)];
+        let block_world = air_world(&[far, near].concat());
+
+        let nearest = block_world.map.nearest_n(center, 3).into_iter().map(|(pos, _)| *pos).collect::<Vec<_>>();
+        // ties go by position so the order never depends on the hash map
+        assert_eq!(nearest, vec![ivec3(0, 0, -1), ivec3(1, 0, 0), ivec3(1, 1, 0)]);
+        let all = block_world.map.iter_by_distance(center).map(|(pos, _)| *pos).collect::<Vec<_>>();
+        assert_eq!(all[..3], nearest[..]);
+        assert_eq!(all[3..], [ivec3(0, -4, 0), ivec3(5, 0, 0), ivec3(3, 3, 3)]);
+        assert_eq!(block_world.map.nearest_n(center, 10).len(), 6);
+    }
+
+    #[test]
+    fn generation_queue_starts_nearest_the_player() {
+        let mut block_world = air_world(&[]);
+        for x in (0..8).rev() {
+            block_world.queue_chunk_generation(ivec3(x * 2, 0, 0));
+        }
+        block_world.chunk_queue.prioritize(ivec3(5, 0, 0), 2);
+
+        assert_eq!(block_world.chunk_queue.pop_pending(), Some((ivec3(4, 0, 0), false)));
+        assert_eq!(block_world.chunk_queue.pop_pending(), Some((ivec3(6, 0, 0), false)));
+        // the rest keep their queued order
+        assert_eq!(block_world.chunk_queue.pop_pending(), Some((ivec3(14, 0, 0), false)));
+    }
+
+    #[test]
+    fn pacing_over_a_chunk_border_does_not_reload_chunks() {
+        use crate::core::event::PlayerMovedEvent;
+        use bevy::math::vec3;
+
+        let pace = |despawn_margin: u32| {
+            let settings = CameraSettings { render_distance: 2, despawn_margin, ..Default::default() };
+            let r = settings.render_distance as i32;
+            let loaded = (-r..=r).flat_map(|x| (-r..=r).flat_map(move |y| (-r..=r).map(move |z| ivec3(x, y, z))))
+                .filter(|pos| settings.in_spawn_range(IVec3::ZERO, *pos))
+                .collect::<Vec<_>>();
+            let mut world = World::new();
+            world.add_observer(crate::world::spawn_and_despawn_chunks);
+            world.insert_resource(settings);
+            let entity = world.spawn((air_world(&loaded), ActiveWorld)).id();
+
+            // back and forth between chunk 0 and chunk 1 along x
+            let (here, there) = (vec3(31.5, 5.0, 5.0), vec3(32.5, 5.0, 5.0));
+            for (old, new) in [(here, there), (there, here), (here, there), (there, here), (here, there)] {
+                world.trigger(PlayerMovedEvent { old, new });
+                world.flush();
+            }
+            let block_world = world.get::<BlockWorld>(entity).unwrap();
+            let queued = block_world.chunk_queue.lifecycle.iter().filter(|(_, step)| *step == ChunkLifecycle::Queued).count();
+            (queued, block_world.get_pending_generation().count(), block_world.chunk_queue.to_despawn.len())
+        };
+
+        // only the first step queues anything, and nothing unloads
+        let (queued, pending, despawning) = pace(1);
+        assert!(pending > 0);
+        assert_eq!(queued, pending);
+        assert_eq!(despawning, 0);
+
+        // without a margin, the chunks left behind are unloaded straight away
+        let (_, _, despawning) = pace(0);
+        assert!(despawning > 0);
+    }
+
+    #[test]
+    fn memory_budget_unloads_the_furthest_chunks_and_keeps_them_unloaded() {
+        let loaded = (0..5).map(|x| ivec3(x, 0, 0)).collect::<Vec<_>>();
+        let mut block_world = air_world(&loaded);
+        let chunk_bytes = block_world.map.get_chunk(&IVec3::ZERO).unwrap().memory_footprint();
+        // queued before the budget kicks in, and too far away to fit
+        block_world.queue_chunk_generation(ivec3(0, 0, -4));
+
+        let mut world = World::new();
+        world.init_resource::<TaskAllowance>();
+        world.insert_resource(ChunkMemorySettings { budget: Some(3 * chunk_bytes), resume_fraction: 1.0 });
+        world.spawn((Player, Transform::default()));
+        let entity = world.spawn((block_world, ActiveWorld)).id();
+        let enforce = world.register_system(enforce_memory_budget);
+
+        world.resource_mut::<TaskAllowance>().generation = 4;
+        world.run_system(enforce).unwrap();
+        let block_world = world.get::<BlockWorld>(entity).unwrap();
+        assert_eq!(block_world.chunk_queue.to_despawn, [ivec3(4, 0, 0), ivec3(3, 0, 0)]);
+        assert!(!block_world.is_queued_for_generation(&ivec3(0, 0, -4)));
+        // nothing as far as the unloaded chunks gets generated again, in any direction
+        assert!(block_world.in_budget_range(IVec3::ZERO, ivec3(2, 0, 0)));
+        assert!(!block_world.in_budget_range(IVec3::ZERO, ivec3(3, 0, 0)));
+        assert!(!block_world.in_budget_range(IVec3::ZERO, ivec3(0, -3, 0)));
+        assert_eq!(world.resource::<TaskAllowance>().generation, 4);
+
+        // still over budget while the unloads are waiting, so generation is held off
+        world.resource_mut::<TaskAllowance>().generation = 4;
+        world.get_mut::<BlockWorld>(entity).unwrap().chunk_queue.to_despawn.clear();
+        world.get_mut::<BlockWorld>(entity).unwrap().dirty_chunks.extend(loaded);
+        world.run_system(enforce).unwrap();
+        assert_eq!(world.resource::<TaskAllowance>().generation, 0);
+        assert!(!world.get::<BlockWorld>(entity).unwrap().in_budget_range(IVec3::ZERO, ivec3(3, 0, 0)));
+
+        // a new budget might fit them again
+        world.resource_mut::<ChunkMemorySettings>().budget = None;
+        world.run_system(enforce).unwrap();
+        assert!(world.get::<BlockWorld>(entity).unwrap().in_budget_range(IVec3::ZERO, ivec3(3, 0, 0)));
+    }
+
+    #[test]
+    fn raycast_only_hits_a_partial_block_on_its_shape() {
+        use crate::asset::block::{BlockAsset, CollisionBox};
+        use crate::registry::block::test_asset;
+        use crate::world::shape::BlockShapes;
+        use bevy::math::vec3;
+
+        let mut registry = Registry::<Block>::new("block");
+        registry.register(Block::from_asset(&test_asset("stone"))).unwrap();
+        let half = CollisionBox { min: Vec3::ZERO, max: vec3(1.0, 0.5, 1.0) };
+        registry.register(Block::from_asset(&BlockAsset { collision_shape: Some(vec![half]), ..test_asset("slab") })).unwrap();
+        registry.freeze();
+        // no models, so both are hit on their collision shape
+        let shapes = BlockShapes::new(BlockStateTable::from_registry(&registry).unwrap(), &registry, &[]);
+
+        let mut block_world = air_world(&[ivec3(0, 0, 0)]);
+        let mut events = event_world();
+        let mut commands = events.commands();
+        block_world.set_block(&mut commands, &ivec3(2, 0, 1), BlockState::new("slab", &registry).unwrap(), BlockChangeSource::Programmatic).unwrap();
+        block_world.set_block(&mut commands, &ivec3(4, 0, 1), BlockState::new("stone", &registry).unwrap(), BlockChangeSource::Programmatic).unwrap();
+
+        // passes over the slab and hits the side of the stone
+        let result = block_world.raycast(vec3(0.5, 0.75, 1.5), Vec3::X, 10.0, &shapes);
+        let RayResult::Hit(point, face, b_pos, distance) = result else { panic!("expected a hit, got {result:?}") };
+        assert_eq!(b_pos, ivec3(4, 0, 1));
+        assert_eq!(face, Direction::West);
+        assert!((distance - 3.5).abs() < 1e-4);
+        assert!(point.distance(vec3(4.0, 0.75, 1.5)) < 1e-4);
+
+        // hits the top of the slab, not the top of its cell
+        let result = block_world.raycast(vec3(2.5, 3.0, 1.5), Vec3::NEG_Y, 10.0, &shapes);
+        let RayResult::Hit(point, face, b_pos, distance) = result else { panic!("expected a hit, got {result:?}") };
+        assert_eq!(b_pos, ivec3(2, 0, 1));
+        assert_eq!(face, Direction::Up);
+        assert!((distance - 2.5).abs() < 1e-4);
+        assert!(point.distance(vec3(2.5, 0.5, 1.5)) < 1e-4);
+
+        // the slab's top is out of reach even though its cell isn't
+        assert!(matches!(block_world.raycast(vec3(2.5, 3.0, 1.5), Vec3::NEG_Y, 2.25, &shapes), RayResult::Miss));
+    }
+
+    #[test]
+    fn raycast_matches_per_block_lookups_and_fetches_each_chunk_once() {
+        use crate::world::shape::BlockShapes;
+        use bevy::math::vec3;
+
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let shapes = BlockShapes::new(BlockStateTable::from_registry(&registry).unwrap(), &registry, &[]);
+        let mut block_world = air_world(&[ivec3(0, 0, 0), ivec3(1, 0, 0), ivec3(2, 0, 0), ivec3(3, 0, 0)]);
+        let mut events = event_world();
+        for pos in [ivec3(100, 5, 5), ivec3(40, 9, 20), ivec3(70, 20, 9)] {
+            block_world.set_block(&mut events.commands(), &pos, stone.clone(), BlockChangeSource::Programmatic).unwrap();
+        }
+        // the middle chunks get read packed
+        assert!(block_world.map.get_chunk(&ivec3(1, 0, 0)).unwrap().pack());
+        assert!(block_world.map.get_chunk(&ivec3(2, 0, 0)).unwrap().pack());
+
+        let start = vec3(0.5, 5.5, 5.5);
+        let rays = [
+            (Vec3::X, 4, true),
+            (vec3(40.5, 9.5, 20.5) - start, 2, true),
+            (vec3(70.5, 20.5, 9.5) - start, 3, true),
+            // unloaded chunks are skipped, but still only looked up once each
+            (Vec3::Y, 4, false),
+        ];
+        let mut results = Vec::new();
+        for (direction, chunks, hits) in rays {
+            let mut reader = ChunkReader::new(&block_world.map);
+            let result = block_world.raycast_with(&mut reader, start, direction, 120.0, &shapes);
+            assert_eq!(reader.get_fetch_count(), chunks, "{direction}");
+            assert_eq!(matches!(result, RayResult::Hit(..)), hits, "{direction}");
+            results.push(result);
+        }
+        assert!(block_world.map.get_chunk(&ivec3(2, 0, 0)).unwrap().is_packed());
+
+        // the naive way: a full lookup for every block the ray steps through
+        for ((direction, ..), result) in rays.into_iter().zip(results) {
+            let naive = ray::block_raycast(start, direction, 120.0, |_, _, _, pos| {
+                Ok(block_world.get_block(&pos).is_ok_and(|block| !block.is_air()))
+            }).unwrap();
+            match (result, naive) {
+                (RayResult::Hit(point, face, pos, distance), RayResult::Hit(naive_point, naive_face, naive_pos, naive_distance)) => {
+                    assert_eq!((pos, face), (naive_pos, naive_face), "{direction}");
+                    assert!(point.distance(naive_point) < 1e-4 && (distance - naive_distance).abs() < 1e-4, "{direction}");
+                }
+                (RayResult::Miss, RayResult::Miss) => {}
+                (result, naive) => panic!("{direction}: got {result:?}, naive lookups got {naive:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn despawning_mid_generation_drops_the_task() {
+        use bevy::ecs::system::RunSystemOnce;
+        use bevy::tasks::TaskPool;
+
+        let pool = AsyncComputeTaskPool::get_or_init(TaskPool::new);
+        let pos = ivec3(3, 0, 0);
+        let mut block_world = air_world(&[]);
+        block_world.chunk_queue.start_generating(pos, pool.spawn(async { ChunkData::single(BlockState::air()).into() }));
+        block_world.queue_chunk_despawn(pos);
+
+        let mut world = World::new();
+        world.init_resource::<ChunkEntityPool>();
+        world.insert_resource(RunConfig::from_base(Path::new("unused")));
+        let entity = world.spawn(block_world).id();
+        world.run_system_once(process_despawn_queue).unwrap();
+        world.run_system_once(receive_generated_chunks).unwrap();
+
+        let block_world = world.get::<BlockWorld>(entity).unwrap();
+        assert!(!block_world.chunk_queue.currently_generating.contains_key(&pos));
+        assert!(block_world.chunk_queue.finished_generating.is_empty());
+        assert!(block_world.chunk_queue.generation_order.is_empty());
+        assert!(block_world.chunk_queue.lifecycle.iter().any(|(p, step)| *p == pos && matches!(step, ChunkLifecycle::Despawned)));
+    }
+
+    #[test]
+    fn edits_while_meshing_only_queue_one_more_mesh() {
+        use bevy::ecs::system::RunSystemOnce;
+        use bevy::tasks::TaskPool;
+
+        AsyncComputeTaskPool::get_or_init(TaskPool::new);
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let mut world = World::new();
+        world.add_observer(crate::world::on_set_block);
+        world.insert_resource(MeshDataCache::new(BlockStateTable::from_registry(&registry).unwrap(), vec![]));
+        world.init_resource::<PipelineProfiler>();
+        world.init_resource::<TaskAllowance>();
+
+        // a chunk with all of its neighbors loaded, so it can be meshed
+        let chunk_entity = world.spawn((ChunkMarker::new(IVec3::ZERO), ChunkNeedsMeshing)).id();
+        let mut chunk = Chunk::new(IVec3::ZERO, chunk_entity);
+        chunk.init_data(ChunkData::single(BlockState::air())).unwrap();
+        while chunk.advance_generation().is_ok() {}
+        let mut block_world = air_world(&Direction::ALL.map(|d| IVec3::ZERO.offset(d)));
+        block_world.map.add_chunk(chunk).unwrap();
+        let world_entity = world.spawn(block_world).id();
+        world.entity_mut(chunk_entity).insert(ChildOf(world_entity));
+
+        // an edit every frame, away from the chunk's edges so the neighbors aren't touched
+        for i in 0..100 {
+            let pos = ivec3(1 + i % 30, 1 + i / 30, 5);
+            let state = stone.clone();
+            world.run_system_once(move |mut commands: Commands, mut q_world: Query<&mut BlockWorld>| {
+                q_world.single_mut().unwrap().set_block(&mut commands, &pos, state.clone(), BlockChangeSource::Player).unwrap();
+            }).unwrap();
+            world.resource_mut::<TaskAllowance>().meshing = 8;
+            world.run_system_once(queue_mesh_creation).unwrap();
+        }
+
+        // the first task is still out, and the chunk is meshed once more after it whatever the number of edits
+        assert_eq!(world.get::<BlockWorld>(world_entity).unwrap().chunk_queue.currently_meshing.len(), 1);
+        assert!(world.entity(chunk_entity).contains::<ChunkNeedsMeshing>());
+    }
+
+    #[test]
+    fn swapping_the_mesh_cache_remeshes_every_loaded_chunk() {
+        let registry = test_registry(&["stone"]);
+        let table = BlockStateTable::from_registry(&registry).unwrap();
+        let mut world = World::new();
+        world.insert_resource(MeshDataCache::new(table.clone(), vec![]));
+        let chunks = (0..3).map(|x| world.spawn(ChunkMarker::new(ivec3(x, 0, 0))).id()).collect::<Vec<_>>();
+        let remesh = world.register_system(remesh_on_cache_swap);
+        let flagged = |world: &World| chunks.iter().filter(|&&entity| world.entity(entity).contains::<ChunkNeedsMeshing>()).count();
+
+        // nothing changed yet
+        world.run_system(remesh).unwrap();
+        assert_eq!(flagged(&world), 0);
+
+        world.resource_mut::<MeshDataCache>().swap(table.clone(), vec![]);
+        assert_eq!(world.resource::<MeshDataCache>().get_version(), 1);
+        world.run_system(remesh).unwrap();
+        assert_eq!(flagged(&world), chunks.len());
+
+        // only once per swap
+        for &entity in chunks.iter() {
+            world.entity_mut(entity).remove::<ChunkNeedsMeshing>();
+        }
+        world.run_system(remesh).unwrap();
+        assert_eq!(flagged(&world), 0);
+    }
+
+    #[test]
+    fn mesh_uploads_are_capped_per_frame() {
+        use crate::render::material::BlockMaterial;
+        use bevy::asset::RenderAssetUsages;
+        use bevy::ecs::system::RunSystemOnce;
+        use bevy::image::Image;
+        use bevy::mesh::PrimitiveTopology;
+        use bevy::prelude::AlphaMode;
+
+        let mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+            .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, vec![[0.0_f32; 3]; 100]);
+        let size = mesh_upload_size(&mesh);
+        assert_eq!(size, 100 * size_of::<[f32; 3]>());
+
+        let setup = |bytes_per_frame: usize| {
+            let mut world = World::new();
+            world.init_resource::<Assets<Mesh>>();
+            world.init_resource::<Assets<Image>>();
+            world.init_resource::<Assets<BlockMaterial>>();
+            world.init_resource::<PipelineProfiler>();
+            world.insert_resource(MeshUploadSettings { bytes_per_frame });
+            let array_texture = world.resource_mut::<Assets<Image>>().add(Image::default());
+            let material = world.resource_mut::<Assets<BlockMaterial>>().add(BlockMaterial::new(array_texture.clone(), AlphaMode::Opaque));
+            let materials = RenderLayer::ALL.into_iter().map(|layer| (layer, material.clone())).collect();
+            world.insert_resource(BlockTextures { array_texture, materials, ..Default::default() });
+
+            // five chunks finished meshing at once
+            let mut block_world = air_world(&[]);
+            for x in 0..5 {
+                let mut chunk = Chunk::new(ivec3(x, 0, 0), world.spawn(ChunkMarker::new(ivec3(x, 0, 0))).id());
+                chunk.init_data(ChunkData::single(BlockState::air())).unwrap();
+                while chunk.advance_generation().is_ok() {}
+                block_world.map.add_chunk(chunk).unwrap();
+                block_world.chunk_queue.finished_meshing.push_back((ivec3(x, 0, 0), MeshResult::Meshes(vec![(RenderLayer::Opaque, mesh.clone())])));
+            }
+            let entity = world.spawn(block_world).id();
+            (world, entity)
+        };
+        let queued = |world: &World, entity: Entity| world.get::<BlockWorld>(entity).unwrap().chunk_queue.finished_meshing.len();
+        let upload = |bytes_per_frame: usize| {
+            let (mut world, entity) = setup(bytes_per_frame);
+            let mut left = Vec::new();
+            while queued(&world, entity) > 0 {
+                world.run_system_once(upload_meshes).unwrap();
+                left.push(queued(&world, entity));
+            }
+            assert_eq!(world.query::<&ChunkMeshMarker>().iter(&world).count(), 5);
+            left
+        };
+
+        assert_eq!(upload(2 * size), vec![3, 1, 0]);
+        // the last mesh of a frame can go over
+        assert_eq!(upload(2 * size + 1), vec![2, 0]);
+        // and a mesh bigger than the whole budget still goes up on its own
+        assert_eq!(upload(size / 2), vec![4, 3, 2, 1, 0]);
+
+        // nothing goes up until the block textures are ready, then uploads carry on as normal
+        let (mut world, entity) = setup(2 * size);
+        let textures = world.remove_resource::<BlockTextures>().unwrap();
+        world.init_resource::<BlockTextures>();
+        assert!(!world.resource::<BlockTextures>().is_ready());
+        for _ in 0..3 {
+            world.run_system_once(upload_meshes).unwrap();
+            assert_eq!(queued(&world, entity), 5);
+        }
+        assert_eq!(world.query::<&ChunkMeshMarker>().iter(&world).count(), 0);
+        world.insert_resource(textures);
+        world.run_system_once(upload_meshes).unwrap();
+        assert_eq!(queued(&world, entity), 3);
+        assert_eq!(world.query::<&ChunkMeshMarker>().iter(&world).count(), 2);
+    }
+
+    #[test]
+    fn distant_chunks_are_packed_a_few_at_a_time_unless_meshing_needs_them() {
+        use bevy::ecs::system::RunSystemOnce;
+
+        let mut world = World::new();
+        world.insert_resource(ChunkPackingSettings { pack_distance: Some(1.0), max_per_frame: 2 });
+        world.spawn((Player, Transform::default()));
+        let mut block_world = air_world(&[]);
+        for x in 0..8 {
+            let mut chunk = Chunk::new(ivec3(x, 0, 0), world.spawn(ChunkMarker::new(ivec3(x, 0, 0))).id());
+            chunk.init_data(ChunkData::single(BlockState::air())).unwrap();
+            while chunk.advance_generation().is_ok() {}
+            block_world.map.add_chunk(chunk).unwrap();
+        }
+        let waiting = block_world.map.get_chunk(&ivec3(4, 0, 0)).unwrap().get_entity();
+        world.entity_mut(waiting).insert(ChunkNeedsMeshing);
+        let entity = world.spawn(block_world).id();
+
+        let packed = |world: &World| (0..8).filter(|&x| world.get::<BlockWorld>(entity).unwrap().map.get_chunk(&ivec3(x, 0, 0)).unwrap().is_packed()).collect::<Vec<_>>();
+        world.run_system_once(pack_distant_chunks).unwrap();
+        assert_eq!(packed(&world).len(), 2);
+        world.run_system_once(pack_distant_chunks).unwrap();
+        // close to the player, or waiting to be meshed or next to a chunk that is
+        assert_eq!(packed(&world), [2, 6, 7]);
+
+        // packing is off by default
+        world.insert_resource(ChunkPackingSettings::default());
+        world.entity_mut(waiting).remove::<ChunkNeedsMeshing>();
+        world.run_system_once(pack_distant_chunks).unwrap();
+        assert_eq!(packed(&world), [2, 6, 7]);
+    }
+
+    // a world with one generated chunk that has a real entity, so it can be despawned
+    fn despawnable_world(pos: IVec3, data_dir: &Path) -> (World, Entity) {
+        let mut world = World::new();
+        world.init_resource::<ChunkEntityPool>();
+        world.insert_resource(RunConfig::from_base(data_dir));
+        let mut chunk = Chunk::new(pos, world.spawn_empty().id());
+        chunk.init_data(ChunkData::single(BlockState::air())).unwrap();
+        while chunk.advance_generation().is_ok() {}
+        let mut block_world = air_world(&[]);
+        block_world.map.add_chunk(chunk).unwrap();
+        let entity = world.spawn(block_world).id();
+        (world, entity)
+    }
+
+    #[test]
+    fn edits_are_saved_when_their_chunk_unloads() {
+        use bevy::ecs::system::RunSystemOnce;
+
+        let base = std::env::temp_dir().join(format!("gtclone-unload-{}", std::process::id()));
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let pos = ivec3(-2, 0, 1);
+        let edited = chunk::chunk_pos_to_world_pos(pos) + ivec3(5, 6, 7);
+        let (mut world, entity) = despawnable_world(pos, &base);
+        let mut events = event_world();
+        let mut block_world = world.get_mut::<BlockWorld>(entity).unwrap();
+        block_world.set_block(&mut events.commands(), &edited, stone.clone(), BlockChangeSource::Player).unwrap();
+
+        // the player walks away long before the next autosave
+        block_world.queue_chunk_despawn(pos);
+        world.run_system_once(process_despawn_queue).unwrap();
+
+        let mut block_world = world.get_mut::<BlockWorld>(entity).unwrap();
+        assert!(block_world.map.get_chunk(&pos).is_none());
+        assert!(!block_world.is_dirty(&pos));
+        assert!(block_world.is_saved(&pos));
+        // coming back loads it from disk instead of generating it again
+        block_world.queue_chunk_generation(pos);
+        assert_eq!(block_world.chunk_queue.pop_pending(), Some((pos, true)));
+        let file = save::chunk_file(&RunConfig::from_base(&base).data_dir, block_world.get_dimension(), pos);
+        let loaded = save::load_chunk(&file);
+        std::fs::remove_dir_all(&base).unwrap();
+        assert_eq!(loaded.unwrap().get_block(5, 6, 7).unwrap(), stone);
+    }
+
+    #[test]
+    fn chunks_being_saved_stay_loaded_until_the_save_finishes() {
+        use bevy::ecs::system::RunSystemOnce;
+
+        let base = std::env::temp_dir().join(format!("gtclone-unload-saving-{}", std::process::id()));
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let pos = ivec3(0, 1, 0);
+        let (mut world, entity) = despawnable_world(pos, &base);
+        let mut events = event_world();
+        let mut block_world = world.get_mut::<BlockWorld>(entity).unwrap();
+        block_world.set_block(&mut events.commands(), &chunk::chunk_pos_to_world_pos(pos), stone, BlockChangeSource::Player).unwrap();
+        // an autosave has it, but hasn't written it yet
+        assert_eq!(block_world.take_dirty_chunks(), vec![pos]);
+
+        block_world.queue_chunk_despawn(pos);
+        world.run_system_once(process_despawn_queue).unwrap();
+        let mut block_world = world.get_mut::<BlockWorld>(entity).unwrap();
+        assert!(block_world.map.get_chunk(&pos).is_some());
+        assert!(block_world.get_keep_center().is_none());
+
+        block_world.finish_saving(true);
+        assert!(block_world.is_saved(&pos));
+        block_world.queue_chunk_despawn(pos);
+        world.run_system_once(process_despawn_queue).unwrap();
+        assert!(world.get::<BlockWorld>(entity).unwrap().map.get_chunk(&pos).is_none());
+        // nothing was dirty, so nothing was written by unloading
+        assert!(!base.exists());
+    }
+    #[test]
+    fn despawned_chunk_entities_are_reused() {
+        use bevy::ecs::system::RunSystemOnce;
+
+        let base = std::env::temp_dir().join(format!("gtclone-pool-{}", std::process::id()));
+        let pos = ivec3(3, 0, -1);
+        let (mut world, entity) = despawnable_world(pos, &base);
+        let chunk_entity = world.get::<BlockWorld>(entity).unwrap().map.get_chunk(&pos).unwrap().get_entity();
+        world.entity_mut(chunk_entity).insert(ChunkMarker::new(pos)).with_child(ChunkMeshMarker(RenderLayer::Opaque));
+
+        world.get_mut::<BlockWorld>(entity).unwrap().queue_chunk_despawn(pos);
+        world.run_system_once(process_despawn_queue).unwrap();
+
+        // still around, but emptied out
+        assert!(world.get::<ChunkMarker>(chunk_entity).is_none());
+        assert!(world.get::<Children>(chunk_entity).is_none());
+        assert_eq!(world.query::<&ChunkMeshMarker>().iter(&world).count(), 0);
+        let reused = world.run_system_once(|mut commands: Commands, mut pool: ResMut<ChunkEntityPool>| pool.take(&mut commands)).unwrap();
+        assert_eq!(reused, chunk_entity);
+
+        // a full pool despawns them instead
+        let (mut world, entity) = despawnable_world(pos, &base);
+        world.resource_mut::<ChunkEntityPool>().capacity = 0;
+        let chunk_entity = world.get::<BlockWorld>(entity).unwrap().map.get_chunk(&pos).unwrap().get_entity();
+        world.get_mut::<BlockWorld>(entity).unwrap().queue_chunk_despawn(pos);
+        world.run_system_once(process_despawn_queue).unwrap();
+        let _ = std::fs::remove_dir_all(&base);
+        assert!(world.get_entity(chunk_entity).is_err());
+    }
+
+    #[test]
+    fn failed_saves_leave_chunks_dirty_and_unsaved() {
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let mut block_world = air_world(&[ivec3(0, 0, 0)]);
+        let mut events = event_world();
+        block_world.set_block(&mut events.commands(), &ivec3(1, 1, 1), stone, BlockChangeSource::Player).unwrap();
+
+        // a file where the save folder should be, so writing fails
+        let blocker = std::env::temp_dir().join(format!("gtclone-blocked-save-{}", std::process::id()));
+        std::fs::write(&blocker, b"").unwrap();
+        let packed = save::pack_dirty_chunks(&mut block_world, &blocker, IVec3::ZERO).unwrap();
+        assert!(!block_world.is_saved(&IVec3::ZERO));
+        let written = packed.iter().try_for_each(|(file, data)| save::write_chunk(file, data));
+        std::fs::remove_file(&blocker).unwrap();
+        assert!(written.is_err());
+
+        block_world.finish_saving(false);
+        assert!(block_world.is_dirty(&IVec3::ZERO));
+        assert!(!block_world.is_saved(&IVec3::ZERO));
+        // the retry goes through
+        assert_eq!(block_world.take_dirty_chunks(), vec![IVec3::ZERO]);
+        block_world.finish_saving(true);
+        assert!(!block_world.is_dirty(&IVec3::ZERO));
+        assert!(block_world.is_saved(&IVec3::ZERO));
+    }
+
+    #[test]
+    fn chunks_finish_generating_in_the_order_they_started() {
+        use bevy::ecs::system::RunSystemOnce;
+        use bevy::tasks::TaskPool;
+        use bevy::tasks::futures_lite::future::yield_now;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let pool = AsyncComputeTaskPool::get_or_init(TaskPool::new);
+        let started = [ivec3(2, 0, 0), ivec3(-1, 0, 5), ivec3(0, 3, 0), ivec3(7, 0, -2)];
+        let run = || {
+            // the first chunk is held back, so everything after it finishes first
+            let release = Arc::new(AtomicBool::new(false));
+            let mut block_world = air_world(&[]);
+            for (i, pos) in started.into_iter().enumerate() {
+                let release = release.clone();
+                block_world.chunk_queue.start_generating(pos, pool.spawn(async move {
+                    while i == 0 && !release.load(Ordering::Acquire) {
+                        yield_now().await;
+                    }
+                    ChunkData::single(BlockState::air()).into()
+                }));
+            }
+            let mut world = World::new();
+            let entity = world.spawn(block_world).id();
+            world.run_system_once(receive_generated_chunks).unwrap();
+            assert!(world.get::<BlockWorld>(entity).unwrap().chunk_queue.finished_generating.is_empty());
+
+            release.store(true, Ordering::Release);
+            while !world.get::<BlockWorld>(entity).unwrap().chunk_queue.currently_generating.is_empty() {
+                world.run_system_once(receive_generated_chunks).unwrap();
+            }
+            world.get::<BlockWorld>(entity).unwrap().chunk_queue.finished_generating.iter().map(|(pos, _)| *pos).collect::<Vec<_>>()
+        };
+        let first = run();
+        assert_eq!(first, started);
+        assert_eq!(run(), first);
+    }
+
+    #[test]
+    fn poisoned_but_valid_data_is_recovered() {
+        let lock = RwLock::new(ChunkData::single(BlockState::air()));
+        let _ = std::thread::scope(|s| s.spawn(|| {
+            let _guard = lock.write().unwrap();
+            panic!("poisoning the lock on purpose");
+        }).join());
+        assert!(lock.is_poisoned());
+
+        assert!(read_or_recover(IVec3::ZERO, &lock).is_ok());
+        assert!(!lock.is_poisoned());
+    }
+
+    #[test]
+    fn invalid_poisoned_data_is_not_retried() {
+        let mut queue = ChunkQueue::default();
+        let pos = ivec3(1, 2, 3);
+        let error = MeshError::PoisonedData(pos, ChunkError::RefCountTotal(1, ChunkData::BLOCKS_PER_CHUNK));
+        assert!(!queue.should_retry_meshing(pos, &error));
+        assert!(queue.mesh_failures.is_empty());
+    }
+
+    #[test]
+    fn dirty_chunks_are_packed_nearest_first() {
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let chunks = [ivec3(3, 0, 0), ivec3(0, 0, 1), ivec3(-1, 0, 0), ivec3(0, 0, 0)];
+        let mut block_world = air_world(&chunks);
+        let mut events = event_world();
+        for pos in chunks {
+            block_world.set_block(&mut events.commands(), &(pos * ChunkData::CHUNK_SIZE as i32), stone.clone(), BlockChangeSource::Programmatic).unwrap();
+        }
+
+        let packed = save::pack_dirty_chunks(&mut block_world, std::path::Path::new("saves"), IVec3::ZERO).unwrap();
+        let order = packed.iter().map(|(file, _)| file.clone()).collect::<Vec<_>>();
+        let expected = [ivec3(0, 0, 0), ivec3(-1, 0, 0), ivec3(0, 0, 1), ivec3(3, 0, 0)]
+            .map(|pos| save::chunk_file(std::path::Path::new("saves"), block_world.get_dimension(), pos));
+        assert_eq!(order, expected);
+    }
+
+    #[test]
+    fn states_look_up_their_own_block() {
+        let registry = test_registry(&["stone", "dirt"]);
+        for id in ["stone", "dirt", "air"] {
+            let block = BlockState::new(id, &registry).unwrap().block(&registry);
+            assert!(block.is_some_and(|block| std::ptr::eq(block, registry.get(id).unwrap())), "{id}");
+        }
+
+        // a state from a registry that has a block this one doesn't
+        let other = test_registry(&["glass"]);
+        let glass = BlockState::new("glass", &other).unwrap();
+        assert!(glass.block(&registry).is_none());
+        assert!(glass.block(&other).is_some());
+    }
+
+    #[test]
+    fn interned_air_matches_the_air_block() {
+        let registry = test_registry(&["stone"]);
+        let table = BlockStateTable::from_registry(&registry).unwrap();
+        let stone = BlockState::new("stone", &registry).unwrap();
+
+        assert_eq!(table.get_state(table.get_air_id()), Some(&BlockState::air()));
+        assert_eq!(table.is_air_state(&BlockState::air()), BlockState::air().is_air());
+        assert_eq!(table.is_air_state(&stone), stone.is_air());
+        assert!(ChunkData::single(BlockState::air()).is_empty(&table));
+        assert!(!ChunkData::single(stone).is_empty(&table));
+    }
+
+    #[test]
+    fn compact_states_round_trip() {
+        use crate::asset::block::{BlockAsset, BlockStateAsset};
+        use crate::registry::block::test_asset;
+
+        let property = |name: &str, values: &[&str]| BlockStateAsset {
+            name: String::from(name),
+            values: values.iter().map(|v| String::from(*v)).collect(),
+        };
+        let mut registry = Registry::<Block>::new("block");
+        registry.register(Block::from_asset(&BlockAsset {
+            states: vec![property("axis", &["x", "y", "z"]), property("mossy", &["false", "true"])],
+            ..test_asset("log")
+        })).unwrap();
+        registry.register(Block::from_asset(&test_asset("stone"))).unwrap();
+        registry.freeze();
+
+        let state = BTreeMap::from([(String::from("axis"), String::from("z")), (String::from("mossy"), String::from("true"))]);
+        let log = BlockState::with_state("log", state, &registry).unwrap();
+        let compact = log.to_compact(&registry);
+        assert_eq!(compact, CompactBlockState::Indexed { block: registry.get_index("log").unwrap() as u32, state: vec![2, 1] });
+        assert_eq!(BlockState::from_compact(&compact, &registry).unwrap(), log);
+
+        // a value the block doesn't declare can't be indexed, so it falls back to the string form
+        let odd = BlockState::with_state("log", BTreeMap::from([(String::from("axis"), String::from("w"))]), &registry).unwrap();
+        assert_eq!(odd.to_compact(&registry), CompactBlockState::Named(odd.clone()));
+        assert_eq!(BlockState::from_compact(&odd.to_compact(&registry), &registry).unwrap(), odd);
+
+        let missing = CompactBlockState::Indexed { block: 99, state: vec![] };
+        assert!(matches!(BlockState::from_compact(&missing, &registry), Err(BlockStateError::InvalidIndex(99))));
+    }
+
+    #[test]
+    fn queueing_a_chunk_twice_queues_it_once() {
+        let mut block_world = air_world(&[]);
+        block_world.queue_chunk_generation(ivec3(1, 0, 0));
+        block_world.queue_chunk_generation(ivec3(1, 0, 0));
+        assert!(block_world.is_queued_for_generation(&ivec3(1, 0, 0)));
+        assert!(!block_world.is_queued_for_generation(&ivec3(2, 0, 0)));
+        assert_eq!(block_world.get_pending_generation().count(), 1);
+    }
+
+    #[test]
+    fn reconcile_keeps_or_drops_chunks_queued_both_ways() {
+        let mut block_world = air_world(&[]);
+        for pos in [ivec3(1, 0, 0), ivec3(2, 0, 0)] {
+            block_world.queue_chunk_generation(pos);
+            block_world.queue_chunk_despawn(pos);
+        }
+        block_world.reconcile_queues(|pos| pos == ivec3(1, 0, 0));
+
+        assert!(block_world.is_queued_for_generation(&ivec3(1, 0, 0)));
+        assert!(!block_world.is_queued_for_generation(&ivec3(2, 0, 0)));
+        // neither is loaded, so there's nothing left to despawn
+        assert!(block_world.chunk_queue.to_despawn.is_empty());
+    }
+
+    #[test]
+    fn prioritized_meshing_is_scheduled_first() {
+        let budget = TaskBudget { total: 10, max_generation: 8, max_meshing: 6, priority: TaskPriority::Meshing };
+        // both queues have more waiting than fits
+        assert_eq!(budget.allowance(0, 0, 100, 100), (4, 6));
+        // running tasks use up the total first
+        assert_eq!(budget.allowance(2, 2, 100, 100), (2, 4));
+
+        let budget = TaskBudget { priority: TaskPriority::Generation, ..budget };
+        assert_eq!(budget.allowance(0, 0, 100, 100), (8, 2));
+    }
+}
@@ -1,4 +1,6 @@
-use bevy::prelude::{Component, Resource};
+use bevy::prelude::{vec2, Component, IVec3, Resource, Vec2};
+use crate::world::chunk;
+use crate::world::chunk::ChunkData;
 
 #[derive(Component)]
 pub struct MainCamera;
@@ -9,6 +11,20 @@ pub struct CameraSettings {
     pub yaw_sensitivity: f32,
     pub fov: f32,
     pub movement_speed: f32,
+    /// Radius in chunks around the player that gets loaded. Also decides the far clip plane.
+    pub render_distance: u32,
+    /// Extra chunks past the render distance before chunks get despawned. Chunks in this gap stay loaded but aren't generated,
+    /// so pacing back and forth over the edge of the render distance doesn't keep loading and unloading the same chunks.
+    pub despawn_margin: u32,
+    pub near_plane: f32,
+    /// Fov is multiplied by this while sprinting.
+    pub sprint_fov_multiplier: f32,
+    /// How quickly the fov moves toward its target when it changes, higher is faster. 0 snaps instantly.
+    pub fov_transition_speed: f32,
+    /// Gamepad sticks pushed less than this far, from 0 to 1, count as centered, so sticks that don't quite center don't drift.
+    pub gamepad_dead_zone: f32,
+    /// Degrees per second the camera turns with the right stick pushed all the way, before the yaw and pitch sensitivities.
+    pub gamepad_look_speed: f32,
 }
 impl Default for CameraSettings {
     fn default() -> Self {
@@ -16,7 +32,177 @@ impl Default for CameraSettings {
             pitch_sensitivity: 0.75,
             yaw_sensitivity: 0.75,
             fov: 90.0,
-            movement_speed: 50.0
+            movement_speed: 50.0,
+            render_distance: 8,
+            despawn_margin: 4,
+            near_plane: 0.1,
+            sprint_fov_multiplier: 1.15,
+            fov_transition_speed: 10.0,
+            gamepad_dead_zone: 0.15,
+            gamepad_look_speed: 240.0,
         }
     }
-}
\ No newline at end of file
+}
+impl CameraSettings {
+    pub fn get_far_plane(&self) -> f32 {
+        far_plane(self.render_distance)
+    }
+
+    /// Roughly how many chunks are loaded at once at this render distance: a sphere out to the despawn distance.
+    /// Used to size the chunk map up front, so it doesn't rehash while the world loads.
+    pub fn get_chunk_capacity(&self) -> usize {
+        let r = self.get_despawn_distance() as f32;
+        (4.0 / 3.0 * std::f32::consts::PI * r * r * r) as usize
+    }
+
+    /// The fov the camera should be moving toward, in degrees.
+    pub fn get_target_fov(&self, sprinting: bool) -> f32 {
+        let fov = if sprinting { self.fov * self.sprint_fov_multiplier } else { self.fov };
+        fov.clamp(MIN_FOV, MAX_FOV)
+    }
+
+    /// Distance in chunks past which loaded chunks get despawned. See [`CameraSettings::despawn_margin`].
+    pub fn get_despawn_distance(&self) -> u32 {
+        self.render_distance + self.despawn_margin
+    }
+
+    /// Whether a chunk is close enough to `center`, also a chunk position, to be generated.
+    pub fn in_spawn_range(&self, center: IVec3, pos: IVec3) -> bool {
+        chunk::chunk_distance_squared(center, pos) <= (self.render_distance as i64).pow(2)
+    }
+
+    /// Whether a chunk is close enough to `center` to stay loaded. Always true for chunks in spawn range.
+    pub fn in_keep_range(&self, center: IVec3, pos: IVec3) -> bool {
+        chunk::chunk_distance_squared(center, pos) <= (self.get_despawn_distance() as i64).pow(2)
+    }
+
+    /// How far a right stick position turns the camera over `delta` seconds, as (yaw, pitch) in radians.
+    /// Pushing right turns right and pushing up looks up, matching the mouse.
+    pub fn gamepad_look_delta(&self, stick: Vec2, delta: f32) -> Vec2 {
+        let stick = apply_dead_zone(stick, self.gamepad_dead_zone);
+        let degrees = vec2(-stick.x * self.yaw_sensitivity, stick.y * self.pitch_sensitivity) * self.gamepad_look_speed * delta;
+        vec2(degrees.x.to_radians(), degrees.y.to_radians())
+    }
+}
+
+/// Treats a stick inside the dead zone as centered, and rescales the rest so the edge of the dead zone is 0 and all the way is 1.
+pub fn apply_dead_zone(stick: Vec2, dead_zone: f32) -> Vec2 {
+    let length = stick.length();
+    if length <= dead_zone || dead_zone >= 1.0 {
+        return Vec2::ZERO;
+    }
+    let scaled = ((length - dead_zone) / (1.0 - dead_zone)).min(1.0);
+    stick * (scaled / length)
+}
+
+/// Smallest fov the camera can have, in degrees.
+pub const MIN_FOV: f32 = 30.0;
+/// Largest fov the camera can have, in degrees.
+pub const MAX_FOV: f32 = 150.0;
+
+/// Moves `current` fov toward `target` over `delta` seconds. Eases out, so big changes start fast and settle smoothly.
+/// Both are in degrees, and the result is always within [`MIN_FOV`] and [`MAX_FOV`].
+pub fn step_fov(current: f32, target: f32, speed: f32, delta: f32) -> f32 {
+    if speed <= 0.0 {
+        return target.clamp(MIN_FOV, MAX_FOV);
+    }
+    let t = 1.0 - (-speed * delta).exp();
+    let mut fov = current + (target - current) * t;
+    // close enough, stop so the projection doesn't change every frame
+    if (target - fov).abs() < 0.01 {
+        fov = target;
+    }
+    fov.clamp(MIN_FOV, MAX_FOV)
+}
+
+/// Extra distance past the furthest loaded chunk before the far plane, in blocks.
+pub const FAR_PLANE_MARGIN: f32 = 16.0;
+
+/// Computes the far clip plane for a render distance in chunks.
+/// Uses the diagonal of a chunk so corners of the furthest loaded chunks are never clipped.
+pub fn far_plane(render_distance: u32) -> f32 {
+    render_distance as f32 * ChunkData::CHUNK_SIZE as f32 * 3.0_f32.sqrt() + FAR_PLANE_MARGIN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::ivec3;
+
+    #[test]
+    fn sticks_inside_the_dead_zone_are_centered() {
+        assert_eq!(apply_dead_zone(vec2(0.1, -0.05), 0.2), Vec2::ZERO);
+        assert_eq!(apply_dead_zone(vec2(0.2, 0.0), 0.2), Vec2::ZERO);
+        // a dead zone covering the whole stick never moves
+        assert_eq!(apply_dead_zone(vec2(1.0, 0.0), 1.0), Vec2::ZERO);
+    }
+
+    #[test]
+    fn sticks_outside_the_dead_zone_are_rescaled() {
+        // halfway between the dead zone and the edge comes out as half
+        let half = apply_dead_zone(vec2(0.0, 0.6), 0.2);
+        assert!(half.abs_diff_eq(vec2(0.0, 0.5), 1e-5), "{half}");
+        // the direction is kept
+        let diagonal = apply_dead_zone(Vec2::splat(0.5), 0.2);
+        assert!((diagonal.x - diagonal.y).abs() < 1e-6);
+        assert!((diagonal.length() - (0.5_f32.hypot(0.5) - 0.2) / 0.8).abs() < 1e-5);
+        // all the way out is 1, even past the edge
+        assert!(apply_dead_zone(vec2(-1.0, 0.0), 0.2).abs_diff_eq(vec2(-1.0, 0.0), 1e-6));
+        assert!(apply_dead_zone(vec2(1.0, 1.0), 0.2).length() <= 1.0 + 1e-6);
+    }
+
+    #[test]
+    fn fov_approaches_the_target_without_overshooting() {
+        for (start, target) in [(70.0, 90.0), (90.0, 70.0)] {
+            let mut fov = start;
+            let mut last_gap = f32::abs(target - start);
+            for _ in 0..600 {
+                fov = step_fov(fov, target, 8.0, 1.0 / 60.0);
+                let gap = f32::abs(target - fov);
+                assert!(gap <= last_gap, "moved away from {target}: {fov}");
+                // never crosses over to the other side of the target
+                assert!(fov >= start.min(target) && fov <= start.max(target), "overshot {target}: {fov}");
+                last_gap = gap;
+            }
+            assert_eq!(fov, target);
+        }
+    }
+
+    #[test]
+    fn fov_is_clamped() {
+        assert_eq!(step_fov(70.0, 500.0, 0.0, 0.1), MAX_FOV);
+        assert_eq!(step_fov(70.0, 1.0, 0.0, 0.1), MIN_FOV);
+        assert_eq!(step_fov(MAX_FOV, 500.0, 8.0, 10.0), MAX_FOV);
+        assert!(step_fov(35.0, -20.0, 8.0, 1.0) >= MIN_FOV);
+    }
+
+    #[test]
+    fn chunk_capacity_matches_what_stays_loaded() {
+        let mut last = 0;
+        for render_distance in [2, 8, 16] {
+            let settings = CameraSettings { render_distance, ..Default::default() };
+            let r = settings.get_despawn_distance() as i32;
+            let kept = (-r..=r)
+                .flat_map(|x| (-r..=r).flat_map(move |y| (-r..=r).map(move |z| ivec3(x, y, z))))
+                .filter(|pos| settings.in_keep_range(IVec3::ZERO, *pos))
+                .count();
+            let capacity = settings.get_chunk_capacity();
+            // within 10%, so the map neither keeps rehashing while the world loads nor holds far more than it needs
+            assert!(capacity.abs_diff(kept) * 10 <= kept, "render distance {render_distance}: capacity {capacity} for {kept} chunks");
+            assert!(capacity > last);
+            last = capacity;
+        }
+    }
+
+    #[test]
+    fn far_plane_reaches_past_the_render_distance() {
+        assert_eq!(far_plane(0), FAR_PLANE_MARGIN);
+        // 8 chunk diagonals, 32 * sqrt(3) each
+        assert!((far_plane(8) - (8.0 * 55.425625 + FAR_PLANE_MARGIN)).abs() < 1e-3);
+        assert!(far_plane(16) > far_plane(8));
+        // a chunk straight ahead at the edge of the render distance is always in view
+        let settings = CameraSettings { render_distance: 12, ..Default::default() };
+        assert_eq!(settings.get_far_plane(), far_plane(12));
+        assert!(settings.get_far_plane() > (12 * ChunkData::CHUNK_SIZE) as f32);
+    }
+}
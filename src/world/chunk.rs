@@ -1,24 +1,34 @@
+use std::collections::BinaryHeap;
 use std::slice::Iter;
-use std::sync::{Arc, RwLock};
-use bevy::ecs::error::panic;
-use bevy::log::info_span;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use bevy::log::{debug, info_span};
+use crate::asset::block::RenderLayer;
 use crate::core::errors::ChunkError;
 use crate::math::block::Vec3Ext;
-use crate::world::block::BlockState;
+use crate::world::block::{BlockState, BlockStateTable};
 use bevy::math::ivec3;
 use bevy::prelude::{Component, Entity, IVec3, Transform};
 use serde::{Deserialize, Serialize};
 
+/// How a chunk's data is kept in memory. Chunks far from the player can be kept packed to save memory,
+/// and get unpacked again the first time anything reads, edits or meshes them. See [`Chunk::pack`].
+#[derive(Debug)]
+pub enum ChunkStorage {
+    Unpacked(Arc<RwLock<ChunkData>>),
+    Packed(PackedChunkData),
+}
+
 /// A data structure that represents a chunk in the world. Stores some information about it tied to
 /// its physical state, like the blocks in the chunk and its state.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Chunk {
     /// The position of this chunk in the world. Should always be the same.
     pos: IVec3,
     /// Data may be read by multiple threads, but only modified by one thread.
     /// Structure somewhat mirrors how chunks are stored to disk
     /// Note: this may not be available! Especially if the chunk is not generated yet.
-    data: Option<Arc<RwLock<ChunkData>>>,
+    /// The lock is only for swapping between packed and unpacked, so reads don't need a mutable chunk.
+    data: Option<RwLock<ChunkStorage>>,
     /// The entity ID of the corresponding entity.
     /// The entity stores all mesh information and rendering data and other in world data
     chunk_entity: Entity,
@@ -38,27 +48,93 @@ impl Chunk {
     /// borrows the inner ChunkData. Mostly used for meshing on other threads, or for bulk read/writes / specific operations on the data.
     /// Unlike the main getter/setter method, you CAN read and write while a chunk is not fully generated, however this method still returns
     /// an error if the chunk data is None.
+    /// Unpacks the data first if it's packed, and it stays unpacked until the next [`Chunk::pack`].
     pub fn get_data(&self) -> Result<Arc<RwLock<ChunkData>>, ChunkError> {
-        if self.data.is_none() {
+        let Some(storage) = self.data.as_ref() else {
+            return Err(ChunkError::Uninitialized(self.pos));
+        };
+        if let ChunkStorage::Unpacked(data) = &*storage.read().unwrap() {
+            return Ok(data.clone());
+        }
+        // someone else may have unpacked it while we waited for the write lock
+        let mut write_lock = storage.write().unwrap();
+        let data = match &*write_lock {
+            ChunkStorage::Unpacked(data) => return Ok(data.clone()),
+            ChunkStorage::Packed(packed) => {
+                let _span = info_span!("chunk_unpack").entered();
+                Arc::new(RwLock::new(ChunkData::try_from(packed.clone())?))
+            }
+        };
+        *write_lock = ChunkStorage::Unpacked(data.clone());
+        Ok(data)
+    }
+
+    /// Packs this chunk's data to save memory, see [`ChunkStorage`].
+    /// Does nothing if it's already packed, or if the data is borrowed elsewhere (e.g. by a meshing task), since whoever has it could still write to it.
+    /// Returns whether the chunk got packed.
+    pub fn pack(&self) -> bool {
+        let Some(storage) = self.data.as_ref() else {
+            return false;
+        };
+        let mut write_lock = storage.write().unwrap();
+        let ChunkStorage::Unpacked(data) = &*write_lock else {
+            return false;
+        };
+        if Arc::strong_count(data) > 1 {
+            return false;
+        }
+        let packed = PackedChunkData::from(&*data.read().unwrap());
+        *write_lock = ChunkStorage::Packed(packed);
+        true
+    }
+
+    /// Roughly how many bytes this chunk's data takes up, packed or not. 0 if it has no data yet.
+    pub fn memory_footprint(&self) -> usize {
+        let Some(storage) = self.data.as_ref() else {
+            return 0;
+        };
+        match &*storage.read().unwrap() {
+            ChunkStorage::Unpacked(data) => data.read().unwrap().memory_footprint(),
+            ChunkStorage::Packed(packed) => packed.memory_footprint(),
+        }
+    }
+
+    pub fn is_packed(&self) -> bool {
+        self.data.as_ref().is_some_and(|storage| matches!(*storage.read().unwrap(), ChunkStorage::Packed(_)))
+    }
+
+    /// Gets this chunk's data in its packed form, e.g. for saving. Packed chunks are copied as they are, without unpacking them.
+    pub fn to_packed(&self) -> Result<PackedChunkData, ChunkError> {
+        let Some(storage) = self.data.as_ref() else {
             return Err(ChunkError::Uninitialized(self.pos));
+        };
+        match &*storage.read().unwrap() {
+            ChunkStorage::Unpacked(data) => Ok(PackedChunkData::from(&*data.read().unwrap())),
+            ChunkStorage::Packed(packed) => Ok(packed.clone()),
         }
-        Ok(self.data.as_ref().unwrap().clone())
+    }
+
+    /// Read locks this chunk's storage as it is, packed or not, without unpacking it. None if the chunk has no data yet.
+    /// Holding on to this blocks packing and unpacking, so don't keep it around for long.
+    pub fn read_storage(&self) -> Option<RwLockReadGuard<'_, ChunkStorage>> {
+        self.data.as_ref().map(|storage| storage.read().unwrap())
     }
 
     pub fn set_block(&mut self, pos: IVec3, state: BlockState) -> Result<BlockState, ChunkError> {
         if !self.is_initialized() {
             return Err(ChunkError::Uninitialized(self.pos));
         }
-        let data = self.data.as_mut().unwrap();
+        let data = self.get_data()?;
         let mut write_lock = data.write().unwrap();
         write_lock.set_block(pos.x as usize, pos.y as usize, pos.z as usize, state)
     }
 
+    /// Gets a block in a fully generated chunk. Use [`Chunk::get_block_partial`] to read chunks that are still generating.
     pub fn get_block(&self, pos: IVec3) -> Result<BlockState, ChunkError> {
         if !self.is_initialized() {
             return Err(ChunkError::Uninitialized(self.pos));
         }
-        let data = self.data.as_ref().unwrap();
+        let data = self.get_data()?;
         let read_lock = data.read().unwrap();
         read_lock.get_block(pos.x as usize, pos.y as usize, pos.z as usize)
     }
@@ -67,36 +143,99 @@ impl Chunk {
         self.pos
     }
 
+    /// Gets a block in a chunk that has at least its terrain, even if it hasn't finished generating.
+    /// Meant for generation passes like decorations, which need to read neighbouring chunks that may not be done yet.
+    /// Whatever later passes haven't placed yet won't be there. Errors if the chunk has no data at all.
+    pub fn get_block_partial(&self, pos: IVec3) -> Result<BlockState, ChunkError> {
+        if !self.has_terrain() {
+            return Err(ChunkError::Uninitialized(self.pos));
+        }
+        let data = self.get_data()?;
+        let read_lock = data.read().unwrap();
+        read_lock.get_block(pos.x as usize, pos.y as usize, pos.z as usize)
+    }
+
     pub fn get_generation_status(&self) -> ChunkGenerationStatus {
         self.generation_status
     }
 
     pub fn is_initialized(&self) -> bool {
-        self.data.is_some() && match self.generation_status {
-            ChunkGenerationStatus::Generated => true,
-            _ => false
-        }
+        self.data.is_some() && matches!(self.generation_status, ChunkGenerationStatus::Generated)
+    }
+
+    /// Whether terrain has been generated, so the data can be read by [`Chunk::get_block_partial`].
+    pub fn has_terrain(&self) -> bool {
+        self.data.is_some() && !matches!(self.generation_status, ChunkGenerationStatus::NotGenerated)
     }
 
     pub fn get_entity(&self) -> Entity {
         self.chunk_entity
     }
 
+    /// Gives this chunk its terrain, moving it to [`ChunkGenerationStatus::AfterTerrain`].
+    /// It can't be read normally or meshed until it's advanced to [`ChunkGenerationStatus::Generated`], see [`Chunk::advance_generation`].
     pub fn init_data(&mut self, data: ChunkData) -> Result<(), ChunkError> {
         if self.data.is_some() {
             return Err(ChunkError::AlreadyInitialized(self.pos));
         }
         let _span = info_span!("chunk_init_data").entered();
+        self.check_transition(ChunkGenerationStatus::AfterTerrain)?;
 
+        self.data = Some(RwLock::new(ChunkStorage::Unpacked(Arc::new(RwLock::new(data)))));
+        self.generation_status = ChunkGenerationStatus::AfterTerrain;
 
+        Ok(())
+    }
 
-        self.data = Some(Arc::new(RwLock::new(data)));
+    /// Places the blocks from the decoration pass (see [`GeneratedChunk`](crate::world::pipeline::GeneratedChunk)),
+    /// moving this chunk to [`ChunkGenerationStatus::AfterDecorations`]. Needs its terrain first, see [`Chunk::init_data`].
+    pub fn place_decorations(&mut self, decorations: &[(IVec3, BlockState)]) -> Result<(), ChunkError> {
+        self.check_transition(ChunkGenerationStatus::AfterDecorations)?;
+        let data = self.get_data()?;
+        let mut write_lock = data.write().unwrap();
+        for (pos, state) in decorations {
+            write_lock.set_block(pos.x as usize, pos.y as usize, pos.z as usize, state.clone())?;
+        }
+        drop(write_lock);
+        self.set_generation_status(ChunkGenerationStatus::AfterDecorations)
+    }
 
-        //TODO: switch to AfterTerrain when implemented decorators
-        self.generation_status = ChunkGenerationStatus::Generated;
+    /// Moves this chunk to the next generation status. Chunks only go forward one step at a time,
+    /// so e.g. decorations can't be marked done before the terrain is there. Returns the new status.
+    pub fn advance_generation(&mut self) -> Result<ChunkGenerationStatus, ChunkError> {
+        let Some(next) = self.generation_status.next() else {
+            return Err(ChunkError::IllegalTransition(self.pos, self.generation_status, self.generation_status));
+        };
+        self.set_generation_status(next)?;
+        Ok(next)
+    }
 
+    /// Sets the generation status, erroring if it isn't the step right after the current one.
+    pub fn set_generation_status(&mut self, status: ChunkGenerationStatus) -> Result<(), ChunkError> {
+        self.check_transition(status)?;
+        // terrain comes with the data, so it has to go through init_data
+        if self.data.is_none() {
+            return Err(ChunkError::Uninitialized(self.pos));
+        }
+        self.generation_status = status;
         Ok(())
     }
+
+    fn check_transition(&self, status: ChunkGenerationStatus) -> Result<(), ChunkError> {
+        match self.generation_status.can_advance_to(status) {
+            true => Ok(()),
+            false => Err(ChunkError::IllegalTransition(self.pos, self.generation_status, status)),
+        }
+    }
+
+    /// A fully generated chunk with this data and no entity, for tests that need a loaded world.
+    #[cfg(test)]
+    pub(crate) fn generated(pos: IVec3, data: ChunkData) -> Self {
+        let mut chunk = Self::new(pos, Entity::PLACEHOLDER);
+        chunk.init_data(data).unwrap();
+        while chunk.advance_generation().is_ok() {}
+        chunk
+    }
 }
 
 #[derive(Default, Debug, Component)]
@@ -119,18 +258,45 @@ impl ChunkMarker {
 }
 
 
-#[derive(Debug, Copy, Clone)]
+/// How far along generating a chunk is. Chunks move through these in order, one step at a time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ChunkGenerationStatus {
     NotGenerated,
+    /// Has data, which decorations can read from and place into with [`Chunk::get_block_partial`].
     AfterTerrain,
-    // todo: maybe remove
+    /// Decorations are placed, but the chunk isn't ready to be read normally or meshed.
     AfterDecorations,
     Generated
 }
+impl ChunkGenerationStatus {
+    /// The status after this one, or None if this is the last.
+    pub fn next(self) -> Option<Self> {
+        match self {
+            Self::NotGenerated => Some(Self::AfterTerrain),
+            Self::AfterTerrain => Some(Self::AfterDecorations),
+            Self::AfterDecorations => Some(Self::Generated),
+            Self::Generated => None,
+        }
+    }
+
+    /// Whether a chunk can go from this status to `status`. Only the next status is allowed, no skipping or going back.
+    pub fn can_advance_to(self, status: Self) -> bool {
+        self.next() == Some(status)
+    }
+}
 
 
 /// Representation of chunks in memory
 /// A chunk is a 32x32x32 region of the world which contains blocks and blockstates.
+///
+/// Blocks are stored as ids into this chunk's own palette, not as global ids. Global ids ([`crate::world::block::BlockStateId`]) are `u32`s
+/// covering every blockstate in the game, while palette ids only cover the states actually in this chunk, so a chunk stays one byte per block
+/// no matter how many blocks are registered. Palette entries store the full [`BlockState`], so nothing here depends on global ids.
+///
+/// Limits: ids are at most two bytes, so the palette could hold up to [`ChunkData::MAX_PALETTE_ENTRIES`] entries.
+/// Entries nothing uses anymore are reused before new ones are added, so the palette of a valid chunk never gets much bigger than the
+/// number of blocks in a chunk (32768). Adding past the limit is an error, see [`ChunkData::add_palette`].
+/// The `u16` refcount of a palette entry (max 65535) can't overflow either.
 #[derive(Debug, Clone)]
 pub struct ChunkData {
     palette: Vec<PaletteEntry>,
@@ -154,6 +320,9 @@ impl ChunkData {
 
     pub const DOUBLE_BLOCKS_PER_CHUNK: usize = Self::BLOCKS_PER_CHUNK * 2;
 
+    /// Max number of palette entries, limited by the two byte id size.
+    pub const MAX_PALETTE_ENTRIES: usize = u16::MAX as usize + 1;
+
     // generally do not create this yourself
     pub fn with_data(data: Vec<u8>, palette: Vec<PaletteEntry>) -> Self {
 
@@ -177,7 +346,10 @@ impl ChunkData {
 
     pub fn single(state: BlockState) -> Self {
         let palette = vec![
-            PaletteEntry::new(state),
+            PaletteEntry {
+                ref_count: Self::BLOCKS_PER_CHUNK as u16,
+                block: state,
+            },
         ];
 
         ChunkData {
@@ -192,8 +364,52 @@ impl ChunkData {
         self.is_single
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.is_single && self.palette[0].block.is_air()
+    /// Checks that this chunk's data is consistent:
+    /// - single chunks have exactly one palette entry, used by every block.
+    /// - other chunks have the right amount of data for their id size.
+    /// - every id in the data is in the palette.
+    /// - refcounts add up to the number of blocks, and each one matches how many blocks actually use that entry.
+    pub fn validate(&self) -> Result<(), ChunkError> {
+        if self.is_single {
+            if self.palette.len() != 1 {
+                return Err(ChunkError::InvalidSinglePalette(self.palette.len()));
+            }
+            let ref_count = self.palette[0].ref_count as usize;
+            if ref_count != Self::BLOCKS_PER_CHUNK {
+                return Err(ChunkError::RefCountMismatch(0, ref_count, Self::BLOCKS_PER_CHUNK));
+            }
+            return Ok(());
+        }
+
+        let expected_len = if self.double_bytes { Self::DOUBLE_BLOCKS_PER_CHUNK } else { Self::BLOCKS_PER_CHUNK };
+        if self.data.len() != expected_len {
+            return Err(ChunkError::InvalidDataLength(expected_len, self.data.len()));
+        }
+
+        let mut used = vec![0_usize; self.palette.len()];
+        for i in 0..Self::BLOCKS_PER_CHUNK {
+            let id = self.block_at_index(i);
+            match used.get_mut(id) {
+                Some(count) => *count += 1,
+                None => return Err(ChunkError::InvalidPaletteId(i, id)),
+            }
+        }
+
+        let total: usize = self.palette.iter().map(|p| p.ref_count as usize).sum();
+        if total != Self::BLOCKS_PER_CHUNK {
+            return Err(ChunkError::RefCountTotal(total, Self::BLOCKS_PER_CHUNK));
+        }
+        for (id, (entry, count)) in self.palette.iter().zip(used).enumerate() {
+            if entry.ref_count as usize != count {
+                return Err(ChunkError::RefCountMismatch(id, entry.ref_count as usize, count));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether every block in this chunk is air.
+    pub fn is_empty(&self, table: &BlockStateTable) -> bool {
+        self.is_single && table.is_air_state(&self.palette[0].block)
     }
 
     // there are 32768 blocks in a chunk, so 32768 possible states. Could be stored in a u16 but eh.
@@ -212,6 +428,7 @@ impl ChunkData {
         self.block_at_index(index)
     }
 
+    /// Gets the palette id (not the global id) of the block at a raw index.
     pub fn block_at_index(&self, index: usize) -> usize {
         // if single we just return 0
         if self.is_single {
@@ -235,24 +452,36 @@ impl ChunkData {
         self.palette.len()
     }
 
+    /// Roughly how many bytes this chunk takes up in memory, counting the block data and palette.
+    /// Doesn't count the state maps of palette entries, so it's an estimate, but a close one for most chunks.
+    pub fn memory_footprint(&self) -> usize {
+        size_of::<Self>()
+            + self.data.capacity()
+            + self.palette.capacity() * size_of::<PaletteEntry>()
+            + self.palette.iter().map(|p| p.block.get_id().len()).sum::<usize>()
+    }
+
     pub fn lookup_palette(&self, index: usize) -> Result<&PaletteEntry, ChunkError> {
         Ok(&self.palette[index])
     }
 
-    // adds palette to the entry and returns the id it adds at
-    pub fn add_palette(&mut self, entry: PaletteEntry) -> usize {
+    // adds palette to the entry and returns the id it adds at. Errors if the palette is out of ids.
+    pub fn add_palette(&mut self, entry: PaletteEntry) -> Result<usize, ChunkError> {
         // do nothing if palette exists
         for i in 0..self.palette.len() {
             if self.palette[i] == entry {
-                return i;
+                return Ok(i);
             }
         }
 
         if let Some(i) = self.first_free_palette() {
             self.palette[i] = entry;
-            return i;
+            return Ok(i);
+        }
+        // no free palettes, add a new one. Every entry is in use by at least one block, so valid chunks never get here
+        if self.palette.len() >= Self::MAX_PALETTE_ENTRIES {
+            return Err(ChunkError::PaletteFull(Self::MAX_PALETTE_ENTRIES));
         }
-        // no free palettes, add a new one.
         // palettes are full. Resize the data.
         if (self.palette.len()) == 256 {
             self.grow_data();
@@ -260,7 +489,7 @@ impl ChunkData {
         // push palette at the end.
         self.palette.push(entry);
         // return last index
-        self.palette.len() - 1
+        Ok(self.palette.len() - 1)
     }
 
     pub fn get_block(&self, x: usize, y: usize, z: usize) -> Result<BlockState, ChunkError> {
@@ -293,14 +522,14 @@ impl ChunkData {
             // need to make data now - since we're setting block lol.
             self.is_single = false;
             // init data to a vec of 0s
-            self.data = vec![0; Self::CHUNK_SIZE];
+            self.data = vec![0; Self::BLOCKS_PER_CHUNK];
             // set refcount to 32768
             self.palette[0].ref_count = Self::BLOCKS_PER_CHUNK as u16;
         }
 
 
         //Grab the old block and decrease the refcount.
-        let mut p = &mut self.palette[old_block];
+        let p = &mut self.palette[old_block];
         if p.is_free() {
             panic!("Invalid palette data: palette {:?} is free, but exists in data", p);
         }
@@ -310,7 +539,7 @@ impl ChunkData {
 
         // check the palette to see if this block already is in it (including free ones!)
         for palette_idx in 0..self.palette.len() {
-            let mut p = &mut self.palette[palette_idx];
+            let p = &mut self.palette[palette_idx];
             // block is already in the palette, so just increase the refcount and set the data.
             if p.block == block {
                 p.ref_count += 1;
@@ -319,7 +548,14 @@ impl ChunkData {
             }
         }
         // block is not in the palette, add it to the palette.
-        let block_id = self.add_palette(PaletteEntry::new(block));
+        let block_id = match self.add_palette(PaletteEntry::new(block)) {
+            Ok(id) => id,
+            Err(e) => {
+                // undo the decrement so the chunk stays valid
+                self.palette[old_block].ref_count += 1;
+                return Err(e);
+            }
+        };
         // increase palette's refcount
         self.palette[block_id].ref_count += 1;
 
@@ -331,10 +567,14 @@ impl ChunkData {
 
     }
 
+    /// Sets the palette id of the block at a raw index. The id must already be in the palette,
+    /// and the data must already be double byte if it's past 255, see [`ChunkData::add_palette`].
     pub fn set_raw(&mut self, index: usize, block_id: usize) {
         if self.is_single {
             panic!("Cannot set raw on single chunks!")
         }
+        debug_assert!(block_id < self.palette.len(), "Palette id {block_id} is not in the palette");
+        debug_assert!(self.double_bytes || block_id <= u8::MAX as usize, "Palette id {block_id} doesn't fit in one byte");
         if self.double_bytes {
             let lsb = block_id as u8;
             let msb = (block_id >> 8) as u8;
@@ -365,26 +605,9 @@ impl ChunkData {
     }
 
 
-    // attempts to shrink data. Panics if shrinking would fail.
-    fn shrink_data(&mut self) {
-        if !self.double_bytes {
-            panic!("Cannot shrink chunk data that is only single byte!")
-        }
-        todo!("Shrinking not yet Implemented")
-    }
-
-
     // returns the first free palette in the list, or none if all existing palette entries are active
     fn first_free_palette(&self) -> Option<usize> {
-        if self.palette.len() == 0 {
-            return None;
-        }
-        for i in 1..self.palette.len() {
-            if self.palette[i].is_free() {
-                return Some(i);
-            }
-        }
-        None
+        (1..self.palette.len()).find(|&i| self.palette[i].is_free())
     }
 }
 #[derive(Debug, Clone, PartialEq)]
@@ -435,7 +658,7 @@ impl PaletteEntry {
 //     }
 // }
 
-fn xyz_to_index(x: usize, y: usize, z: usize) -> usize {
+pub fn xyz_to_index(x: usize, y: usize, z: usize) -> usize {
     // reverse: i = (depth * width * y) + (depth * x) + z
     let max = ChunkData::CHUNK_SIZE;
     (max * max * y) + (max * x) + z
@@ -452,17 +675,98 @@ pub fn transform_to_chunk_pos(transform: &Transform) -> IVec3 {
     let vec = transform.translation.as_block_pos();
     pos_to_chunk_pos(vec)
 }
+/// Squared distance between two chunk positions. Exact, and wide enough that it can't overflow.
+pub fn chunk_distance_squared(a: IVec3, b: IVec3) -> i64 {
+    (a.as_i64vec3() - b.as_i64vec3()).length_squared()
+}
+
+/// Sort key that orders chunk positions from nearest to furthest from `center`, with chunks the same distance away sorted by position.
+pub fn distance_order(center: IVec3, pos: IVec3) -> (i32, [i32; 3]) {
+    ((pos - center).length_squared(), pos.to_array())
+}
+
+/// The `n` positions nearest to `center`, nearest first, in [`distance_order`].
+/// Only keeps `n` positions around while searching, instead of sorting all of them.
+pub fn nearest_n(center: IVec3, positions: impl IntoIterator<Item = IVec3>, n: usize) -> Vec<IVec3> {
+    if n == 0 {
+        return Vec::new();
+    }
+    // max heap, so the furthest of the nearest n is always on top and gets kicked out first
+    let mut heap = BinaryHeap::with_capacity(n + 1);
+    for pos in positions {
+        heap.push(distance_order(center, pos));
+        if heap.len() > n {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec().into_iter().map(|(_, pos)| IVec3::from_array(pos)).collect()
+}
+
+/// Chunk positions within `radius` of `old` but not within `radius` of `new`, i.e. the ones left behind when moving from `old` to `new`.
+/// Works one column at a time, so it only visits the chunks that leave range instead of everything in range.
+pub fn chunks_leaving_range(old: IVec3, new: IVec3, radius: u32) -> Vec<IVec3> {
+    let r = radius as i64;
+    let mut leaving = Vec::new();
+    // half the height of a column of the sphere, or None if the column misses it
+    let half_height = |dx: i64, dz: i64| {
+        let rem = r * r - dx * dx - dz * dz;
+        (rem >= 0).then(|| rem.isqrt())
+    };
+    for dx in -r..=r {
+        for dz in -r..=r {
+            let Some(h) = half_height(dx, dz) else {
+                continue;
+            };
+            let (x, z) = (old.x as i64 + dx, old.z as i64 + dz);
+            let kept = half_height(x - new.x as i64, z - new.z as i64)
+                .map(|nh| (new.y as i64 - nh, new.y as i64 + nh));
+            for y in old.y as i64 - h..=old.y as i64 + h {
+                if kept.is_some_and(|(low, high)| (low..=high).contains(&y)) {
+                    continue;
+                }
+                leaving.push(ivec3(x as i32, y as i32, z as i32));
+            }
+        }
+    }
+    leaving
+}
+
+/// Gets the chunk a block is in. Integer division rounding down, so negative positions end up in the right chunk,
+/// and large ones don't lose precision going through floats.
 pub fn pos_to_chunk_pos(pos: IVec3) -> IVec3 {
-    let vec = pos.as_vec3();
-    ivec3((vec.x / ChunkData::CHUNK_SIZE as f32).floor() as i32, (vec.y / ChunkData::CHUNK_SIZE as f32).floor() as i32, (vec.z / ChunkData::CHUNK_SIZE as f32).floor() as i32)
+    pos.div_euclid(IVec3::splat(ChunkData::CHUNK_SIZE as i32))
 }
 
+/// Gets a block's position within its chunk, always from 0 to [`ChunkData::CHUNK_SIZE`] - 1.
 pub fn pos_to_chunk_local(pos: IVec3) -> IVec3 {
-    pos - (ChunkData::CHUNK_SIZE as i32 * pos_to_chunk_pos(pos))
+    pos.rem_euclid(IVec3::splat(ChunkData::CHUNK_SIZE as i32))
 }
 
+/// A child of a chunk holding one of its meshes. Chunks have a mesh per render layer they have blocks in.
 #[derive(Component)]
-pub struct ChunkMeshMarker;
+pub struct ChunkMeshMarker(pub RenderLayer);
+
+/// Known block layouts a whole chunk can be replaced with, for reproducing meshing and culling bugs.
+/// See the `pattern` command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChunkPattern {
+    /// Every other block, so every face of every block is visible.
+    Checkerboard,
+    /// One block in the middle of the chunk.
+    Single,
+    /// A staircase going up towards +x, one block higher per step.
+    Stairs,
+}
+impl ChunkPattern {
+    /// Whether there's a block at this chunk local position. Everything else is air.
+    pub fn is_filled(&self, local_pos: IVec3) -> bool {
+        match self {
+            ChunkPattern::Checkerboard => (local_pos.x + local_pos.y + local_pos.z) % 2 == 0,
+            ChunkPattern::Single => local_pos == IVec3::splat(ChunkData::CHUNK_SIZE as i32 / 2),
+            ChunkPattern::Stairs => local_pos.y <= local_pos.x,
+        }
+    }
+}
 
 
 /// A packed representation of ChunkData. Fits the data itself into as little u64s as it can.
@@ -474,6 +778,31 @@ pub struct PackedChunkData {
     block_data: Vec<u64>,
     is_single: bool
 }
+impl PackedChunkData {
+    /// Roughly how many bytes this takes up in memory, see [`ChunkData::memory_footprint`].
+    pub fn memory_footprint(&self) -> usize {
+        size_of::<Self>()
+            + self.block_data.capacity() * size_of::<u64>()
+            + self.palette.capacity() * size_of::<PackedPaletteEntry>()
+            + self.palette.iter().map(|p| p.block.get_id().len()).sum::<usize>()
+    }
+
+    /// Reads a block straight out of the packed data, without unpacking it. None if the data is malformed.
+    pub fn get_block_state(&self, x: usize, y: usize, z: usize) -> Option<&BlockState> {
+        if self.is_single {
+            return self.palette.first().map(|p| &p.block);
+        }
+        // every block takes the same number of bits, so this is the id size used when packing
+        let id_size = self.block_data.len() * 64 / ChunkData::BLOCKS_PER_CHUNK;
+        if id_size == 0 {
+            return None;
+        }
+        let bit = xyz_to_index(x, y, z) * id_size;
+        let mask = (1_u64 << id_size) - 1;
+        let id = (self.block_data.get(bit / 64)? >> (bit % 64)) & mask;
+        self.palette.get(id as usize).map(|p| &p.block)
+    }
+}
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PackedPaletteEntry {
     ref_count: u16,
@@ -487,14 +816,15 @@ impl From<PaletteEntry> for PackedPaletteEntry {
         }
     }
 }
-impl Into<PaletteEntry> for PackedPaletteEntry {
-    fn into(self) -> PaletteEntry {
+impl From<PackedPaletteEntry> for PaletteEntry {
+    fn from(value: PackedPaletteEntry) -> Self {
         PaletteEntry {
-            ref_count: self.ref_count,
-            block: self.block,
+            ref_count: value.ref_count,
+            block: value.block,
         }
     }
 }
+
 impl From<ChunkData> for PackedChunkData {
     fn from(value: ChunkData) -> Self {
         Self::from(&value)
@@ -522,10 +852,17 @@ impl From<&ChunkData> for PackedChunkData {
             }
         }
 
-        let palette: Vec<PackedPaletteEntry> = value.palette.iter().filter_map(|entry| {
-            // trim empty palette entries
-            if entry.ref_count == 0 { None } else { Some(entry.clone().into()) } //TODO: remove clone
-        }).collect::<Vec<_>>();
+        // trim empty palette entries. Removing an entry shifts the ones after it down,
+        // so ids in the data get remapped to match, otherwise they could point past the end of the new palette.
+        let mut remap = vec![0_usize; value.palette.len()];
+        let mut palette: Vec<PackedPaletteEntry> = Vec::with_capacity(value.palette.len());
+        for (old_id, entry) in value.palette.iter().enumerate() {
+            if entry.ref_count == 0 {
+                continue;
+            }
+            remap[old_id] = palette.len();
+            palette.push(entry.clone().into()); //TODO: remove clone
+        }
 
 
         // number of bits per id to use, rounded to power of 2
@@ -534,13 +871,13 @@ impl From<&ChunkData> for PackedChunkData {
             f32::ceil(
                 f32::log2(
                     f32::log2(
-                        (palette.len() as f32)
+                        palette.len() as f32
                     )
                 )
             ) as u32
         ).max(1); // sets to 1 in the case id_size = 1
 
-        println!("Id size: {}. Palette length: {}.", id_size, palette.len());
+        debug!("Id size: {}. Palette length: {}.", id_size, palette.len());
 
 
         let mut packed_data = Vec::<u64>::with_capacity(id_size * 32768 / 64);
@@ -551,7 +888,7 @@ impl From<&ChunkData> for PackedChunkData {
 
         for i in 0..ChunkData::BLOCKS_PER_CHUNK {
             // grabs the block id regardless of double_bytes or not
-            let id = value.block_at_index(i);
+            let id = remap[value.block_at_index(i)];
 
             // creates a bit mask - for example, if we need 4 bits per block, we get 2^4 - 1 = 15 = 0b1111
             let mask = 2_usize.pow(id_size as u32) - 1;
@@ -576,25 +913,23 @@ impl From<&ChunkData> for PackedChunkData {
         }
     }
 }
-impl Into<ChunkData> for PackedChunkData {
-    fn into(self) -> ChunkData {
+/// Unpacks saved chunk data. Errors if the saved data is malformed, see [`ChunkData::validate`].
+impl TryFrom<PackedChunkData> for ChunkData {
+    type Error = ChunkError;
+
+    fn try_from(value: PackedChunkData) -> Result<Self, Self::Error> {
         // move everything out
-        let (palette, block_data, is_single) = (self.palette, self.block_data, self.is_single);
+        let (palette, block_data, is_single) = (value.palette, value.block_data, value.is_single);
 
         if is_single {
-            //TODO: move to ChunkData validate function
-            if palette.len() != 1 {
-                panic!("Malformed saved chunk data: data marked as single, but palette length is not 1!");
-            }
-            if palette[0].ref_count as usize != ChunkData::BLOCKS_PER_CHUNK {
-                panic!("Malformed saved chunk data: data marked as single must have refcount of {}", ChunkData::BLOCKS_PER_CHUNK)
-            }
-            return ChunkData {
-                palette: vec![palette[0].clone().into()],
+            let data = ChunkData {
+                palette: palette.into_iter().map(|entry| entry.into()).collect(),
                 data: Vec::new(),
                 is_single: true,
                 double_bytes: false
-            }
+            };
+            data.validate()?;
+            return Ok(data);
         }
         // we don't discard 0 size palettes
         let palette: Vec<PaletteEntry> = palette.into_iter().map(|entry| entry.into()).collect::<Vec<_>>();
@@ -605,7 +940,7 @@ impl Into<ChunkData> for PackedChunkData {
             f32::ceil(
                 f32::log2(
                     f32::log2(
-                        (palette.len() as f32)
+                        palette.len() as f32
                     )
                 )
             ) as u32
@@ -642,13 +977,248 @@ impl Into<ChunkData> for PackedChunkData {
                 bit_pointer = 0;
             }
         }
-        assert_eq!(unpacked_data.len(), vec_size);
-
-        ChunkData {
+        let data = ChunkData {
             data: unpacked_data,
             palette,
             is_single,
             double_bytes,
+        };
+        data.validate()?;
+        Ok(data)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::registry::block::test_registry;
+
+    #[test]
+    fn chunks_step_through_every_generation_status() {
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let pos = ivec3(1, 2, 3);
+        let mut chunk = Chunk::new(pos, Entity::PLACEHOLDER);
+
+        // nothing to decorate or finish before there's terrain
+        assert!(matches!(chunk.place_decorations(&[]), Err(ChunkError::IllegalTransition(..))));
+        assert!(matches!(
+            chunk.set_generation_status(ChunkGenerationStatus::Generated),
+            Err(ChunkError::IllegalTransition(_, ChunkGenerationStatus::NotGenerated, ChunkGenerationStatus::Generated))
+        ));
+
+        chunk.init_data(ChunkData::single(BlockState::air())).unwrap();
+        assert_eq!(chunk.get_generation_status(), ChunkGenerationStatus::AfterTerrain);
+        assert!(chunk.get_block(IVec3::ZERO).is_err());
+        assert!(chunk.set_generation_status(ChunkGenerationStatus::Generated).is_err());
+
+        chunk.place_decorations(&[(ivec3(4, 5, 6), stone.clone())]).unwrap();
+        assert_eq!(chunk.get_generation_status(), ChunkGenerationStatus::AfterDecorations);
+        assert_eq!(chunk.get_block_partial(ivec3(4, 5, 6)).unwrap(), stone);
+        assert!(chunk.place_decorations(&[]).is_err());
+        assert!(!chunk.is_initialized());
+
+        chunk.set_generation_status(ChunkGenerationStatus::Generated).unwrap();
+        assert!(chunk.is_initialized());
+        assert_eq!(chunk.get_block(ivec3(4, 5, 6)).unwrap(), stone);
+        assert!(chunk.advance_generation().is_err());
+    }
+
+    #[test]
+    fn a_chunk_of_all_different_blocks_reuses_palette_entries() {
+        let names = (0..=ChunkData::BLOCKS_PER_CHUNK).map(|i| format!("block_{i}")).collect::<Vec<_>>();
+        let registry = test_registry(&names.iter().map(String::as_str).collect::<Vec<_>>());
+        let states = names.iter().map(|name| BlockState::new(name, &registry).unwrap()).collect::<Vec<_>>();
+
+        // the biggest a palette can get, every block is different
+        let mut palette = Vec::with_capacity(ChunkData::BLOCKS_PER_CHUNK);
+        let mut data = Vec::with_capacity(ChunkData::DOUBLE_BLOCKS_PER_CHUNK);
+        for (i, state) in states[..ChunkData::BLOCKS_PER_CHUNK].iter().enumerate() {
+            let mut entry = PaletteEntry::new(state.clone());
+            entry.increment_ref_count();
+            palette.push(entry);
+            data.extend((i as u16).to_le_bytes());
+        }
+        let mut chunk = ChunkData::with_data(data, palette);
+        chunk.validate().unwrap();
+        assert_eq!(chunk.get_block(31, 31, 31).unwrap(), states[ChunkData::BLOCKS_PER_CHUNK - 1]);
+
+        // one more kind of block takes the entry of the block it replaced, instead of growing the palette.
+        // Entry 0 is never reused, so this replaces the block with id 1
+        let extra = &states[ChunkData::BLOCKS_PER_CHUNK];
+        assert_eq!(chunk.set_block(0, 0, 1, extra.clone()).unwrap(), states[1]);
+        assert_eq!(chunk.palette_len(), ChunkData::BLOCKS_PER_CHUNK);
+        assert_eq!(chunk.get_block(0, 0, 1).unwrap(), *extra);
+        chunk.validate().unwrap();
+    }
+
+    #[test]
+    fn a_full_palette_refuses_new_entries() {
+        let registry = test_registry(&["stone"]);
+        let state = |i: usize| BlockState::with_state("stone", BTreeMap::from([(String::from("i"), i.to_string())]), &registry).unwrap();
+        // every id in use, which no valid chunk gets to. Every block is the first entry
+        let palette = (0..ChunkData::MAX_PALETTE_ENTRIES).map(|i| {
+            let mut entry = PaletteEntry::new(state(i));
+            entry.increment_ref_count();
+            entry
+        }).collect::<Vec<_>>();
+        let mut chunk = ChunkData::with_data(vec![0; ChunkData::DOUBLE_BLOCKS_PER_CHUNK], palette);
+        let extra = state(ChunkData::MAX_PALETTE_ENTRIES);
+
+        assert!(matches!(chunk.add_palette(PaletteEntry::new(extra.clone())), Err(ChunkError::PaletteFull(ChunkData::MAX_PALETTE_ENTRIES))));
+        assert!(matches!(chunk.set_block(0, 0, 0, extra), Err(ChunkError::PaletteFull(_))));
+        // nothing changed
+        assert_eq!(chunk.palette_len(), ChunkData::MAX_PALETTE_ENTRIES);
+        assert_eq!(chunk.get_block(0, 0, 0).unwrap(), state(0));
+        assert_eq!(chunk.lookup_palette(0).unwrap().ref_count, 1);
+    }
+
+    #[test]
+    fn packed_data_reads_the_same_blocks() {
+        let names = ["stone", "dirt", "grass", "sand"];
+        let registry = test_registry(&names);
+        let states = names.map(|name| BlockState::new(name, &registry).unwrap());
+
+        let mut data = ChunkData::single(BlockState::air());
+        let single = PackedChunkData::from(&data);
+        assert!(single.get_block_state(31, 0, 7).is_some_and(|b| b.is_air()));
+
+        // five palette entries, so ids don't line up with byte boundaries
+        for i in 0..200_usize {
+            let (x, y, z) = (i % 32, (i * 7) % 32, (i * 13) % 32);
+            data.set_block(x, y, z, states[i % states.len()].clone()).unwrap();
+        }
+        let packed = PackedChunkData::from(&data);
+        for i in 0..ChunkData::BLOCKS_PER_CHUNK {
+            let (x, y, z) = (i % 32, (i / 32) % 32, i / 1024);
+            assert_eq!(packed.get_block_state(x, y, z), Some(&data.get_block(x, y, z).unwrap()), "{x}, {y}, {z}");
+        }
+    }
+
+    // air everywhere except one stone and two dirt blocks, so ids take 2 bits
+    fn three_entry_packed() -> PackedChunkData {
+        let registry = test_registry(&["stone", "dirt"]);
+        let mut blocks = vec![0_u8; ChunkData::BLOCKS_PER_CHUNK];
+        blocks[5] = 1;
+        blocks[9] = 2;
+        blocks[10] = 2;
+        let palette = vec![
+            PaletteEntry { ref_count: ChunkData::BLOCKS_PER_CHUNK as u16 - 3, block: BlockState::air() },
+            PaletteEntry { ref_count: 1, block: BlockState::new("stone", &registry).unwrap() },
+            PaletteEntry { ref_count: 2, block: BlockState::new("dirt", &registry).unwrap() },
+        ];
+        let data = ChunkData::with_data(blocks, palette);
+        data.validate().unwrap();
+        PackedChunkData::from(&data)
+    }
+
+    #[test]
+    fn single_chunks_need_exactly_one_entry() {
+        let mut packed = PackedChunkData::from(&ChunkData::single(BlockState::air()));
+        packed.palette.push(packed.palette[0].clone());
+        assert!(matches!(ChunkData::try_from(packed), Err(ChunkError::InvalidSinglePalette(2))));
+    }
+
+    #[test]
+    fn truncated_data_is_rejected() {
+        let mut packed = three_entry_packed();
+        packed.block_data.pop();
+        assert!(matches!(
+            ChunkData::try_from(packed),
+            Err(ChunkError::InvalidDataLength(ChunkData::BLOCKS_PER_CHUNK, len)) if len == ChunkData::BLOCKS_PER_CHUNK - 32
+        ));
+    }
+
+    #[test]
+    fn ids_outside_the_palette_are_rejected() {
+        let mut packed = three_entry_packed();
+        // the first block gets id 3, one past the end of the palette
+        packed.block_data[0] |= 0b11;
+        assert!(matches!(ChunkData::try_from(packed), Err(ChunkError::InvalidPaletteId(0, 3))));
+    }
+
+    #[test]
+    fn refcounts_have_to_add_up_to_the_chunk() {
+        let mut packed = three_entry_packed();
+        packed.palette[0].ref_count -= 1;
+        assert!(matches!(
+            ChunkData::try_from(packed),
+            Err(ChunkError::RefCountTotal(total, ChunkData::BLOCKS_PER_CHUNK)) if total == ChunkData::BLOCKS_PER_CHUNK - 1
+        ));
+    }
+
+    #[test]
+    fn refcounts_have_to_match_the_blocks_using_them() {
+        // still adds up, but the stone and dirt counts are swapped
+        let mut packed = three_entry_packed();
+        packed.palette[1].ref_count = 2;
+        packed.palette[2].ref_count = 1;
+        assert!(matches!(ChunkData::try_from(packed), Err(ChunkError::RefCountMismatch(1, 2, 1))));
+    }
+
+    #[test]
+    fn packed_chunks_unpack_on_first_read() {
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let mut data = ChunkData::single(BlockState::air());
+        data.set_block(3, 4, 5, stone.clone()).unwrap();
+        let chunk = Chunk::generated(IVec3::ZERO, data);
+
+        assert!(chunk.pack());
+        assert!(chunk.is_packed());
+        assert_eq!(chunk.get_block(ivec3(3, 4, 5)).unwrap(), stone);
+        assert!(!chunk.is_packed());
+        assert!(chunk.get_block(ivec3(5, 4, 3)).unwrap().is_air());
+    }
+
+    #[test]
+    fn negative_positions_round_down_to_their_chunk() {
+        let cases = [
+            (0, 0, 0),
+            (-1, -1, 31),
+            (-16, -1, 16),
+            (-17, -1, 15),
+            (-32, -1, 0),
+            (-33, -2, 31),
+            (1_000_000, 31_250, 0),
+            (1_000_001, 31_250, 1),
+            (-1_000_000, -31_250, 0),
+            (-1_000_001, -31_251, 31),
+        ];
+        for (pos, chunk, local) in cases {
+            assert_eq!(pos_to_chunk_pos(IVec3::splat(pos)), IVec3::splat(chunk), "chunk of {pos}");
+            assert_eq!(pos_to_chunk_local(IVec3::splat(pos)), IVec3::splat(local), "local of {pos}");
+            // and back again
+            assert_eq!(IVec3::splat(chunk * ChunkData::CHUNK_SIZE as i32 + local), IVec3::splat(pos));
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn chunks_leaving_range_is_exactly_the_ring_left_behind() {
+        let radius = 4;
+        let in_range = |center: IVec3, pos: IVec3| (pos - center).length_squared() <= (radius * radius) as i32;
+        let old = ivec3(3, -2, 7);
+        for step in [IVec3::X, IVec3::NEG_Z, IVec3::Y, ivec3(1, 0, 1), ivec3(-1, 1, -1), ivec3(2, 0, -1)] {
+            let new = old + step;
+            let mut expected = Vec::new();
+            let r = radius as i32;
+            for x in -r..=r {
+                for y in -r..=r {
+                    for z in -r..=r {
+                        let pos = old + ivec3(x, y, z);
+                        if in_range(old, pos) && !in_range(new, pos) {
+                            expected.push(pos.to_array());
+                        }
+                    }
+                }
+            }
+            let mut leaving: Vec<_> = chunks_leaving_range(old, new, radius).iter().map(|p| p.to_array()).collect();
+            expected.sort();
+            leaving.sort();
+            assert!(!leaving.is_empty());
+            assert_eq!(leaving, expected, "moving by {step}");
+        }
+        // not moving leaves nothing behind
+        assert!(chunks_leaving_range(old, old, radius).is_empty());
+    }
+}
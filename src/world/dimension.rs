@@ -0,0 +1,32 @@
+use std::fmt::{Display, Formatter};
+use bevy::prelude::Component;
+
+/// The dimension the player starts in.
+pub const OVERWORLD: &str = "overworld";
+/// A superflat dimension, for building in.
+pub const FLAT: &str = "flat";
+
+/// Identifies a dimension, e.g. the overworld. Every `BlockWorld` belongs to exactly one dimension.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DimensionId(String);
+
+impl DimensionId {
+    pub fn new(id: &str) -> Self {
+        Self(String::from(id))
+    }
+
+    pub fn get_id(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl Display for DimensionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Marker component for the world the player is currently in.
+/// Only the active world loads chunks around the player and is visible.
+#[derive(Debug, Default, Component)]
+pub struct ActiveWorld;
@@ -1,11 +1,22 @@
+use crate::core::errors::GenerationConfigError;
+use crate::core::errors::GenerationConfigError::{NoOctaves, NotPositive};
 use crate::math::NoiseFunction2D;
 use crate::world::chunk;
 use crate::world::chunk::ChunkData;
-use bevy::prelude::{ivec2, ivec3, Component, IVec2};
+use crate::world::pipeline::GenerationPipeline;
+use bevy::asset::ron;
+use bevy::log::warn;
+use bevy::prelude::{ivec2, ivec3, BevyError, Component, IVec2, Resource};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
 use std::sync::{Arc, OnceLock, RwLock};
 use noiz::SampleableFor;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use bevy::prelude::IVec3;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HeightMapGroup([i32; HeightMapGroup::BLOCKS_PER_GROUP]);
@@ -28,8 +39,16 @@ impl HeightMapGroup {
     pub fn delinearize(local_pos: IVec2) -> usize {
         ChunkData::CHUNK_SIZE * local_pos.y as usize + local_pos.x as usize
     }
+
+    /// The highest point in this group.
+    pub fn max(&self) -> i32 {
+        self.0.iter().copied().max().unwrap_or(i32::MIN)
+    }
 }
 
+/// Y level of the sea. Noise terrain puts planks here wherever the ground is lower.
+pub const SEA_LEVEL: i32 = 0;
+
 
 pub trait HeightMapProvider: Send + Sync {
 
@@ -94,20 +113,266 @@ impl HeightMapProvider for SineHeightMap {
 // all temporary lol
 #[derive(Component)]
 pub struct WorldGenerator {
-    height_map: Arc<dyn HeightMapProvider>
+    seed: u64,
+    height_map: Arc<dyn HeightMapProvider>,
+    /// The lowest the top block of a column can be, whatever the height map says. Sea level for noise terrain, which puts planks there.
+    min_surface: i32,
+    pipeline: GenerationPipeline,
 }
 impl WorldGenerator {
-    pub fn new(height_map: impl HeightMapProvider + 'static) -> Self {
+    /// Creates a generator for noise terrain, see [`GenerationPipeline::noise`].
+    pub fn new(seed: u64, height_map: impl HeightMapProvider + 'static) -> Self {
+        let height_map: Arc<dyn HeightMapProvider> = Arc::new(height_map);
+        Self {
+            min_surface: SEA_LEVEL,
+            ..Self::with_pipeline(seed, height_map.clone(), GenerationPipeline::noise(height_map))
+        }
+    }
+
+    /// Creates a generator running custom stages. The height map is only used to find the surface (e.g. for spawning),
+    /// so it should match what the stages place.
+    pub fn with_pipeline(seed: u64, height_map: Arc<dyn HeightMapProvider>, pipeline: GenerationPipeline) -> Self {
+        Self {
+            seed,
+            height_map,
+            min_surface: i32::MIN,
+            pipeline,
+        }
+    }
+
+    pub fn superflat(seed: u64, preset: SuperflatPreset) -> Self {
+        let height_map = Arc::new(FlatHeightMap::new(preset.get_surface_height()));
+        Self::with_pipeline(seed, height_map, GenerationPipeline::superflat(Arc::new(preset)))
+    }
+
+    pub fn borrow_pipeline(&self) -> GenerationPipeline {
+        self.pipeline.clone()
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Gets the y level of the top block this generator places in a column. Doesn't know about any edits made since.
+    pub fn get_surface_height(&self, pos: IVec2) -> i32 {
+        self.height_map.get_height(pos).max(self.min_surface)
+    }
+
+    /// Gets what's needed to check for chunks above this generator's terrain. Cheap to clone, so it can be sent to generation tasks.
+    pub fn borrow_ceiling(&self) -> TerrainCeiling {
+        TerrainCeiling {
+            height_map: self.height_map.clone(),
+            min_surface: self.min_surface,
+        }
+    }
+}
+
+/// Finds chunks above a [`WorldGenerator`]'s terrain. Sampling a column's height map can be slow the first time,
+/// so this is meant to be used from generation tasks rather than the main thread.
+#[derive(Clone)]
+pub struct TerrainCeiling {
+    height_map: Arc<dyn HeightMapProvider>,
+    min_surface: i32,
+}
+impl TerrainCeiling {
+    /// Whether a chunk is entirely above anything the generator places, meaning it's guaranteed to be all air.
+    /// Samples the height map for the chunk's column, which gets cached so the column's other chunks don't pay for it again.
+    pub fn is_above_terrain(&self, chunk_pos: IVec3) -> bool {
+        let bottom = chunk_pos.y * ChunkData::CHUNK_SIZE as i32;
+        let top = self.height_map.get_chunk(ivec2(chunk_pos.x, chunk_pos.z)).max().max(self.min_surface);
+        bottom > top
+    }
+}
+
+
+/// Which kind of terrain a world generates. Serializable, so it can be stored in world configs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GeneratorKind {
+    /// Default noise based terrain.
+    Noise,
+    Superflat(SuperflatPreset),
+}
+
+/// Settings for creating a world.
+#[derive(Debug, Clone, PartialEq, Eq, Resource, Serialize, Deserialize)]
+pub struct WorldConfig {
+    pub seed: u64,
+    pub generator: GeneratorKind,
+    /// (x, z) column the player spawns in. The height is found once the world exists.
+    #[serde(default)]
+    pub spawn_point: IVec2,
+}
+impl Default for WorldConfig {
+    fn default() -> Self {
         Self {
-            height_map: Arc::new(height_map)
+            seed: 69420,
+            generator: GeneratorKind::Noise,
+            spawn_point: IVec2::ZERO,
         }
     }
-    
-    pub fn borrow_height_map(&self) -> Arc<dyn HeightMapProvider> {
-        self.height_map.clone()
+}
+
+/// Tuning for noise terrain, read from `generation.ron` in the config folder so terrain can be tweaked without recompiling.
+/// Missing fields use the defaults. The seed isn't here, it belongs to the world (see [`WorldConfig`]).
+#[derive(Debug, Clone, PartialEq, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenerationConfig {
+    /// Frequency of the whole height map. Higher means everything is smaller and closer together.
+    pub frequency: f32,
+    pub lacunarity: f32,
+    pub persistence: f32,
+    /// How far below sea level oceans go.
+    pub ocean_depth: f32,
+    /// Frequency of the noise deciding where oceans are, relative to `frequency`.
+    pub ocean_frequency: f32,
+    pub ocean_octaves: u32,
+    /// Height of the tallest mountains.
+    pub mountain_scale: f32,
+    /// Frequency of the noise deciding where mountains are, relative to `frequency`.
+    pub mountain_frequency: f32,
+    pub mountain_octaves: u32,
+}
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            frequency: 0.01,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            ocean_depth: 50.0,
+            ocean_frequency: 0.0125,
+            ocean_octaves: 3,
+            mountain_scale: 350.0,
+            mountain_frequency: 0.25,
+            mountain_octaves: 5,
+        }
     }
 }
+impl GenerationConfig {
+    pub const FILE_NAME: &'static str = "generation.ron";
 
+    pub fn validate(&self) -> Result<(), GenerationConfigError> {
+        let positive = [
+            ("frequency", self.frequency),
+            ("lacunarity", self.lacunarity),
+            ("persistence", self.persistence),
+            ("ocean_depth", self.ocean_depth),
+            ("ocean_frequency", self.ocean_frequency),
+            ("mountain_scale", self.mountain_scale),
+            ("mountain_frequency", self.mountain_frequency),
+        ];
+        for (name, value) in positive {
+            if value.is_nan() || value <= 0.0 {
+                return Err(NotPositive(name, value));
+            }
+        }
+        if self.ocean_octaves == 0 {
+            return Err(NoOctaves("ocean_octaves"));
+        }
+        if self.mountain_octaves == 0 {
+            return Err(NoOctaves("mountain_octaves"));
+        }
+        Ok(())
+    }
+
+    /// Reads the config from the config folder. Uses the defaults if there isn't one.
+    pub fn load(config_dir: &Path) -> Result<Self, BevyError> {
+        let file = config_dir.join(Self::FILE_NAME);
+        if !file.exists() {
+            return Ok(Self::default());
+        }
+        let bytes = fs::read(file)?;
+        let config: Self = ron::de::from_bytes(bytes.as_slice())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Like [`GenerationConfig::load`], but falls back to the defaults if the config can't be used.
+    pub fn load_or_default(config_dir: &Path) -> Self {
+        match Self::load(config_dir) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Could not load generation config, using defaults: {}", e);
+                Self::default()
+            }
+        }
+    }
+}
+
+/// One layer of a superflat world, e.g. 3 dirt.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlatLayer {
+    pub block: String,
+    pub thickness: u32,
+}
+impl FlatLayer {
+    pub fn new(block: &str, thickness: u32) -> Self {
+        Self {
+            block: String::from(block),
+            thickness,
+        }
+    }
+}
+
+/// A stack of layers for superflat worlds, listed bottom to top. Everything below the bottom layer and above the top layer is air.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SuperflatPreset {
+    /// y level of the bottom of the first layer.
+    pub bottom: i32,
+    pub layers: Vec<FlatLayer>,
+}
+impl Default for SuperflatPreset {
+    fn default() -> Self {
+        Self {
+            bottom: -4,
+            layers: vec![
+                FlatLayer::new("stone", 1),
+                FlatLayer::new("dirt", 3),
+                FlatLayer::new("grass_block", 1),
+            ]
+        }
+    }
+}
+impl SuperflatPreset {
+    /// y level of the top most block. If there are no layers, this is the block below the bottom.
+    pub fn get_surface_height(&self) -> i32 {
+        self.bottom + self.layers.iter().map(|l| l.thickness as i32).sum::<i32>() - 1
+    }
+
+    /// Gets the block id at a y level, or None if it is air.
+    pub fn get_layer_at(&self, y: i32) -> Option<&str> {
+        let mut layer_bottom = self.bottom;
+        for layer in self.layers.iter() {
+            let layer_top = layer_bottom + layer.thickness as i32;
+            if y >= layer_bottom && y < layer_top {
+                return Some(layer.block.as_str());
+            }
+            layer_bottom = layer_top;
+        }
+        None
+    }
+}
+
+/// Mixes the world seed with a chunk position. Only depends on its inputs, so it's the same no matter what order chunks are generated in.
+pub fn chunk_seed(seed: u64, chunk_pos: IVec3) -> u64 {
+    // splitmix64 finalizer, applied once per component
+    fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+    let mut hash = mix(seed);
+    for v in chunk_pos.to_array() {
+        hash = mix(hash.wrapping_add(0x9e3779b97f4a7c15) ^ (v as u32 as u64));
+    }
+    hash
+}
+
+/// Creates the rng for generating a chunk.
+/// All randomness during chunk generation (caves, decorations, etc.) should come from this,
+/// never from a shared or thread local rng, or chunks will depend on the order they were generated in.
+pub fn chunk_rng(seed: u64, chunk_pos: IVec3) -> impl Rng {
+    StdRng::seed_from_u64(chunk_seed(seed, chunk_pos))
+}
 
 // one downside of Noiz: ts is type hell
 
@@ -130,7 +395,7 @@ impl <N: NoiseFunction2D> NoiseHeightMap<N> {
 
                 let point = (chunk_pos * ChunkData::CHUNK_SIZE as i32) + ivec2(x as i32, y as i32);
 
-                let noise_value: f32 = (self.generator.sample(point.as_vec2()));
+                let noise_value: f32 = self.generator.sample(point.as_vec2());
 
                 // let noise_value = point.y;
 
@@ -163,16 +428,74 @@ impl <N: NoiseFunction2D + Send + Sync> HeightMapProvider for NoiseHeightMap<N>
         drop(read);
 
         // If the data doesn't exist, then we need to write to the hashmap
-        let data_ref = if data_ref.is_none() {
-            // we write no data to prevent expensive call from slowing down this thread
-            let mut write = self.map.write().unwrap();
-            let ret = Arc::new(OnceLock::new());
-            write.insert(chunk_pos, ret.clone());
-            ret
-        } else { data_ref.unwrap() };
+        let data_ref = match data_ref {
+            Some(data_ref) => data_ref,
+            None => {
+                // we write no data to prevent expensive call from slowing down this thread
+                let mut write = self.map.write().unwrap();
+                let ret = Arc::new(OnceLock::new());
+                write.insert(chunk_pos, ret.clone());
+                ret
+            }
+        };
 
         // get or init. Shouldn't cause race conditions since its the same init function always
         // TODO: this clone might be bad. Maybe find a way to not do this.
         data_ref.get_or_init(|| {self.create_noise(chunk_pos)}).clone()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_chunks_above_the_surface_are_above_terrain() {
+        let preset = SuperflatPreset::default();
+        let surface = preset.get_surface_height();
+        let ceiling = WorldGenerator::superflat(0, preset).borrow_ceiling();
+        let surface_chunk = surface.div_euclid(ChunkData::CHUNK_SIZE as i32);
+
+        assert!(!ceiling.is_above_terrain(ivec3(3, surface_chunk, -2)));
+        assert!(!ceiling.is_above_terrain(ivec3(3, surface_chunk - 1, -2)));
+        assert!(ceiling.is_above_terrain(ivec3(3, surface_chunk + 1, -2)));
+        assert!(ceiling.is_above_terrain(ivec3(3, surface_chunk + 10, -2)));
+    }
+
+    #[test]
+    fn noise_terrain_reaches_at_least_sea_level() {
+        // the sine height map never gets above 10, but sea level planks still have to go in
+        let ceiling = WorldGenerator::new(0, SineHeightMap::new()).borrow_ceiling();
+        let sea_chunk = SEA_LEVEL.div_euclid(ChunkData::CHUNK_SIZE as i32);
+        assert!(!ceiling.is_above_terrain(ivec3(0, sea_chunk, 0)));
+        assert!(ceiling.is_above_terrain(ivec3(0, sea_chunk + 1, 0)));
+    }
+
+    #[test]
+    fn generation_config_rejects_bad_values() {
+        GenerationConfig::default().validate().unwrap();
+        let zero = GenerationConfig { frequency: 0.0, ..Default::default() };
+        assert!(matches!(zero.validate(), Err(NotPositive("frequency", _))));
+        let nan = GenerationConfig { mountain_scale: f32::NAN, ..Default::default() };
+        assert!(matches!(nan.validate(), Err(NotPositive("mountain_scale", _))));
+        let no_octaves = GenerationConfig { ocean_octaves: 0, ..Default::default() };
+        assert!(matches!(no_octaves.validate(), Err(NoOctaves("ocean_octaves"))));
+    }
+
+    #[test]
+    fn generation_config_loads_without_writing_anything() {
+        let config_dir = std::env::temp_dir().join(format!("gtclone-generation-{}", std::process::id()));
+        fs::create_dir_all(&config_dir).unwrap();
+        let file = config_dir.join(GenerationConfig::FILE_NAME);
+        let _ = fs::remove_file(&file);
+
+        assert_eq!(GenerationConfig::load(&config_dir).unwrap(), GenerationConfig::default());
+        assert!(!file.exists());
+
+        // missing fields use the defaults, but what's there still has to be valid
+        fs::write(&file, "(mountain_scale: 500.0)").unwrap();
+        assert_eq!(GenerationConfig::load(&config_dir).unwrap().mountain_scale, 500.0);
+        fs::write(&file, "(mountain_octaves: 0)").unwrap();
+        assert!(GenerationConfig::load(&config_dir).is_err());
+        let _ = fs::remove_dir_all(&config_dir);
+    }
+}
@@ -1,27 +1,184 @@
 use std::collections::{HashMap, HashSet};
 use bevy::math::IVec3;
-use bevy::prelude::{Component, Entity};
+use bevy::prelude::{Commands, Component, Entity, On, Query, Res};
+use serde::{Deserialize, Serialize};
+use crate::core::event::{BulkSetBlockEvent, SetBlockEvent};
+use crate::registry::tag::BlockTags;
+use crate::world::block::BlockWorld;
+use crate::world::chunk;
 
 /// A component that tracks machines in the world.
 /// The machine world exists mostly separate from the block world, as machines should always be loaded.
+/// Because of that, machines are saved on their own (see [`PackedMachines`]), whether or not their chunk is loaded or dirty.
 #[derive(Component)]
 pub struct MachineWorld {
     block_map: HashMap<IVec3, MachineBlock>,
-    
+
     // TODO: is this necessary?
-    machine_set: HashSet<Entity>
+    machine_set: HashSet<Entity>,
+    /// Chunks whose machines changed since the last save.
+    dirty_chunks: HashSet<IVec3>,
+    /// Chunks taken by [`MachineWorld::take_dirty_chunks`] whose save hasn't been written yet.
+    saving_chunks: HashSet<IVec3>,
+}
+impl Default for MachineWorld {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl MachineWorld {
     pub fn new() -> Self {
         Self {
             block_map: HashMap::new(),
-            machine_set: HashSet::new()
+            machine_set: HashSet::new(),
+            dirty_chunks: HashSet::new(),
+            saving_chunks: HashSet::new(),
         }
     }
-    
+
     pub fn get_machine(&self, pos: &IVec3) -> Option<&MachineBlock> {
         self.block_map.get(pos)
     }
+
+    /// Adds a machine at a block position, returning the one that was there before.
+    pub fn insert_machine(&mut self, pos: IVec3, machine: MachineBlock) -> Option<MachineBlock> {
+        self.dirty_chunks.insert(chunk::pos_to_chunk_pos(pos));
+        self.machine_set.insert(machine.entity);
+        let old = self.block_map.insert(pos, machine);
+        if let Some(old) = &old {
+            self.machine_set.remove(&old.entity);
+        }
+        old
+    }
+
+    pub fn remove_machine(&mut self, pos: &IVec3) -> Option<MachineBlock> {
+        let old = self.block_map.remove(pos)?;
+        self.dirty_chunks.insert(chunk::pos_to_chunk_pos(*pos));
+        self.machine_set.remove(&old.entity);
+        Some(old)
+    }
+
+    /// Changes the metadata of a machine. Returns false if there's no machine there.
+    pub fn set_metadata(&mut self, pos: &IVec3, metadata: u8) -> bool {
+        let Some(machine) = self.block_map.get_mut(pos) else {
+            return false;
+        };
+        machine.metadata = metadata;
+        self.dirty_chunks.insert(chunk::pos_to_chunk_pos(*pos));
+        true
+    }
+
+    /// Takes every chunk whose machines changed, marking them all as clean. Sorted by position, like [`BlockWorld::take_dirty_chunks`].
+    /// Call [`MachineWorld::finish_saving`] once they're written.
+    pub fn take_dirty_chunks(&mut self) -> Vec<IVec3> {
+        let mut dirty = std::mem::take(&mut self.dirty_chunks).into_iter().collect::<Vec<_>>();
+        self.saving_chunks.extend(dirty.iter().copied());
+        dirty.sort_by_key(|pos| pos.to_array());
+        dirty
+    }
+
+    /// Called once the chunks from [`MachineWorld::take_dirty_chunks`] have been written.
+    /// If writing failed they count as dirty again, so the next save retries them.
+    pub fn finish_saving(&mut self, success: bool) {
+        let saving = std::mem::take(&mut self.saving_chunks);
+        if !success {
+            self.dirty_chunks.extend(saving);
+        }
+    }
+
+    /// Packs the machines in a chunk for saving. Empty if the chunk has none.
+    pub fn pack_chunk(&self, chunk_pos: IVec3) -> PackedMachines {
+        let mut machines = self.block_map.iter()
+            .filter(|(pos, _)| chunk::pos_to_chunk_pos(**pos) == chunk_pos)
+            .map(|(pos, machine)| PackedMachine {
+                pos: chunk::pos_to_chunk_local(*pos),
+                metadata: machine.metadata,
+            })
+            .collect::<Vec<_>>();
+        machines.sort_by_key(|m| m.pos.to_array());
+        PackedMachines {
+            machines,
+        }
+    }
+
+    /// Restores saved machines of a chunk. Entities don't survive saving, so `spawn` creates a new one for every machine,
+    /// see [`MachineMarker`]. Restored machines aren't dirty, since they match what's on disk.
+    pub fn unpack_chunk(&mut self, chunk_pos: IVec3, packed: PackedMachines, mut spawn: impl FnMut(IVec3) -> Entity) {
+        let origin = chunk::chunk_pos_to_world_pos(chunk_pos);
+        for machine in packed.machines {
+            let pos = origin + machine.pos;
+            let entity = spawn(pos);
+            self.machine_set.insert(entity);
+            self.block_map.insert(pos, MachineBlock::with_metadata(entity, machine.metadata));
+        }
+    }
+}
+
+// keeps the machine at a position in line with the block there. Machines get a new entity when placed, which is despawned when broken
+fn update_machine(machines: &mut MachineWorld, commands: &mut Commands, pos: IVec3, is_machine: bool) {
+    match (is_machine, machines.get_machine(&pos).is_some()) {
+        (true, false) => {
+            let entity = commands.spawn(MachineMarker::new(pos)).id();
+            machines.insert_machine(pos, MachineBlock::new(entity));
+        }
+        (false, true) => {
+            if let Some(old) = machines.remove_machine(&pos) {
+                commands.entity(old.entity).despawn();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Places or removes a machine when a block tagged [`BlockTags::MACHINE`] is placed or broken.
+/// Replacing a machine with a different block starts a new machine, rather than keeping the old one's metadata.
+pub(crate) fn on_set_block(
+    trigger: On<SetBlockEvent>,
+    mut commands: Commands,
+    mut q_world: Query<(&BlockWorld, &mut MachineWorld)>,
+    block_tags: Res<BlockTags>,
+) {
+    let Some((_, mut machines)) = q_world.iter_mut().find(|(w, _)| *w.get_dimension() == trigger.dimension) else {
+        return;
+    };
+    if trigger.old.get_id() != trigger.new.get_id() {
+        update_machine(&mut machines, &mut commands, trigger.pos, false);
+    }
+    let is_machine = block_tags.contains(BlockTags::MACHINE, trigger.new.get_id());
+    update_machine(&mut machines, &mut commands, trigger.pos, is_machine);
+}
+
+/// Same as [`on_set_block`], for fills. Machines are only added or removed, blocks that stay machines keep theirs.
+pub(crate) fn on_bulk_set_block(
+    trigger: On<BulkSetBlockEvent>,
+    mut commands: Commands,
+    mut q_world: Query<(&BlockWorld, &mut MachineWorld)>,
+    block_tags: Res<BlockTags>,
+) {
+    let Some((world, mut machines)) = q_world.iter_mut().find(|(w, _)| *w.get_dimension() == trigger.dimension) else {
+        return;
+    };
+    for pos in trigger.changed_positions.iter() {
+        let Ok(state) = world.get_block(pos) else {
+            continue;
+        };
+        update_machine(&mut machines, &mut commands, *pos, block_tags.contains(BlockTags::MACHINE, state.get_id()));
+    }
+}
+
+/// The entity of a machine, which stays around whether or not its chunk is loaded.
+#[derive(Debug, Component)]
+pub struct MachineMarker {
+    pos: IVec3,
+}
+impl MachineMarker {
+    pub fn new(pos: IVec3) -> Self {
+        Self { pos }
+    }
+    pub fn get_pos(&self) -> IVec3 {
+        self.pos
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -33,11 +190,83 @@ impl MachineBlock {
     pub fn new(entity: Entity) -> Self {
         Self::with_metadata(entity, 0)
     }
-    
+
     pub fn with_metadata(entity: Entity, metadata: u8) -> Self {
         Self {
             entity,
             metadata
         }
     }
-}
\ No newline at end of file
+}
+
+/// Saved machines of one chunk, stored next to the chunk's own save.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PackedMachines {
+    machines: Vec<PackedMachine>,
+}
+impl PackedMachines {
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PackedMachine {
+    /// Position within the chunk.
+    pos: IVec3,
+    metadata: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::event::BlockChangeSource;
+    use crate::registry::block::test_registry;
+    use crate::world::block::BlockState;
+    use crate::world::dimension::DimensionId;
+    use bevy::math::ivec3;
+    use bevy::prelude::World;
+
+    #[test]
+    fn placing_and_breaking_a_machine_block_adds_and_removes_its_machine() {
+        let registry = test_registry(&["crusher", "stone"]);
+        let crusher = BlockState::new("crusher", &registry).unwrap();
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let mut tags = BlockTags::default();
+        tags.add(BlockTags::MACHINE, "crusher");
+
+        let mut world = World::new();
+        world.insert_resource(tags);
+        world.add_observer(on_set_block);
+        let dimension = DimensionId::new("overworld");
+        let entity = world.spawn((BlockWorld::new(dimension.clone(), 0), MachineWorld::new())).id();
+        let pos = ivec3(5, 2, -40);
+        let set = |world: &mut World, old: &BlockState, new: &BlockState| {
+            world.trigger(SetBlockEvent { pos, old: old.clone(), new: new.clone(), source: BlockChangeSource::Player, dimension: dimension.clone() });
+            world.flush();
+            world.get::<MachineWorld>(entity).unwrap().get_machine(&pos).cloned()
+        };
+
+        let placed = set(&mut world, &BlockState::air(), &crusher).expect("placing a machine block adds a machine");
+        assert_eq!(world.get::<MachineMarker>(placed.entity).unwrap().get_pos(), pos);
+        assert_eq!(world.get_mut::<MachineWorld>(entity).unwrap().take_dirty_chunks(), vec![chunk::pos_to_chunk_pos(pos)]);
+
+        assert!(set(&mut world, &crusher, &stone).is_none());
+        assert!(world.get_entity(placed.entity).is_err());
+        assert!(set(&mut world, &stone, &BlockState::air()).is_none());
+    }
+
+    #[test]
+    fn failed_saves_leave_machines_dirty() {
+        let mut world = World::new();
+        let mut machines = MachineWorld::new();
+        let pos = ivec3(33, 0, 0);
+        machines.insert_machine(pos, MachineBlock::new(world.spawn_empty().id()));
+
+        assert_eq!(machines.take_dirty_chunks(), vec![ivec3(1, 0, 0)]);
+        machines.finish_saving(false);
+        assert_eq!(machines.take_dirty_chunks(), vec![ivec3(1, 0, 0)]);
+        machines.finish_saving(true);
+        assert!(machines.take_dirty_chunks().is_empty());
+    }
+}
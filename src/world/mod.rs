@@ -1,37 +1,40 @@
-use crate::core::event::{JoinedWorldEvent, PlayerMovedEvent, SetBlockEvent};
-use crate::core::state::MainGameState;
+use crate::core::event::{BlockChangeSource, BulkSetBlockEvent, JoinedWorldEvent, PlayerMovedEvent, SetBlockEvent, SwitchDimensionEvent, TeleportEvent};
+use crate::core::state::{InGameState, MainGameState};
 use crate::math::block::{BlockPos, Vec3Ext};
 use crate::math::ray;
 use crate::math::ray::RayResult;
 use crate::registry::block::Block;
-use crate::registry::{Registry, RegistryHandle};
+use crate::registry::tag::BlockTags;
+use crate::registry::RegistryHandle;
 use crate::world::block::BlockWorld;
-use crate::world::camera::{CameraSettings, MainCamera};
-use crate::world::chunk::{ChunkData, ChunkNeedsMeshing, PackedChunkData, PaletteEntry};
-use crate::world::generation::{HeightMapProvider, NoiseHeightMap, WorldGenerator};
-use crate::world::machine::MachineWorld;
-use crate::world::player::BlockPicker;
-use bevy::color::palettes::css;
+use crate::world::shape::BlockShapes;
+use crate::render::block::MeshDataCache;
+use crate::world::camera::{apply_dead_zone, CameraSettings, MainCamera};
+use crate::world::chunk::{ChunkData, ChunkNeedsMeshing, PackedChunkData};
+use crate::world::dimension::{ActiveWorld, DimensionId};
+use crate::world::generation::{GenerationConfig, GeneratorKind, HeightMapProvider, NoiseHeightMap, SuperflatPreset, WorldConfig, WorldGenerator};
+use crate::world::machine::{MachineMarker, MachineWorld};
+use crate::world::player::{BlockPicker, BreakProgress, BreakSettings, GameMode, HeldTool, Inventory, Player, PlayerCollider, PlayerVelocity, ReachSettings, GRAVITY, JUMP_VELOCITY, SPRINT_MULTIPLIER, WALK_SPEED};
+use crate::command::console_closed;
+use crate::world::save::{SaveSettings, SaveWorker};
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsStore, RegisterDiagnostic};
+use bevy::input::gamepad::{Gamepad, GamepadButton};
 use bevy::input::mouse::{AccumulatedMouseMotion, MouseScrollUnit, MouseWheel};
-use bevy::math::bounding::{Aabb3d, IntersectsVolume};
-use bevy::pbr::wireframe::{NoWireframe, WireframeConfig};
 use bevy::prelude::*;
-use bevy::tasks::{AsyncComputeTaskPool, Task};
 use bevy::window::{CursorGrabMode, CursorOptions, PrimaryWindow};
 use block::{BlockState, ChunkMap, Direction};
 use noiz::layering::Octave;
 use noiz::prelude::common_noise::{Perlin, PerlinWithDerivative, Simplex};
-use noiz::prelude::{EuclideanLength, FractalLayers, LayeredNoise, Masked, Normed, NormedByDerivative, Offset, PeakDerivativeContribution, Persistence, SNormToUNorm, Scaled, Translated, UNormToSNorm};
+use noiz::prelude::{EuclideanLength, FractalLayers, LayeredNoise, Masked, Normed, NormedByDerivative, PeakDerivativeContribution, Persistence, SNormToUNorm, Scaled};
 use noiz::rng::NoiseRng;
 use player::LookAtData;
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::f32::consts::PI;
 use std::fs;
 use std::ops::Deref;
-use std::sync::{Arc, RwLock};
+use std::path::Path;
 use bevy::asset::ron;
-use bevy::math::cubic_splines::LinearSpline;
-use noiz::math_noise::{Negate, NoiseCurve, Pow2, Pow3};
+use noiz::math_noise::{Negate, NoiseCurve, Pow2};
 use noiz::misc_noise::ExtraRng;
 use crate::math::noise::Combined;
 use crate::RunConfig;
@@ -39,9 +42,48 @@ use crate::RunConfig;
 pub mod chunk;
 pub mod camera;
 pub mod block;
+pub mod shape;
 pub mod machine;
 pub mod player;
 pub mod generation;
+pub mod pipeline;
+pub mod dimension;
+pub mod save;
+
+/// Radius in chunks that gets loaded right away when joining a world or teleporting.
+pub const SPAWN_CHUNK_RADIUS: i32 = 5;
+/// Radius in chunks that has to be meshed before the player can start playing.
+/// Smaller than [`SPAWN_CHUNK_RADIUS`], since chunks on the edge can't mesh until their neighbors load.
+pub const SPAWN_READY_RADIUS: i32 = 2;
+
+/// Gets the y level the player's feet go at to spawn on top of a column, whose top solid block is at `surface`.
+pub fn safe_spawn_y(surface: i32) -> f32 {
+    (surface + 1) as f32
+}
+/// Gets where the player goes when joining a world: back where they were when it was saved, or on top of the spawn column
+/// if there's no save.
+pub fn join_position(saved: Option<Vec3>, spawn_point: IVec2, surface_height: impl FnOnce(IVec2) -> i32) -> Vec3 {
+    saved.unwrap_or_else(|| {
+        let y = safe_spawn_y(surface_height(spawn_point));
+        vec3(spawn_point.x as f32 + 0.5, y, spawn_point.y as f32 + 0.5)
+    })
+}
+/// How far the player gets pushed up at most, if they spawn inside blocks that were placed after generation.
+const MAX_SPAWN_NUDGE: i32 = 64;
+
+/// Where the player was in the saved world, if it had metadata. Taken when joining.
+#[derive(Debug, Default, Resource)]
+pub struct SavedPlayerPos(pub Option<Vec3>);
+
+/// How many chunks around spawn are meshed, measured while in [`InGameState::LoadingSpawn`].
+pub const SPAWN_CHUNKS_READY: DiagnosticPath = DiagnosticPath::const_new("world/spawn_chunks_ready");
+/// How many chunks around spawn have to be meshed before the game starts.
+pub const SPAWN_CHUNKS_TOTAL: DiagnosticPath = DiagnosticPath::const_new("world/spawn_chunks_total");
+
+/// Whether the spawn area is loaded, given the counts from [`BlockWorld::count_meshed_around`].
+pub fn spawn_loaded(ready: usize, total: usize) -> bool {
+    total > 0 && ready >= total
+}
 
 #[derive(Default)]
 pub struct GameWorldPlugin;
@@ -50,16 +92,37 @@ impl Plugin for GameWorldPlugin {
     fn build(&self, app: &mut App) {
         app
             .init_resource::<CameraSettings>()
+            .init_resource::<WorldConfig>()
+            .init_resource::<GameMode>()
+            .init_resource::<ReachSettings>()
+            .init_resource::<BreakSettings>()
+            .init_resource::<SaveSettings>()
+            .init_resource::<SaveWorker>()
+            .register_diagnostic(Diagnostic::new(SPAWN_CHUNKS_READY).with_max_history_length(1))
+            .register_diagnostic(Diagnostic::new(SPAWN_CHUNKS_TOTAL).with_max_history_length(1))
+            .init_resource::<SavedPlayerPos>()
             // temp
 
-            .add_systems(Update, (handle_input, look_at_block, place_and_break, scroll_pick_block).run_if(in_state(MainGameState::InGame)))
+            .add_systems(Update, look_at_block.run_if(in_state(MainGameState::InGame)))
+            .add_systems(Update, (handle_input, place_and_break, pick_block, scroll_pick_block, cycle_dimension).run_if(in_state(InGameState::Playing).and(console_closed)))
+            .add_systems(Update, check_spawn_loaded.run_if(in_state(InGameState::LoadingSpawn)))
+            .add_systems(OnEnter(InGameState::LoadingSpawn), reset_spawn_progress)
             .add_systems(PreUpdate, (join_world, setup_block_picker).run_if(in_state(MainGameState::InGame)))
             // .add_systems(Update, track_chunks_around_player)
             .add_systems(OnEnter(MainGameState::InGame), (setup_world, grab_cursor, create_world))
             .add_observer(on_set_block)
+            .add_observer(on_bulk_set_block)
+            .add_observer(machine::on_set_block)
+            .add_observer(machine::on_bulk_set_block)
             .add_observer(spawn_and_despawn_chunks)
-
-            .add_systems(Update, (temp_save_a_chunk, temp_load_a_chunk).run_if(in_state(MainGameState::InGame)))
+            .add_observer(on_switch_dimension)
+            .add_observer(on_teleport)
+
+            .add_systems(Update, (temp_save_a_chunk, temp_load_a_chunk).run_if(in_state(InGameState::Playing).and(console_closed)))
+            .add_systems(Update, update_projection.run_if(in_state(MainGameState::InGame).and(resource_changed::<CameraSettings>)))
+            .add_systems(Update, update_fov.run_if(in_state(MainGameState::InGame)))
+            .add_systems(Update, (save::autosave, save::receive_autosave).chain().run_if(in_state(MainGameState::InGame)))
+            .add_systems(Last, save::save_on_exit)
         ;
         block::add_systems(app);
     }
@@ -69,22 +132,47 @@ impl Plugin for GameWorldPlugin {
 fn setup_world(
     mut commands: Commands,
     camera_settings: Res<CameraSettings>,
+    mut world_config: ResMut<WorldConfig>,
+    run_config: Res<RunConfig>,
+    mut saved_pos: ResMut<SavedPlayerPos>,
 
 
     // mut materials: ResMut<Assets<StandardMaterial>>,
     // mut meshes: ResMut<Assets<Mesh>>,
 ) {
     info!("Loading world...");
+    match save::load_metadata(&run_config.data_dir) {
+        Ok(Some(metadata)) => {
+            world_config.spawn_point = metadata.spawn_point;
+            saved_pos.0 = Some(metadata.player_pos);
+        },
+        Ok(None) => {}
+        Err(e) => warn!("Could not read world metadata: {}", e),
+    }
+    let collider = PlayerCollider::default();
+    let eye_offset = collider.get_eye_offset();
+    // the player is at their feet, the camera sits at their eyes
     commands.spawn((
+        Player,
+        Transform::from_xyz(0.0, 100.0, 0.0),
+        Visibility::default(),
+        LookAtData::default(),
+        BlockPicker::default(),
+        Inventory::default(),
+        HeldTool::default(),
+        BreakProgress::default(),
+        PlayerVelocity::default(),
+        collider,
+    )).with_child((
         Camera3d::default(),
         Projection::Perspective(PerspectiveProjection {
             fov: camera_settings.fov.to_radians(),
+            near: camera_settings.near_plane,
+            far: camera_settings.get_far_plane(),
             ..default()
         }),
         MainCamera,
-        Transform::from_xyz(0.0, 100.0, 0.0),
-        LookAtData::default(),
-        BlockPicker::default(),
+        Transform::from_translation(eye_offset),
     ));
 
     commands.spawn((
@@ -122,7 +210,66 @@ fn setup_block_picker(
 
 fn create_world(
     mut commands: Commands,
+    config: Res<WorldConfig>,
+    run_config: Res<RunConfig>,
+    camera_settings: Res<CameraSettings>,
 ) {
+    let capacity = camera_settings.get_chunk_capacity();
+    let gen_config = GenerationConfig::load_or_default(&run_config.config_dir);
+    let overworld_machines = load_machine_world(&mut commands, dimension::OVERWORLD, &run_config.data_dir);
+    let flat_machines = load_machine_world(&mut commands, dimension::FLAT, &run_config.data_dir);
+    // the player starts in the overworld
+    commands.spawn((
+        load_block_world(dimension::OVERWORLD, &run_config.data_dir, capacity),
+        overworld_machines,
+        create_generator(&config.generator, config.seed, &gen_config),
+        ActiveWorld,
+        Transform::default(),
+        Visibility::Visible,
+    ))
+        .observe(on_world_join);
+
+    commands.spawn((
+        load_block_world(dimension::FLAT, &run_config.data_dir, capacity),
+        flat_machines,
+        create_generator(&GeneratorKind::Superflat(SuperflatPreset::default()), config.seed, &gen_config),
+        Transform::default(),
+        Visibility::Hidden,
+    ))
+        .observe(on_world_join);
+}
+
+// creates an empty world, which knows which of its chunks are saved on disk
+fn load_block_world(dimension: &str, data_dir: &Path, chunk_capacity: usize) -> BlockWorld {
+    let mut world = BlockWorld::new(DimensionId::new(dimension), chunk_capacity);
+    world.set_saved_chunks(save::find_saved_chunks(data_dir, world.get_dimension()));
+    world
+}
+
+// machines stay loaded no matter which chunks are, so every saved machine is restored up front
+fn load_machine_world(commands: &mut Commands, dimension: &str, data_dir: &Path) -> MachineWorld {
+    let mut machines = MachineWorld::new();
+    let saved = match save::load_machines(data_dir, &DimensionId::new(dimension)) {
+        Ok(saved) => saved,
+        Err(e) => {
+            error!("Could not load machines of {}: {}", dimension, e);
+            return machines;
+        }
+    };
+    for (chunk_pos, packed) in saved {
+        machines.unpack_chunk(chunk_pos, packed, |pos| commands.spawn(MachineMarker::new(pos)).id());
+    }
+    machines
+}
+
+fn create_generator(kind: &GeneratorKind, seed: u64, gen_config: &GenerationConfig) -> WorldGenerator {
+    match kind {
+        GeneratorKind::Noise => WorldGenerator::new(seed, create_noise_height_map(seed as u32, gen_config)),
+        GeneratorKind::Superflat(preset) => WorldGenerator::superflat(seed, preset.clone()),
+    }
+}
+
+pub fn create_noise_height_map(seed: u32, config: &GenerationConfig) -> impl HeightMapProvider + 'static {
 
 
     // let height_map = NoiseHeightMap::new(
@@ -145,27 +292,27 @@ fn create_world(
         // Scaled::<f32>(0.75),
         LayeredNoise::new(
             Normed::<f32>::default(),
-            Persistence(0.5),
+            Persistence(config.persistence),
             FractalLayers {
                 layer: Octave::<Perlin>::default(),
-                lacunarity: 2.0,
-                amount: 3
+                lacunarity: config.lacunarity,
+                amount: config.ocean_octaves
             }
         ),
         SNormToUNorm,
-        Scaled::<f32>(50.0),
+        Scaled::<f32>(config.ocean_depth),
         Negate,
     );
 
     let ocean_control = (
-        Scaled::<f32>(0.0125),
+        Scaled::<f32>(config.ocean_frequency),
         LayeredNoise::new(
             Normed::<f32>::default(),
-            Persistence(0.5),
+            Persistence(config.persistence),
             FractalLayers {
                 layer: Octave::<Simplex>::default(),
-                lacunarity: 2.0,
-                amount: 3
+                lacunarity: config.lacunarity,
+                amount: config.ocean_octaves
             }
         ),
         SNormToUNorm,
@@ -182,63 +329,104 @@ fn create_world(
                 EuclideanLength,
                 PeakDerivativeContribution,
             >::default().with_falloff(1.25),
-            Persistence(0.5),
+            Persistence(config.persistence),
             FractalLayers {
                 layer: Octave::<PerlinWithDerivative>::default(),
-                lacunarity: 2.0,
-                amount: 5,
+                lacunarity: config.lacunarity,
+                amount: config.mountain_octaves,
             }
         ),
         SNormToUNorm,
         Pow2,
-        Scaled::<f32>(350.0)
+        Scaled::<f32>(config.mountain_scale)
     );
 
     let mountain_control = (
         ExtraRng,
-        Scaled(0.25),
+        Scaled(config.mountain_frequency),
         Perlin::default(),
         SNormToUNorm,
         NoiseCurve(SmoothStepCurve.reparametrize_by_curve(SmoothStepCurve))
     );
 
-
     let noise = noiz::Noise {
         noise: Combined(
             Masked(Masked(mountains, mountain_control), ocean_control),
             Masked(oceans, ocean_control)
         ),
         // noise: ocean_control,
-        seed: NoiseRng(69420),
-        frequency: 0.01,
+        seed: NoiseRng(seed),
+        frequency: config.frequency,
     };
 
-    let height_map = NoiseHeightMap::new(noise);
+    NoiseHeightMap::new(noise)
+}
 
-    commands.spawn((
-        BlockWorld::new(),
-        MachineWorld::new(),
-        WorldGenerator::new(height_map)
-    ))
-        .observe(on_world_join);
+// keeps the camera projection in sync with the camera settings. Fov is handled by update_fov
+fn update_projection(
+    camera_settings: Res<CameraSettings>,
+    mut projection: Single<&mut Projection, With<MainCamera>>,
+) {
+    let Projection::Perspective(perspective) = projection.as_mut() else {
+        return;
+    };
+    perspective.near = camera_settings.near_plane;
+    perspective.far = camera_settings.get_far_plane();
+}
+
+// smoothly moves the fov toward what it should be, for settings changes and sprinting
+fn update_fov(
+    camera_settings: Res<CameraSettings>,
+    player: Single<&PlayerVelocity, With<Player>>,
+    mut projection: Single<&mut Projection, With<MainCamera>>,
+    time: Res<Time>,
+) {
+    let Projection::Perspective(perspective) = projection.as_ref() else {
+        return;
+    };
+    let current = perspective.fov.to_degrees();
+    let target = camera_settings.get_target_fov(player.sprinting);
+    let fov = camera::step_fov(current, target, camera_settings.fov_transition_speed, time.delta_secs());
+    // only touch the projection when it actually changes
+    if fov == current {
+        return;
+    }
+    if let Projection::Perspective(perspective) = projection.as_mut() {
+        perspective.fov = fov.to_radians();
+    }
 }
 
 fn handle_input(
     mut commands: Commands,
-    mut transform: Single<&mut Transform, With<MainCamera>>,
+    player: Single<(&mut Transform, &mut PlayerVelocity, &PlayerCollider), (With<Player>, Without<MainCamera>)>,
+    camera: Single<&mut Transform, (With<MainCamera>, Without<Player>)>,
+    world: Single<&BlockWorld, With<ActiveWorld>>,
+    shapes: Res<BlockShapes>,
     // mut proj: Single<&mut Projection, With<MainCamera>>,
     camera_settings: Res<CameraSettings>,
+    game_mode: Res<GameMode>,
     timer: Res<Time>,
     kb_input: Res<ButtonInput<KeyCode>>,
     mouse_motion: Res<AccumulatedMouseMotion>,
+    gamepads: Query<&Gamepad>,
 ) {
     let delta = mouse_motion.delta;
 
-    let delta_yaw = (camera_settings.yaw_sensitivity * -delta.x).to_radians();
-    let delta_pitch = (camera_settings.pitch_sensitivity * -delta.y).to_radians();
+    let mut delta_yaw = (camera_settings.yaw_sensitivity * -delta.x).to_radians();
+    let mut delta_pitch = (camera_settings.pitch_sensitivity * -delta.y).to_radians();
+    // gamepads work alongside the mouse, any of them can look around
+    for gamepad in gamepads.iter() {
+        let look = camera_settings.gamepad_look_delta(gamepad.right_stick(), timer.delta_secs());
+        delta_yaw += look.x;
+        delta_pitch += look.y;
+    }
 
 
-    let (yaw_old, pitch_old, roll_old) = transform.rotation.to_euler(EulerRot::YXZ);
+    let ((mut player, mut velocity, collider), mut camera) = (player.into_inner(), camera.into_inner());
+
+    // yaw turns the whole player, pitch only tilts the camera
+    let (yaw_old, _, _) = player.rotation.to_euler(EulerRot::YXZ);
+    let (_, pitch_old, roll_old) = camera.rotation.to_euler(EulerRot::YXZ);
 
     let pitch = (pitch_old + delta_pitch).clamp(
         -89.9 * PI/180.,
@@ -246,65 +434,129 @@ fn handle_input(
     );
     let yaw = yaw_old + delta_yaw;
     let roll = roll_old;
+    player.rotation = Quat::from_rotation_y(yaw);
     // important: this is Y X Z, not X Y Z
-    transform.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll);
+    camera.rotation = Quat::from_euler(EulerRot::YXZ, 0.0, pitch, roll);
+
+    let old = player.translation;
+    let (movement, vertical) = movement_input(&kb_input, &gamepads, camera_settings.gamepad_dead_zone);
+    // sprinting only counts while moving forward
+    let sprint_held = kb_input.pressed(KeyCode::ControlLeft) || gamepads.iter().any(|g| g.pressed(GamepadButton::LeftThumb));
+    velocity.sprinting = sprint_held && movement.y > 0.0;
+    let speed_multiplier = if velocity.sprinting { SPRINT_MULTIPLIER } else { 1.0 };
+    if game_mode.is_flying() {
+        velocity.velocity = Vec3::ZERO;
+        velocity.on_ground = false;
+        fly(&mut player, &camera, movement, vertical, camera_settings.movement_speed * speed_multiplier * timer.delta_secs());
+        // creative players can still walk around on the ground. Spectators go through blocks, so they never stand on anything
+        velocity.on_ground = *game_mode == GameMode::Creative && collider.is_on_ground(player.translation, &world, &shapes);
+    }
+    else {
+        walk(&mut player, &mut velocity, collider, &world, &shapes, movement, vertical > 0.0, speed_multiplier, timer.delta_secs());
+    }
 
-    let mut movement = Vec3::ZERO;
+    if player.translation != old {
+        commands.trigger(PlayerMovedEvent {
+            old,
+            new: player.translation
+        });
+    }
+}
 
+// combines the keyboard and every gamepad into how the player wants to move: (right, forward) from -1 to 1, and up or down.
+// Keys are all or nothing, sticks can be pushed part way
+fn movement_input(kb_input: &ButtonInput<KeyCode>, gamepads: &Query<&Gamepad>, dead_zone: f32) -> (Vec2, f32) {
+    let mut movement = Vec2::ZERO;
+    let mut vertical: f32 = 0.0;
     if kb_input.pressed(KeyCode::KeyW) {
-        movement += transform.forward().as_vec3();
+        movement.y += 1.0;
     }
     if kb_input.pressed(KeyCode::KeyA) {
-        movement -= transform.right().as_vec3();
+        movement.x -= 1.0;
     }
     if kb_input.pressed(KeyCode::KeyS) {
-        movement -= transform.forward().as_vec3();
+        movement.y -= 1.0;
     }
     if kb_input.pressed(KeyCode::KeyD) {
-        movement += transform.right().as_vec3();
+        movement.x += 1.0;
     }
-    // up and down use world up instead - more intuitive
     if kb_input.pressed(KeyCode::Space) {
-        movement += vec3(0., 1., 0.);
+        vertical += 1.0;
     }
     if kb_input.pressed(KeyCode::ShiftLeft) {
-        movement -= vec3(0., 1., 0.);
+        vertical -= 1.0;
     }
+    for gamepad in gamepads.iter() {
+        movement += apply_dead_zone(gamepad.left_stick(), dead_zone);
+        if gamepad.pressed(GamepadButton::South) {
+            vertical += 1.0;
+        }
+        if gamepad.pressed(GamepadButton::East) {
+            vertical -= 1.0;
+        }
+    }
+    (movement.clamp_length_max(1.0), vertical.clamp(-1.0, 1.0))
+}
 
-    let old = transform.translation;
-    movement = movement.normalize_or_zero();
-    transform.translation += movement * camera_settings.movement_speed * timer.delta_secs();
-    if movement != Vec3::ZERO {
-        commands.trigger(PlayerMovedEvent {
-            old,
-            new: transform.translation
-        });
+// creative and spectator: move where the eyes are looking, no collisions
+fn fly(player: &mut Transform, camera: &Transform, input: Vec2, vertical: f32, distance: f32) {
+    let transform = player.mul_transform(*camera);
+    let mut movement = transform.forward().as_vec3() * input.y + transform.right().as_vec3() * input.x;
+    // up and down use world up instead - more intuitive
+    movement += vec3(0., vertical, 0.);
+
+    movement = movement.clamp_length_max(1.0);
+    player.translation += movement * distance;
+}
+
+// survival: walk along the ground with gravity, colliding with blocks one axis at a time
+fn walk(player: &mut Transform, velocity: &mut PlayerVelocity, collider: &PlayerCollider, world: &BlockWorld, shapes: &BlockShapes, input: Vec2, jump: bool, speed_multiplier: f32, delta: f32) {
+    // only the yaw matters when walking
+    let (forward, right) = (player.forward().as_vec3(), player.right().as_vec3());
+    let movement = (forward * input.y + right * input.x).clamp_length_max(1.0) * WALK_SPEED * speed_multiplier;
+
+    if velocity.on_ground && jump {
+        velocity.velocity.y = JUMP_VELOCITY;
+    }
+    velocity.velocity.y -= GRAVITY * delta;
+    velocity.velocity.x = movement.x;
+    velocity.velocity.z = movement.z;
+
+    let step = velocity.velocity * delta;
+    velocity.on_ground = false;
+    for axis in [Vec3::X, Vec3::Z, Vec3::Y] {
+        let next = player.translation + step * axis;
+        if collider.collides(next, world, shapes) {
+            if axis == Vec3::Y {
+                velocity.on_ground = velocity.velocity.y < 0.0;
+                velocity.velocity.y = 0.0;
+            }
+            continue;
+        }
+        player.translation = next;
     }
 }
 
     fn scroll_pick_block(
     mut target: Single<&mut BlockPicker>,
-    mut mouse_scroll: EventReader<MouseWheel>,
+    mut mouse_scroll: MessageReader<MouseWheel>,
+    gamepads: Query<&Gamepad>,
 ) {
+    // bumpers step through the blocks like the scroll wheel
+    for gamepad in gamepads.iter() {
+        if gamepad.just_pressed(GamepadButton::RightTrigger) {
+            target.cycle(true);
+        }
+        if gamepad.just_pressed(GamepadButton::LeftTrigger) {
+            target.cycle(false);
+        }
+    }
     for event in mouse_scroll.read() {
         match event.unit {
             MouseScrollUnit::Line => {
                 // info!("Scrolled {}, {}", event.x, event.y);
-                if event.y < 0.0 {
-                    target.index = if target.index == 0 {
-                         target.block_order.len() - 1
-                    }
-                    else {
-                        target.index - 1
-                    };
-                }
-                else if event.y > 0.0 {
-                    target.index = if target.index == target.block_order.len() - 1 {
-                        0
-                    }
-                    else {
-                        target.index + 1
-                    };
+                if event.y != 0.0 {
+                    target.cycle(event.y > 0.0);
                 }
             },
             MouseScrollUnit::Pixel => {
@@ -325,35 +577,123 @@ fn handle_input(
 
 fn place_and_break(
     mut commands: Commands,
-    player: Single<(&LookAtData, &BlockPicker)>,
-    mut world: Single<&mut BlockWorld>,
+    player: Single<(&LookAtData, &BlockPicker, &Transform, &PlayerCollider, &HeldTool, &mut Inventory, &mut BreakProgress)>,
+    mesh_cache: Res<MeshDataCache>,
+    shapes: Res<BlockShapes>,
+    mut world: Single<&mut BlockWorld, With<ActiveWorld>>,
     mouse_input: Res<ButtonInput<MouseButton>>,
+    kb_input: Res<ButtonInput<KeyCode>>,
     block_registry: Res<RegistryHandle<Block>>,
+    block_tags: Res<BlockTags>,
+    break_settings: Res<BreakSettings>,
+    game_mode: Res<GameMode>,
+    time: Res<Time>,
+    gamepads: Query<&Gamepad>,
 ) -> Result<(), BevyError> {
-    let (target, picker) = player.into_inner();
+    if *game_mode == GameMode::Spectator {
+        return Ok(());
+    }
+    // the right trigger breaks and the left trigger places, alongside the mouse
+    let break_held = mouse_input.pressed(MouseButton::Left) || gamepads.iter().any(|g| g.pressed(GamepadButton::RightTrigger2));
+    let break_pressed = mouse_input.just_pressed(MouseButton::Left) || gamepads.iter().any(|g| g.just_pressed(GamepadButton::RightTrigger2));
+    let place_released = mouse_input.just_released(MouseButton::Right) || gamepads.iter().any(|g| g.just_released(GamepadButton::LeftTrigger2));
+
+    let (target, picker, transform, collider, tool, mut inventory, mut progress) = player.into_inner();
+    progress.tick(time.delta_secs(), break_held);
+    // the camera only pitches, the player's yaw is the way they're facing
+    let facing = Direction::nearest_horizontal(transform.forward().as_vec3());
     
     let (Some(pos), Some(face)) = (target.look_pos, target.face) else {
+        progress.reset();
         return Ok(());
     };
-    if mouse_input.just_pressed(MouseButton::Left) {
-        world.set_block(&mut commands, &pos, BlockState::new("air", &block_registry)?)?;
+    if *game_mode == GameMode::Creative {
+        // a held button only breaks more with auto repeat on, and never faster than the cooldown
+        if progress.wants_creative_break(break_pressed, break_held, &break_settings) {
+            world.set_block(&mut commands, &pos, BlockState::air(), BlockChangeSource::Player)?;
+            progress.finish_break(&break_settings);
+        }
     }
-    else if mouse_input.just_released(MouseButton::Right) {
+    // survival has to hold the button down for a while, and gets the block back
+    else if break_held && progress.can_break() {
+        if progress.pos != Some(pos) {
+            progress.reset();
+            progress.pos = Some(pos);
+        }
+        progress.elapsed += time.delta_secs();
 
-        let new_pos = pos.offset(face);
+        let state = world.get_block(&pos)?;
+        let Some(block) = state.block(&block_registry) else {
+            return Ok(());
+        };
+        let tool = tool.0.as_ref();
+        if progress.elapsed >= game_mode.get_break_duration(block, tool) {
+            world.set_block(&mut commands, &pos, BlockState::air(), BlockChangeSource::Player)?;
+            // no drop without the right tool
+            if block.can_harvest(tool) {
+                inventory.add(state.get_id(), 1);
+            }
+            progress.finish_break(&break_settings);
+        }
+    }
+    else {
+        progress.reset();
+    }
 
-        if world.get_block(&new_pos)?.is_air() {
-            let id = &picker.block_order[picker.index];
-            world.set_block(&mut commands, &new_pos, BlockState::new(id, &block_registry)?)?;
+    // holding alt in creative replaces the targeted block instead of placing next to it
+    if mouse_input.just_released(MouseButton::Right) && kb_input.pressed(KeyCode::AltLeft) && *game_mode == GameMode::Creative {
+        let state = picker.get_placement_state(facing, &block_registry, &mesh_cache)?;
+        if world.get_block(&pos)? != state {
+            world.set_block(&mut commands, &pos, state, BlockChangeSource::Player)?;
+        }
+    }
+    else if place_released {
+
+        // replaceable blocks get placed into, instead of next to
+        let table = mesh_cache.get_table();
+        let new_pos = if block_tags.is_replaceable(&world.get_block(&pos)?, table) { pos } else { pos.offset(face) };
+
+        if block_tags.is_replaceable(&world.get_block(&new_pos)?, table) {
+            let state = picker.get_placement_state(facing, &block_registry, &mesh_cache)?;
+            // don't let the player place blocks inside themselves
+            if collider.overlaps_block(transform.translation, new_pos, shapes.get_collision_shape(&state)) {
+                return Ok(());
+            }
+            if *game_mode == GameMode::Survival && !inventory.take(state.get_id()) {
+                return Ok(());
+            }
+            world.set_block(&mut commands, &new_pos, state, BlockChangeSource::Player)?;
         }
     }
 
 
     Ok(())
 }
+// middle click selects the block being looked at, keeping its exact state
+fn pick_block(
+    player: Single<(&LookAtData, &mut BlockPicker)>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+) {
+    if !mouse_input.just_pressed(MouseButton::Middle) {
+        return;
+    }
+    let (target, mut picker) = player.into_inner();
+    let Some(block) = &target.look_block else {
+        return;
+    };
+    picker.select(block.get_id());
+    if picker.get_selected_id() == block.get_id() {
+        picker.picked_state = Some(block.clone());
+    }
+}
+
 fn look_at_block(
-    player: Single<(&mut Transform, &mut LookAtData), With<MainCamera>>,
-    world: Single<&BlockWorld>,
+    player: Single<(&Transform, &mut LookAtData, &HeldTool), With<Player>>,
+    camera: Single<&Transform, (With<MainCamera>, Without<Player>)>,
+    world: Single<&BlockWorld, With<ActiveWorld>>,
+    game_mode: Res<GameMode>,
+    reach_settings: Res<ReachSettings>,
+    shapes: Res<BlockShapes>,
     // kb_input: Res<ButtonInput<KeyCode>>,
     // mut gizmos: Gizmos,
 ) {
@@ -362,41 +702,27 @@ fn look_at_block(
     //     return;
     // }
 
-    let (mut transform, mut look_at_data) = player.into_inner();
+    let (player_transform, mut look_at_data, held_tool) = player.into_inner();
+    // raycast from the eyes
+    let transform = player_transform.mul_transform(*camera.into_inner());
 
-    let distance = 5.0;
+    let distance = player::effective_reach(*game_mode, held_tool.0.as_ref(), &reach_settings);
     let view_dir = transform.forward().as_vec3();
     let pos = transform.translation;
 
     // gizmos.line(pos, pos + (view_dir * distance), css::GREEN);
 
 
-    let result = ray::block_raycast(pos, view_dir, distance, |_context, _intersection_point, _face, b_pos| {
-        // println!("Testing block {}", b_pos);
-
-        let Ok(block) = world.get_block(&b_pos) else {
-            return Ok(false);
-        };
-        // println!("State: {:?}", block);
-        let b = block.is_air();
-        let _color = if b {
-            css::LIGHT_BLUE
-        } else {
-            css::LIGHT_GREEN
-        };
-
-        // let voxel_center = b_pos.center();
-        // gizmos.cuboid(Transform::from_translation(voxel_center).with_scale(Vec3::splat(1.0)), color);
-
-        Ok(!b)
-    });
+    let result = world.raycast(pos, view_dir, distance, &shapes);
     // println!("Result: {:?}", result);
-    if let Ok(RayResult::Hit(pos, face, b_pos)) = result {
+    if let RayResult::Hit(pos, face, b_pos, hit_distance) = result {
         // *sphere_vis = Visibility::Visible;
         // look_at_data.translation = pos;
         look_at_data.look_pos = Some(b_pos);
         look_at_data.surface = Some(pos);
         look_at_data.face = Some(face);
+        look_at_data.distance = Some(hit_distance);
+        look_at_data.face_uv = Some(ray::face_uv(pos, face));
 
         let block = world.get_block(&b_pos).unwrap();
 
@@ -418,18 +744,22 @@ fn grab_cursor(
 }
 
 
+// puts the player on the surface of the spawn column, then starts loading around them
 fn join_world(
     mut commands: Commands,
-    q_world: Query<Entity, With<BlockWorld>>,
-    camera: Single<&Transform, With<MainCamera>>,
+    q_world: Query<(Entity, &WorldGenerator), (With<BlockWorld>, With<ActiveWorld>)>,
+    mut player: Single<&mut Transform, With<Player>>,
+    world_config: Res<WorldConfig>,
+    mut saved_pos: ResMut<SavedPlayerPos>,
     mut has_run: Local<bool>
 ) {
     if *has_run {
         return;
     }
-    for world in q_world.iter() {
+    for (world, generator) in q_world.iter() {
+        player.translation = join_position(saved_pos.0.take(), world_config.spawn_point, |pos| generator.get_surface_height(pos));
         commands.trigger(JoinedWorldEvent {
-            pos: camera.translation,
+            pos: player.translation,
             world,
         });
     }
@@ -438,55 +768,136 @@ fn join_world(
 
 
 
-fn on_world_join(
-    trigger: On<JoinedWorldEvent>,
-    mut q_world: Query<&mut BlockWorld>,
+// moves the player into another dimension. The old world stops loading chunks and is hidden.
+fn on_switch_dimension(
+    trigger: On<SwitchDimensionEvent>,
+    mut commands: Commands,
+    mut q_world: Query<(Entity, &BlockWorld, &mut Visibility, Has<ActiveWorld>)>,
+    mut player: Single<&mut Transform, With<Player>>,
 ) {
-    let id = trigger.world;
-    let Ok(mut world) = q_world.get_mut(id) else {
+    let Some((new_world, _, _, is_active)) = q_world.iter().find(|(_, w, _, _)| *w.get_dimension() == trigger.dimension) else {
+        warn!("Cannot switch to dimension {}, as it does not exist.", trigger.dimension);
         return;
     };
-    let world = world.as_mut();
-
-    let chunk_pos = chunk::pos_to_chunk_pos(trigger.pos.as_block_pos());
+    if is_active {
+        return;
+    }
 
-    let rad = 5;
+    for (entity, world, mut visibility, is_active) in q_world.iter_mut() {
+        if entity == new_world {
+            *visibility = Visibility::Visible;
+            commands.entity(entity).insert(ActiveWorld);
+        }
+        else if is_active {
+            info!("Leaving dimension {}", world.get_dimension());
+            *visibility = Visibility::Hidden;
+            commands.entity(entity).remove::<ActiveWorld>();
+        }
+    }
 
-    let mut queue = VecDeque::new();
+    player.translation = trigger.pos;
+    info!("Entering dimension {}", trigger.dimension);
+    commands.trigger(JoinedWorldEvent {
+        pos: trigger.pos,
+        world: new_world,
+    });
+}
 
-    // force map and read_guard to be dropped before queuing chunk generation
-    {
-        let map = world.get_chunk_map();
+// temp: cycles through all dimensions, keeping the player's position.
+fn cycle_dimension(
+    mut commands: Commands,
+    kb_input: Res<ButtonInput<KeyCode>>,
+    q_world: Query<(&BlockWorld, Has<ActiveWorld>)>,
+    player: Single<&Transform, With<Player>>,
+) {
+    if !kb_input.just_pressed(KeyCode::KeyN) {
+        return;
+    }
+    let mut dimensions = q_world.iter().map(|(w, active)| (w.get_dimension().clone(), active)).collect::<Vec<_>>();
+    if dimensions.is_empty() {
+        return;
+    }
+    dimensions.sort();
+    let current = dimensions.iter().position(|(_, active)| *active).unwrap_or(0);
+    let (next, _) = &dimensions[(current + 1) % dimensions.len()];
 
-        info!("Loading spawn chunks...");
-        let mut i = 0;
-        for x in -rad..rad + 1 {
-            for z in -rad..rad + 1 {
-                for y in -rad..rad + 1 {
-                    let coord = ivec3(x, y, z) + chunk_pos;
-                    if map.get_chunk(&coord).is_some() {
-                        continue;
-                    }
+    commands.trigger(SwitchDimensionEvent {
+        dimension: next.clone(),
+        pos: player.translation,
+    });
+}
 
-                    queue.push_back(coord);
+fn on_world_join(
+    trigger: On<JoinedWorldEvent>,
+    mut q_world: Query<&mut BlockWorld>,
+) {
+    let id = trigger.world;
+    let Ok(mut world) = q_world.get_mut(id) else {
+        return;
+    };
+    info!("Loading spawn chunks...");
+    world.load_around(trigger.pos, SPAWN_CHUNK_RADIUS);
+}
 
-                    i += 1;
-                }
-            }
+// so the loading screen doesn't show the last spawn's count
+fn reset_spawn_progress(mut diagnostics: ResMut<DiagnosticsStore>) {
+    for path in [&SPAWN_CHUNKS_READY, &SPAWN_CHUNKS_TOTAL] {
+        if let Some(diagnostic) = diagnostics.get_mut(path) {
+            diagnostic.clear_history();
         }
     }
+}
 
-    while !queue.is_empty() {
-        world.queue_chunk_generation(queue.pop_front().unwrap());
+// counts how many chunks around the player are meshed, and starts the game once they all are
+fn check_spawn_loaded(
+    world: Single<&BlockWorld, With<ActiveWorld>>,
+    mut player: Single<(&mut Transform, &PlayerCollider), With<Player>>,
+    shapes: Res<BlockShapes>,
+    needs_meshing: Query<(), With<ChunkNeedsMeshing>>,
+    mut diagnostics: Diagnostics,
+    mut next_state: ResMut<NextState<InGameState>>,
+) {
+    let (transform, collider) = &mut *player;
+    let center = chunk::pos_to_chunk_pos(transform.translation.as_block_pos());
+    let (ready, total) = world.count_meshed_around(center, SPAWN_READY_RADIUS, |e| needs_meshing.contains(e));
+    diagnostics.add_measurement(&SPAWN_CHUNKS_READY, || ready as f64);
+    diagnostics.add_measurement(&SPAWN_CHUNKS_TOTAL, || total as f64);
+    if spawn_loaded(ready, total) {
+        // the surface height only knows about generated terrain, so climb out of anything built on top of it
+        for _ in 0..MAX_SPAWN_NUDGE {
+            if !collider.collides(transform.translation, &world, &shapes) {
+                break;
+            }
+            transform.translation.y += 1.0;
+        }
+        info!("Spawn area loaded.");
+        next_state.set(InGameState::Playing);
     }
 }
 
-
+// moves the player within the active world. Spawn chunks around the destination are queued first,
+// then the move is treated like any other, so the rest of the render distance loads and far chunks unload.
+fn on_teleport(
+    trigger: On<TeleportEvent>,
+    mut commands: Commands,
+    mut world: Single<&mut BlockWorld, With<ActiveWorld>>,
+    mut player: Single<&mut Transform, With<Player>>,
+) {
+    let old = player.translation;
+    player.translation = trigger.pos;
+    let queued = world.load_around(trigger.pos, SPAWN_CHUNK_RADIUS);
+    info!("Teleported to {}, queued {} chunks", trigger.pos, queued);
+    commands.trigger(PlayerMovedEvent {
+        old,
+        new: trigger.pos,
+    });
+}
 
 // Spawns and despawns chunks
 fn spawn_and_despawn_chunks(
     trigger: On<PlayerMovedEvent>,
-    mut world: Single<&mut BlockWorld>,
+    mut world: Single<&mut BlockWorld, With<ActiveWorld>>,
+    camera_settings: Res<CameraSettings>,
 ) {
 
     let old_chunk = chunk::pos_to_chunk_pos(trigger.old.as_block_pos());
@@ -504,19 +915,17 @@ fn spawn_and_despawn_chunks(
     let mut to_despawn = VecDeque::new();
     
 
-    let spawn_distance = 8;
-    let spawn_squared = (spawn_distance * spawn_distance) as f32;
+    let spawn_distance = camera_settings.render_distance as i32;
 
     // for all chunks within the radius
     for x in -spawn_distance..spawn_distance + 1 {
         for y in -spawn_distance..spawn_distance + 1 {
             for z in -spawn_distance..spawn_distance + 1 {
-                let distance = vec3(x as f32, y as f32, z as f32).distance_squared(Vec3::ZERO);
-                // skip chunks not close enough
-                if distance > spawn_squared {
+                let pos = new_chunk + ivec3(x, y, z);
+                // skip chunks not close enough, or too far to fit in the memory budget
+                if !camera_settings.in_spawn_range(new_chunk, pos) || !world.in_budget_range(new_chunk, pos) {
                     continue;
                 }
-                let pos = new_chunk + ivec3(x, y, z);
                 // skip chunks already in the chunk map
                 if world.is_queued_for_generation(&pos) {
                     continue;
@@ -529,20 +938,30 @@ fn spawn_and_despawn_chunks(
             }
         }
     }
-    let despawn_distance = 12.0;
-    let despawn_squared = despawn_distance * despawn_distance;
-
-
-
-    // despawn chunks
-    for (pos, _) in map.iter() {
-        let distance = new_chunk.as_vec3().distance_squared(pos.as_vec3());
-
-        if distance > despawn_squared {
-            // queue despawn
-            to_despawn.push_back(pos.clone());
+    // Chunks between the spawn and despawn distance are left alone either way, so moving back and forth doesn't thrash them
+    let despawn_distance = camera_settings.get_despawn_distance();
+    if world.get_keep_center() == Some((old_chunk, despawn_distance)) {
+        // everything was in range of the old chunk, so only the chunks that just left it can be out of range now
+        for pos in chunk::chunks_leaving_range(old_chunk, new_chunk, despawn_distance) {
+            if map.get_chunk(&pos).is_some() {
+                to_despawn.push_back(pos);
+            }
+        }
+    }
+    else {
+        // despawn chunks, furthest first. Only the chunks out of range get sorted
+        let mut leaving = map.iter()
+            .map(|(pos, _)| *pos)
+            .filter(|pos| !camera_settings.in_keep_range(new_chunk, *pos))
+            .collect::<Vec<_>>();
+        leaving.sort_by_key(|pos| std::cmp::Reverse(chunk::distance_order(new_chunk, *pos)));
+        to_despawn.extend(leaving);
+    }
+    // and chunks that haven't started generating yet
+    for pos in world.get_pending_generation() {
+        if !camera_settings.in_keep_range(new_chunk, *pos) {
+            to_despawn.push_back(*pos);
         }
-
     }
     // mutable world access
     while !to_generate.is_empty() {
@@ -553,6 +972,8 @@ fn spawn_and_despawn_chunks(
         let pos = to_despawn.pop_front().unwrap();
         world.queue_chunk_despawn(pos);
     }
+    // everything left is in range now, including what was just queued
+    world.set_keep_center(new_chunk, despawn_distance);
     
 
 }
@@ -560,64 +981,70 @@ fn spawn_and_despawn_chunks(
 
 
 fn on_set_block(
-    trigger: Trigger<SetBlockEvent>,
+    trigger: On<SetBlockEvent>,
     mut commands: Commands,
-    world: Single<&BlockWorld>,
+    q_world: Query<&BlockWorld>,
 ) {
 
-    let map = world.get_chunk_map();
-
-    let pos = trigger.pos;
-    let chunk_pos = chunk::pos_to_chunk_pos(pos);
-
-    // Remesh neighboring chunks if needed
-    let local_pos = chunk::pos_to_chunk_local(pos);
-
-    let x_axis = if local_pos.x == 0 {
-        Some(chunk_pos.west())
-    } else if local_pos.x == (ChunkData::CHUNK_SIZE as i32 - 1) {
-        Some(chunk_pos.east())
-    } else {
-        None
-    };
-    let y_axis = if local_pos.y == 0 {
-        Some(chunk_pos.down())
-    } else if local_pos.y == (ChunkData::CHUNK_SIZE as i32 - 1) {
-        Some(chunk_pos.up())
-    } else {
-        None
-    };
-    let z_axis = if local_pos.z == 0 {
-        Some(chunk_pos.south())
-    } else if local_pos.z == (ChunkData::CHUNK_SIZE as i32 - 1) {
-        Some(chunk_pos.north())
-    } else {
-        None
+    // only remesh chunks in the world the block was set in
+    let Some(world) = q_world.iter().find(|w| *w.get_dimension() == trigger.dimension) else {
+        return;
     };
+    remesh_changed(world.get_chunk_map(), &mut commands, &[trigger.pos]);
+}
 
-    let chunk = map.get_chunk(&chunk_pos).unwrap();
-    let entity = chunk.get_entity();
-    commands.entity(entity).insert(ChunkNeedsMeshing);
+fn on_bulk_set_block(
+    trigger: On<BulkSetBlockEvent>,
+    mut commands: Commands,
+    q_world: Query<&BlockWorld>,
+) {
+    let Some(world) = q_world.iter().find(|w| *w.get_dimension() == trigger.dimension) else {
+        return;
+    };
+    remesh_changed(world.get_chunk_map(), &mut commands, &trigger.changed_positions);
+}
 
-    // remesh neighboring chunks if necessary
-    if let Some(x_axis) = x_axis {
-        let chunk = map.get_chunk(&x_axis).unwrap();
-        commands.entity(chunk.get_entity()).insert(ChunkNeedsMeshing);
-    }
-    if let Some(y_axis) = y_axis {
-        let chunk = map.get_chunk(&y_axis).unwrap();
-        commands.entity(chunk.get_entity()).insert(ChunkNeedsMeshing);
+// marks the chunks containing these positions for remeshing, as well as neighboring chunks for positions on a chunk border.
+// each chunk is only marked once.
+fn remesh_changed(map: &ChunkMap, commands: &mut Commands, positions: &[IVec3]) {
+    let last = ChunkData::CHUNK_SIZE as i32 - 1;
+    let mut to_remesh = HashSet::new();
+
+    for pos in positions.iter() {
+        let chunk_pos = chunk::pos_to_chunk_pos(*pos);
+        to_remesh.insert(chunk_pos);
+
+        // Remesh neighboring chunks if needed
+        let local_pos = chunk::pos_to_chunk_local(*pos);
+        if local_pos.x == 0 {
+            to_remesh.insert(chunk_pos.west());
+        } else if local_pos.x == last {
+            to_remesh.insert(chunk_pos.east());
+        }
+        if local_pos.y == 0 {
+            to_remesh.insert(chunk_pos.down());
+        } else if local_pos.y == last {
+            to_remesh.insert(chunk_pos.up());
+        }
+        if local_pos.z == 0 {
+            to_remesh.insert(chunk_pos.south());
+        } else if local_pos.z == last {
+            to_remesh.insert(chunk_pos.north());
+        }
     }
-    if let Some(z_axis) = z_axis {
-        let chunk = map.get_chunk(&z_axis).unwrap();
-        commands.entity(chunk.get_entity()).insert(ChunkNeedsMeshing);
+
+    for chunk_pos in to_remesh {
+        // neighbors might not be loaded, that's fine
+        if let Some(chunk) = map.get_chunk(&chunk_pos) {
+            commands.entity(chunk.get_entity()).insert(ChunkNeedsMeshing);
+        }
     }
 }
 
 
 fn temp_save_a_chunk(
-    camera: Single<&Transform, With<MainCamera>>,
-    world: Single<&BlockWorld>,
+    player: Single<&Transform, With<Player>>,
+    world: Single<&BlockWorld, With<ActiveWorld>>,
     kb_input: Res<ButtonInput<KeyCode>>,
     run_config: Res<RunConfig>,
 ) -> Result<(), BevyError> {
@@ -627,9 +1054,9 @@ fn temp_save_a_chunk(
 
 
     let chunk_map = world.get_chunk_map();
-    let camera_chunk = chunk::pos_to_chunk_pos(camera.translation.as_block_pos());
+    let player_chunk = chunk::pos_to_chunk_pos(player.translation.as_block_pos());
 
-    let chunk = chunk_map.get_chunk(&camera_chunk).unwrap();
+    let chunk = chunk_map.get_chunk(&player_chunk).unwrap();
     let chunk_data = chunk.get_data()?;
     let read_guard = chunk_data.read().unwrap();
     let data = read_guard.deref();
@@ -647,8 +1074,8 @@ fn temp_save_a_chunk(
 }
 
 fn temp_load_a_chunk(
-    camera: Single<&Transform, With<MainCamera>>,
-    world: Single<&BlockWorld>,
+    player: Single<&Transform, With<Player>>,
+    world: Single<&BlockWorld, With<ActiveWorld>>,
     kb_input: Res<ButtonInput<KeyCode>>,
     run_config: Res<RunConfig>,
     mut commands: Commands,
@@ -659,9 +1086,9 @@ fn temp_load_a_chunk(
 
 
     let chunk_map = world.get_chunk_map();
-    let camera_chunk = chunk::pos_to_chunk_pos(camera.translation.as_block_pos());
+    let player_chunk = chunk::pos_to_chunk_pos(player.translation.as_block_pos());
 
-    let chunk = chunk_map.get_chunk(&camera_chunk).unwrap();
+    let chunk = chunk_map.get_chunk(&player_chunk).unwrap();
     let chunk_data = chunk.get_data()?;
     let mut write_data = chunk_data.write().unwrap();
 
@@ -673,7 +1100,7 @@ fn temp_load_a_chunk(
 
     let file = fs::read(file)?;
     let packed_data: PackedChunkData = ron::de::from_bytes(file.as_slice())?;
-    *write_data = packed_data.into();
+    *write_data = packed_data.try_into()?;
 
     commands.entity(chunk.get_entity()).insert(ChunkNeedsMeshing);
 
@@ -820,105 +1247,272 @@ fn temp_load_a_chunk(
 
 
 
-fn height_map_temp(pos: IVec3, block_reg: &Registry<Block>) -> BlockState {
-    if pos.y < -4 {
-        BlockState::new("stone", block_reg).unwrap()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::block::BlockAsset;
+    use crate::registry::block::{test_asset, test_registry};
+    use crate::registry::Registry;
+    use crate::world::block::BlockStateTable;
+    use crate::world::chunk::Chunk;
+
+    #[test]
+    fn joining_restores_the_saved_position() {
+        let saved = vec3(12.5, 70.0, -3.25);
+        assert_eq!(join_position(Some(saved), IVec2::ZERO, |_| panic!("no need to look at the surface")), saved);
+        assert_eq!(join_position(None, IVec2::new(4, -2), |_| 63), vec3(4.5, 64.0, -1.5));
     }
-    else if pos.y < 0 {
-        BlockState::new("dirt", block_reg).unwrap()
+
+    #[test]
+    fn taller_mountains_make_taller_terrain() {
+        let highest = |mountain_scale| {
+            let height_map = create_noise_height_map(7, &GenerationConfig { mountain_scale, ..Default::default() });
+            (0..64).flat_map(|x| (0..64).map(move |z| IVec2::new(x, z) * 32)).map(|pos| height_map.get_height(pos)).max().unwrap()
+        };
+        assert!(highest(700.0) > highest(350.0));
     }
-    else if pos.y == 0 {
-        BlockState::new("grass_block", block_reg).unwrap()
+
+    #[test]
+    fn dimensions_keep_their_own_chunks() {
+        let mut world = World::new();
+        world.add_observer(on_switch_dimension);
+        world.spawn((Player, Transform::default()));
+        let mut overworld = BlockWorld::new(DimensionId::new(dimension::OVERWORLD), 1);
+        overworld.get_chunk_map_mut().add_chunk(Chunk::generated(IVec3::ZERO, ChunkData::single(BlockState::air()))).unwrap();
+        let overworld = world.spawn((overworld, ActiveWorld, Visibility::Visible)).id();
+        let flat = world.spawn((BlockWorld::new(DimensionId::new(dimension::FLAT), 1), Visibility::Hidden)).id();
+
+        world.trigger(SwitchDimensionEvent { dimension: DimensionId::new(dimension::FLAT), pos: Vec3::ONE });
+        world.flush();
+        assert!(world.entity(flat).contains::<ActiveWorld>());
+        assert!(!world.entity(overworld).contains::<ActiveWorld>());
+        assert_eq!(world.get::<BlockWorld>(flat).unwrap().get_chunk_map().len(), 0);
+        assert_eq!(world.get::<BlockWorld>(overworld).unwrap().get_chunk_map().len(), 1);
+
+        // chunks loaded in one don't show up in the other
+        world.get_mut::<BlockWorld>(flat).unwrap().get_chunk_map_mut().add_chunk(Chunk::generated(ivec3(0, 5, 0), ChunkData::single(BlockState::air()))).unwrap();
+        assert!(world.get::<BlockWorld>(overworld).unwrap().get_chunk_map().get_chunk(&ivec3(0, 5, 0)).is_none());
     }
-    else {
-        BlockState::new("air", block_reg).unwrap()
+
+    #[test]
+    fn spawn_is_loaded_once_every_chunk_is_ready() {
+        assert!(!spawn_loaded(0, 0));
+        assert!(!spawn_loaded(26, 27));
+        assert!(spawn_loaded(27, 27));
     }
-}
 
+    #[test]
+    fn teleporting_queues_the_chunks_around_the_destination() {
+        let mut world = World::new();
+        world.add_observer(on_teleport);
+        let player = world.spawn((Player, Transform::default())).id();
+        let mut block_world = BlockWorld::new(DimensionId::new(dimension::OVERWORLD), 1);
+        block_world.get_chunk_map_mut().add_chunk(Chunk::generated(IVec3::ZERO, ChunkData::single(BlockState::air()))).unwrap();
+        let block_world = world.spawn((block_world, ActiveWorld)).id();
+
+        // the chunk that's already loaded isn't queued again
+        world.trigger(TeleportEvent { pos: Vec3::ONE });
+        world.flush();
+        let side = (2 * SPAWN_CHUNK_RADIUS + 1) as usize;
+        let mut blocks = world.get_mut::<BlockWorld>(block_world).unwrap();
+        assert_eq!(blocks.get_pending_generation().count(), side * side * side - 1);
+        assert!(!blocks.is_queued_for_generation(&IVec3::ZERO));
+        // and neither is anything that's already queued
+        assert_eq!(blocks.load_around(Vec3::ONE, SPAWN_CHUNK_RADIUS), 0);
+
+        let far = vec3(10000.0, 100.0, -10000.0);
+        world.trigger(TeleportEvent { pos: far });
+        world.flush();
+        assert_eq!(world.get::<Transform>(player).unwrap().translation, far);
+        let blocks = world.get::<BlockWorld>(block_world).unwrap();
+        assert!(blocks.is_queued_for_generation(&chunk::pos_to_chunk_pos(far.as_ivec3())));
+        assert_eq!(blocks.get_pending_generation().count(), 2 * side * side * side - 1);
+    }
 
+    // a creative player standing out of the way, looking down at the top of a stone block at `STONE`,
+    // and everything place_and_break and pick_block need to run
+    fn input_world(registry: Registry<Block>, held: &str) -> (World, Entity) {
+        let table = BlockStateTable::from_registry(&registry).unwrap();
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let mut data = ChunkData::single(BlockState::air());
+        data.set_block(STONE.x as usize, STONE.y as usize, STONE.z as usize, stone.clone()).unwrap();
+        let mut block_world = BlockWorld::new(DimensionId::new(dimension::OVERWORLD), 1);
+        block_world.get_chunk_map_mut().add_chunk(Chunk::generated(IVec3::ZERO, data)).unwrap();
+
+        let mut world = World::new();
+        world.insert_resource(BlockShapes::new(table.clone(), &registry, &[]));
+        world.insert_resource(MeshDataCache::new(table, vec![]));
+        world.insert_resource(BlockTags::from_registry(&registry));
+        world.init_resource::<BreakSettings>();
+        world.insert_resource(GameMode::Creative);
+        world.init_resource::<Time>();
+        world.init_resource::<ButtonInput<MouseButton>>();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        let block_order = registry.iter().map(|(id, _)| id.to_string()).filter(|id| id != "air").collect::<Vec<_>>();
+        let index = block_order.iter().position(|id| id == held).unwrap();
+        world.insert_resource(RegistryHandle::new(registry));
+        world.spawn((block_world, ActiveWorld));
+        let player = world.spawn((
+            Player,
+            Transform::from_xyz(20.5, 10.0, 20.5),
+            LookAtData { look_pos: Some(STONE), look_block: Some(stone), face: Some(Direction::Up), ..default() },
+            BlockPicker { block_order, index, picked_state: None },
+            PlayerCollider::default(),
+            HeldTool::default(),
+            Inventory::default(),
+            BreakProgress::default(),
+        )).id();
+        (world, player)
+    }
 
+    const STONE: IVec3 = ivec3(5, 5, 5);
 
+    // presses and releases a mouse button over two frames
+    fn click(world: &mut World, button: MouseButton, alt: bool) {
+        use bevy::ecs::system::RunSystemOnce;
 
-fn temp_gen_function(chunk_pos: IVec3, block_reg: &Registry<Block>) -> ChunkData {
-    let mut palette = vec![
-        PaletteEntry::new(BlockState::new("air", block_reg).unwrap()),
-        PaletteEntry::new(BlockState::new("stone", block_reg).unwrap()),
-        PaletteEntry::new(BlockState::new("dirt", block_reg).unwrap()),
-        PaletteEntry::new(BlockState::new("grass_block", block_reg).unwrap()),
-    ];
-    
-    let mut vec = Vec::with_capacity(ChunkData::BLOCKS_PER_CHUNK);
-    
-    // Data is stored Z -> X -> Y, so we iterate over all z first then all x then all y.
-    for y in 0..ChunkData::CHUNK_SIZE {
-        for x in 0..ChunkData::CHUNK_SIZE {
-            for z in 0..ChunkData::CHUNK_SIZE {
-                let block_pos = chunk::chunk_pos_to_world_pos(chunk_pos) + ivec3(x as i32, y as i32, z as i32);
-
-                // all of this is temporary lol
-                let state = height_map_temp(block_pos, block_reg);
-                let id = match state.get_id() {
-                    "air" => 0,
-                    "stone" => 1,
-                    "dirt" => 2,
-                    "grass_block" => 3,
-                    _ => unreachable!(),
-                };
-
-
-
-                palette[id].increment_ref_count();
-
-                // if block_pos.y > 0 && id == 2 {
-                //     println!("Why is this dirt? {}, local: {}", block_pos, ivec3(x as i32, y as i32, z as i32));
-                // }
-                
-                vec.push(id as u8);
+        if alt {
+            world.resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::AltLeft);
+        }
+        world.resource_mut::<ButtonInput<MouseButton>>().press(button);
+        for frame in 0..2 {
+            if frame == 1 {
+                let mut mouse = world.resource_mut::<ButtonInput<MouseButton>>();
+                mouse.clear();
+                mouse.release(button);
             }
+            let placed: Result<(), BevyError> = world.run_system_once(place_and_break).unwrap();
+            placed.unwrap();
+            world.run_system_once(pick_block).unwrap();
         }
+        world.resource_mut::<ButtonInput<MouseButton>>().clear();
+        world.resource_mut::<ButtonInput<KeyCode>>().reset_all();
     }
 
+    fn block_at(world: &mut World, pos: IVec3) -> String {
+        world.query::<&BlockWorld>().single(world).unwrap().get_block(&pos).unwrap().get_id().to_string()
+    }
 
-    ChunkData::with_data(vec, palette)
-
-}
-
+    #[test]
+    fn alt_right_click_replaces_the_targeted_block() {
+        let (mut world, _) = input_world(test_registry(&["stone", "dirt"]), "dirt");
+        click(&mut world, MouseButton::Right, true);
+        assert_eq!(block_at(&mut world, STONE), "dirt");
+        assert_eq!(block_at(&mut world, STONE + IVec3::Y), "air");
+
+        // without alt it goes on top like normal
+        let (mut world, _) = input_world(test_registry(&["stone", "dirt"]), "dirt");
+        click(&mut world, MouseButton::Right, false);
+        assert_eq!(block_at(&mut world, STONE), "stone");
+        assert_eq!(block_at(&mut world, STONE + IVec3::Y), "dirt");
+    }
 
+    #[test]
+    fn middle_click_picks_the_targeted_block() {
+        let (mut world, player) = input_world(test_registry(&["stone", "dirt"]), "dirt");
+        click(&mut world, MouseButton::Middle, false);
+        let picker = world.get::<BlockPicker>(player).unwrap();
+        assert_eq!(picker.get_selected_id(), "stone");
+        assert_eq!(picker.picked_state.as_ref().map(|s| s.get_id()), Some("stone"));
+        // and places what was picked
+        click(&mut world, MouseButton::Right, false);
+        assert_eq!(block_at(&mut world, STONE + IVec3::Y), "stone");
+
+        // blocks that aren't in the picker can't be picked
+        let (mut world, player) = input_world(test_registry(&["stone", "dirt"]), "dirt");
+        let mut picker = world.get_mut::<BlockPicker>(player).unwrap();
+        picker.block_order.retain(|id| id != "stone");
+        picker.index = 0;
+        click(&mut world, MouseButton::Middle, false);
+        let picker = world.get::<BlockPicker>(player).unwrap();
+        assert_eq!(picker.get_selected_id(), "dirt");
+        assert!(picker.picked_state.is_none());
+    }
 
-fn noise_gen_function(chunk_pos: IVec3, block_reg: &Registry<Block>, height_map: Arc<dyn HeightMapProvider>) -> ChunkData {
-    let _span = info_span!("noise_gen_function");
-    let mut palette = vec![
-        PaletteEntry::new(BlockState::new("air", block_reg).unwrap()),
-        PaletteEntry::new(BlockState::new("stone", block_reg).unwrap()),
-        PaletteEntry::new(BlockState::new("dirt", block_reg).unwrap()),
-        PaletteEntry::new(BlockState::new("grass_block", block_reg).unwrap()),
-        PaletteEntry::new(BlockState::new("oak_planks", block_reg).unwrap()),
-    ];
+    #[test]
+    fn placing_goes_into_replaceable_blocks_and_next_to_the_rest() {
+        use bevy::ecs::system::RunSystemOnce;
+
+        let mut registry = Registry::<Block>::new("block");
+        registry.register(Block::from_asset(&test_asset("stone"))).unwrap();
+        registry.register(Block::from_asset(&test_asset("dirt"))).unwrap();
+        registry.register(Block::from_asset(&BlockAsset { tags: vec![String::from(BlockTags::REPLACEABLE)], ..test_asset("tall_grass") })).unwrap();
+        registry.freeze();
+        let (mut world, player) = input_world(registry, "dirt");
+
+        // next to stone
+        click(&mut world, MouseButton::Right, false);
+        assert_eq!(block_at(&mut world, STONE), "stone");
+        assert_eq!(block_at(&mut world, STONE + IVec3::Y), "dirt");
+
+        // tall grass on the side of the stone, looked at from above
+        let grass = STONE + IVec3::X;
+        world.run_system_once(move |mut commands: Commands, mut q_world: Query<&mut BlockWorld>, registry: Res<RegistryHandle<Block>>| {
+            let state = BlockState::new("tall_grass", &registry).unwrap();
+            q_world.single_mut().unwrap().set_block(&mut commands, &grass, state, BlockChangeSource::Programmatic).unwrap();
+        }).unwrap();
+        world.get_mut::<LookAtData>(player).unwrap().look_pos = Some(grass);
+        click(&mut world, MouseButton::Right, false);
+        assert_eq!(block_at(&mut world, grass), "dirt");
+        assert_eq!(block_at(&mut world, grass + IVec3::Y), "air");
+    }
 
-    let heights = height_map.get_chunk(ivec2(chunk_pos.x, chunk_pos.z));
+    #[test]
+    fn facings_without_a_model_place_the_default_state() {
+        use crate::asset::block::BlockStateAsset;
+        use crate::render::block::test_cube_model;
+        use std::collections::BTreeMap;
+
+        let facing = |value: &str| BTreeMap::from([(String::from(BlockPicker::FACING_PROPERTY), String::from(value))]);
+        let mut registry = Registry::<Block>::new("block");
+        registry.register(Block::from_asset(&test_asset("stone"))).unwrap();
+        registry.register(Block::from_asset(&BlockAsset {
+            states: vec![BlockStateAsset { name: String::from(BlockPicker::FACING_PROPERTY), values: ["north", "east", "south", "west"].map(String::from).to_vec() }],
+            default_state: facing("north"),
+            ..test_asset("furnace")
+        })).unwrap();
+        registry.freeze();
+        // only north (the default) and east have a model
+        let table = BlockStateTable::from_registry(&registry).unwrap();
+        let mut models = vec![None; table.len()];
+        for value in ["north", "east"] {
+            let state = BlockState::with_state("furnace", facing(value), &registry).unwrap();
+            models[table.get_id(&state).unwrap().get_index()] = Some(test_cube_model());
+        }
+        let (mut world, player) = input_world(registry, "furnace");
+        world.insert_resource(MeshDataCache::new(table, models));
+
+        // placed facing each way in turn, replacing the last one each time
+        let mut placed = Vec::new();
+        for quarter_turns in 0..4 {
+            world.get_mut::<Transform>(player).unwrap().rotation = Quat::from_rotation_y(quarter_turns as f32 * std::f32::consts::FRAC_PI_2);
+            click(&mut world, MouseButton::Right, true);
+            let state = world.query::<&BlockWorld>().single(&world).unwrap().get_block(&STONE).unwrap();
+            assert_eq!(state.get_id(), "furnace");
+            placed.push(state.get_state()[BlockPicker::FACING_PROPERTY].clone());
+        }
+        placed.sort();
+        assert_eq!(placed, ["east", "north", "north", "north"]);
+    }
 
+    #[test]
+    fn blocks_are_not_placed_inside_the_player() {
+        let (mut world, player) = input_world(test_registry(&["stone", "dirt"]), "dirt");
+        let place = |world: &mut World, feet: Vec3, face: Direction| {
+            world.get_mut::<Transform>(player).unwrap().translation = feet;
+            world.get_mut::<LookAtData>(player).unwrap().face = Some(face);
+            click(world, MouseButton::Right, false);
+        };
 
-    let mut vec = Vec::with_capacity(ChunkData::BLOCKS_PER_CHUNK);
-    // Data is stored Z -> X -> Y, so we iterate over all z first then all x then all y.
-    for y in 0..ChunkData::CHUNK_SIZE {
-        for x in 0..ChunkData::CHUNK_SIZE {
-            for z in 0..ChunkData::CHUNK_SIZE {
-                let block_pos = chunk::chunk_pos_to_world_pos(chunk_pos) + ivec3(x as i32, y as i32, z as i32);
-                let height = heights.get(ivec2(x as i32, z as i32));
-                let diff = block_pos.y - height;
-                let sea_level = 0;
-                let id = if diff > 0 && block_pos.y == sea_level { 4 } else {
-                    match diff {
-                        i32::MIN..=-5 => 1,
-                        -4..=-1 => 2,
-                        0 => 3,
-                        _ => 0
-                    }
-                };
-                palette[id].increment_ref_count();
-                vec.push(id as u8);
-            }
-        }
+        // standing on the stone, at the feet
+        place(&mut world, vec3(5.5, 6.0, 5.5), Direction::Up);
+        assert_eq!(block_at(&mut world, STONE + IVec3::Y), "air");
+        // right under it, at the head
+        place(&mut world, vec3(5.5, 3.0, 5.5), Direction::Down);
+        assert_eq!(block_at(&mut world, STONE - IVec3::Y), "air");
+        // next to the player is fine
+        let side = STONE.offset(Direction::East);
+        place(&mut world, vec3(5.5, 3.0, 5.5), Direction::East);
+        assert_eq!(block_at(&mut world, side), "dirt");
     }
-    ChunkData::with_data(vec, palette)
-}
\ No newline at end of file
+}
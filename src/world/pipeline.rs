@@ -0,0 +1,719 @@
+use crate::registry::block::Block;
+use crate::registry::Registry;
+use crate::world::block::BlockState;
+use crate::world::chunk;
+use crate::world::chunk::{ChunkData, PaletteEntry};
+use crate::world::generation::{chunk_rng, chunk_seed, HeightMapGroup, HeightMapProvider, SuperflatPreset, SEA_LEVEL};
+use bevy::prelude::{ivec2, ivec3, IVec3, Vec3};
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+
+/// One step of generating a chunk, e.g. the base terrain, caves or decorations.
+/// Stages run in order on the same [`ChunkBuilder`], so every stage sees what the ones before it placed.
+pub trait GenerationStage: Send + Sync {
+    fn generate(&self, builder: &mut ChunkBuilder);
+
+    /// Which pass this stage belongs to. Terrain unless it says otherwise.
+    fn pass(&self) -> GenerationPass {
+        GenerationPass::Terrain
+    }
+
+    /// Blocks this stage places in most chunks. They go in the [`BasePalette`] every chunk starts with,
+    /// so they're only looked up in the registry once.
+    fn palette_blocks(&self) -> Vec<&str> {
+        vec![]
+    }
+}
+
+/// The passes generation goes through. What each pass places is handed to the chunk as its own step, moving it through
+/// [`ChunkGenerationStatus`](crate::world::chunk::ChunkGenerationStatus) as each one is applied.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GenerationPass {
+    Terrain,
+    Decoration,
+}
+
+/// An ordered list of [`GenerationStage`]s. Backed by an arc, so it is cheap to clone into generation tasks.
+#[derive(Clone)]
+pub struct GenerationPipeline {
+    stages: Arc<Vec<Box<dyn GenerationStage>>>,
+    /// Built the first time a chunk is generated, since the block registry isn't around before that.
+    palette: Arc<OnceLock<BasePalette>>,
+}
+impl GenerationPipeline {
+    /// Decoration stages always run after every terrain stage, otherwise stages keep the order they're given in.
+    pub fn new(mut stages: Vec<Box<dyn GenerationStage>>) -> Self {
+        stages.sort_by_key(|stage| stage.pass());
+        Self {
+            stages: Arc::new(stages),
+            palette: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Default noise terrain: stone and planks up to the height map, caves, a dirt and grass surface, then loose rocks on the grass.
+    pub fn noise(height_map: Arc<dyn HeightMapProvider>) -> Self {
+        Self::new(vec![
+            Box::new(HeightMapStage(height_map)),
+            Box::new(BaseTerrainStage),
+            Box::new(CaveStage { threshold: 0.7 }),
+            Box::new(SurfaceStage),
+            Box::new(DecorationStage { block: String::from("stone"), chance: 1.0 / 256.0 }),
+        ])
+    }
+
+    pub fn superflat(preset: Arc<SuperflatPreset>) -> Self {
+        Self::new(vec![Box::new(SuperflatStage(preset))])
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Gets the palette every chunk starts with, building it if this is the first time.
+    /// The registry is frozen before any generation happens, so the palette never goes out of date.
+    pub fn get_palette(&self, block_reg: &Registry<Block>) -> &BasePalette {
+        self.palette.get_or_init(|| {
+            let blocks = self.stages.iter().flat_map(|s| s.palette_blocks()).collect::<Vec<_>>();
+            BasePalette::new(&blocks, block_reg)
+        })
+    }
+
+    /// Runs every stage in order on an empty chunk.
+    pub fn generate(&self, chunk_pos: IVec3, seed: u64, block_reg: &Registry<Block>) -> GeneratedChunk {
+        let mut builder = ChunkBuilder::with_palette(chunk_pos, seed, block_reg, self.get_palette(block_reg));
+        for stage in self.stages.iter() {
+            builder.pass = stage.pass();
+            stage.generate(&mut builder);
+        }
+        builder.build()
+    }
+}
+
+/// What generating a chunk makes, split by [`GenerationPass`] so the chunk can take each pass as its own step.
+#[derive(Debug, Clone)]
+pub struct GeneratedChunk {
+    pub terrain: ChunkData,
+    /// Blocks the decoration pass placed over the terrain, by chunk local position. Sorted in storage order.
+    pub decorations: Vec<(IVec3, BlockState)>,
+}
+impl GeneratedChunk {
+    /// Places the decorations straight into the terrain, for when there's no chunk to step through its statuses.
+    pub fn into_data(self) -> ChunkData {
+        let mut data = self.terrain;
+        for (pos, state) in self.decorations {
+            // positions come from the builder, so they're always in bounds
+            let _ = data.set_block(pos.x as usize, pos.y as usize, pos.z as usize, state);
+        }
+        data
+    }
+}
+/// Data that's already complete, like a loaded save, has nothing left to decorate.
+impl From<ChunkData> for GeneratedChunk {
+    fn from(terrain: ChunkData) -> Self {
+        Self {
+            terrain,
+            decorations: vec![],
+        }
+    }
+}
+
+/// Block states a chunk's palette starts out with: air, then the blocks from [`GenerationStage::palette_blocks`], in order.
+#[derive(Debug, Clone)]
+pub struct BasePalette {
+    states: Vec<BlockState>,
+    /// Block id to palette id, for blocks placed in their default state.
+    ids: HashMap<String, u16>,
+}
+impl BasePalette {
+    /// Creates a palette of the default states of `blocks`. Duplicates and blocks that aren't registered are left out.
+    pub fn new(blocks: &[&str], block_reg: &Registry<Block>) -> Self {
+        let mut palette = Self {
+            states: vec![BlockState::air()],
+            ids: HashMap::new(),
+        };
+        palette.ids.insert(String::from(BlockState::air().get_id()), ChunkBuilder::AIR);
+        for id in blocks {
+            if palette.ids.contains_key(*id) {
+                continue;
+            }
+            let Ok(state) = BlockState::new(id, block_reg) else {
+                continue;
+            };
+            palette.ids.insert(String::from(*id), palette.states.len() as u16);
+            palette.states.push(state);
+        }
+        palette
+    }
+
+    pub fn get_states(&self) -> &[BlockState] {
+        &self.states
+    }
+
+    /// Gets the palette id of a block's default state, if it's in this palette.
+    pub fn get_id(&self, block: &str) -> Option<u16> {
+        self.ids.get(block).copied()
+    }
+}
+
+/// A chunk in the middle of being generated. Starts out as all air.
+/// Blocks are stored as ids into a palette, like [`ChunkData`], so stages can look up a block once and place it many times.
+pub struct ChunkBuilder<'a> {
+    chunk_pos: IVec3,
+    seed: u64,
+    block_reg: &'a Registry<Block>,
+    base_palette: Option<&'a BasePalette>,
+    palette: Vec<BlockState>,
+    /// Indexed like [`ChunkData`], see [`chunk::xyz_to_index`].
+    blocks: Vec<u16>,
+    /// Set by [`HeightMapStage`], for later stages to build on.
+    heights: Option<HeightMapGroup>,
+    /// The pass of the stage that's running. Blocks set in the decoration pass go in `decorations` instead of `blocks`.
+    pass: GenerationPass,
+    /// Palette ids placed by the decoration pass, by chunk local position.
+    decorations: HashMap<IVec3, u16>,
+}
+impl<'a> ChunkBuilder<'a> {
+    /// Palette id of air, which every chunk starts filled with.
+    pub const AIR: u16 = 0;
+
+    pub fn new(chunk_pos: IVec3, seed: u64, block_reg: &'a Registry<Block>) -> Self {
+        Self {
+            chunk_pos,
+            seed,
+            block_reg,
+            base_palette: None,
+            palette: vec![BlockState::air()],
+            blocks: vec![Self::AIR; ChunkData::BLOCKS_PER_CHUNK],
+            heights: None,
+            pass: GenerationPass::Terrain,
+            decorations: HashMap::new(),
+        }
+    }
+
+    /// Creates a builder whose palette starts as a copy of `base_palette`.
+    pub fn with_palette(chunk_pos: IVec3, seed: u64, block_reg: &'a Registry<Block>, base_palette: &'a BasePalette) -> Self {
+        Self {
+            base_palette: Some(base_palette),
+            palette: base_palette.get_states().to_vec(),
+            ..Self::new(chunk_pos, seed, block_reg)
+        }
+    }
+
+    pub fn get_chunk_pos(&self) -> IVec3 {
+        self.chunk_pos
+    }
+
+    /// World position of this chunk's (0, 0, 0) block.
+    pub fn get_origin(&self) -> IVec3 {
+        chunk::chunk_pos_to_world_pos(self.chunk_pos)
+    }
+
+    pub fn get_block_reg(&self) -> &Registry<Block> {
+        self.block_reg
+    }
+
+    /// The world seed. Stages that need noise lining up across chunks should sample it from world positions with this,
+    /// rather than use [`ChunkBuilder::rng`].
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Creates this chunk's rng, see [`chunk_rng`]. Stages that need randomness should make their own with this,
+    /// so a stage's output doesn't depend on how much randomness the stages before it used.
+    pub fn rng(&self) -> impl Rng + use<> {
+        chunk_rng(self.seed, self.chunk_pos)
+    }
+
+    /// Gets the palette id of a state, adding it to the palette if needed.
+    pub fn state_id(&mut self, state: BlockState) -> u16 {
+        match self.palette.iter().position(|s| *s == state) {
+            Some(i) => i as u16,
+            None => {
+                self.palette.push(state);
+                (self.palette.len() - 1) as u16
+            }
+        }
+    }
+
+    /// Gets the palette id of a block's default state. Blocks that aren't registered are replaced with air.
+    pub fn block_id(&mut self, id: &str) -> u16 {
+        // base palette entries are never moved, so their ids hold for every chunk
+        if let Some(palette_id) = self.base_palette.and_then(|p| p.get_id(id)) {
+            return palette_id;
+        }
+        match BlockState::new(id, self.block_reg) {
+            Ok(state) => self.state_id(state),
+            Err(_) => Self::AIR,
+        }
+    }
+
+    /// Gets the palette id at a chunk local position, including anything decorations placed.
+    pub fn get(&self, local_pos: IVec3) -> u16 {
+        self.decorations.get(&local_pos).copied().unwrap_or(self.blocks[Self::index(local_pos)])
+    }
+
+    pub fn get_state(&self, local_pos: IVec3) -> &BlockState {
+        &self.palette[self.get(local_pos) as usize]
+    }
+
+    /// Sets a chunk local position to a palette id from [`ChunkBuilder::state_id`] or [`ChunkBuilder::block_id`].
+    pub fn set(&mut self, local_pos: IVec3, id: u16) {
+        match self.pass {
+            GenerationPass::Terrain => self.blocks[Self::index(local_pos)] = id,
+            GenerationPass::Decoration => {
+                self.decorations.insert(local_pos, id);
+            }
+        }
+    }
+
+    pub fn get_heights(&self) -> Option<&HeightMapGroup> {
+        self.heights.as_ref()
+    }
+
+    pub fn set_heights(&mut self, heights: HeightMapGroup) {
+        self.heights = Some(heights);
+    }
+
+    fn index(local_pos: IVec3) -> usize {
+        chunk::xyz_to_index(local_pos.x as usize, local_pos.y as usize, local_pos.z as usize)
+    }
+
+    /// Turns the builder into chunk data, with what the decoration pass placed kept apart.
+    /// Palette entries no block ended up using are kept, with a refcount of 0.
+    pub fn build(self) -> GeneratedChunk {
+        let mut decorations = self.decorations.into_iter().collect::<Vec<_>>();
+        decorations.sort_unstable_by_key(|(pos, _)| Self::index(*pos));
+        let decorations = decorations.into_iter()
+            .map(|(pos, id)| (pos, self.palette[id as usize].clone()))
+            .collect();
+        let mut palette = self.palette.into_iter().map(PaletteEntry::new).collect::<Vec<_>>();
+        let double_bytes = palette.len() > 256;
+        let mut data = Vec::with_capacity(if double_bytes { ChunkData::DOUBLE_BLOCKS_PER_CHUNK } else { ChunkData::BLOCKS_PER_CHUNK });
+        for id in self.blocks {
+            palette[id as usize].increment_ref_count();
+            if double_bytes {
+                data.extend_from_slice(&id.to_le_bytes());
+            } else {
+                data.push(id as u8);
+            }
+        }
+        GeneratedChunk {
+            terrain: ChunkData::with_data(data, palette),
+            decorations,
+        }
+    }
+}
+
+/// Samples a height map for the chunk's column. Places nothing by itself.
+pub struct HeightMapStage(pub Arc<dyn HeightMapProvider>);
+impl GenerationStage for HeightMapStage {
+    fn generate(&self, builder: &mut ChunkBuilder) {
+        let pos = builder.get_chunk_pos();
+        builder.set_heights(self.0.get_chunk(ivec2(pos.x, pos.z)));
+    }
+}
+
+/// Fills everything up to the height map with stone, and puts planks on sea level wherever the ground is lower.
+/// Does nothing without a [`HeightMapStage`] before it.
+pub struct BaseTerrainStage;
+impl GenerationStage for BaseTerrainStage {
+    fn palette_blocks(&self) -> Vec<&str> {
+        vec!["stone", "oak_planks"]
+    }
+
+    fn generate(&self, builder: &mut ChunkBuilder) {
+        let Some(heights) = builder.get_heights().cloned() else {
+            return;
+        };
+        let (stone, planks) = (builder.block_id("stone"), builder.block_id("oak_planks"));
+        let origin = builder.get_origin();
+        for_each_local(|local| {
+            let y = origin.y + local.y;
+            let height = heights.get(ivec2(local.x, local.z));
+            if y <= height {
+                builder.set(local, stone);
+            } else if y == SEA_LEVEL {
+                builder.set(local, planks);
+            }
+        });
+    }
+}
+
+/// Turns the top of the terrain into grass with a few blocks of dirt under it.
+/// Only replaces stone, so earlier stages can leave other blocks at the surface.
+pub struct SurfaceStage;
+impl SurfaceStage {
+    /// How many blocks of dirt go under the grass.
+    pub const DIRT_DEPTH: i32 = 4;
+}
+impl GenerationStage for SurfaceStage {
+    fn palette_blocks(&self) -> Vec<&str> {
+        vec!["stone", "dirt", "grass_block"]
+    }
+
+    fn generate(&self, builder: &mut ChunkBuilder) {
+        let Some(heights) = builder.get_heights().cloned() else {
+            return;
+        };
+        let (stone, dirt, grass) = (builder.block_id("stone"), builder.block_id("dirt"), builder.block_id("grass_block"));
+        let origin = builder.get_origin();
+        for_each_local(|local| {
+            if builder.get(local) != stone {
+                return;
+            }
+            let diff = origin.y + local.y - heights.get(ivec2(local.x, local.z));
+            match diff {
+                0 => builder.set(local, grass),
+                d if d >= -Self::DIRT_DEPTH => builder.set(local, dirt),
+                _ => {}
+            }
+        });
+    }
+}
+
+/// Carves caves out of the stone under the surface, wherever 3d noise goes over `threshold`.
+/// The noise comes from world positions rather than the chunk's rng, so caves carry on across chunk borders.
+/// Leaves [`CaveStage::ROOF`] blocks under the height map alone, so the surface doesn't get holes in it.
+/// Does nothing without a [`HeightMapStage`] before it.
+pub struct CaveStage {
+    /// Between 0 and 1, higher means fewer caves.
+    pub threshold: f32,
+}
+impl CaveStage {
+    /// How many blocks under the height map are never carved.
+    pub const ROOF: i32 = 8;
+    /// Blocks between noise lattice points. Bigger means wider caves.
+    const SCALE: i32 = 16;
+}
+impl GenerationStage for CaveStage {
+    fn palette_blocks(&self) -> Vec<&str> {
+        vec!["stone"]
+    }
+
+    fn generate(&self, builder: &mut ChunkBuilder) {
+        let Some(heights) = builder.get_heights().cloned() else {
+            return;
+        };
+        let stone = builder.block_id("stone");
+        let (seed, origin) = (builder.get_seed(), builder.get_origin());
+        for_each_local(|local| {
+            let pos = origin + local;
+            if builder.get(local) != stone || pos.y > heights.get(ivec2(local.x, local.z)) - Self::ROOF {
+                return;
+            }
+            if value_noise(seed, pos, Self::SCALE) > self.threshold {
+                builder.set(local, ChunkBuilder::AIR);
+            }
+        });
+    }
+}
+
+// smooth noise between 0 and 1 at a world position, blended from random values on a lattice `scale` blocks apart
+fn value_noise(seed: u64, pos: IVec3, scale: i32) -> f32 {
+    let cell = pos.div_euclid(IVec3::splat(scale));
+    let t = (pos - cell * scale).as_vec3() / scale as f32;
+    let t = t * t * (Vec3::splat(3.0) - 2.0 * t);
+    // top 24 bits of the hash, so it fits in an f32 exactly
+    let corner = |x: i32, y: i32, z: i32| (chunk_seed(seed, cell + ivec3(x, y, z)) >> 40) as f32 / (1 << 24) as f32;
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let face = |y: i32| lerp(
+        lerp(corner(0, y, 0), corner(1, y, 0), t.x),
+        lerp(corner(0, y, 1), corner(1, y, 1), t.x),
+        t.z,
+    );
+    lerp(face(0), face(1), t.y)
+}
+
+/// Scatters a block on top of grass, e.g. loose rocks. Each grass column gets one with a chance of `chance`.
+/// Only places inside the chunk, so a surface on the top layer of a chunk never gets one and chunks above the terrain stay empty.
+/// Does nothing without a [`HeightMapStage`] before it.
+pub struct DecorationStage {
+    pub block: String,
+    pub chance: f64,
+}
+impl GenerationStage for DecorationStage {
+    fn pass(&self) -> GenerationPass {
+        GenerationPass::Decoration
+    }
+
+    fn palette_blocks(&self) -> Vec<&str> {
+        vec![self.block.as_str()]
+    }
+
+    fn generate(&self, builder: &mut ChunkBuilder) {
+        let Some(heights) = builder.get_heights().cloned() else {
+            return;
+        };
+        let (grass, block) = (builder.block_id("grass_block"), builder.block_id(&self.block));
+        let origin = builder.get_origin();
+        let mut rng = builder.rng();
+        for x in 0..ChunkData::CHUNK_SIZE as i32 {
+            for z in 0..ChunkData::CHUNK_SIZE as i32 {
+                let y = heights.get(ivec2(x, z)) - origin.y;
+                if !(0..ChunkData::CHUNK_SIZE as i32 - 1).contains(&y) {
+                    continue;
+                }
+                let (surface, above) = (ivec3(x, y, z), ivec3(x, y + 1, z));
+                if builder.get(surface) == grass && builder.get(above) == ChunkBuilder::AIR && rng.random_bool(self.chance) {
+                    builder.set(above, block);
+                }
+            }
+        }
+    }
+}
+
+/// Places the layers of a superflat preset. Blocks that aren't registered are replaced with air.
+pub struct SuperflatStage(pub Arc<SuperflatPreset>);
+impl GenerationStage for SuperflatStage {
+    fn generate(&self, builder: &mut ChunkBuilder) {
+        let origin = builder.get_origin();
+        for y in 0..ChunkData::CHUNK_SIZE as i32 {
+            let Some(block) = self.0.get_layer_at(origin.y + y) else {
+                continue;
+            };
+            let id = builder.block_id(block);
+            for x in 0..ChunkData::CHUNK_SIZE as i32 {
+                for z in 0..ChunkData::CHUNK_SIZE as i32 {
+                    builder.set(IVec3::new(x, y, z), id);
+                }
+            }
+        }
+    }
+}
+
+// calls f with every chunk local position, in storage order
+fn for_each_local(mut f: impl FnMut(IVec3)) {
+    for y in 0..ChunkData::CHUNK_SIZE as i32 {
+        for x in 0..ChunkData::CHUNK_SIZE as i32 {
+            for z in 0..ChunkData::CHUNK_SIZE as i32 {
+                f(IVec3::new(x, y, z));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::block::test_registry;
+    use crate::world::generation::FlatHeightMap;
+
+    struct FillStage;
+    impl GenerationStage for FillStage {
+        fn generate(&self, builder: &mut ChunkBuilder) {
+            let stone = builder.block_id("stone");
+            for_each_local(|local| builder.set(local, stone));
+        }
+    }
+
+    // carves the bottom half out of whatever stone is there
+    struct CarveStage;
+    impl GenerationStage for CarveStage {
+        fn generate(&self, builder: &mut ChunkBuilder) {
+            let stone = builder.block_id("stone");
+            for_each_local(|local| {
+                if local.y < 16 && builder.get(local) == stone {
+                    builder.set(local, ChunkBuilder::AIR);
+                }
+            });
+        }
+    }
+
+    fn block_at(data: &ChunkData, pos: IVec3) -> BlockState {
+        data.get_block(pos.x as usize, pos.y as usize, pos.z as usize).unwrap()
+    }
+
+    #[test]
+    fn later_stages_build_on_earlier_ones() {
+        let registry = test_registry(&["stone"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+
+        let carved = GenerationPipeline::new(vec![Box::new(FillStage), Box::new(CarveStage)]).generate(IVec3::ZERO, 0, &registry).into_data();
+        assert!(block_at(&carved, ivec3(3, 15, 9)).is_air());
+        assert_eq!(block_at(&carved, ivec3(3, 16, 9)), stone);
+
+        // carving first finds nothing to carve, then the fill covers everything
+        let filled = GenerationPipeline::new(vec![Box::new(CarveStage), Box::new(FillStage)]).generate(IVec3::ZERO, 0, &registry).into_data();
+        assert_eq!(block_at(&filled, ivec3(3, 15, 9)), stone);
+    }
+
+    #[test]
+    fn caves_stay_under_the_roof() {
+        let registry = test_registry(&["stone", "oak_planks"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let surface = 200;
+        let pipeline = GenerationPipeline::new(vec![
+            Box::new(HeightMapStage(Arc::new(FlatHeightMap::new(surface)))),
+            Box::new(BaseTerrainStage),
+            Box::new(CaveStage { threshold: 0.5 }),
+        ]);
+
+        // chunk 5 is 160 to 191, all of it deep enough to carve
+        let deep = pipeline.generate(ivec3(0, 5, 0), 7, &registry).into_data();
+        let mut air = 0;
+        for_each_local(|local| air += block_at(&deep, local).is_air() as usize);
+        assert!(air > 0 && air < ChunkData::BLOCKS_PER_CHUNK, "{air} blocks carved");
+        // the same seed carves the same caves
+        let again = pipeline.generate(ivec3(0, 5, 0), 7, &registry).into_data();
+        for_each_local(|local| assert_eq!(block_at(&deep, local), block_at(&again, local)));
+
+        // chunk 6 is 192 to 223, so it has the whole roof: the blocks above 200 - ROOF, up to the surface
+        let shallow = pipeline.generate(ivec3(0, 6, 0), 7, &registry).into_data();
+        for_each_local(|local| {
+            let y = 192 + local.y;
+            if y > surface - CaveStage::ROOF && y <= surface {
+                assert_eq!(block_at(&shallow, local), stone, "{y}");
+            }
+        });
+    }
+
+    #[test]
+    fn decorations_go_on_grass_inside_the_chunk() {
+        let registry = test_registry(&["stone", "oak_planks", "dirt", "grass_block"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let pipeline = |surface: i32| GenerationPipeline::new(vec![
+            Box::new(HeightMapStage(Arc::new(FlatHeightMap::new(surface)))),
+            Box::new(BaseTerrainStage),
+            Box::new(SurfaceStage),
+            Box::new(DecorationStage { block: String::from("stone"), chance: 1.0 }),
+        ]);
+
+        let decorated = pipeline(10).generate(IVec3::ZERO, 0, &registry).into_data();
+        for_each_local(|local| {
+            let expected = match local.y {
+                11 => Some(&stone),
+                12.. => Some(&BlockState::air()),
+                _ => None,
+            };
+            if let Some(expected) = expected {
+                assert_eq!(&block_at(&decorated, local), expected, "{local}");
+            }
+        });
+
+        // the terrain pass doesn't include them, they're handed over separately
+        let generated = pipeline(10).generate(IVec3::ZERO, 0, &registry);
+        assert!(generated.terrain.get_block(0, 11, 0).unwrap().is_air());
+        assert_eq!(generated.decorations.len(), HeightMapGroup::BLOCKS_PER_GROUP);
+        assert!(generated.decorations.iter().all(|(pos, state)| pos.y == 11 && *state == stone));
+
+        // the top layer of a chunk has nowhere to put one
+        let top = pipeline(31).generate(IVec3::ZERO, 0, &registry).into_data();
+        assert_eq!(block_at(&top, ivec3(0, 31, 0)).get_id(), "grass_block");
+    }
+
+    #[test]
+    fn chunks_come_out_the_same_whatever_order_they_generate_in() {
+        use crate::world::chunk::PackedChunkData;
+        use bevy::asset::ron;
+
+        let registry = test_registry(&["stone", "oak_planks", "dirt", "grass_block"]);
+        let pipeline = GenerationPipeline::new(vec![
+            Box::new(HeightMapStage(Arc::new(FlatHeightMap::new(20)))),
+            Box::new(BaseTerrainStage),
+            Box::new(SurfaceStage),
+            Box::new(CaveStage { threshold: 0.5 }),
+            Box::new(DecorationStage { block: String::from("stone"), chance: 0.3 }),
+        ]);
+        // every block and every decoration, as bytes
+        let generate = |pos: IVec3| {
+            let generated = pipeline.generate(pos, 11, &registry);
+            let decorations = generated.decorations.iter().map(|(pos, state)| (pos.to_array(), state.get_id().to_string())).collect::<Vec<_>>();
+            let packed = PackedChunkData::from(&generated.into_data());
+            (ron::ser::to_string(&packed).unwrap(), decorations)
+        };
+
+        // caves under the surface and decorations on top, in one chunk
+        let target = ivec3(1, 0, -1);
+        let first = generate(target);
+        assert!(!first.1.is_empty());
+        // its neighbors before it, in both orders
+        for order in [[ivec3(0, 0, -1), ivec3(2, 0, -1)], [ivec3(2, 0, -1), ivec3(0, 0, -1)]] {
+            for pos in order {
+                generate(pos);
+            }
+            assert_eq!(generate(target), first);
+        }
+        // and a different chunk really is different
+        assert_ne!(generate(ivec3(1, 0, 0)), first);
+
+        // the rng itself only depends on the seed and position
+        let draw = |seed, pos| chunk_rng(seed, pos).random::<u64>();
+        assert_eq!(draw(11, target), draw(11, target));
+        assert_ne!(draw(11, target), draw(12, target));
+        assert_ne!(draw(11, target), draw(11, target + IVec3::Y));
+    }
+
+    #[test]
+    fn the_base_palette_does_not_change_what_gets_generated() {
+        let registry = test_registry(&["stone", "oak_planks", "dirt", "grass_block"]);
+        let pipeline = GenerationPipeline::new(vec![
+            Box::new(HeightMapStage(Arc::new(FlatHeightMap::new(20)))),
+            Box::new(BaseTerrainStage),
+            Box::new(SurfaceStage),
+            Box::new(CaveStage { threshold: 0.5 }),
+            Box::new(DecorationStage { block: String::from("stone"), chance: 0.3 }),
+        ]);
+
+        // the cached palette is the one the stages ask for, and it's only built once
+        let blocks = pipeline.stages.iter().flat_map(|s| s.palette_blocks()).collect::<Vec<_>>();
+        let fresh = BasePalette::new(&blocks, &registry);
+        let cached = pipeline.get_palette(&registry);
+        assert_eq!(cached.get_states(), fresh.get_states());
+        for id in blocks.iter().chain(&["air", "unregistered"]) {
+            assert_eq!(cached.get_id(id), fresh.get_id(id), "{id}");
+        }
+        assert!(std::ptr::eq(cached, pipeline.get_palette(&registry)));
+
+        // the same stages on a builder that starts from an empty palette
+        let without_palette = |pos: IVec3| {
+            let mut builder = ChunkBuilder::new(pos, 11, &registry);
+            for stage in pipeline.stages.iter() {
+                builder.pass = stage.pass();
+                stage.generate(&mut builder);
+            }
+            builder.build()
+        };
+        for pos in [ivec3(1, 0, -1), ivec3(0, -1, 0)] {
+            let expected = without_palette(pos);
+            let generated = pipeline.generate(pos, 11, &registry);
+            assert_eq!(generated.decorations, expected.decorations);
+            let (generated, expected) = (generated.into_data(), expected.into_data());
+            for_each_local(|local| assert_eq!(block_at(&generated, local), block_at(&expected, local), "{local}"));
+        }
+    }
+
+    #[test]
+    fn superflat_places_exactly_its_layers() {
+        use bevy::asset::ron;
+
+        let registry = test_registry(&["bedrock", "stone", "dirt", "grass_block"]);
+        // read the same way a custom profile would be. Straddles the border between chunk 0 and 1
+        let preset: SuperflatPreset = ron::from_str(r#"(
+            bottom: 28,
+            layers: [
+                (block: "bedrock", thickness: 1),
+                (block: "stone", thickness: 2),
+                (block: "dirt", thickness: 3),
+                (block: "grass_block", thickness: 1),
+            ],
+        )"#).unwrap();
+        assert_eq!(preset.get_surface_height(), 34);
+        let pipeline = GenerationPipeline::superflat(Arc::new(preset));
+
+        let stack = ["bedrock", "stone", "stone", "dirt", "dirt", "dirt", "grass_block"];
+        for chunk_y in -1..=2 {
+            let data = pipeline.generate(ivec3(2, chunk_y, -3), 0, &registry).into_data();
+            // every column is the same
+            for_each_local(|local| {
+                let y = chunk_y * ChunkData::CHUNK_SIZE as i32 + local.y;
+                let expected = usize::try_from(y - 28).ok().and_then(|i| stack.get(i)).copied().unwrap_or("air");
+                assert_eq!(block_at(&data, local).get_id(), expected, "at y {y}");
+            });
+        }
+    }
+}
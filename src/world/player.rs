@@ -1,6 +1,29 @@
-use bevy::prelude::Component;
-use bevy::math::{IVec3, Vec3};
-use crate::world::block::{BlockState, Direction};
+use bevy::prelude::{warn, Component, Resource};
+use bevy::math::{vec3, IVec3, Vec2, Vec3};
+use bevy::math::bounding::Aabb3d;
+use std::collections::HashMap;
+use crate::asset::block::{CollisionBox, ToolType};
+use crate::core::errors::BlockStateError;
+use crate::registry::block::Block;
+use crate::registry::Registry;
+use crate::render::block::MeshDataCache;
+use crate::world::block::{BlockState, BlockWorld, ChunkReader, Direction};
+use crate::world::shape::BlockShapes;
+
+/// Downwards acceleration on the player in survival, in blocks per second squared.
+pub const GRAVITY: f32 = 28.0;
+/// Upwards velocity the player gets when jumping.
+pub const JUMP_VELOCITY: f32 = 9.0;
+/// Movement speed in survival. Creative flies at [`CameraSettings::movement_speed`] instead.
+///
+/// [`CameraSettings::movement_speed`]: crate::world::camera::CameraSettings::movement_speed
+pub const WALK_SPEED: f32 = 4.3;
+/// Movement speed is multiplied by this while sprinting.
+pub const SPRINT_MULTIPLIER: f32 = 1.5;
+/// How long it takes to break a block in survival, per point of hardness.
+pub const BREAK_SECONDS_PER_HARDNESS: f32 = 0.75;
+/// How much longer breaking takes without the tool a block needs.
+pub const WRONG_TOOL_PENALTY: f32 = 3.0;
 
 #[derive(Component, Default)]
 pub struct LookAtData {
@@ -8,10 +31,576 @@ pub struct LookAtData {
     pub look_block: Option<BlockState>,
     pub surface: Option<Vec3>,
     pub face: Option<Direction>,
+    /// How far the targeted block is from the camera.
+    pub distance: Option<f32>,
+    /// Where on the targeted face the player is looking, see [`crate::math::ray::face_uv`].
+    pub face_uv: Option<Vec2>,
+}
+
+/// Marker component for the player entity. The player's transform is at their feet, and the [`MainCamera`] is
+/// a child of it, offset up to their eyes by [`PlayerCollider::eye_height`].
+///
+/// [`MainCamera`]: crate::world::camera::MainCamera
+#[derive(Component, Default)]
+pub struct Player;
+
+/// What the player is allowed to do.
+/// - Creative: flies, breaks blocks instantly and has infinite blocks.
+/// - Survival: walks with gravity, breaks blocks over time based on hardness, and places blocks from their [`Inventory`].
+/// - Spectator: flies around, but can't place or break blocks.
+#[derive(Resource, Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum GameMode {
+    #[default]
+    Creative,
+    Survival,
+    Spectator,
+}
+impl GameMode {
+    /// Whether the player flies freely, or walks around with gravity and collisions.
+    pub fn is_flying(&self) -> bool {
+        !matches!(self, GameMode::Survival)
+    }
+
+    /// How long, in seconds, breaking `block` with `tool` takes. Zero means it breaks instantly.
+    /// The right tool speeds it up, and not being able to harvest the block slows it down.
+    pub fn get_break_duration(&self, block: &Block, tool: Option<&Tool>) -> f32 {
+        if *self != GameMode::Survival {
+            return 0.0;
+        }
+        let base = block.get_hardness() as f32 * BREAK_SECONDS_PER_HARDNESS;
+        if !block.can_harvest(tool) {
+            return base * WRONG_TOOL_PENALTY;
+        }
+        match tool {
+            Some(tool) if block.get_tool() == Some(tool.kind) => base / tool.get_speed(),
+            _ => base,
+        }
+    }
+}
+
+/// How far away the player can target blocks from, in blocks.
+#[derive(Resource, Debug)]
+pub struct ReachSettings {
+    pub creative: f32,
+    pub survival: f32,
+    pub spectator: f32,
+    /// Extra reach for each level of the held tool, so better tools reach further.
+    pub per_tool_level: f32,
+}
+impl Default for ReachSettings {
+    fn default() -> Self {
+        Self {
+            creative: 5.0,
+            survival: 4.5,
+            spectator: 5.0,
+            per_tool_level: 0.25,
+        }
+    }
+}
+
+/// How far the player can target blocks from, given their game mode and what they're holding.
+pub fn effective_reach(mode: GameMode, tool: Option<&Tool>, settings: &ReachSettings) -> f32 {
+    let base = match mode {
+        GameMode::Creative => settings.creative,
+        GameMode::Survival => settings.survival,
+        GameMode::Spectator => settings.spectator,
+    };
+    let bonus = tool.map_or(0.0, |t| t.level as f32 * settings.per_tool_level);
+    base + bonus
+}
+
+/// Blocks the player is carrying. Only used in survival, creative has infinite blocks.
+#[derive(Component, Debug, Default)]
+pub struct Inventory {
+    blocks: HashMap<String, u32>,
+}
+impl Inventory {
+    pub fn add(&mut self, id: &str, count: u32) {
+        *self.blocks.entry(String::from(id)).or_insert(0) += count;
+    }
+
+    /// Takes one of this block out of the inventory. Returns false if there weren't any.
+    pub fn take(&mut self, id: &str) -> bool {
+        match self.blocks.get_mut(id) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn get_count(&self, id: &str) -> u32 {
+        self.blocks.get(id).copied().unwrap_or(0)
+    }
+}
+
+/// A tool the player can hold. Higher levels can harvest more blocks, and break them faster.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Tool {
+    pub kind: ToolType,
+    pub level: u32,
+}
+impl Tool {
+    /// How many times faster this tool breaks blocks that need it.
+    pub fn get_speed(&self) -> f32 {
+        2.0 + self.level as f32 * 2.0
+    }
+}
+
+/// The tool in the player's hand, if any.
+#[derive(Component, Debug, Default)]
+pub struct HeldTool(pub Option<Tool>);
+
+/// How breaking blocks repeats. A click breaks one block, in creative straight away and in survival once it's been held long enough.
+/// What happens after that while the button stays down is up to `auto_repeat`, and the cooldown applies either way,
+/// so fast clicking can't break blocks quicker than it.
+#[derive(Resource, Debug)]
+pub struct BreakSettings {
+    /// Seconds after breaking a block before the next one can be started.
+    pub cooldown: f32,
+    /// Keeps breaking whatever is being looked at while the button is held. When off, the button has to be let go first.
+    pub auto_repeat: bool,
+}
+impl Default for BreakSettings {
+    fn default() -> Self {
+        Self {
+            cooldown: 0.25,
+            auto_repeat: false,
+        }
+    }
+}
+
+/// How far along breaking a block the player is. Progress is only used in survival, the cooldown in every game mode.
+#[derive(Component, Debug, Default)]
+pub struct BreakProgress {
+    pub pos: Option<IVec3>,
+    /// Seconds spent breaking the block at `pos`.
+    pub elapsed: f32,
+    /// Seconds until another block can be broken, see [`BreakSettings::cooldown`].
+    pub cooldown: f32,
+    /// Whether this press of the button already broke a block, and it has to be let go before breaking another.
+    pub waiting_for_release: bool,
+    /// A creative click made during the cooldown, which breaks a block once the cooldown is over.
+    pub queued: bool,
+}
+impl BreakProgress {
+    /// Clears progress on the current block, and any queued click. Doesn't touch the cooldown.
+    pub fn reset(&mut self) {
+        self.pos = None;
+        self.elapsed = 0.0;
+        self.queued = false;
+    }
+
+    /// Counts down the cooldown, and lets the next press break a block once the button is let go.
+    pub fn tick(&mut self, delta: f32, button_held: bool) {
+        self.cooldown = (self.cooldown - delta).max(0.0);
+        if !button_held {
+            self.waiting_for_release = false;
+        }
+    }
+
+    pub fn can_break(&self) -> bool {
+        self.cooldown <= 0.0 && !self.waiting_for_release
+    }
+
+    /// Whether creative breaks a block this frame. Clicks aren't lost to the cooldown, they wait for it instead.
+    pub fn wants_creative_break(&mut self, pressed: bool, held: bool, settings: &BreakSettings) -> bool {
+        if pressed {
+            self.queued = true;
+        }
+        (self.queued || (settings.auto_repeat && held)) && self.can_break()
+    }
+
+    /// Call after breaking a block, to start the cooldown.
+    pub fn finish_break(&mut self, settings: &BreakSettings) {
+        self.reset();
+        self.cooldown = settings.cooldown;
+        self.waiting_for_release = !settings.auto_repeat;
+    }
+}
+
+/// The player's velocity, used for gravity and jumping in survival.
+#[derive(Component, Debug, Default)]
+pub struct PlayerVelocity {
+    pub velocity: Vec3,
+    pub on_ground: bool,
+    /// Sprinting moves faster, and widens the fov.
+    pub sprinting: bool,
 }
 
 #[derive(Component, Default)]
 pub struct BlockPicker {
     pub block_order: Vec<String>,
     pub index: usize,
-}
\ No newline at end of file
+    /// A full state copied with pick block, e.g. a specific facing. Placed instead of the selected block's
+    /// default state until the selection changes.
+    pub picked_state: Option<BlockState>,
+}
+impl BlockPicker {
+    pub fn get_selected_id(&self) -> &str {
+        &self.block_order[self.index]
+    }
+
+    /// Selects the next block, or the previous one if `forward` is false, wrapping around at the ends.
+    /// Loses whatever state was picked.
+    pub fn cycle(&mut self, forward: bool) {
+        if self.block_order.is_empty() {
+            return;
+        }
+        self.picked_state = None;
+        let len = self.block_order.len();
+        self.index = if forward { (self.index + 1) % len } else { (self.index + len - 1) % len };
+    }
+
+    /// Gets the state to place: the picked state if there is one, otherwise the selected block's default state.
+    pub fn get_selected_state(&self, block_reg: &Registry<Block>) -> Result<BlockState, BlockStateError> {
+        match &self.picked_state {
+            Some(state) => Ok(state.clone()),
+            None => BlockState::new(self.get_selected_id(), block_reg),
+        }
+    }
+
+    /// Property that directional blocks turn to face the player with when placed.
+    pub const FACING_PROPERTY: &'static str = "facing";
+
+    /// Gets the state to place when the player is facing `facing`. Like [`BlockPicker::get_selected_state`],
+    /// but blocks with a facing property get turned toward the player, unless a picked state already has one.
+    /// Facings without a model fall back to the default state, so placing never makes an invisible block.
+    pub fn get_placement_state(&self, facing: Direction, block_reg: &Registry<Block>, cache: &MeshDataCache) -> Result<BlockState, BlockStateError> {
+        let state = self.get_selected_state(block_reg)?;
+        if self.picked_state.is_some() {
+            return Ok(state);
+        }
+        let Some(block) = state.block(block_reg) else {
+            return Ok(state);
+        };
+        // the block faces back at the player
+        let value = facing.rotate_y(2).get_name();
+        let has_value = block.get_states().iter()
+            .any(|s| s.name == Self::FACING_PROPERTY && s.values.iter().any(|v| v == value));
+        if !has_value {
+            return Ok(state);
+        }
+
+        let mut properties = state.get_state().clone();
+        properties.insert(String::from(Self::FACING_PROPERTY), String::from(value));
+        let turned = BlockState::with_state(state.get_id(), properties, block_reg)?;
+        if !cache.has_model(&turned) {
+            warn!("{} has no model facing {value}, placing its default state instead.", state.get_id());
+            return Ok(state);
+        }
+        Ok(turned)
+    }
+
+    /// Selects a block by id, forgetting any picked state. Does nothing if the block isn't in the picker.
+    pub fn select(&mut self, id: &str) {
+        if let Some(index) = self.block_order.iter().position(|b| b == id) {
+            self.index = index;
+            self.picked_state = None;
+        }
+    }
+}
+
+/// How far below the feet [`PlayerCollider::is_on_ground`] looks for something to stand on.
+const GROUND_PROBE: f32 = 0.05;
+
+/// The player's collision box. The player's transform is at their feet, so the box starts there.
+#[derive(Component, Debug)]
+pub struct PlayerCollider {
+    pub width: f32,
+    pub height: f32,
+    /// Distance from the feet to the eyes, where the camera is.
+    pub eye_height: f32,
+}
+impl Default for PlayerCollider {
+    fn default() -> Self {
+        Self {
+            width: 0.6,
+            height: 1.8,
+            eye_height: 1.62,
+        }
+    }
+}
+impl PlayerCollider {
+    /// Offset of the eyes from the feet.
+    pub fn get_eye_offset(&self) -> Vec3 {
+        vec3(0.0, self.eye_height, 0.0)
+    }
+
+    /// Gets the world space bounding box of the player, with feet at `feet`.
+    pub fn get_aabb(&self, feet: Vec3) -> Aabb3d {
+        let half_width = self.width / 2.0;
+        Aabb3d {
+            min: (feet - vec3(half_width, 0.0, half_width)).into(),
+            max: (feet + vec3(half_width, self.height, half_width)).into(),
+        }
+    }
+
+    /// Checks if a world space box would overlap the player. Only touching (e.g. standing on top of it) doesn't count.
+    pub fn overlaps(&self, feet: Vec3, other: &Aabb3d) -> bool {
+        let aabb = self.get_aabb(feet);
+        let (min, max) = (Vec3::from(aabb.min), Vec3::from(aabb.max));
+        let (other_min, other_max) = (Vec3::from(other.min), Vec3::from(other.max));
+        min.x < other_max.x && max.x > other_min.x
+            && min.y < other_max.y && max.y > other_min.y
+            && min.z < other_max.z && max.z > other_min.z
+    }
+
+    /// Checks if a block with collision shape `shape` (see [`BlockShapes`]) placed at `pos` would overlap the player.
+    pub fn overlaps_block(&self, feet: Vec3, pos: IVec3, shape: &[CollisionBox]) -> bool {
+        let offset = pos.as_vec3();
+        shape.iter().any(|b| self.overlaps(feet, &Aabb3d {
+            min: (offset + b.min).into(),
+            max: (offset + b.max).into(),
+        }))
+    }
+
+    /// Checks if the player, with feet at `feet`, would be inside any block's collision shape. Unloaded blocks don't collide.
+    pub fn collides(&self, feet: Vec3, world: &BlockWorld, shapes: &BlockShapes) -> bool {
+        let aabb = self.get_aabb(feet);
+        // one extra block below, since tall collision boxes (e.g. fences) stick out the top of their block
+        let min = Vec3::from(aabb.min).floor().as_ivec3() - IVec3::Y;
+        let max = Vec3::from(aabb.max).ceil().as_ivec3() - 1;
+        let blocks = (min.x..=max.x)
+            .flat_map(|x| (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| (IVec3::new(x, y, z), ()))));
+        ChunkReader::new(world.get_chunk_map())
+            .scan(blocks, |pos, _, state| self.overlaps_block(feet, pos, shapes.get_collision_shape(state)).then_some(()))
+            .is_some()
+    }
+
+    /// Checks if the player, with feet at `feet`, is standing on something: not inside any block, but with one right under their feet.
+    /// Walking sets this as it falls onto the ground, but flying players need to check.
+    pub fn is_on_ground(&self, feet: Vec3, world: &BlockWorld, shapes: &BlockShapes) -> bool {
+        !self.collides(feet, world, shapes) && self.collides(feet - vec3(0.0, GROUND_PROBE, 0.0), world, shapes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::block::BlockAsset;
+    use crate::registry::block::{test_asset, test_registry};
+    use crate::world::block::BlockStateTable;
+    use crate::world::chunk::{Chunk, ChunkData};
+    use crate::world::dimension::DimensionId;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn half_height_collision_box_stops_the_player_on_top() {
+        let half = CollisionBox { min: Vec3::ZERO, max: vec3(1.0, 0.5, 1.0) };
+        let mut registry = Registry::<Block>::new("block");
+        registry.register(Block::from_asset(&BlockAsset { collision_shape: Some(vec![half]), ..test_asset("slab") })).unwrap();
+        registry.freeze();
+        let shapes = BlockShapes::new(BlockStateTable::from_registry(&registry).unwrap(), &registry, &[]);
+
+        let mut world = BlockWorld::new(DimensionId::new("test"), 1);
+        let slab = BlockState::new("slab", &registry).unwrap();
+        // the bottom layer of the chunk is all slabs
+        let mut data = ChunkData::single(BlockState::air());
+        for x in 0..ChunkData::CHUNK_SIZE {
+            for z in 0..ChunkData::CHUNK_SIZE {
+                data.set_block(x, 0, z, slab.clone()).unwrap();
+            }
+        }
+        world.get_chunk_map_mut().add_chunk(Chunk::generated(IVec3::ZERO, data)).unwrap();
+
+        // drop the player from above until they hit something
+        let collider = PlayerCollider::default();
+        let mut feet = vec3(4.5, 3.0, 4.5);
+        while !collider.collides(feet - vec3(0.0, 0.125, 0.0), &world, &shapes) {
+            feet.y -= 0.125;
+            assert!(feet.y > -1.0, "fell through the slab");
+        }
+        assert_eq!(feet.y, 0.5);
+        assert!(collider.is_on_ground(feet, &world, &shapes));
+        assert!(!collider.is_on_ground(feet + Vec3::Y, &world, &shapes));
+    }
+
+    #[test]
+    fn invisible_blocks_stop_the_player_but_draw_nothing() {
+        use crate::core::profiling::PipelineProfiler;
+        use crate::render::block::test_mesh_cache;
+        use crate::render::chunk::{create_chunk_mesh_with, MeshScratch};
+
+        // no model and no collision shape, like a barrier
+        let registry = test_registry(&["barrier"]);
+        let shapes = BlockShapes::new(BlockStateTable::from_registry(&registry).unwrap(), &registry, &[]);
+        let barrier = BlockState::new("barrier", &registry).unwrap();
+        let mut data = ChunkData::single(BlockState::air());
+        for x in 0..ChunkData::CHUNK_SIZE {
+            for z in 0..ChunkData::CHUNK_SIZE {
+                data.set_block(x, 0, z, barrier.clone()).unwrap();
+            }
+        }
+        let air = ChunkData::single(BlockState::air());
+        let meshes = create_chunk_mesh_with(&data, &test_mesh_cache(&registry, &[]), [&air; 6], &mut MeshScratch::default(), &PipelineProfiler::default());
+        assert!(meshes.is_empty());
+
+        let mut world = BlockWorld::new(DimensionId::new("test"), 1);
+        world.get_chunk_map_mut().add_chunk(Chunk::generated(IVec3::ZERO, data)).unwrap();
+        let collider = PlayerCollider::default();
+        let mut feet = vec3(4.5, 3.0, 4.5);
+        while !collider.collides(feet - vec3(0.0, 0.125, 0.0), &world, &shapes) {
+            feet.y -= 0.125;
+            assert!(feet.y > -1.0, "fell through the barrier");
+        }
+        // a full block, even without a model to take the shape from
+        assert_eq!(feet.y, 1.0);
+        assert!(collider.is_on_ground(feet, &world, &shapes));
+    }
+
+    #[test]
+    fn reach_depends_on_game_mode_and_tool() {
+        let settings = ReachSettings::default();
+        assert_eq!(effective_reach(GameMode::Creative, None, &settings), 5.0);
+        assert_eq!(effective_reach(GameMode::Survival, None, &settings), 4.5);
+
+        let pickaxe = Tool { kind: ToolType::Pickaxe, level: 2 };
+        assert_eq!(effective_reach(GameMode::Survival, Some(&pickaxe), &settings), 5.0);
+        assert_eq!(effective_reach(GameMode::Creative, Some(&pickaxe), &settings), 5.5);
+        // level 0 tools don't add anything
+        let wooden = Tool { kind: ToolType::Axe, level: 0 };
+        assert_eq!(effective_reach(GameMode::Survival, Some(&wooden), &settings), 4.5);
+    }
+
+    #[test]
+    fn break_duration_scales_with_hardness() {
+        let block = |id: &str, hardness: u32| Block::from_asset(&BlockAsset { hardness, ..test_asset(id) });
+        let (soft, hard) = (block("dirt", 1), block("obsidian", 4));
+        let survival = GameMode::Survival;
+        assert_eq!(survival.get_break_duration(&soft, None), BREAK_SECONDS_PER_HARDNESS);
+        assert_eq!(survival.get_break_duration(&hard, None), 4.0 * survival.get_break_duration(&soft, None));
+        assert_eq!(survival.get_break_duration(&block("flower", 0), None), 0.0);
+        // everything breaks instantly outside survival
+        assert_eq!(GameMode::Creative.get_break_duration(&hard, None), 0.0);
+
+        // the right tool speeds it up, but needing one and not having it slows it down
+        let stone = Block::from_asset(&BlockAsset { hardness: 2, tool: Some(ToolType::Pickaxe), ..test_asset("stone") });
+        let pickaxe = Tool { kind: ToolType::Pickaxe, level: 1 };
+        let shovel = Tool { kind: ToolType::Shovel, level: 1 };
+        let base = 2.0 * BREAK_SECONDS_PER_HARDNESS;
+        assert_eq!(survival.get_break_duration(&stone, Some(&pickaxe)), base / pickaxe.get_speed());
+        assert_eq!(survival.get_break_duration(&stone, Some(&shovel)), base * WRONG_TOOL_PENALTY);
+        assert_eq!(survival.get_break_duration(&stone, None), base * WRONG_TOOL_PENALTY);
+    }
+
+    #[test]
+    fn picked_states_are_placed_until_the_selection_changes() {
+        let registry = test_registry(&["stone", "furnace"]);
+        let mut picker = BlockPicker { block_order: vec![String::from("stone"), String::from("furnace")], ..Default::default() };
+        assert_eq!(picker.get_selected_state(&registry).unwrap(), BlockState::new("stone", &registry).unwrap());
+
+        let lit = BlockState::with_state("furnace", BTreeMap::from([(String::from("lit"), String::from("true"))]), &registry).unwrap();
+        picker.select("furnace");
+        picker.picked_state = Some(lit.clone());
+        assert_eq!(picker.get_selected_state(&registry).unwrap(), lit);
+
+        // unknown blocks leave everything alone
+        picker.select("dirt");
+        assert_eq!(picker.get_selected_id(), "furnace");
+        assert_eq!(picker.get_selected_state(&registry).unwrap(), lit);
+
+        // selecting anything, even the same block, goes back to the default state
+        picker.select("furnace");
+        assert_eq!(picker.picked_state, None);
+        assert_eq!(picker.get_selected_state(&registry).unwrap(), BlockState::new("furnace", &registry).unwrap());
+        picker.picked_state = Some(lit);
+        picker.cycle(true);
+        assert_eq!(picker.get_selected_id(), "stone");
+        assert_eq!(picker.picked_state, None);
+    }
+
+    const FRAME: f32 = 1.0 / 60.0;
+
+    // runs creative breaking for a frame, the same way place_and_break does
+    fn creative_frame(progress: &mut BreakProgress, settings: &BreakSettings, pressed: bool, held: bool) -> bool {
+        progress.tick(FRAME, held);
+        let broke = progress.wants_creative_break(pressed, held, settings);
+        if broke {
+            progress.finish_break(settings);
+        }
+        broke
+    }
+
+    #[test]
+    fn cooldown_blocks_breaking_until_it_runs_out() {
+        let settings = BreakSettings { cooldown: 0.25, auto_repeat: true };
+        let mut progress = BreakProgress::default();
+        assert!(progress.can_break());
+
+        progress.finish_break(&settings);
+        assert!(!progress.can_break());
+        progress.tick(0.2, true);
+        assert!(!progress.can_break());
+        progress.tick(0.1, true);
+        assert!(progress.can_break());
+    }
+
+    #[test]
+    fn without_auto_repeat_the_button_has_to_be_let_go() {
+        let settings = BreakSettings { cooldown: 0.25, auto_repeat: false };
+        let mut progress = BreakProgress::default();
+        progress.finish_break(&settings);
+
+        // still holding long after the cooldown
+        progress.tick(1.0, true);
+        assert!(!progress.can_break());
+        progress.tick(FRAME, false);
+        assert!(progress.can_break());
+    }
+
+    #[test]
+    fn finishing_a_break_clears_progress() {
+        let mut progress = BreakProgress { pos: Some(IVec3::ONE), elapsed: 0.5, ..Default::default() };
+        progress.finish_break(&BreakSettings::default());
+        assert_eq!(progress.pos, None);
+        assert_eq!(progress.elapsed, 0.0);
+    }
+
+    #[test]
+    fn held_button_breaks_once_per_cooldown_with_auto_repeat() {
+        let settings = BreakSettings { cooldown: 0.25, auto_repeat: true };
+        let mut progress = BreakProgress::default();
+        let mut broken = 0;
+        // hold for one second
+        for frame in 0..60 {
+            if creative_frame(&mut progress, &settings, frame == 0, true) {
+                broken += 1;
+            }
+        }
+        assert_eq!(broken, 4);
+    }
+
+    #[test]
+    fn held_button_breaks_once_without_auto_repeat() {
+        let settings = BreakSettings { cooldown: 0.25, auto_repeat: false };
+        let mut progress = BreakProgress::default();
+        let broken = (0..60).filter(|&frame| creative_frame(&mut progress, &settings, frame == 0, true)).count();
+        assert_eq!(broken, 1);
+    }
+
+    #[test]
+    fn clicks_during_the_cooldown_are_buffered() {
+        let settings = BreakSettings { cooldown: 0.25, auto_repeat: false };
+        let mut progress = BreakProgress::default();
+        assert!(creative_frame(&mut progress, &settings, true, true));
+        assert!(!creative_frame(&mut progress, &settings, false, false));
+
+        // a second click well inside the cooldown
+        assert!(!creative_frame(&mut progress, &settings, true, true));
+        let later = (0..30).filter(|_| creative_frame(&mut progress, &settings, false, false)).count();
+        assert_eq!(later, 1);
+    }
+
+    #[test]
+    fn looking_away_drops_a_buffered_click() {
+        let settings = BreakSettings::default();
+        let mut progress = BreakProgress::default();
+        creative_frame(&mut progress, &settings, true, true);
+        creative_frame(&mut progress, &settings, true, true);
+        progress.reset();
+        let later = (0..30).filter(|_| creative_frame(&mut progress, &settings, false, false)).count();
+        assert_eq!(later, 0);
+    }
+}
@@ -0,0 +1,398 @@
+use crate::math::block::Vec3Ext;
+use crate::world::block::BlockWorld;
+use crate::world::chunk::{self, ChunkData, PackedChunkData};
+use crate::world::dimension::{ActiveWorld, DimensionId};
+use crate::world::generation::WorldConfig;
+use crate::world::machine::{MachineWorld, PackedMachines};
+use crate::world::player::Player;
+use crate::RunConfig;
+use bevy::asset::ron;
+use bevy::prelude::*;
+use bevy::tasks::futures_lite::future;
+use bevy::tasks::{block_on, IoTaskPool, Task};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Metadata for a whole save, like the seed and where the player is. Written next to the chunk folders.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldMetadata {
+    pub seed: u64,
+    pub active_dimension: String,
+    pub player_pos: Vec3,
+    /// (x, z) column new players spawn in.
+    #[serde(default)]
+    pub spawn_point: IVec2,
+}
+
+#[derive(Debug, Resource)]
+pub struct SaveSettings {
+    /// Seconds between autosaves. None turns autosaving off, so the world is only saved on exit.
+    pub autosave_interval: Option<f32>,
+}
+impl Default for SaveSettings {
+    fn default() -> Self {
+        Self {
+            autosave_interval: Some(300.0),
+        }
+    }
+}
+
+/// The background save worker. At most one save is being written at a time.
+#[derive(Default, Resource)]
+pub struct SaveWorker {
+    since_last_save: f32,
+    task: Option<Task<Result<usize, BevyError>>>,
+}
+
+impl SaveWorker {
+    /// Whether a save is currently being written in the background.
+    pub fn is_saving(&self) -> bool {
+        self.task.is_some()
+    }
+
+    /// Blocks until the current save (if any) is written.
+    pub fn finish(&mut self) -> Option<Result<usize, BevyError>> {
+        self.task.take().map(block_on)
+    }
+}
+
+/// Everything that needs to be written for one save. Chunks are packed on the main thread,
+/// so the job can be written on another thread without touching the world.
+#[derive(Debug)]
+pub struct SaveJob {
+    chunks: Vec<(PathBuf, PackedChunkData)>,
+    /// Chunks with no machines left have their machine file removed.
+    machines: Vec<(PathBuf, PackedMachines)>,
+    metadata: Option<(PathBuf, WorldMetadata)>,
+}
+
+impl SaveJob {
+    pub fn get_chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Writes everything in this job to disk. Returns the number of chunks written.
+    pub fn write(self) -> Result<usize, BevyError> {
+        for (file, packed_data) in self.chunks.iter() {
+            write_chunk(file, packed_data)?;
+        }
+        for (file, machines) in self.machines.iter() {
+            if machines.is_empty() {
+                if file.exists() {
+                    fs::remove_file(file)?;
+                }
+                continue;
+            }
+            if let Some(folder) = file.parent() {
+                fs::create_dir_all(folder)?;
+            }
+            fs::write(file, ron::ser::to_string(machines)?.as_bytes())?;
+        }
+        if let Some((file, metadata)) = self.metadata {
+            fs::write(file, ron::ser::to_string(&metadata)?.as_bytes())?;
+        }
+        Ok(self.chunks.len())
+    }
+}
+
+/// Path of the metadata file for a save.
+pub fn metadata_file(data_dir: &Path) -> PathBuf {
+    data_dir.join("world.ron")
+}
+
+/// Reads the metadata of a save, or None if nothing has been saved yet.
+pub fn load_metadata(data_dir: &Path) -> Result<Option<WorldMetadata>, BevyError> {
+    let file = metadata_file(data_dir);
+    if !file.exists() {
+        return Ok(None);
+    }
+    let bytes = fs::read(file)?;
+    Ok(Some(ron::de::from_bytes(bytes.as_slice())?))
+}
+
+/// Path a chunk is saved to, e.g. `dimensions/overworld/chunks/0.-1.2.ron`
+pub fn chunk_file(data_dir: &Path, dimension: &DimensionId, pos: IVec3) -> PathBuf {
+    data_dir
+        .join("dimensions")
+        .join(dimension.get_id())
+        .join("chunks")
+        .join(format!("{}.{}.{}.ron", pos.x, pos.y, pos.z))
+}
+
+/// Path the machines of a chunk are saved to, e.g. `dimensions/overworld/machines/0.-1.2.ron`.
+/// Kept apart from chunk saves, since machines stay loaded when their chunk doesn't.
+pub fn machines_file(data_dir: &Path, dimension: &DimensionId, pos: IVec3) -> PathBuf {
+    data_dir
+        .join("dimensions")
+        .join(dimension.get_id())
+        .join("machines")
+        .join(format!("{}.{}.{}.ron", pos.x, pos.y, pos.z))
+}
+
+/// Reads every saved machine of a dimension, grouped by chunk.
+pub fn load_machines(data_dir: &Path, dimension: &DimensionId) -> Result<Vec<(IVec3, PackedMachines)>, BevyError> {
+    let mut machines = Vec::new();
+    let mut positions = find_saved_positions(&machines_file(data_dir, dimension, IVec3::ZERO)).into_iter().collect::<Vec<_>>();
+    positions.sort_by_key(|pos| pos.to_array());
+    for pos in positions {
+        let bytes = fs::read(machines_file(data_dir, dimension, pos))?;
+        machines.push((pos, ron::de::from_bytes(bytes.as_slice())?));
+    }
+    Ok(machines)
+}
+
+/// Writes one packed chunk to disk, creating its folder if it doesn't exist yet.
+pub fn write_chunk(file: &Path, packed_data: &PackedChunkData) -> Result<(), BevyError> {
+    if let Some(folder) = file.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    fs::write(file, ron::ser::to_string(packed_data)?.as_bytes())?;
+    Ok(())
+}
+
+/// Reads a saved chunk from disk.
+pub fn load_chunk(file: &Path) -> Result<ChunkData, BevyError> {
+    let bytes = fs::read(file)?;
+    let packed_data: PackedChunkData = ron::de::from_bytes(bytes.as_slice())?;
+    Ok(packed_data.try_into()?)
+}
+
+/// Finds the positions of every chunk saved for a dimension. Files that don't look like chunk saves are ignored.
+pub fn find_saved_chunks(data_dir: &Path, dimension: &DimensionId) -> HashSet<IVec3> {
+    find_saved_positions(&chunk_file(data_dir, dimension, IVec3::ZERO))
+}
+
+// positions of every file named like `file` (`x.y.z.ron`) in the same folder
+fn find_saved_positions(file: &Path) -> HashSet<IVec3> {
+    let folder = file.parent().unwrap().to_path_buf();
+    let Ok(entries) = fs::read_dir(&folder) else {
+        return HashSet::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            let mut coords = name.strip_suffix(".ron")?.split('.').map(|c| c.parse::<i32>().ok());
+            let pos = IVec3::new(coords.next()??, coords.next()??, coords.next()??);
+            coords.next().is_none().then_some(pos)
+        })
+        .collect()
+}
+
+/// Packs every dirty chunk of a world, marking them clean. Edits made after this are part of the next save.
+/// They only count as saved once the job is written, see [`BlockWorld::finish_saving`].
+/// Dirty chunks that have been unloaded since they were edited are skipped.
+/// Chunks are packed (and so written) nearest to `center` first, so if the game is closed mid-save the edits around the player are kept.
+pub fn pack_dirty_chunks(world: &mut BlockWorld, data_dir: &Path, center: IVec3) -> Result<Vec<(PathBuf, PackedChunkData)>, BevyError> {
+    let mut dirty = world.take_dirty_chunks();
+    dirty.sort_by_key(|pos| chunk::distance_order(center, *pos));
+    let mut packed = Vec::new();
+    for pos in dirty {
+        let Some(chunk) = world.get_chunk_map().get_chunk(&pos) else {
+            warn!("Dirty chunk {pos} in {} was unloaded before it could be saved.", world.get_dimension());
+            world.cancel_saving(&pos);
+            continue;
+        };
+        // chunks packed in memory are saved as they are
+        let packed_data = chunk.to_packed()?;
+        packed.push((chunk_file(data_dir, world.get_dimension(), pos), packed_data));
+    }
+    Ok(packed)
+}
+
+/// Packs the machines of every chunk whose machines changed, whether or not the chunk itself is loaded.
+pub fn pack_dirty_machines(machines: &mut MachineWorld, dimension: &DimensionId, data_dir: &Path) -> Vec<(PathBuf, PackedMachines)> {
+    machines.take_dirty_chunks()
+        .into_iter()
+        .map(|pos| (machines_file(data_dir, dimension, pos), machines.pack_chunk(pos)))
+        .collect()
+}
+
+// packs dirty chunks and machines of every world, plus metadata if there's an active world.
+fn create_save_job(
+    q_world: &mut Query<(&mut BlockWorld, &mut MachineWorld, Has<ActiveWorld>)>,
+    player: &Query<&Transform, With<Player>>,
+    world_config: &WorldConfig,
+    data_dir: &Path,
+) -> Result<SaveJob, BevyError> {
+    let mut chunks = Vec::new();
+    let mut machines = Vec::new();
+    let mut active_dimension = None;
+    let player_pos = player.single().map(|t| t.translation).unwrap_or_default();
+    let player_chunk = chunk::pos_to_chunk_pos(player_pos.as_block_pos());
+    for (mut world, mut machine_world, active) in q_world.iter_mut() {
+        if active {
+            active_dimension = Some(world.get_dimension().clone());
+        }
+        // the player is only in the active world, so there's nothing to prioritize in the others
+        let center = if active { player_chunk } else { IVec3::ZERO };
+        chunks.append(&mut pack_dirty_chunks(world.as_mut(), data_dir, center)?);
+        machines.append(&mut pack_dirty_machines(machine_world.as_mut(), world.get_dimension(), data_dir));
+    }
+
+    let metadata = active_dimension.map(|dimension| {
+        (metadata_file(data_dir), WorldMetadata {
+            seed: world_config.seed,
+            active_dimension: String::from(dimension.get_id()),
+            player_pos,
+            spawn_point: world_config.spawn_point,
+        })
+    });
+    Ok(SaveJob {
+        chunks,
+        machines,
+        metadata,
+    })
+}
+
+// hands a save to the worker every autosave interval. Packing happens here, but writing happens on the IO pool.
+pub(crate) fn autosave(
+    mut worker: ResMut<SaveWorker>,
+    settings: Res<SaveSettings>,
+    time: Res<Time>,
+    mut q_world: Query<(&mut BlockWorld, &mut MachineWorld, Has<ActiveWorld>)>,
+    player: Query<&Transform, With<Player>>,
+    world_config: Res<WorldConfig>,
+    run_config: Res<RunConfig>,
+) -> Result<(), BevyError> {
+    let Some(interval) = settings.autosave_interval else {
+        return Ok(());
+    };
+    worker.since_last_save += time.delta_secs();
+    // if the last save is still being written, wait for it
+    if worker.since_last_save < interval || worker.is_saving() {
+        return Ok(());
+    }
+    worker.since_last_save = 0.0;
+
+    let job = create_save_job(&mut q_world, &player, &world_config, &run_config.data_dir)?;
+    debug!("Autosaving {} chunks.", job.get_chunk_count());
+    worker.task = Some(IoTaskPool::get().spawn(async move { job.write() }));
+    Ok(())
+}
+
+pub(crate) fn receive_autosave(
+    mut worker: ResMut<SaveWorker>,
+    mut q_world: Query<(&mut BlockWorld, &mut MachineWorld)>,
+) {
+    let Some(task) = worker.task.as_mut() else {
+        return;
+    };
+    let Some(result) = block_on(future::poll_once(task)) else {
+        return;
+    };
+    worker.task = None;
+    for (mut world, mut machines) in q_world.iter_mut() {
+        world.finish_saving(result.is_ok());
+        machines.finish_saving(result.is_ok());
+    }
+    match result {
+        Ok(count) => info!("Autosaved {} chunks.", count),
+        Err(e) => error!("Autosave failed: {}", e),
+    }
+}
+
+// Flushes everything to disk once the app is about to exit, e.g. from closing the window.
+// Runs in Last, so exits requested anywhere earlier in the frame are caught before the app loop stops.
+// Waits for any autosave in flight first, then writes on this thread so every write is complete once this returns.
+pub(crate) fn save_on_exit(
+    mut exit: MessageReader<AppExit>,
+    mut worker: ResMut<SaveWorker>,
+    mut q_world: Query<(&mut BlockWorld, &mut MachineWorld, Has<ActiveWorld>)>,
+    player: Query<&Transform, With<Player>>,
+    world_config: Res<WorldConfig>,
+    run_config: Res<RunConfig>,
+) -> Result<(), BevyError> {
+    if exit.is_empty() {
+        return Ok(());
+    }
+    exit.clear();
+
+    if let Some(result) = worker.finish() {
+        for (mut world, mut machines, _) in q_world.iter_mut() {
+            world.finish_saving(result.is_ok());
+            machines.finish_saving(result.is_ok());
+        }
+        if let Err(e) = result {
+            error!("Autosave failed: {}", e);
+        }
+    }
+    // nothing to save if we never got in game
+    if q_world.is_empty() {
+        return Ok(());
+    }
+
+    let job = create_save_job(&mut q_world, &player, &world_config, &run_config.data_dir)?;
+    let result = job.write();
+    for (mut world, mut machines, _) in q_world.iter_mut() {
+        world.finish_saving(result.is_ok());
+        machines.finish_saving(result.is_ok());
+    }
+    info!("Saved {} chunks before exiting.", result?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::machine::{MachineBlock, MachineMarker};
+
+    #[test]
+    fn saved_machines_are_restored_with_their_metadata() {
+        let data_dir = std::env::temp_dir().join(format!("gtclone-machines-{}", std::process::id()));
+        let dimension = DimensionId::new("overworld");
+        let mut world = World::new();
+        let pos = IVec3::new(40, -3, 7);
+        let mut machines = MachineWorld::new();
+        machines.insert_machine(pos, MachineBlock::new(world.spawn_empty().id()));
+        assert!(machines.set_metadata(&pos, 9));
+
+        let job = SaveJob {
+            chunks: vec![],
+            machines: pack_dirty_machines(&mut machines, &dimension, &data_dir),
+            metadata: None,
+        };
+        let written = job.write();
+        machines.finish_saving(written.is_ok());
+        written.unwrap();
+
+        let mut restored = MachineWorld::new();
+        for (chunk_pos, packed) in load_machines(&data_dir, &dimension).unwrap() {
+            restored.unpack_chunk(chunk_pos, packed, |pos| world.spawn(MachineMarker::new(pos)).id());
+        }
+        fs::remove_dir_all(&data_dir).unwrap();
+
+        let machine = restored.get_machine(&pos).expect("the machine was saved");
+        assert_eq!(machine.metadata, 9);
+        assert_eq!(world.get::<MachineMarker>(machine.entity).unwrap().get_pos(), pos);
+        // what came off disk doesn't need saving again
+        assert!(restored.take_dirty_chunks().is_empty());
+    }
+
+    #[test]
+    fn saved_chunks_survive_new_blocks_being_registered() {
+        use crate::registry::block::test_registry;
+        use crate::world::block::BlockState;
+
+        let file = std::env::temp_dir().join(format!("gtclone-registry-shift-{}", std::process::id())).join("chunk.ron");
+        let registry = test_registry(&["stone", "dirt"]);
+        let stone = BlockState::new("stone", &registry).unwrap();
+        let dirt = BlockState::new("dirt", &registry).unwrap();
+        let mut data = ChunkData::single(BlockState::air());
+        data.set_block(1, 2, 3, stone.clone()).unwrap();
+        data.set_block(4, 5, 6, dirt.clone()).unwrap();
+        write_chunk(&file, &PackedChunkData::from(&data)).unwrap();
+
+        // a new block that sorts before the others moves every registry index after it
+        let grown = test_registry(&["andesite", "stone", "dirt"]);
+        assert_ne!(registry.get_index("stone"), grown.get_index("stone"));
+
+        let loaded = load_chunk(&file);
+        fs::remove_dir_all(file.parent().unwrap()).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.get_block(1, 2, 3).unwrap(), stone);
+        assert_eq!(loaded.get_block(4, 5, 6).unwrap(), dirt);
+        assert_eq!(BlockState::new("stone", &grown).unwrap(), stone);
+    }
+}
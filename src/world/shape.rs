@@ -0,0 +1,131 @@
+use crate::asset::block::CollisionBox;
+use crate::registry::block::Block;
+use crate::registry::Registry;
+use crate::render::block::BlockModelMinimal;
+use crate::world::block::{BlockState, BlockStateId, BlockStateTable};
+use bevy::prelude::*;
+use std::sync::Arc;
+
+/// The shape of every blockstate, indexed by [`BlockStateId`]. Built along with the block models when they're cached,
+/// since shapes of most blocks come from their model.
+///
+/// Backed by arcs, so it is cheap to clone.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct BlockShapes {
+    table: BlockStateTable,
+    /// Boxes the player collides with, in block local coordinates. Empty for states nothing collides with, like air.
+    collision: Arc<Vec<Vec<CollisionBox>>>,
+    /// What raycasts (and so the player's cursor) hit. None for states rays pass through, like air.
+    hit_boxes: Arc<Vec<Option<CollisionBox>>>,
+}
+
+impl BlockShapes {
+    /// Works out the shape of every state in the table.
+    ///
+    /// States collide with the shape their block declared if it has one, otherwise with the box around their model, so a slab
+    /// only collides on its bottom half. Blocks without either (like barriers) are full cubes.
+    /// States with a model are hit on the model's bounds, and anything else on the bounds of its collision shape.
+    pub fn new(table: BlockStateTable, block_reg: &Registry<Block>, models: &[Option<BlockModelMinimal>]) -> Self {
+        let mut collision = Vec::with_capacity(table.len());
+        let mut hit_boxes = Vec::with_capacity(table.len());
+        for (id, state) in table.iter() {
+            let model_bounds = models.get(id.get_index())
+                .and_then(Option::as_ref)
+                .map(|model| model.bounds())
+                .filter(|(min, max)| min != max)
+                .map(|(min, max)| CollisionBox { min, max });
+            let shape = match state.block(block_reg).and_then(Block::get_collision_shape) {
+                Some(shape) => shape.to_vec(),
+                None => vec![model_bounds.unwrap_or(CollisionBox::FULL)],
+            };
+            hit_boxes.push(model_bounds.or_else(|| bounds_of(&shape)));
+            collision.push(shape);
+        }
+        Self {
+            table,
+            collision: Arc::new(collision),
+            hit_boxes: Arc::new(hit_boxes),
+        }
+    }
+
+    /// Gets the boxes a blockstate collides with, in block local coordinates. Empty if nothing collides with it,
+    /// or if the state isn't in the table.
+    pub fn get_collision_shape(&self, state: &BlockState) -> &[CollisionBox] {
+        match self.table.get_id(state) {
+            Some(id) => self.get_collision_shape_by_id(id),
+            None => &[],
+        }
+    }
+
+    pub fn get_collision_shape_by_id(&self, id: BlockStateId) -> &[CollisionBox] {
+        self.collision.get(id.get_index()).map_or(&[], Vec::as_slice)
+    }
+
+    /// Gets the box rays hit for a blockstate, in block local coordinates. None if rays pass straight through it.
+    pub fn get_hit_box(&self, state: &BlockState) -> Option<CollisionBox> {
+        self.get_hit_box_by_id(self.table.get_id(state)?)
+    }
+
+    pub fn get_hit_box_by_id(&self, id: BlockStateId) -> Option<CollisionBox> {
+        *self.hit_boxes.get(id.get_index())?
+    }
+}
+
+// smallest box around all of these, or None if there aren't any
+fn bounds_of(boxes: &[CollisionBox]) -> Option<CollisionBox> {
+    let (first, rest) = boxes.split_first()?;
+    Some(rest.iter().fold(*first, |acc, b| CollisionBox {
+        min: acc.min.min(b.min),
+        max: acc.max.max(b.max),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::block::{BlockAsset, FaceType};
+    use crate::registry::block::test_asset;
+    use crate::render::block::{test_face, FaceMinimal};
+    use crate::world::block::Direction;
+    use bevy::math::{vec2, vec3};
+
+    // a flat square at height y, enough to give a model bounds
+    fn square(y: f32) -> FaceMinimal {
+        let vertices = [vec3(0.0, y, 1.0), vec3(1.0, y, 1.0), vec3(1.0, y, 0.0), vec3(0.0, y, 0.0)].map(|pos| (pos, vec2(pos.x, pos.z)));
+        FaceMinimal::from_asset(&test_face(FaceType::Quad, &vertices, Vec3::Y), 0, None).unwrap()
+    }
+
+    #[test]
+    fn shapes_come_from_the_block_then_the_model() {
+        let fence_box = CollisionBox { min: vec3(0.375, 0.0, 0.375), max: vec3(0.625, 1.5, 0.625) };
+        let mut registry = Registry::<Block>::new("block");
+        registry.register(Block::from_asset(&test_asset("slab"))).unwrap();
+        registry.register(Block::from_asset(&test_asset("barrier"))).unwrap();
+        registry.register(Block::from_asset(&BlockAsset { collision_shape: Some(vec![fence_box]), ..test_asset("fence") })).unwrap();
+        registry.freeze();
+        let table = BlockStateTable::from_registry(&registry).unwrap();
+
+        let slab = BlockState::new("slab", &registry).unwrap();
+        let barrier = BlockState::new("barrier", &registry).unwrap();
+        let fence = BlockState::new("fence", &registry).unwrap();
+        let mut models = vec![None; table.len()];
+        let slab_model = BlockModelMinimal::new(vec![square(0.0), square(0.5)], &[Direction::Down]);
+        models[table.get_id(&slab).unwrap().get_index()] = Some(slab_model.clone());
+        models[table.get_id(&fence).unwrap().get_index()] = Some(slab_model);
+        let shapes = BlockShapes::new(table, &registry, &models);
+
+        let half = CollisionBox { min: Vec3::ZERO, max: vec3(1.0, 0.5, 1.0) };
+        // no declared shape, so it follows the model
+        assert_eq!(shapes.get_collision_shape(&slab), &[half]);
+        assert_eq!(shapes.get_hit_box(&slab), Some(half));
+        // no shape and no model is a full cube you can't see
+        assert_eq!(shapes.get_collision_shape(&barrier), &[CollisionBox::FULL]);
+        assert_eq!(shapes.get_hit_box(&barrier), Some(CollisionBox::FULL));
+        // declared shapes win over the model for collisions, but the cursor still hits the model
+        assert_eq!(shapes.get_collision_shape(&fence), &[fence_box]);
+        assert_eq!(shapes.get_hit_box(&fence), Some(half));
+
+        assert!(shapes.get_collision_shape(&BlockState::air()).is_empty());
+        assert_eq!(shapes.get_hit_box(&BlockState::air()), None);
+    }
+}